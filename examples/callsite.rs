@@ -0,0 +1,40 @@
+use log::{Level, LevelFilter};
+use mmlog::Builder;
+use std::hint::black_box;
+use std::time::Instant;
+
+const RECORDS: u32 = 10_000_000;
+
+// 被 module_level 关掉的 target：log::info! 每次都要构造 Metadata、调用 enabled()，
+// log_if! 和 target_enabled() 只看调用点的缓存
+fn main() {
+    let logger = Builder::new()
+        .level(Level::Info)
+        .module_level("audit", LevelFilter::Off)
+        .init("callsite.log")
+        .expect("Builder::init()");
+
+    let start = Instant::now();
+    for i in 0..RECORDS {
+        log::info!(target: "audit", "user {} logged in", black_box(i));
+    }
+    let plain = start.elapsed();
+
+    let start = Instant::now();
+    for i in 0..RECORDS {
+        mmlog::log_if!(target: "audit", Level::Info, "user {} logged in", black_box(i));
+    }
+    let cached = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..RECORDS {
+        black_box(logger.target_enabled(Level::Info, black_box("audit")));
+    }
+    let direct = start.elapsed();
+
+    let per = |d: std::time::Duration| d / RECORDS;
+    println!("log::info!:      {:?} per disabled record", per(plain));
+    println!("mmlog::log_if!:  {:?} per disabled record", per(cached));
+    println!("target_enabled:  {:?} per call", per(direct));
+    assert_eq!(logger.stats().records_written, 0);
+}
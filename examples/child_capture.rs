@@ -0,0 +1,25 @@
+use lazy_static::lazy_static;
+use mmlog::{capture, Builder, Logger};
+use std::process::{Command, Stdio};
+
+lazy_static! {
+    static ref LOGGER: Logger = Builder::new().build("child.log").expect("Builder::build()");
+}
+
+fn main() {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg("echo hello; echo oops >&2; printf 'no newline'")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Command::spawn()");
+
+    let handle = capture::pipe_child(&LOGGER, &mut child, "sh");
+    child.wait().expect("Child::wait()");
+    let n = handle
+        .join()
+        .expect("JoinHandle::join()")
+        .expect("pipe_child()");
+    println!("captured {} lines into child.log", n);
+}
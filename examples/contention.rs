@@ -0,0 +1,56 @@
+use log::{Level, Log, Record};
+use mmlog::{Builder, Logger, KB, MB};
+use std::thread;
+use std::time::Instant;
+
+const THREADS: usize = 16;
+const RECORDS: usize = 100000;
+
+// 同样的负载分别跑一遍普通的写法和 Builder::batched。
+// 多核机器上普通的写法卡在写锁上，batched 才能快起来；单核上两者差不多
+fn run(name: &str, logger: Logger) {
+    let start = Instant::now();
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let logger = &logger;
+            s.spawn(move || {
+                for i in 0..RECORDS {
+                    logger.log(
+                        &Record::builder()
+                            .level(Level::Info)
+                            .target("contention")
+                            .args(format_args!("thread {} record {}", t, i))
+                            .build(),
+                    );
+                }
+            });
+        }
+    });
+    logger.flush();
+    println!(
+        "{}: {} threads x {} records: {:?}",
+        name,
+        THREADS,
+        RECORDS,
+        start.elapsed()
+    );
+    assert_eq!(logger.stats().records_written, (THREADS * RECORDS) as u64);
+}
+
+fn main() {
+    run(
+        "plain",
+        Builder::new()
+            .size(5 * MB)
+            .build("contention.log")
+            .expect("Builder::build()"),
+    );
+    run(
+        "batched",
+        Builder::new()
+            .size(5 * MB)
+            .batched(64 * KB)
+            .build("contention_batched.log")
+            .expect("Builder::build()"),
+    );
+}
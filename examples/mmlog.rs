@@ -4,14 +4,10 @@ use mmlog::{Builder, Logger, MB};
 use std::time::SystemTime;
 
 lazy_static! {
-    static ref LOGGER: Logger = {
-        let b = Builder::new().size(5 * MB);
-        if matches!(std::fs::metadata("test.log"), Ok(_)) {
-            b.open("test.log").expect("Builder::open()")
-        } else {
-            b.build("test.log").expect("Builder::build()")
-        }
-    };
+    static ref LOGGER: Logger = Builder::new()
+        .size(5 * MB)
+        .build_anonymous()
+        .expect("Builder::build_anonymous()");
 }
 
 fn main() {
@@ -16,4 +16,4 @@ fn main() {
         }
     }
     println!("{:?}", start.elapsed().unwrap());
-}
\ No newline at end of file
+}
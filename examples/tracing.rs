@@ -0,0 +1,27 @@
+use mmlog::reader::LogReader;
+use mmlog::tracing::MmapLayer;
+use mmlog::Builder;
+use tracing::{info, info_span, warn};
+use tracing_subscriber::layer::SubscriberExt;
+
+fn main() {
+    let logger = Builder::new()
+        .build("tracing.log")
+        .expect("Builder::build()");
+    let subscriber = tracing_subscriber::registry().with(MmapLayer::new(logger));
+    tracing::subscriber::set_global_default(subscriber).expect("set_global_default()");
+
+    for id in 0..3 {
+        let span = info_span!("request", id);
+        let _enter = span.enter();
+        info!(path = "/index.html", "start");
+        let inner = info_span!("db", table = "users");
+        inner.in_scope(|| warn!(rows = 0, "query returned nothing"));
+        info!("done");
+    }
+
+    // 全局的 subscriber 不会被 drop，读之前的内容已经在映射里了
+    for record in LogReader::open("tracing.log").unwrap().records() {
+        println!("{}", record);
+    }
+}
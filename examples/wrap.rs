@@ -0,0 +1,88 @@
+// 用最小的缓冲区反复翻转，检查读回来的记录是连续的。
+//
+// 也用来在 sanitizer 下跑一遍写入路径：
+//
+//     cargo +nightly miri run --example wrap --no-default-features
+//     RUSTFLAGS=-Zsanitizer=thread cargo +nightly run --example wrap -Zbuild-std --target x86_64-unknown-linux-gnu
+//
+// 目前两个都是干净的。Miri 不支持 mmap，只能检查 std_file 实现；
+// ThreadSanitizer 两种实现都可以。AddressSanitizer 和边界情况见 tests/wrap.rs。
+
+use log::{LevelFilter, Log};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Framing, KB};
+use std::sync::Arc;
+use std::thread;
+
+const THREADS: usize = 4;
+const RECORDS: usize = 300;
+// 让记录长一点，少写几条就能翻转（Miri 很慢）
+const PADDING: usize = 1000;
+
+fn main() {
+    let padding = ".".repeat(PADDING);
+    for framing in [Framing::Text, Framing::LengthPrefixed] {
+        let path = std::env::temp_dir().join(format!(
+            "mmlog-wrap-{}-{:?}.log",
+            std::process::id(),
+            framing
+        ));
+        let logger = Arc::new(
+            Builder::new()
+                .size(512 * KB)
+                .framing(framing)
+                .build(&path)
+                .expect("Builder::build()"),
+        );
+        log::set_max_level(LevelFilter::Info);
+        let threads: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let logger = logger.clone();
+                let padding = padding.clone();
+                thread::spawn(move || {
+                    for i in 0..RECORDS {
+                        logger.log(
+                            &log::Record::builder()
+                                .level(log::Level::Info)
+                                .args(format_args!("{} thread {} record {}", padding, t, i))
+                                .build(),
+                        );
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        logger.flush();
+
+        let stats = logger.stats();
+        assert_eq!(stats.records_written, (THREADS * RECORDS) as u64);
+        assert!(stats.wrap_count > 0);
+        drop(logger);
+
+        // 每个线程留下来的记录是连续的，最后一条是某个线程的最后一条
+        let reader = LogReader::open(&path).expect("LogReader::open()");
+        let records: Vec<_> = reader.records().collect();
+        assert!(!records.is_empty());
+        for t in 0..THREADS {
+            let prefix = format!("thread {} record ", t);
+            let numbers: Vec<usize> = records
+                .iter()
+                .filter_map(|r| r.split_once(&prefix))
+                .map(|(_, n)| n.parse().unwrap())
+                .collect();
+            assert!(numbers.windows(2).all(|w| w[1] == w[0] + 1));
+        }
+        let last = format!(" record {}", RECORDS - 1);
+        assert!(records.last().unwrap().ends_with(&last));
+        println!(
+            "{:?}: {} records, {} wraps, {} left in the buffer",
+            framing,
+            stats.records_written,
+            stats.wrap_count,
+            records.len()
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,186 @@
+use log::Level;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Transition {
+    Stay,
+    Enter,
+    Leave,
+}
+
+// 出现 Warn/Error 之后的一段时间里每条记录都 MS_SYNC，之后退回平时的策略。
+// 窗口内每来一条 Warn/Error 都会顺延，持续的错误不会反复切换。
+#[derive(Debug)]
+pub(crate) struct Adaptive {
+    window: u64,
+    markers: bool,
+    base: Instant,
+    until: AtomicU64,
+    active: AtomicBool,
+    episodes: AtomicU64,
+}
+
+impl Adaptive {
+    pub(crate) fn new(window: Duration, markers: bool) -> Adaptive {
+        Adaptive {
+            window: window.as_nanos() as u64,
+            markers,
+            base: Instant::now(),
+            until: AtomicU64::new(0),
+            active: AtomicBool::new(false),
+            episodes: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn markers(&self) -> bool {
+        self.markers
+    }
+
+    /// 在写锁内调用，返回状态变化以及这条记录写完后是否需要同步。
+    pub(crate) fn observe(&self, level: Level) -> (Transition, bool) {
+        self.observe_at(level, self.base.elapsed())
+    }
+
+    // observe()，now 是从 new() 开始的时长
+    fn observe_at(&self, level: Level, now: Duration) -> (Transition, bool) {
+        let now = now.as_nanos() as u64;
+        let active = self.active.load(Ordering::Relaxed);
+        if level <= Level::Warn {
+            self.until.store(now + self.window, Ordering::Relaxed);
+            if active {
+                return (Transition::Stay, true);
+            }
+            self.active.store(true, Ordering::Relaxed);
+            self.episodes.fetch_add(1, Ordering::Relaxed);
+            return (Transition::Enter, true);
+        }
+        if !active {
+            return (Transition::Stay, false);
+        }
+        if now < self.until.load(Ordering::Relaxed) {
+            return (Transition::Stay, true);
+        }
+        self.active.store(false, Ordering::Relaxed);
+        (Transition::Leave, false)
+    }
+
+    pub(crate) fn active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn episodes(&self) -> u64 {
+        self.episodes.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    fn at(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn enter_and_decay() {
+        let adaptive = Adaptive::new(WINDOW, false);
+        assert_eq!(
+            adaptive.observe_at(Level::Info, at(0.0)),
+            (Transition::Stay, false)
+        );
+        assert!(!adaptive.active());
+
+        assert_eq!(
+            adaptive.observe_at(Level::Warn, at(1.0)),
+            (Transition::Enter, true)
+        );
+        assert!(adaptive.active());
+        assert_eq!(adaptive.episodes(), 1);
+        assert_eq!(
+            adaptive.observe_at(Level::Info, at(2.0)),
+            (Transition::Stay, true)
+        );
+        assert_eq!(
+            adaptive.observe_at(Level::Debug, at(10.999)),
+            (Transition::Stay, true)
+        );
+
+        // 窗口正好结束的时候退出，之后的记录不再同步
+        assert_eq!(
+            adaptive.observe_at(Level::Info, at(11.0)),
+            (Transition::Leave, false)
+        );
+        assert!(!adaptive.active());
+        assert_eq!(
+            adaptive.observe_at(Level::Info, at(12.0)),
+            (Transition::Stay, false)
+        );
+
+        assert_eq!(
+            adaptive.observe_at(Level::Error, at(30.0)),
+            (Transition::Enter, true)
+        );
+        assert_eq!(adaptive.episodes(), 2);
+    }
+
+    // 没有新的记录就没有人检查窗口是否结束，下一条记录来的时候才退出
+    #[test]
+    fn decay_is_lazy() {
+        let adaptive = Adaptive::new(WINDOW, false);
+        adaptive.observe_at(Level::Warn, at(0.0));
+        assert!(adaptive.active());
+        assert_eq!(
+            adaptive.observe_at(Level::Info, at(1000.0)),
+            (Transition::Leave, false)
+        );
+    }
+
+    #[test]
+    fn warnings_extend_the_window() {
+        let adaptive = Adaptive::new(WINDOW, false);
+        adaptive.observe_at(Level::Warn, at(0.0));
+        assert_eq!(
+            adaptive.observe_at(Level::Warn, at(9.0)),
+            (Transition::Stay, true)
+        );
+        // 没有顺延的话 10 秒就结束了
+        assert_eq!(
+            adaptive.observe_at(Level::Info, at(15.0)),
+            (Transition::Stay, true)
+        );
+        assert_eq!(
+            adaptive.observe_at(Level::Info, at(19.0)),
+            (Transition::Leave, false)
+        );
+        assert_eq!(adaptive.episodes(), 1);
+    }
+
+    // 一直有错误的话一直留在窗口里，不会反复进出
+    #[test]
+    fn steady_errors_dont_thrash() {
+        let adaptive = Adaptive::new(WINDOW, false);
+        for i in 0..200 {
+            let now = at(i as f64 * 0.5);
+            let level = if i % 10 == 0 {
+                Level::Error
+            } else {
+                Level::Info
+            };
+            let (transition, sync) = adaptive.observe_at(level, now);
+            assert_eq!(
+                transition,
+                if i == 0 {
+                    Transition::Enter
+                } else {
+                    Transition::Stay
+                }
+            );
+            assert!(sync);
+        }
+        assert_eq!(adaptive.episodes(), 1);
+        assert!(adaptive.active());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::crypt::Cipher;
+use crate::format::{self, Expiry};
+use crate::mapping::Mapping;
+use crate::merge;
+use crate::Result;
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// [`Logger::swap_file`](crate::Logger::swap_file) 换下来的旧缓冲区，
+/// 仍然映射着原来的文件，但已经不会再有新的记录写进来。
+#[derive(Debug)]
+pub struct ArchivedBuffer {
+    mapping: Mapping,
+    cipher: Option<Cipher>,
+}
+
+impl ArchivedBuffer {
+    pub(crate) fn new(mapping: Mapping, cipher: Option<Cipher>) -> ArchivedBuffer {
+        ArchivedBuffer { mapping, cipher }
+    }
+
+    // 按时间顺序的每一条记录（文本格式含换行），超过保留期限的记录会被跳过
+    fn lines(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        let mapping = &self.mapping;
+        let halves = mapping.rings().map(|ring| mapping.halves(ring));
+        let mut expiry = Expiry::new(mapping.retention(), mapping.anchor());
+        merge::rings(
+            halves,
+            mapping.framing(),
+            mapping.checksum(),
+            self.cipher.as_ref(),
+        )
+        .into_iter()
+        .filter(move |line| expiry.keep(line))
+    }
+
+    /// 按时间顺序写出全部记录，返回写出的字节数。
+    ///
+    /// 总是写成文本格式：[`Framing::LengthPrefixed`](crate::Framing::LengthPrefixed)
+    /// 的长度前缀会被去掉。
+    ///
+    /// 设置了 [`Builder::retention`](crate::Builder::retention) 的话，过期的记录不会写出。
+    pub fn dump_to<W: Write>(&self, mut w: W) -> Result<u64> {
+        let mut n = 0;
+        for line in self.lines() {
+            w.write_all(&line)?;
+            n += line.len() as u64;
+        }
+        Ok(n)
+    }
+
+    /// 按时间顺序逐条返回记录（不含结尾的换行），过期的记录同样会被跳过。
+    ///
+    /// [`Builder::escape_newlines`](crate::Builder::escape_newlines) 转义的换行会被还原。
+    pub fn records(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        let escaped = self.mapping.escaped();
+        self.lines().map(move |line| {
+            let record = match line {
+                Cow::Borrowed(line) => {
+                    String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line))
+                }
+                Cow::Owned(line) => {
+                    let record = line.strip_suffix(b"\n").unwrap_or(&line);
+                    Cow::Owned(String::from_utf8_lossy(record).into_owned())
+                }
+            };
+            if escaped {
+                format::unescape_lossy(record)
+            } else {
+                record
+            }
+        })
+    }
+
+    /// 同步到磁盘并解除映射，返回文件路径。
+    /// [`Builder::build_anonymous`](crate::Builder::build_anonymous) 的缓冲区没有文件，返回空路径。
+    pub fn into_path(self) -> Result<PathBuf> {
+        self.mapping.sync(true)?;
+        Ok(self.mapping.path().to_path_buf())
+    }
+}
+
+unsafe impl Send for ArchivedBuffer {}
+unsafe impl Sync for ArchivedBuffer {}
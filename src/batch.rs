@@ -0,0 +1,111 @@
+use log::Level;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Builder::batched：每个线程先把格式化好的记录攒在自己的 Batch 里，攒够 flush_bytes
+// 或者 flush() 时才拿一次写锁，把整批记录写进缓冲区。
+//
+// Batches 登记了所有线程的 Batch，flush()、close()、drop 和 panic hook 都能把别的线程
+// 还攒着的记录写进去。线程退出之后它的 Batch 只剩这里的一个引用，写完就从列表里去掉。
+// 锁的顺序总是先 Batch 的锁，再写锁。
+#[derive(Debug)]
+pub(crate) struct Batches {
+    // 区分同一个线程里不同 Logger 的 Batch
+    id: u64,
+    flush_bytes: usize,
+    all: Mutex<Vec<Arc<Mutex<Batch>>>>,
+}
+
+// 首尾相接的记录，以及每一条的级别和 target（配额和 sync_on 要用）
+#[derive(Debug, Default)]
+pub(crate) struct Batch {
+    text: String,
+    targets: String,
+    // (级别, text 里的结尾, targets 里的结尾)
+    records: Vec<(Level, usize, usize)>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<Vec<(u64, Arc<Mutex<Batch>>)>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Batch {
+    pub(crate) fn push(&mut self, level: Level, target: &str, msg: &str) {
+        self.text.push_str(msg);
+        self.targets.push_str(target);
+        self.records
+            .push((level, self.text.len(), self.targets.len()));
+    }
+
+    /// 攒着的字节数。
+    pub(crate) fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    /// 按写入的顺序返回 `(级别, target, 记录)`。
+    pub(crate) fn records(&self) -> impl Iterator<Item = (Level, &str, &str)> {
+        let mut start = (0, 0);
+        self.records.iter().map(move |&(level, text, target)| {
+            let record = (
+                level,
+                &self.targets[start.1..target],
+                &self.text[start.0..text],
+            );
+            start = (text, target);
+            record
+        })
+    }
+
+    /// 清空，保留已经分配的内存。
+    pub(crate) fn clear(&mut self) {
+        self.text.clear();
+        self.targets.clear();
+        self.records.clear();
+    }
+}
+
+impl Batches {
+    pub(crate) fn new(flush_bytes: usize) -> Batches {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Batches {
+            id: NEXT.fetch_add(1, Ordering::Relaxed),
+            flush_bytes,
+            all: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn flush_bytes(&self) -> usize {
+        self.flush_bytes
+    }
+
+    /// 用当前线程的 Batch 调用 `f`，第一次用时登记到列表里。
+    /// 线程正在退出、`thread_local` 已经销毁的话返回 `None`。
+    pub(crate) fn with_local<R>(&self, f: impl FnOnce(&Mutex<Batch>) -> R) -> Option<R> {
+        LOCAL
+            .try_with(|local| {
+                if let Some((_, batch)) = local.borrow().iter().find(|(id, _)| *id == self.id) {
+                    return f(batch);
+                }
+                let batch = Arc::new(Mutex::new(Batch::default()));
+                lock(&self.all).push(batch.clone());
+                local.borrow_mut().push((self.id, batch.clone()));
+                f(&batch)
+            })
+            .ok()
+    }
+
+    /// 所有线程的 Batch，已经退出了的线程的 Batch 这次之后就不再返回。
+    pub(crate) fn drain(&self) -> Vec<Arc<Mutex<Batch>>> {
+        let mut all = lock(&self.all);
+        let batches = all.clone();
+        // 列表和 batches 各一个引用，线程还在的话它的 thread_local 里还有一个
+        all.retain(|batch| Arc::strong_count(batch) > 2);
+        batches
+    }
+}
+
+// 某个线程在拿着锁时 panic 了也接着用，记录都是完整的
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,1005 @@
+//! 按时间顺序输出 mmlog 的日志文件。
+//!
+//! ```text
+//! mmlog-cat [-f] [--tail N] [--history] [--lenient] <path>
+//! mmlog-cat [--since TIME] [--until TIME] [--tail N] <path>
+//! mmlog-cat --channel NAME [--tail N] <path>
+//! mmlog-cat --session last|N [--tail N] <path>
+//! mmlog-cat --grep PATTERN [-C N] <path>
+//! mmlog-cat --merge <path>...
+//! mmlog-cat --info <path>
+//! mmlog-cat --tui <path>
+//! ```
+//!
+//! 每种用法都可以加上 `--output text|ndjson|tsv`，见最后。
+//!
+//! `-f` 输出完已有的记录之后继续等待新的记录，Ctrl-C 退出。
+//! `--history` 先从旧到新输出 `Builder::rotate` 轮转出来的 `<path>.N`（压缩过的
+//! `<path>.N.zst` 也可以），`--tail` 只对 `<path>` 本身起作用。
+//! `--merge` 把几个文件的记录按时间戳合并输出，每行前面是来源文件的文件名，见 `mmlog::merge`。
+//! 几个文件的时间戳写法和时钟可以不一样；换算不了时间戳的文件（没有锚点的单调时钟）跟在最后，
+//! 在 stderr 上提示。
+//! `--since`、`--until` 只输出时间戳在这之间的记录（不含 `--until` 那一刻），见
+//! `LogReader::records_between`。`TIME` 可以是 RFC 3339（`2024-05-01T12:00:00Z`、
+//! `2024-05-01T20:00:00+08:00`）、记录里默认的 `<秒>.<纳秒>s`，或者相对现在的
+//! `-15m` 这样的写法，单位是 `s`、`m`、`h`、`d`。
+//! `--channel` 只输出 `Builder::channels` 的一个通道里的记录，不加的话所有通道按时间合在一起。
+//! `--session` 只输出 `Builder::open` 反复打开的文件里的一次运行，`last` 是最后一次，
+//! `N` 是从 0 开始数的第几次，`--info` 列出所有的运行，见 `LogReader::sessions`。
+//! `--grep` 只输出包含 `PATTERN` 的记录（子串，不是正则表达式），`-C` 带上前后各 `N` 条记录，
+//! 不连续的地方和 `grep -C` 一样用 `--` 隔开，见 `LogReader::grep`。
+//! `--lenient` 读传到一半断了、比 header 里说的短的文件：能读的记录照常输出，在 stderr 上报告
+//! 文件少了多少字节和跨过截断处的记录，见 `LogReader::open_lenient`。不能和 `--key-file`、`-f` 一起用。
+//! `--info` 只输出创建文件时写进去的元数据（进程号、启动时间、可执行文件、`Builder::tag`）、
+//! header 里的统计、通道、单调时钟的锚点和每次运行，不输出记录。
+//! `Clock::Monotonic` 的文件原样输出单调时钟的时间戳，`--since`、`--until` 照样按墙上时间算。
+//! 设置了 `Builder::retention` 的文件不输出过期的记录，`--ignore-retention` 例外，只给内部排查问题用，
+//! 不能和 `--merge`、`--lenient` 一起用，见 `LogReader::open_ignoring_retention`。
+//! `--key-file` 读 `Builder::encrypt` 加密了的文件（包括 `--history` 的旧文件），`PATH`
+//! 里是 32 个字节的密钥，或者 64 个十六进制数字（后面可以有换行），不能和 `--merge` 一起用。
+//! `--tui` 在终端里交互式地翻看文件：按级别和 target 过滤、查找、跳到某个时间、跟随文件末尾，
+//! 按键见 `mmlog::tui`。需要打开 `tui` feature，除了 `--key-file`、`--ignore-retention`
+//! 不能和别的选项一起用。
+//!
+//! `--output` 是给脚本用的稳定写法，默认的 `text` 原样输出记录，以后可能会变。
+//! `ndjson` 每行一个 JSON 对象，`type` 说明是什么，字段见下面的 `Json`：`record` 是一条记录，
+//! `match` 是 `--grep` 的一次命中和它前后的上下文，`info` 和 `session` 是 `--info` 的文件信息和每次运行。
+//! `tsv` 每条记录一行，列的顺序固定：`timestamp_ns`、`level`、`tid`、`target`、`file`、`line`、`message`，
+//! `--merge` 在最前面多一列来源文件名，`--grep` 多两列：记录的位置和是不是命中的那条（`true`/`false`）。
+//! `--info` 每行是名字和值两列，每次运行一行 `session`、第几次、`start`、`end`、`pid`、`started_ns`、
+//! `clean`、`partial`。值里的 `\`、制表符、换行和回车写成 `\\`、`\t`、`\n`、`\r`，没有的值是空的。
+//! 两种写法里的时间戳都是换算成 UNIX 纪元以来的纳秒数的 `timestamp_ns`（单调时钟的文件按锚点换算，
+//! 换算不了的话没有），解析不了的记录只有 `raw`（TSV 里放在 `message` 那一列）。
+//! 警告和坏记录照样报告在 stderr 上，退出码和写法无关：成功是 0，出错是 1，参数不对是 2。
+//! tests/cli_output.rs 对比 tests/cli-output/ 里的快照，保证写法不会悄悄变化。
+
+use mmlog::format::Anchor;
+use mmlog::parse::Entry;
+use mmlog::reader::{LogReader, Match};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str =
+    "usage: mmlog-cat [--key-file PATH] [--ignore-retention] [-f] [--tail N] [--history] [--lenient] <path>
+       mmlog-cat [--since TIME] [--until TIME] [--tail N] <path>
+       mmlog-cat --channel NAME [--tail N] <path>
+       mmlog-cat --session last|N [--tail N] <path>
+       mmlog-cat --grep PATTERN [-C N] <path>
+       mmlog-cat --merge <path>...
+       mmlog-cat --info <path>
+       mmlog-cat --tui <path>
+each form also takes --output text|ndjson|tsv";
+
+// -f 时轮询 offset 的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+struct Args {
+    follow: bool,
+    tail: Option<usize>,
+    history: bool,
+    merge: bool,
+    info: bool,
+    tui: bool,
+    lenient: bool,
+    ignore_retention: bool,
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+    channel: Option<String>,
+    session: Option<Session>,
+    grep: Option<String>,
+    context: Option<usize>,
+    key: Option<[u8; 32]>,
+    output: Output,
+    // 不是 --merge 的话正好一个
+    paths: Vec<PathBuf>,
+}
+
+// --output 的参数
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Text,
+    Ndjson,
+    Tsv,
+}
+
+// --session 的参数
+#[derive(Clone, Copy)]
+enum Session {
+    Last,
+    Index(usize),
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut follow = false;
+    let mut tail = None;
+    let mut history = false;
+    let mut merge = false;
+    let mut info = false;
+    let mut tui = false;
+    let mut lenient = false;
+    let mut ignore_retention = false;
+    let mut since = None;
+    let mut until = None;
+    let mut channel = None;
+    let mut session = None;
+    let mut grep = None;
+    let mut context = None;
+    let mut key = None;
+    let mut output = Output::Text;
+    let mut paths = Vec::new();
+    // 路径不一定是 UTF-8
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-f" | "--follow") => follow = true,
+            Some("-n" | "--tail") => {
+                let n = args.next().ok_or("--tail needs a number")?;
+                let n = n.to_string_lossy();
+                tail = Some(n.parse().map_err(|_| format!("bad number: {}", n))?);
+            }
+            Some("--history") => history = true,
+            Some("--merge") => merge = true,
+            Some("--info") => info = true,
+            Some("--tui") if cfg!(feature = "tui") => tui = true,
+            Some("--tui") => return Err("--tui needs the tui feature".to_string()),
+            Some("--lenient") => lenient = true,
+            Some("--ignore-retention") => ignore_retention = true,
+            Some(flag @ ("--since" | "--until")) => {
+                let time = args.next().ok_or(format!("{} needs a time", flag))?;
+                let time = time.to_string_lossy();
+                let time = parse_time(&time).ok_or(format!("bad time: {}", time))?;
+                if flag == "--since" {
+                    since = Some(time);
+                } else {
+                    until = Some(time);
+                }
+            }
+            Some("--channel") => {
+                let name = args.next().ok_or("--channel needs a name")?;
+                channel = Some(name.to_string_lossy().into_owned());
+            }
+            Some("--session") => {
+                let n = args.next().ok_or("--session needs `last` or a number")?;
+                let n = n.to_string_lossy();
+                session = Some(match &*n {
+                    "last" => Session::Last,
+                    n => Session::Index(n.parse().map_err(|_| format!("bad session: {}", n))?),
+                });
+            }
+            Some("--grep") => {
+                let pattern = args.next().ok_or("--grep needs a pattern")?;
+                grep = Some(pattern.to_string_lossy().into_owned());
+            }
+            Some("-C" | "--context") => {
+                let n = args.next().ok_or("-C needs a number")?;
+                let n = n.to_string_lossy();
+                context = Some(n.parse().map_err(|_| format!("bad number: {}", n))?);
+            }
+            Some("--key-file") => {
+                let path = args.next().ok_or("--key-file needs a path")?;
+                key = Some(read_key(Path::new(&path))?);
+            }
+            Some("--output") => {
+                let name = args.next().ok_or("--output needs text, ndjson or tsv")?;
+                output = match name.to_str() {
+                    Some("text") => Output::Text,
+                    Some("ndjson") => Output::Ndjson,
+                    Some("tsv") => Output::Tsv,
+                    _ => return Err(format!("bad output: {}", name.to_string_lossy())),
+                };
+            }
+            Some("-h" | "--help") => return Err(USAGE.to_string()),
+            _ if !arg.as_encoded_bytes().starts_with(b"-") => paths.push(PathBuf::from(arg)),
+            _ => {
+                return Err(format!(
+                    "unexpected argument: {}\n{}",
+                    arg.to_string_lossy(),
+                    USAGE
+                ))
+            }
+        }
+    }
+    if merge && (follow || tail.is_some() || history) {
+        return Err("--merge can't be combined with -f, --tail or --history".to_string());
+    }
+    if info
+        && (merge
+            || follow
+            || tail.is_some()
+            || history
+            || since.is_some()
+            || until.is_some()
+            || channel.is_some()
+            || session.is_some()
+            || grep.is_some())
+    {
+        return Err("--info can't be combined with other options".to_string());
+    }
+    if tui
+        && (merge
+            || info
+            || follow
+            || tail.is_some()
+            || history
+            || lenient
+            || since.is_some()
+            || until.is_some()
+            || channel.is_some()
+            || session.is_some()
+            || grep.is_some()
+            || context.is_some()
+            || output != Output::Text)
+    {
+        return Err(
+            "--tui can't be combined with options other than --key-file and --ignore-retention"
+                .to_string(),
+        );
+    }
+    if (since.is_some() || until.is_some()) && (merge || follow || history) {
+        return Err(
+            "--since and --until can't be combined with -f, --merge or --history".to_string(),
+        );
+    }
+    if channel.is_some() && (merge || follow || history || since.is_some() || until.is_some()) {
+        return Err(
+            "--channel can't be combined with -f, --merge, --history, --since or --until"
+                .to_string(),
+        );
+    }
+    if session.is_some()
+        && (merge || follow || history || since.is_some() || until.is_some() || channel.is_some())
+    {
+        return Err(
+            "--session can't be combined with -f, --merge, --history, --since, --until or --channel"
+                .to_string(),
+        );
+    }
+    if grep.is_some()
+        && (merge
+            || follow
+            || tail.is_some()
+            || history
+            || since.is_some()
+            || until.is_some()
+            || channel.is_some()
+            || session.is_some())
+    {
+        return Err(
+            "--grep can't be combined with options other than -C and --key-file".to_string(),
+        );
+    }
+    if context.is_some() && grep.is_none() {
+        return Err("-C needs --grep".to_string());
+    }
+    if lenient && (merge || follow || key.is_some()) {
+        return Err("--lenient can't be combined with -f, --merge or --key-file".to_string());
+    }
+    if ignore_retention && (merge || lenient) {
+        return Err("--ignore-retention can't be combined with --merge or --lenient".to_string());
+    }
+    if merge && key.is_some() {
+        return Err("--key-file can't be combined with --merge".to_string());
+    }
+    if paths.is_empty() || (!merge && paths.len() > 1) {
+        return Err(USAGE.to_string());
+    }
+    Ok(Args {
+        follow,
+        tail,
+        history,
+        merge,
+        info,
+        tui,
+        lenient,
+        ignore_retention,
+        since,
+        until,
+        channel,
+        session,
+        grep,
+        context,
+        key,
+        output,
+        paths,
+    })
+}
+
+// 32 个字节，或者 64 个十六进制数字
+fn read_key(path: &Path) -> Result<[u8; 32], String> {
+    let data = std::fs::read(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    if let Ok(key) = <[u8; 32]>::try_from(data.as_slice()) {
+        return Ok(key);
+    }
+    let hex = data.strip_suffix(b"\n").unwrap_or(&data);
+    let hex = hex.strip_suffix(b"\r").unwrap_or(hex);
+    let mut key = [0; 32];
+    if hex.len() != 64 {
+        return Err(format!(
+            "{}: expected 32 bytes or 64 hex digits",
+            path.display()
+        ));
+    }
+    for (byte, pair) in key.iter_mut().zip(hex.chunks(2)) {
+        *byte = std::str::from_utf8(pair)
+            .ok()
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or(format!("{}: bad hex digit", path.display()))?;
+    }
+    Ok(key)
+}
+
+fn open(path: &Path, key: Option<&[u8; 32]>, ignore_retention: bool) -> mmlog::Result<LogReader> {
+    match key {
+        _ if ignore_retention => LogReader::open_ignoring_retention(path, key),
+        Some(key) => LogReader::open_encrypted(path, key),
+        None => LogReader::open(path),
+    }
+}
+
+// RFC 3339、`<秒>.<纳秒>s`，或者相对现在的 `-15m`
+fn parse_time(time: &str) -> Option<SystemTime> {
+    if let Some(ago) = time.strip_prefix('-') {
+        let unit = match ago.as_bytes().last()? {
+            b's' => 1,
+            b'm' => 60,
+            b'h' => 3600,
+            b'd' => 86400,
+            _ => return None,
+        };
+        let n: u64 = ago[..ago.len() - 1].parse().ok()?;
+        return SystemTime::now().checked_sub(Duration::from_secs(n.checked_mul(unit)?));
+    }
+    Some(UNIX_EPOCH + mmlog::format::parse_timestamp(time)?)
+}
+
+// 轮转出来的文件从旧到新排好，`.N` 和 `.N.zst` 都找不到时停下
+fn history(path: &Path) -> Vec<PathBuf> {
+    let mut segments = Vec::new();
+    for n in 1.. {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(format!(".{}", n));
+        let raw = PathBuf::from(&name);
+        name.push(".zst");
+        let compressed = PathBuf::from(name);
+        if raw.exists() {
+            segments.push(raw);
+        } else if compressed.exists() {
+            segments.push(compressed);
+        } else {
+            break;
+        }
+    }
+    segments.reverse();
+    segments
+}
+
+// --output ndjson 的一行。字段名和类型是给脚本用的接口，改了的话要重新生成 tests/cli-output/ 里的快照
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Json<'a> {
+    Record(Fields<'a>),
+    // --grep 的一次命中，position 是命中的记录在文件里是第几条
+    Match {
+        position: usize,
+        record: Fields<'a>,
+        before: Vec<Fields<'a>>,
+        after: Vec<Fields<'a>>,
+    },
+    Info(Info<'a>),
+    // --info 里的一次运行，见 LogReader::sessions
+    Session {
+        index: usize,
+        start: usize,
+        end: usize,
+        pid: Option<u32>,
+        started_ns: Option<i128>,
+        clean: bool,
+        partial: bool,
+    },
+}
+
+// 一条记录解析出来的字段和记录本身，解析不了的话只有 raw
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+struct Fields<'a> {
+    // --merge 时的来源文件名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+    timestamp_ns: Option<i128>,
+    level: Option<&'static str>,
+    tid: Option<u64>,
+    target: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    message: Option<String>,
+    raw: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    // anchor 是单调时钟的文件的锚点，见 LogReader::normalize
+    fn new(anchor: Option<Anchor>, source: Option<&'a str>, raw: &'a str) -> Fields<'a> {
+        let Some(entry) = parse(raw) else {
+            return Fields {
+                source,
+                timestamp_ns: None,
+                level: None,
+                tid: None,
+                target: None,
+                file: None,
+                line: None,
+                message: None,
+                raw,
+            };
+        };
+        let timestamp_ns = match anchor {
+            None => Some(entry.timestamp.as_nanos() as i128),
+            Some(anchor) if anchor.realtime.is_zero() || !anchor.same_boot(entry.timestamp) => None,
+            Some(anchor) => Some(anchor.to_nanos(entry.timestamp)),
+        };
+        Fields {
+            source,
+            timestamp_ns,
+            level: Some(entry.level.as_str()),
+            tid: Some(entry.tid),
+            target: Some(entry.target),
+            file: entry.file,
+            line: entry.line,
+            message: Some(entry.message),
+            raw,
+        }
+    }
+
+    // TSV 的列，来源文件名不算
+    fn cells(self) -> Vec<String> {
+        vec![
+            cell(self.timestamp_ns),
+            cell(self.level),
+            cell(self.tid),
+            cell(self.target),
+            cell(self.file),
+            cell(self.line),
+            self.message.unwrap_or_else(|| self.raw.to_string()),
+        ]
+    }
+}
+
+// 默认格式、logfmt 和 JSON 的记录都能解析
+fn parse(record: &str) -> Option<Entry> {
+    Entry::parse(record)
+        .or_else(|| Entry::parse_logfmt(record))
+        .or_else(|| json_entry(record))
+}
+
+// Builder::json 写的记录，字段见 mmlog::format
+#[derive(Deserialize)]
+struct JsonRecord {
+    ts: String,
+    tid: u64,
+    level: String,
+    target: String,
+    file: Option<String>,
+    line: Option<u32>,
+    msg: String,
+}
+
+fn json_entry(record: &str) -> Option<Entry> {
+    // Builder::sequence_numbers 的 `#<序号> `
+    let record = match record.strip_prefix('#') {
+        Some(rest) => rest.split_once(' ')?.1,
+        None => record,
+    };
+    let record: JsonRecord = serde_json::from_str(record).ok()?;
+    Some(Entry {
+        timestamp: mmlog::format::parse_timestamp(&record.ts)?,
+        tid: record.tid,
+        level: record.level.parse().ok()?,
+        file: record.file,
+        line: record.line,
+        target: record.target,
+        message: record.msg,
+    })
+}
+
+// --info 的文件信息，text 以外的写法用
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+struct Info<'a> {
+    pid: Option<u32>,
+    started_ns: Option<i128>,
+    exe: Option<String>,
+    tag: Option<String>,
+    capacity: usize,
+    records: u64,
+    bytes: u64,
+    wraps: u64,
+    channels: Vec<&'a str>,
+    anchor_realtime_ns: Option<i128>,
+    anchor_monotonic_ns: Option<i128>,
+}
+
+fn cell<T: ToString>(value: Option<T>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+// UNIX 纪元以来的纳秒数，早于纪元的话是负数
+fn nanos(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_nanos() as i128,
+        Err(before) => -(before.duration().as_nanos() as i128),
+    }
+}
+
+// TSV 的一个值
+fn escape_tsv(value: &str) -> Cow<'_, str> {
+    if !value.contains(['\\', '\t', '\n', '\r']) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+// 按 --output 的写法输出
+struct Printer<W: Write> {
+    out: W,
+    output: Output,
+}
+
+impl<W: Write> Printer<W> {
+    // 一条记录，--merge 的话带着来源文件名
+    fn record(
+        &mut self,
+        anchor: Option<Anchor>,
+        source: Option<&str>,
+        record: &str,
+    ) -> io::Result<()> {
+        match self.output {
+            // 和 mmlog::merge 一样，每一行前面都有文件名
+            Output::Text => match source {
+                Some(source) => record
+                    .split('\n')
+                    .try_for_each(|line| writeln!(self.out, "{}: {}", source, line)),
+                None => writeln!(self.out, "{}", record),
+            },
+            Output::Ndjson => self.json(&Json::Record(Fields::new(anchor, source, record))),
+            Output::Tsv => {
+                let mut cells: Vec<_> = source.map(str::to_string).into_iter().collect();
+                cells.extend(Fields::new(anchor, source, record).cells());
+                self.row(&cells)
+            }
+        }
+    }
+
+    // --grep 的一次命中，text 的话 separate 时先输出和前一次命中隔开的 `--`
+    fn grep(&mut self, anchor: Option<Anchor>, m: &Match, separate: bool) -> io::Result<()> {
+        let fields = |record| Fields::new(anchor, None, record);
+        let mut records = m.before.iter().chain([&m.record]).chain(&m.after);
+        match self.output {
+            Output::Text => {
+                if separate {
+                    writeln!(self.out, "--")?;
+                }
+                records.try_for_each(|record| writeln!(self.out, "{}", record))
+            }
+            Output::Ndjson => self.json(&Json::Match {
+                position: m.position,
+                record: fields(m.record),
+                before: m.before.iter().map(|record| fields(record)).collect(),
+                after: m.after.iter().map(|record| fields(record)).collect(),
+            }),
+            Output::Tsv => records.enumerate().try_for_each(|(i, record)| {
+                let position = m.start() + i;
+                let mut cells = vec![position.to_string(), (position == m.position).to_string()];
+                cells.extend(fields(record).cells());
+                self.row(&cells)
+            }),
+        }
+    }
+
+    fn json(&mut self, line: &Json) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, line)?;
+        writeln!(self.out)
+    }
+
+    fn row(&mut self, cells: &[String]) -> io::Result<()> {
+        for (i, value) in cells.iter().enumerate() {
+            if i > 0 {
+                self.out.write_all(b"\t")?;
+            }
+            self.out.write_all(escape_tsv(value).as_bytes())?;
+        }
+        writeln!(self.out)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn print_segment(
+    out: &mut Printer<impl Write>,
+    path: &Path,
+    key: Option<&[u8; 32]>,
+    ignore_retention: bool,
+) -> mmlog::Result<()> {
+    if path.extension().is_some_and(|ext| ext == "zst") {
+        // 压缩时已经还原成纯文本了，没有 header，也就没有锚点
+        let mut decoder = zstd::Decoder::new(File::open(path)?)?;
+        if out.output == Output::Text {
+            io::copy(&mut decoder, &mut out.out)?;
+        } else {
+            let mut text = String::new();
+            decoder.read_to_string(&mut text)?;
+            for record in text.lines() {
+                out.record(None, None, record)?;
+            }
+        }
+    } else {
+        let reader = open(path, key, ignore_retention)?;
+        for record in reader.records() {
+            out.record(reader.anchor(), None, record)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_info(out: &mut Printer<impl Write>, reader: &LogReader) -> mmlog::Result<()> {
+    if out.output == Output::Text {
+        return print_info_text(&mut out.out, reader);
+    }
+    let metadata = reader.metadata();
+    let stats = reader.stats();
+    let anchor = reader.anchor();
+    let info = Info {
+        pid: metadata.as_ref().map(|metadata| metadata.pid),
+        started_ns: metadata
+            .as_ref()
+            .map(|metadata| nanos(metadata.process_started)),
+        exe: metadata.as_ref().map(|metadata| metadata.exe.clone()),
+        tag: metadata.and_then(|metadata| metadata.tag),
+        capacity: stats.capacity,
+        records: stats.records_written,
+        bytes: stats.total_bytes_written,
+        wraps: stats.wrap_count,
+        channels: reader.channels(),
+        anchor_realtime_ns: anchor.map(|anchor| anchor.realtime.as_nanos() as i128),
+        anchor_monotonic_ns: anchor.map(|anchor| anchor.monotonic.as_nanos() as i128),
+    };
+    let sessions = reader.sessions();
+    if out.output == Output::Ndjson {
+        out.json(&Json::Info(info))?;
+        for (index, session) in sessions.into_iter().enumerate() {
+            out.json(&Json::Session {
+                index,
+                start: session.start,
+                end: session.end,
+                pid: session.pid,
+                started_ns: session.started.map(nanos),
+                clean: session.clean,
+                partial: session.partial,
+            })?;
+        }
+        return Ok(());
+    }
+    let rows = [
+        ("pid", cell(info.pid)),
+        ("started_ns", cell(info.started_ns)),
+        ("exe", cell(info.exe)),
+        ("tag", cell(info.tag)),
+        ("capacity", info.capacity.to_string()),
+        ("records", info.records.to_string()),
+        ("bytes", info.bytes.to_string()),
+        ("wraps", info.wraps.to_string()),
+        ("channels", info.channels.join(",")),
+        ("anchor_realtime_ns", cell(info.anchor_realtime_ns)),
+        ("anchor_monotonic_ns", cell(info.anchor_monotonic_ns)),
+    ];
+    for (name, value) in rows {
+        out.row(&[name.to_string(), value])?;
+    }
+    for (index, session) in sessions.into_iter().enumerate() {
+        out.row(&[
+            "session".to_string(),
+            index.to_string(),
+            session.start.to_string(),
+            session.end.to_string(),
+            cell(session.pid),
+            cell(session.started.map(nanos)),
+            session.clean.to_string(),
+            session.partial.to_string(),
+        ])?;
+    }
+    Ok(())
+}
+
+// 时间的写法和记录里默认的时间戳一样
+fn print_info_text(out: &mut impl Write, reader: &LogReader) -> mmlog::Result<()> {
+    match reader.metadata() {
+        Some(metadata) => {
+            let started = metadata
+                .process_started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            writeln!(out, "pid: {}", metadata.pid)?;
+            writeln!(
+                out,
+                "started: {}.{:09}s",
+                started.as_secs(),
+                started.subsec_nanos()
+            )?;
+            writeln!(out, "exe: {}", metadata.exe)?;
+            writeln!(out, "tag: {}", metadata.tag.as_deref().unwrap_or(""))?;
+        }
+        None => writeln!(out, "no metadata, the file predates format version 6")?,
+    }
+    let stats = reader.stats();
+    writeln!(out, "capacity: {}", stats.capacity)?;
+    writeln!(out, "records: {}", stats.records_written)?;
+    writeln!(out, "bytes: {}", stats.total_bytes_written)?;
+    writeln!(out, "wraps: {}", stats.wrap_count)?;
+    let channels = reader.channels();
+    if !channels.is_empty() {
+        writeln!(out, "channels: {}", channels.join(", "))?;
+    }
+    if let Some(anchor) = reader.anchor() {
+        writeln!(
+            out,
+            "monotonic anchor: {}.{:09}s = {}.{:09}s",
+            anchor.monotonic.as_secs(),
+            anchor.monotonic.subsec_nanos(),
+            anchor.realtime.as_secs(),
+            anchor.realtime.subsec_nanos()
+        )?;
+    }
+    for (i, session) in reader.sessions().iter().enumerate() {
+        let started = session
+            .started
+            .and_then(|started| started.duration_since(UNIX_EPOCH).ok())
+            .map(|started| format!("{}.{:09}s", started.as_secs(), started.subsec_nanos()));
+        let end = if session.clean {
+            "clean"
+        } else {
+            "no shutdown"
+        };
+        writeln!(
+            out,
+            "session {}: records {}..{}, pid {}, started {}, {}{}",
+            i,
+            session.start,
+            session.end,
+            session.pid.map_or("?".to_string(), |pid| pid.to_string()),
+            started.as_deref().unwrap_or("?"),
+            end,
+            if session.partial { ", partial" } else { "" }
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+fn tui(args: &Args) -> mmlog::Result<()> {
+    let (key, ignore_retention) = (args.key.as_ref(), args.ignore_retention);
+    mmlog::tui::run(
+        &args.paths[0],
+        |path| open(path, key, ignore_retention),
+        parse,
+    )
+}
+
+// parse_args 不接受 --tui
+#[cfg(not(feature = "tui"))]
+fn tui(_: &Args) -> mmlog::Result<()> {
+    unreachable!()
+}
+
+fn run(args: Args) -> mmlog::Result<()> {
+    if args.tui {
+        return tui(&args);
+    }
+    let stdout = io::stdout();
+    let mut out = Printer {
+        out: io::BufWriter::new(stdout.lock()),
+        output: args.output,
+    };
+    if args.merge {
+        let paths: Vec<&Path> = args.paths.iter().map(PathBuf::as_path).collect();
+        // 和 mmlog::merge 一样用文件名
+        let names: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                path.file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+            })
+            .collect();
+        let stats = mmlog::merge_each(&paths, |i, reader, record| {
+            out.record(reader.anchor(), Some(&names[i]), record)
+        })?;
+        out.flush()?;
+        for warning in stats.warnings {
+            eprintln!("mmlog-cat: {}", warning);
+        }
+        return Ok(());
+    }
+
+    let path = &args.paths[0];
+    let mut reader = if args.lenient {
+        LogReader::open_lenient(path)?
+    } else {
+        open(path, args.key.as_ref(), args.ignore_retention)?
+    };
+    let summary = reader.summary();
+    if summary.truncated() {
+        eprintln!(
+            "mmlog-cat: the file is truncated, {} of {} bytes are missing",
+            summary.expected_bytes - summary.present_bytes,
+            summary.expected_bytes
+        );
+    }
+    let anchor = reader.anchor();
+    if args.info {
+        print_info(&mut out, &reader)?;
+        out.flush()?;
+        return Ok(());
+    }
+    if args.history {
+        for segment in history(path) {
+            print_segment(&mut out, &segment, args.key.as_ref(), args.ignore_retention)?;
+        }
+    }
+
+    if reader.overwritten() > 0 {
+        eprintln!(
+            "mmlog-cat: {} older records were overwritten",
+            reader.overwritten()
+        );
+    }
+    if let Some(name) = &args.channel {
+        if !reader.channels().contains(&name.as_str()) {
+            eprintln!("mmlog-cat: no channel named {:?}", name);
+            process::exit(1);
+        }
+        let records: Vec<&str> = reader.channel(name).collect();
+        let skip = records
+            .len()
+            .saturating_sub(args.tail.unwrap_or(usize::MAX));
+        for record in &records[skip..] {
+            out.record(anchor, None, record)?;
+        }
+        out.flush()?;
+        return Ok(());
+    }
+    if let Some(session) = args.session {
+        let count = reader.sessions().len();
+        let index = match session {
+            Session::Last => count.checked_sub(1),
+            Session::Index(i) => Some(i).filter(|&i| i < count),
+        };
+        let index = match index {
+            Some(index) => index,
+            None => {
+                eprintln!("mmlog-cat: no such session, the file has {}", count);
+                process::exit(1);
+            }
+        };
+        let records: Vec<&str> = reader.records_in_session(index).collect();
+        let skip = records
+            .len()
+            .saturating_sub(args.tail.unwrap_or(usize::MAX));
+        for record in &records[skip..] {
+            out.record(anchor, None, record)?;
+        }
+        out.flush()?;
+        return Ok(());
+    }
+    if let Some(pattern) = &args.grep {
+        let mut end = None;
+        for m in reader.grep(pattern, args.context.unwrap_or(0)) {
+            // 和 grep 一样，没有 -C 的话不用分隔
+            let separate = args.context.is_some() && end.is_some_and(|end| end != m.start());
+            out.grep(anchor, &m, separate)?;
+            end = Some(m.end());
+        }
+        out.flush()?;
+        return Ok(());
+    }
+    if args.since.is_some() || args.until.is_some() {
+        let since = args.since.unwrap_or(UNIX_EPOCH);
+        // 再往后的时间戳写不出来
+        let until = args
+            .until
+            .unwrap_or(UNIX_EPOCH + Duration::from_secs(u64::MAX >> 2));
+        let records: Vec<&str> = reader.records_between(since, until).collect();
+        let skip = records
+            .len()
+            .saturating_sub(args.tail.unwrap_or(usize::MAX));
+        for record in &records[skip..] {
+            out.record(anchor, None, record)?;
+        }
+        out.flush()?;
+        return Ok(());
+    }
+    let mut skip = match args.tail {
+        Some(n) => reader.records().count().saturating_sub(n),
+        None => 0,
+    };
+    // 坏记录报告在 stderr 上，--tail 的话只报告最后这几条之间的
+    for record in reader.checked_records() {
+        match record {
+            Ok(_) if skip > 0 => skip -= 1,
+            Ok(record) => out.record(anchor, None, record)?,
+            Err(e) if skip == 0 => eprintln!("mmlog-cat: {}", e),
+            Err(_) => {}
+        }
+    }
+    out.flush()?;
+
+    if !args.follow {
+        return Ok(());
+    }
+    unsafe {
+        libc::signal(libc::SIGINT, on_sigint as *const () as libc::sighandler_t);
+    }
+    let mut follow = reader.follow();
+    while !INTERRUPTED.load(Ordering::Relaxed) {
+        let records = match follow.poll() {
+            Ok(records) => records,
+            Err(lagged) => {
+                eprintln!("mmlog-cat: {}", lagged);
+                continue;
+            }
+        };
+        for record in &records {
+            out.record(anchor, None, record)?;
+        }
+        if records.is_empty() {
+            thread::sleep(POLL_INTERVAL);
+        } else {
+            out.flush()?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        // 管道被关掉（例如 `| head`）不算错误
+        if let mmlog::Error::StdIo(e) = &e {
+            if e.kind() == io::ErrorKind::BrokenPipe {
+                return;
+            }
+        }
+        eprintln!("mmlog-cat: {}", e);
+        process::exit(1);
+    }
+}
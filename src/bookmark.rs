@@ -0,0 +1,49 @@
+// Logger::bookmark：记在文件的书签表里的读取位置，进程重启之后接着读新写入的记录，
+// 表的格式见 format::BOOKMARK_SLOTS。
+//
+// 位置和 Follow 的一样是环里写入过的所有数据里的位置（翻转次数 × 环的长度 + offset），
+// 书签和现在的位置差得比环还长，或者现在的位置反而更小（clear()、轮转、resize 之后计数变了），
+// 说明书签之后的记录有一部分已经被覆盖了。
+
+use crate::mapping::{Mapping, Ring};
+use crate::{Error, Result};
+
+/// 环里写入过的所有数据里的一个位置：翻转次数 × 环的长度 + offset，
+/// 见 [`Logger::bookmark`](crate::Logger::bookmark)。没有设置过的书签是 `LogicalPos(0)`，文件的最开头。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct LogicalPos(pub u64);
+
+/// [`Logger::read_since_bookmark`](crate::Logger::read_since_bookmark) 读到的东西。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinceBookmark {
+    /// 书签之后写入的完整记录，按时间顺序，不含结尾的换行。
+    pub records: Vec<String>,
+    /// 书签之后的记录有一部分在读到之前已经被覆盖了：缓冲区绕过了书签，或者文件被
+    /// [`Logger::clear`](crate::Logger::clear)、轮转过。这时 `records` 是现在缓冲区里所有的记录。
+    pub gap: bool,
+    /// 读到的最后一条记录之后的位置，前进书签的话就移到这里。
+    pub end: LogicalPos,
+}
+
+// 书签名在书签表里的哈希（FNV-1a），0 表示空的槽，所以不会是 0
+pub(crate) fn hash(name: &str) -> u64 {
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100_0000_01b3)
+    });
+    hash.max(1)
+}
+
+// 书签只记一个位置，只能用在只有一个环的文件里
+pub(crate) fn check(mapping: &Mapping) -> Result<()> {
+    if mapping.rings().count() > 1 {
+        return Err(Error::InvalidConfig("bookmarks need a file with a single ring, not Builder::reserve_for_errors or Builder::channels"));
+    }
+    Ok(())
+}
+
+// 书签之后的记录还在不在缓冲区里，返回现在的位置
+pub(crate) fn intact(mapping: &Mapping, bookmark: u64) -> (bool, u64) {
+    let size = mapping.ring_size(Ring::Main) as u64;
+    let (_, pos) = mapping.position(Ring::Main);
+    (bookmark <= pos && pos - bookmark <= size, pos)
+}
@@ -0,0 +1,73 @@
+use log::{Level, Metadata};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 过滤条件每变化一次加一，所有调用点的缓存随之失效
+static EPOCH: AtomicUsize = AtomicUsize::new(1);
+
+/// 让 [`log_if!`](crate::log_if) 的调用点缓存失效。
+///
+/// 直接调用 `log::set_max_level` 或者换了全局 logger 之后需要调用一次。
+pub fn invalidate_callsites() {
+    EPOCH.fetch_add(1, Ordering::Relaxed);
+}
+
+/// [`log_if!`](crate::log_if) 在每个调用点生成的缓存。
+///
+/// 低 4 位是缓存的 level 和结果，其余是缓存时的 epoch。
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct Callsite(AtomicUsize);
+
+impl Callsite {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Callsite {
+        Callsite(AtomicUsize::new(0))
+    }
+
+    #[inline]
+    pub fn is_enabled(&self, level: Level, target: &str) -> bool {
+        let state = self.0.load(Ordering::Relaxed);
+        if state >> 4 == EPOCH.load(Ordering::Relaxed) && (state >> 1) & 0b111 == level as usize {
+            return state & 1 == 1;
+        }
+        self.refresh(level, target)
+    }
+
+    #[cold]
+    fn refresh(&self, level: Level, target: &str) -> bool {
+        // 先取 epoch 再判断，判断期间有变化的话下次还会重新计算
+        let epoch = EPOCH.load(Ordering::Relaxed);
+        let enabled = level <= log::max_level()
+            && log::logger().enabled(&Metadata::builder().level(level).target(target).build());
+        self.0.store(
+            epoch << 4 | (level as usize) << 1 | enabled as usize,
+            Ordering::Relaxed,
+        );
+        enabled
+    }
+}
+
+/// 和 `log::log!` 一样，但每个调用点会缓存是否启用，
+/// 被过滤掉的调用点只需要两次 relaxed load 和比较。
+///
+/// 同一个调用点的 level 和 target 应该保持不变。
+///
+/// ```no_run
+/// use log::Level;
+///
+/// mmlog::log_if!(target: "audit", Level::Info, "user {} logged in", 42);
+/// mmlog::log_if!(Level::Debug, "cache miss");
+/// ```
+#[macro_export]
+macro_rules! log_if {
+    (target: $target:expr, $lvl:expr, $($arg:tt)+) => {{
+        static CALLSITE: $crate::Callsite = $crate::Callsite::new();
+        let lvl = $lvl;
+        if CALLSITE.is_enabled(lvl, $target) {
+            ::log::log!(target: $target, lvl, $($arg)+);
+        }
+    }};
+    ($lvl:expr, $($arg:tt)+) => {
+        $crate::log_if!(target: ::std::module_path!(), $lvl, $($arg)+)
+    };
+}
@@ -0,0 +1,72 @@
+//! 把子进程的输出写进日志。
+
+use crate::Logger;
+use log::{Level, Log, Record};
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::Child;
+use std::thread::{self, JoinHandle};
+
+// 单行超过这个长度就拆成多条记录
+const MAX_LINE: u64 = 16 * 1024;
+
+/// 启动线程逐行读取 `child` 的 stdout 和 stderr（需要事先设置成 `Stdio::piped()`），
+/// 以 Info 级别、`target = tag` 写入 `logger`。
+///
+/// 返回的线程在两个管道都读完之后结束，结果是写入的记录条数。
+pub fn pipe_child(
+    logger: &'static Logger,
+    child: &mut Child,
+    tag: &str,
+) -> JoinHandle<io::Result<u64>> {
+    pipe_child_at(logger, child, tag, Level::Info)
+}
+
+/// 同 [`pipe_child`]，但可以指定级别。
+pub fn pipe_child_at(
+    logger: &'static Logger,
+    child: &mut Child,
+    tag: &str,
+    level: Level,
+) -> JoinHandle<io::Result<u64>> {
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let tag = tag.to_string();
+    thread::spawn(move || {
+        let err = stderr.map(|stderr| {
+            let tag = tag.clone();
+            thread::spawn(move || pump(logger, stderr, &tag, level))
+        });
+        let mut n = match stdout {
+            Some(stdout) => pump(logger, stdout, &tag, level)?,
+            None => 0,
+        };
+        if let Some(err) = err {
+            n += err
+                .join()
+                .map_err(|_| io::Error::other("stderr reader panicked"))??;
+        }
+        Ok(n)
+    })
+}
+
+fn pump<R: Read>(logger: &Logger, pipe: R, tag: &str, level: Level) -> io::Result<u64> {
+    let mut reader = BufReader::new(pipe);
+    let mut line = Vec::new();
+    let mut n = 0;
+    loop {
+        line.clear();
+        // 没有换行的半行在 EOF 时也会读出来
+        if (&mut reader).take(MAX_LINE).read_until(b'\n', &mut line)? == 0 {
+            return Ok(n);
+        }
+        let text = String::from_utf8_lossy(&line);
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target(tag)
+                .args(format_args!("{}", text.trim_end_matches(['\n', '\r'])))
+                .build(),
+        );
+        n += 1;
+    }
+}
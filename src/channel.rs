@@ -0,0 +1,86 @@
+use crate::Logger;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// [`Builder::channels`](crate::Builder::channels) 的一个通道，见 [`Logger::channel`]。
+///
+/// 记录写进这个通道自己的环，格式、配额、`tee` 这些都和 [`Logger`] 一样，只是级别单独设置，
+/// 不看 [`Builder::module_level`](crate::Builder::module_level)。别的通道的记录不攒批
+/// （[`Builder::batched`](crate::Builder::batched)），直接写进去。
+///
+/// 实现了 [`Log`]，可以交给 [`Builder::also_log`](crate::Builder::also_log)
+/// 或者别的只认 `Log` 的地方，这时 `Logger` 要是 `'static` 的：
+///
+/// ```
+/// use log::{Level, Log};
+/// use mmlog::MB;
+///
+/// let path = std::env::temp_dir().join("mmlog-channel-tee.log");
+/// let logger: &'static mmlog::Logger = Box::leak(Box::new(
+///     mmlog::Builder::new()
+///         .channels(&[("app", MB), ("audit", MB / 2)])
+///         .build(&path)
+///         .unwrap(),
+/// ));
+/// let audit = logger.channel("audit").unwrap();
+/// assert_eq!(audit.name(), "audit");
+///
+/// // 另一个 logger 的记录同时写进这个通道
+/// let other = mmlog::Builder::new()
+///     .also_log(Box::new(audit))
+///     .build_anonymous()
+///     .unwrap();
+/// other.log(
+///     &log::Record::builder()
+///         .level(Level::Info)
+///         .args(format_args!("user 42 logged in"))
+///         .build(),
+/// );
+/// other.flush();
+///
+/// let reader = mmlog::reader::LogReader::open(&path).unwrap();
+/// assert_eq!(reader.channel("audit").count(), 1);
+/// assert_eq!(reader.channel("app").count(), 0);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelLogger<'a> {
+    logger: &'a Logger,
+    // 通道表里的第几个，第一个就是 Logger::log() 写的那个
+    channel: usize,
+}
+
+impl<'a> ChannelLogger<'a> {
+    pub(crate) fn new(logger: &'a Logger, channel: usize) -> ChannelLogger<'a> {
+        ChannelLogger { logger, channel }
+    }
+
+    /// 通道的名字。
+    pub fn name(&self) -> &'a str {
+        self.logger.channel_name(self.channel)
+    }
+
+    /// 这个通道当前的级别。
+    pub fn level(&self) -> LevelFilter {
+        self.logger.channel_level(self.channel)
+    }
+
+    /// 运行时调整这个通道的级别，别的通道不受影响。第一个通道的级别就是
+    /// [`Logger::set_level`] 设置的全局级别。
+    pub fn set_level(&self, level: Level) {
+        self.logger.set_channel_level(self.channel, level);
+    }
+}
+
+impl Log for ChannelLogger<'_> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.logger.channel_enabled(self.channel, metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.logger
+            .log_with(record, self.channel, !self.logger.non_blocking);
+    }
+
+    fn flush(&self) {
+        self.logger.flush();
+    }
+}
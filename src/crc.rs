@@ -0,0 +1,65 @@
+// CRC32C（Castagnoli，iSCSI 用的那个），长度前缀格式每条记录后面的校验和，见 format::checksum。
+//
+// 默认查表，一次一个字节。打开 hw-crc 的话在支持 SSE4.2 的 x86_64 上用 crc32 指令，
+// 运行时检测，别的平台照样查表，算出来的结果一样
+
+const POLY: u32 = 0x82f6_3b78;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub(crate) fn crc32c(data: &[u8]) -> u32 {
+    #[cfg(all(feature = "hw-crc", target_arch = "x86_64"))]
+    {
+        if std::is_x86_feature_detected!("sse4.2") {
+            return unsafe { !hw::update(!0, data) };
+        }
+    }
+    !update(!0, data)
+}
+
+fn update(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+#[cfg(all(feature = "hw-crc", target_arch = "x86_64"))]
+mod hw {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    // 调用者保证 CPU 支持 SSE4.2
+    #[target_feature(enable = "sse4.2")]
+    pub(super) unsafe fn update(crc: u32, data: &[u8]) -> u32 {
+        let mut crc = crc as u64;
+        let mut chunks = data.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0; 8];
+            word.copy_from_slice(chunk);
+            crc = _mm_crc32_u64(crc, u64::from_le_bytes(word));
+        }
+        let mut crc = crc as u32;
+        for &b in chunks.remainder() {
+            crc = _mm_crc32_u8(crc, b);
+        }
+        crc
+    }
+}
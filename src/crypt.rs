@@ -0,0 +1,135 @@
+// Builder::encrypt：长度前缀格式的每条记录的内容换成 nonce + 密文 + tag，见 format 模块。
+// 没有 encryption feature 的话 Cipher 是个空的 enum，Option<Cipher> 总是 None，
+// 用到它的地方不用到处加 cfg
+
+use crate::format::{KEY_CHECK_LEN, SALT_LEN};
+
+#[cfg(feature = "encryption")]
+pub(crate) use enabled::Cipher;
+
+#[cfg(not(feature = "encryption"))]
+#[derive(Debug, Clone)]
+pub(crate) enum Cipher {}
+
+#[cfg(not(feature = "encryption"))]
+impl Cipher {
+    pub(crate) fn key_check(&self) -> [u8; KEY_CHECK_LEN] {
+        match *self {}
+    }
+
+    pub(crate) fn seal(&self, _: [u8; SALT_LEN], _: u64, _: &[u8]) -> Vec<u8> {
+        match *self {}
+    }
+
+    pub(crate) fn open(&self, _: &[u8]) -> Option<Vec<u8>> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "encryption")]
+mod enabled {
+    use super::{KEY_CHECK_LEN, SALT_LEN};
+    use crate::format::{SEAL_NONCE, SEAL_TAG};
+    use chacha20poly1305::aead::AeadInPlace;
+    use chacha20poly1305::{Key, KeyInit, Tag, XChaCha20Poly1305, XNonce};
+    use std::fmt;
+
+    // 算 key check 用的 nonce，记录的 nonce 的 salt 部分不会全是 0xff，见 salt()
+    const CHECK_NONCE: [u8; SEAL_NONCE] = [0xff; SEAL_NONCE];
+
+    #[derive(Clone)]
+    pub(crate) struct Cipher(XChaCha20Poly1305);
+
+    // 不把密钥打出来
+    impl fmt::Debug for Cipher {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("Cipher(..)")
+        }
+    }
+
+    impl Cipher {
+        pub(crate) fn new(key: &[u8; 32]) -> Cipher {
+            Cipher(XChaCha20Poly1305::new(Key::from_slice(key)))
+        }
+
+        /// 文件里的 [`KEY_CHECK_POS`](crate::format::KEY_CHECK_POS)：空消息的 tag，
+        /// 打开文件时用来认出密钥不对。
+        pub(crate) fn key_check(&self) -> [u8; KEY_CHECK_LEN] {
+            let tag = self
+                .0
+                .encrypt_in_place_detached(XNonce::from_slice(&CHECK_NONCE), b"", &mut [])
+                .expect("empty message");
+            tag.into()
+        }
+
+        /// 加密一条记录，nonce 是文件的 salt 加上这条记录的序号（小端序）。
+        pub(crate) fn seal(&self, salt: [u8; SALT_LEN], seq: u64, plain: &[u8]) -> Vec<u8> {
+            let mut sealed = Vec::with_capacity(SEAL_NONCE + plain.len() + SEAL_TAG);
+            sealed.extend_from_slice(&salt);
+            sealed.extend_from_slice(&seq.to_le_bytes());
+            sealed.extend_from_slice(plain);
+            let (nonce, text) = sealed.split_at_mut(SEAL_NONCE);
+            let tag = self
+                .0
+                .encrypt_in_place_detached(XNonce::from_slice(nonce), b"", text)
+                .expect("record shorter than the chacha20 limit");
+            sealed.extend_from_slice(&tag);
+            sealed
+        }
+
+        /// 解密 [`Cipher::seal`] 的结果，tag 对不上（被改过或者密钥不对）的话返回 `None`。
+        pub(crate) fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+            let text = sealed.len().checked_sub(SEAL_NONCE + SEAL_TAG)?;
+            let (nonce, rest) = sealed.split_at(SEAL_NONCE);
+            let (text, tag) = rest.split_at(text);
+            let mut plain = text.to_vec();
+            self.0
+                .decrypt_in_place_detached(
+                    XNonce::from_slice(nonce),
+                    b"",
+                    &mut plain,
+                    Tag::from_slice(tag),
+                )
+                .ok()?;
+            Some(plain)
+        }
+    }
+}
+
+/// 新的 salt：nonce 的前 [`SALT_LEN`] 个字节。新建文件、每次打开文件写入和
+/// [`Logger::clear`](crate::Logger::clear) 时都换一个，序号从 0 重新数、崩溃时没写完的记录
+/// 的序号被重新用到也不会和之前的 nonce 重复。记录里带着自己的 nonce，读的时候不看 header。
+///
+/// 从 `/dev/urandom` 读，读不了的话（比如不是 Unix）用 `RandomState`（每个进程一个系统随机数
+/// 做种子）把时间、进程号和一个计数器混在一起，128 位里只要求不重复。
+pub(crate) fn salt() -> [u8; SALT_LEN] {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    use std::io::Read;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::SystemTime;
+
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut salt = [0; SALT_LEN];
+        let random = std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut salt));
+        if random.is_err() {
+            for half in salt.chunks_mut(8) {
+                let mut hasher = RandomState::new().build_hasher();
+                hasher.write_u128(
+                    SystemTime::UNIX_EPOCH
+                        .elapsed()
+                        .unwrap_or_default()
+                        .as_nanos(),
+                );
+                hasher.write_u32(std::process::id());
+                hasher.write_u64(COUNT.fetch_add(1, Ordering::Relaxed));
+                half.copy_from_slice(&hasher.finish().to_le_bytes());
+            }
+        }
+        // 留给 key check 用
+        if salt != [0xff; SALT_LEN] {
+            return salt;
+        }
+    }
+}
@@ -0,0 +1,93 @@
+// Builder::parse_env 用到的解析，出错时返回给用户看的说明
+
+use log::LevelFilter;
+use std::env::{self, VarError};
+use std::ffi::OsString;
+
+// `<prefix>_<name>` 的值，没有设置或者是空的话为 None
+pub(crate) fn var(prefix: &str, name: &str) -> Result<Option<(String, String)>, String> {
+    lookup(&format!("{}_{}", prefix, name))
+}
+
+pub(crate) fn lookup(key: &str) -> Result<Option<(String, String)>, String> {
+    match env::var(key) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some((key.to_string(), value))),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(format!("{} is not valid UTF-8", key)),
+    }
+}
+
+// 路径不要求是 UTF-8
+pub(crate) fn var_os(prefix: &str, name: &str) -> Option<OsString> {
+    env::var_os(format!("{}_{}", prefix, name)).filter(|value| !value.is_empty())
+}
+
+// 和 RUST_LOG 里的全局级别一样，不区分大小写
+pub(crate) fn level(value: &str) -> Result<LevelFilter, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| "expected one of off, error, warn, info, debug, trace".to_string())
+}
+
+// RUST_LOG 风格的 `warn,myapp=trace,hyper::proto=off`：不带 `=` 的级别是全局级别，
+// 不带 `=` 的 target 表示这个 target 的全部级别。后面的覆盖前面的
+pub(crate) fn directives(spec: &str) -> Result<Vec<(Option<&str>, LevelFilter)>, String> {
+    let mut directives = Vec::new();
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let invalid = |e: &str| format!("invalid directive {:?}: {}", directive, e);
+        let (target, level) = match directive.split_once('=') {
+            Some((target, level)) => {
+                let level = self::level(level).map_err(|e| invalid(&e))?;
+                (Some(target.trim()), level)
+            }
+            None => match directive.parse() {
+                Ok(level) => (None, level),
+                Err(_) => (Some(directive), LevelFilter::Trace),
+            },
+        };
+        if let Some(target) = target {
+            if target.is_empty() {
+                return Err(invalid("missing target"));
+            }
+            // env_logger 的 `/regex` 和其他写法都不支持
+            if !target.split("::").all(|part| {
+                !part.is_empty()
+                    && part
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+            }) {
+                return Err(invalid("target must be a module path like a::b"));
+            }
+        }
+        directives.push((target, level));
+    }
+    Ok(directives)
+}
+
+// 字节数，可以带 K 或 M 后缀（1024 进制，不区分大小写）
+pub(crate) fn size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let (digits, unit) = match value.as_bytes().last() {
+        Some(b'k' | b'K') => (&value[..value.len() - 1], 1 << 10),
+        Some(b'm' | b'M') => (&value[..value.len() - 1], 1 << 20),
+        _ => (value, 1),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err("expected a number of bytes with an optional K or M suffix".to_string());
+    }
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| "size is too large".to_string())
+}
+
+pub(crate) fn bool(value: &str) -> Result<bool, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err("expected 0, 1, true or false".to_string()),
+    }
+}
@@ -0,0 +1,123 @@
+use crate::Shared;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle, Thread};
+use std::time::{Duration, Instant};
+
+// Builder::flush_interval 的后台线程：写入之后攒一个周期再同步，
+// 没有新的写入时一直 park，不占用 CPU。
+#[derive(Debug)]
+pub(crate) struct Flusher {
+    state: Arc<State>,
+    thread: Thread,
+    handle: Option<JoinHandle<()>>,
+    // 启动后台线程的进程，fork 出来的子进程里没有这个线程
+    pid: u32,
+}
+
+#[derive(Debug)]
+struct State {
+    // 上次同步之后有没有写入
+    dirty: AtomicBool,
+    stop: AtomicBool,
+}
+
+impl Flusher {
+    pub(crate) fn spawn(
+        shared: Arc<Shared>,
+        interval: Duration,
+        sync: bool,
+    ) -> std::io::Result<Flusher> {
+        #[cfg(feature = "test-util")]
+        if let Some(e) = crate::test::failed_spawn() {
+            return Err(e);
+        }
+        let state = Arc::new(State {
+            dirty: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+        });
+        let handle = {
+            let state = state.clone();
+            thread::Builder::new()
+                .name("mmlog-flusher".to_string())
+                .spawn(move || run(&shared, &state, interval, sync))?
+        };
+        Ok(Flusher {
+            state,
+            thread: handle.thread().clone(),
+            handle: Some(handle),
+            pid: std::process::id(),
+        })
+    }
+
+    /// 每写入一条记录调用一次，只在从干净变脏时唤醒后台线程。
+    pub(crate) fn dirty(&self) {
+        if !self.state.dirty.load(Ordering::Relaxed)
+            && !self.state.dirty.swap(true, Ordering::AcqRel)
+        {
+            self.thread.unpark();
+        }
+    }
+
+    /// 让后台线程退出并等它结束，之后不会再访问映射。
+    pub(crate) fn stop(&mut self) {
+        self.state.stop.store(true, Ordering::Release);
+        self.thread.unpark();
+        if let Some(handle) = self.handle.take() {
+            // 在子进程里 join 父进程的线程会一直等下去
+            if self.pid == std::process::id() {
+                let _ = handle.join();
+            } else {
+                std::mem::forget(handle);
+            }
+        }
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run(shared: &Shared, state: &State, interval: Duration, sync: bool) {
+    // 一直失败的话只报告第一次，恢复之后再失败再报告
+    let mut failing = false;
+    while !state.stop.load(Ordering::Acquire) {
+        if !state.dirty.load(Ordering::Acquire) {
+            thread::park();
+            continue;
+        }
+        // 写入方的 unpark() 可能让 park_timeout() 提前返回，所以按截止时间等
+        let deadline = Instant::now() + interval;
+        loop {
+            let now = Instant::now();
+            if now >= deadline || state.stop.load(Ordering::Acquire) {
+                break;
+            }
+            thread::park_timeout(deadline - now);
+        }
+        if state.stop.load(Ordering::Acquire) {
+            // 剩下的由 Logger 的 drop 同步
+            break;
+        }
+        state.dirty.store(false, Ordering::Release);
+        let _guard = shared.lock();
+        // close() 之后什么都不做；出错了下个周期再试。这里拿着写锁，不能写日志，
+        // 错误交给 Logger 下次写入时写进去，见 report 模块
+        if let Some(mapping) = unsafe { shared.mapping() } {
+            match mapping.sync_dirty(sync) {
+                Ok(()) => failing = false,
+                Err(e) => {
+                    state.dirty.store(true, Ordering::Release);
+                    if !failing {
+                        shared
+                            .reports
+                            .push(format!("flush_interval: background sync failed: {}", e));
+                    }
+                    failing = true;
+                }
+            }
+        }
+    }
+}
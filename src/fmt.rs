@@ -0,0 +1,141 @@
+//! 格式化相关的辅助工具。
+
+use std::fmt::{self, Debug, Display, Formatter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static DBG_BUDGET: AtomicUsize = AtomicUsize::new(64 * crate::KB);
+
+/// [`dbg!`](crate::dbg) 单个值最多输出的字节数，由 [`Builder::dbg_budget`](crate::Builder::dbg_budget) 设置。
+pub fn dbg_budget() -> usize {
+    DBG_BUDGET.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_dbg_budget(budget: usize) {
+    DBG_BUDGET.store(budget, Ordering::Relaxed);
+}
+
+/// 用 `Debug` 格式化 `value`，但最多输出 `max_bytes` 字节，超出的部分换成 `… <truncated>`。
+///
+/// 超出预算时格式化会立即停止，不会先生成完整的字符串再截断。
+/// 用 `{:#}` 输出时相当于 `{:#?}`。
+///
+/// ```
+/// let v = vec![0u8; 1000];
+/// let s = format!("{}", mmlog::fmt::bounded(&v, 16));
+/// assert_eq!(s, "[0, 0, 0, 0, 0, … <truncated>");
+/// ```
+pub fn bounded<T: Debug + ?Sized>(value: &T, max_bytes: usize) -> Bounded<'_, T> {
+    Bounded {
+        value,
+        max: max_bytes,
+    }
+}
+
+/// 见 [`bounded`]。
+#[derive(Debug)]
+pub struct Bounded<'a, T: ?Sized> {
+    value: &'a T,
+    max: usize,
+}
+
+impl<'a, T: Debug + ?Sized> Display for Bounded<'a, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let alternate = f.alternate();
+        let mut w = Limit {
+            inner: f,
+            left: self.max,
+            truncated: false,
+        };
+        let result = if alternate {
+            write!(w, "{:#?}", self.value)
+        } else {
+            write!(w, "{:?}", self.value)
+        };
+        if w.truncated {
+            f.write_str("… <truncated>")
+        } else {
+            result
+        }
+    }
+}
+
+struct Limit<'a, 'b> {
+    inner: &'a mut Formatter<'b>,
+    left: usize,
+    truncated: bool,
+}
+
+impl<'a, 'b> Write for Limit<'a, 'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.len() <= self.left {
+            self.left -= s.len();
+            return self.inner.write_str(s);
+        }
+        let mut end = self.left;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.inner.write_str(&s[..end])?;
+        self.left = 0;
+        self.truncated = true;
+        // 返回错误让 Debug 实现尽早停下来
+        Err(fmt::Error)
+    }
+}
+
+/// 像 `xxd` 一样把 `data` 写成十六进制：第一行是 `<label> (<N> bytes)`，之后每行 16 个字节，
+/// 行首是偏移，右边是 ASCII（不可打印的字节写成 `.`）。
+///
+/// 边格式化边输出，不分配内存，见 [`Logger::log_bytes`](crate::Logger::log_bytes) 和
+/// [`log_bytes!`](crate::log_bytes)。
+///
+/// ```
+/// let s = format!("{}", mmlog::fmt::hex_dump("rx frame", b"GET / HTTP/1.1\r\nHost"));
+/// assert_eq!(
+///     s,
+///     "rx frame (20 bytes)\n\
+///      00000000: 4745 5420 2f20 4854 5450 2f31 2e31 0d0a  GET / HTTP/1.1..\n\
+///      00000010: 486f 7374                                Host"
+/// );
+/// ```
+pub fn hex_dump<'a>(label: &'a str, data: &'a [u8]) -> HexDump<'a> {
+    HexDump { label, data }
+}
+
+/// 见 [`hex_dump`]。
+#[derive(Debug, Clone, Copy)]
+pub struct HexDump<'a> {
+    label: &'a str,
+    data: &'a [u8],
+}
+
+impl Display for HexDump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        write!(f, "{} ({} bytes)", self.label, self.data.len())?;
+        for (i, chunk) in self.data.chunks(16).enumerate() {
+            // `\n` + 偏移和冒号 10 + 十六进制 39 + 两个空格 + ASCII 16
+            let mut line = [b' '; 68];
+            line[0] = b'\n';
+            let offset = (i * 16) as u32;
+            for (j, byte) in line[1..9].iter_mut().enumerate() {
+                *byte = HEX[(offset >> (28 - 4 * j) & 0xf) as usize];
+            }
+            line[9] = b':';
+            for (j, &b) in chunk.iter().enumerate() {
+                let at = 11 + j * 2 + j / 2;
+                line[at] = HEX[(b >> 4) as usize];
+                line[at + 1] = HEX[(b & 0xf) as usize];
+                line[52 + j] = if b.is_ascii_graphic() || b == b' ' {
+                    b
+                } else {
+                    b'.'
+                };
+            }
+            let len = 52 + chunk.len();
+            // 都是 ASCII
+            f.write_str(std::str::from_utf8(&line[..len]).map_err(|_| fmt::Error)?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use crate::Shared;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Arc;
+
+// Builder::fork_safe：pthread_atfork 的子进程回调里对登记过的 logger 调用 Shared::post_fork_child。
+// 子进程里只剩调用 fork() 的线程，别的线程 fork 时可能正拿着锁，回调里什么锁都不能拿，
+// 所以登记表是固定大小的原子指针数组。
+const SLOTS: usize = 32;
+
+static LOGGERS: [AtomicPtr<Shared>; SLOTS] = [const { AtomicPtr::new(ptr::null_mut()) }; SLOTS];
+
+/// 表满了返回 `false`。
+pub(crate) fn register(shared: &Arc<Shared>) -> bool {
+    install();
+    let ptr = Arc::as_ptr(shared) as *mut Shared;
+    LOGGERS.iter().any(|slot| {
+        slot.compare_exchange(ptr::null_mut(), ptr, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    })
+}
+
+/// 要在 `Shared` 被释放之前调用。
+pub(crate) fn unregister(shared: &Arc<Shared>) {
+    let ptr = Arc::as_ptr(shared) as *mut Shared;
+    for slot in &LOGGERS {
+        if slot
+            .compare_exchange(ptr, ptr::null_mut(), Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+extern "C" fn child() {
+    crate::sys::abort_on_unwind(|| {
+        for slot in &LOGGERS {
+            let ptr = slot.load(Ordering::Acquire);
+            if !ptr.is_null() {
+                // 登记着的 Shared 还没有被释放：unregister() 在 drop 之前，
+                // 而正在 drop 它的线程如果不是调用 fork() 的线程，就没有跟到子进程里来
+                unsafe { (*ptr).post_fork_child() };
+            }
+        }
+    })
+}
+
+#[cfg(all(feature = "mmap", unix))]
+fn install() {
+    use std::sync::Once;
+
+    static ATFORK: Once = Once::new();
+    ATFORK.call_once(|| unsafe {
+        libc::pthread_atfork(None, None, Some(child));
+    });
+}
+
+// 没有 libc 就没有 fork()
+#[cfg(not(all(feature = "mmap", unix)))]
+fn install() {}
@@ -0,0 +1,1558 @@
+//! 写入文件的格式。
+//!
+//! 文件由固定长度的 header 和紧随其后的环形缓冲区组成：
+//!
+//! ```text
+//! +-------+-------------+--------------+-----------+--------+
+//! | MAGIC | version u32 | capacity u64 | flags u32 | (保留) |
+//! +-------+-------------+--------------+-----------+--------+
+//! 0       4             8              16          20
+//! +----------------+-------------+-----------+-----------+----------+--------+----------+
+//! | offset (usize) | records u64 | bytes u64 | wraps u64 | data u32 | (保留) | 进程间锁 |
+//! +----------------+-------------+-----------+-----------+----------+--------+----------+
+//! 24               32            40          48          56         60       64
+//! +---------+---------------+---------+---------+--------+--------+----------+
+//! | pid u32 | (保留)        | started | exe     | tag    | (填 0) | 记录 ... |
+//! +---------+---------------+---------+---------+--------+--------+----------+
+//! META_POS                  136       144       208      272      data
+//! ```
+//!
+//! `version`、`capacity`、`flags` 和 `data` 是小端序，`capacity` 是缓冲区的字节数，
+//! `data` 是缓冲区在文件里的起始位置，至少是 `HEADER_SIZE`，按 8 字节对齐。
+//! 文件长度总是 `data + capacity`。`flags` 目前有 [`FLAG_LENGTH_PREFIXED`]、
+//! [`FLAG_SHARED`]、[`FLAG_ESCAPED_NEWLINES`] 和 [`FLAG_CHECKSUM`]。
+//!
+//! 版本 6 开始 header 后面是 [`META_SIZE`] 字节的元数据，创建文件时写入，之后不再改变：
+//! 创建文件的进程号、那个进程的启动时间（UNIX 纪元以来的纳秒数，`u64`）、
+//! `std::env::current_exe()` 的文件名和 [`Builder::tag`](crate::Builder::tag) 设置的标签，
+//! 都是小端序，字符串是 UTF-8，后面填 0。见 [`LogReader::metadata`](crate::reader::LogReader::metadata)。
+//!
+//! 默认 `data` 就是 `HEADER_SIZE + META_SIZE`（版本 12 开始再加上书签表，见下面）；打开了 [`Builder::page_aligned`](crate::Builder::page_aligned)
+//! 的话是创建文件时系统的页大小，中间填 0。版本 5 的文件没有元数据，`data` 就是 `HEADER_SIZE`；
+//! 版本 4 的文件没有 `data`（那里是 0），缓冲区紧跟在 header 后面。它们现在仍然可以打开。
+//!
+//! 版本 7 开始可以设置 [`FLAG_RESERVED`]（[`Builder::reserve_for_errors`](crate::Builder::reserve_for_errors)）：
+//! 缓冲区末尾长度记在 [`RESERVED_POS`] 的那一段是单独的一个环，只写 Warn 和 Error 的记录，
+//! 前面剩下的部分是写其余记录的环。第二个环有自己的写指针 [`RESERVED_OFFSET_POS`] 和翻转次数
+//! [`RESERVED_WRAPS_POS`]（都在元数据里），规则和 header 里的 `offset`、`wraps` 完全一样；
+//! `records` 和 `bytes` 是两个环加起来的。两个环各自按时间顺序，读的时候按时间戳合并。
+//! 没有这个标志的文件只有一个环，元数据里这几个字段都是 0。
+//!
+//! 版本 8 开始可以设置 [`FLAG_CHANNELS`]（[`Builder::channels`](crate::Builder::channels)）：
+//! 缓冲区依次分成几个有名字的通道，每个通道是一个环，个数记在 [`CHANNELS_POS`]。
+//! 元数据后面是 [`CHANNEL_TABLE_POS`] 开始的通道表，`data` 相应地往后挪：
+//!
+//! ```text
+//! +-------------+--------------+----------------+----------------+------------+
+//! | name [u8]   | start u64    | capacity u64   | offset (usize) | wraps u64  |
+//! +-------------+--------------+----------------+----------------+------------+
+//! 0             32             40               48               56           64
+//! ```
+//!
+//! `start` 是通道在缓冲区里的位置，通道首尾相接，加起来正好是 `capacity`。每个通道的 `offset` 和
+//! `wraps` 的规则和 header 里的完全一样，header 里的这两个字段不再使用，`records` 和 `bytes`
+//! 是所有通道加起来的。[`FLAG_RESERVED`] 和它不能同时设置。
+//!
+//! 版本 9 开始可以设置 [`FLAG_MONOTONIC`]（[`Builder::clock`](crate::Builder::clock)）：
+//! 记录的时间戳是 `CLOCK_MONOTONIC` 的读数而不是 UNIX 纪元以来的时长，写法还是 `<秒>.<纳秒>s`。
+//! [`ANCHOR_POS`] 处是 `build()`/`open()` 时同时读的两个时钟（UNIX 纪元以来的纳秒数和
+//! `CLOCK_MONOTONIC` 的纳秒数，都是 `u64`，小端序），读取方用它换算回墙上时间，见 [`Anchor`]。
+//!
+//! 版本 13 开始这样的文件在 [`BOOT_ID_POS`] 处还记着写锚点时的开机 id。单调时钟在重启之后从头开始，
+//! 所以时间戳其实是 `CLOCK_MONOTONIC` 的读数加上 [`BOOT_POS`] 处的偏移：打开文件时发现重启过，
+//! 就把偏移挪到文件里所有的时间戳之后，时间戳小于偏移的记录是之前开机时写的，换算不了墙上时间。
+//! 第一次开机时偏移是 0。
+//!
+//! 版本 10 开始可以设置 [`FLAG_ENCRYPTED`]（[`Builder::encrypt`](crate::Builder::encrypt)），
+//! 只用于带校验和的长度前缀格式：每条记录的内容换成 [`SEAL_NONCE`] 字节的 nonce、XChaCha20-Poly1305
+//! 的密文和 [`SEAL_TAG`] 字节的 tag，长度前缀和校验和都是对这一整段算的。nonce 是 [`SALT_POS`] 处
+//! [`SALT_LEN`] 个字节的随机 salt 加上写入这条记录之前的 `records`（`u64`，小端序），salt 在新建文件、
+//! 每次打开文件写入和 [`Logger::clear`](crate::Logger::clear) 时换一个。[`KEY_CHECK_POS`] 处是用同一个
+//! 密钥、nonce 全是 `0xff` 加密空消息得到的 tag，打开文件时用来认出密钥不对。
+//!
+//! 版本 12 开始通道表（没有通道的话是元数据）后面是 [`BOOKMARK_SLOTS`] 个书签的槽，每个
+//! [`BOOKMARK_SIZE`] 字节：书签名的 FNV-1a 哈希和书签的位置（都是 `u64`，小端序），哈希是 0 的槽是空的。
+//! 位置是书签所在的环写入过的所有数据里的位置（翻转次数 × 环的长度 + `offset`），
+//! 见 [`Logger::bookmark`](crate::Logger::bookmark)。`data` 相应地往后挪。
+//!
+//! 设置了 [`FLAG_SHARED`] 的话 [`MUTEX_POS`] 处是一个进程间共享的 `pthread_mutex_t`，
+//! 各个进程写入时都要先拿到它，见 [`Builder::shared`](crate::Builder::shared)。
+//!
+//! `records`、`bytes` 和 `wraps` 是写入过的记录数、字节数和翻转的次数（本机字节序），
+//! 和 `offset` 一样在写锁下更新，文件重新打开之后继续累加。
+//!
+//! 最早的版本只有一个 `offset`，没有 magic，现在打开这样的文件会直接报错。
+//!
+//! `offset` 是下一条记录在缓冲区内的写入位置（本机字节序），总是小于缓冲区长度：
+//! 记录正好写到末尾时 `offset` 回到 0。缓冲区写满后从头覆盖，
+//! 所以 `offset` 之后是较旧的记录，之前是较新的记录。`wraps` 为 0 时 `offset` 之后没有记录，
+//! 可能全是 0，也可能是 [`Logger::clear`](crate::Logger::clear) 之前留下的旧数据。
+//! 写入方在记录的内容写完之后才原子地（release）更新 `offset`，读取时先（acquire）读 `offset`。
+//!
+//! 每条记录占一行：
+//!
+//! ```text
+//! [<时间戳> <tid> <level> <file>:<line> <target>] <message>\n
+//! ```
+//!
+//! `<时间戳>` 的写法由 [`TimestampFormat`] 决定：默认是 `<秒>.<纳秒>s`（UNIX 纪元以来），
+//! 或者 RFC 3339 格式的 `2024-01-02T03:04:05.123456789Z`（本地时间的话 `Z` 换成 `+08:00` 这样的时区）。
+//!
+//! 打开了 [`Builder::sequence_numbers`](crate::Builder::sequence_numbers) 的话，
+//! 记录前面还有 `#<序号> `，序号就是写入这条记录之前的 `records`。
+//!
+//! 打开了 [`Builder::thread_names`](crate::Builder::thread_names) 的话，有名字的线程的
+//! `<tid>` 写成 `<tid>/<线程名>`。
+//!
+//! `<level>` 是 `E`/`W`/`I`/`D`/`T` 之一；没有文件名或行号时 `<file>:<line>` 为空。
+//! 默认情况下 `<file>` 和 `<target>` 经过 [`sanitize`] 处理，不会出现 `]` 和控制字符，
+//! `<target>` 里的空格也写成 `\x20`；[`unsanitize`] 还原（ANSI 转义序列除外）。
+//!
+//! 打开了 [`Builder::json`](crate::Builder::json) 的话，每条记录是一行 JSON（JSON Lines）：
+//!
+//! ```text
+//! {"ts":"<时间戳>","tid":<tid>,"level":"INFO","target":"<target>","file":"<file>","line":<line>,"msg":"<message>"}\n
+//! ```
+//!
+//! 有名字的线程在 `tid` 之后多一个 `"thread"`；没有文件名或行号时对应的字段是 `null`。
+//! 字符串里的 `"`、`\` 和控制字符（包括换行）都按 JSON 转义，所以一条记录总是只占一行。
+//!
+//! [`FormatKind::Logfmt`] 的话每条记录是一行 logfmt：
+//!
+//! ```text
+//! ts=<时间戳> tid=<tid> level=info target=<target> file=<file> line=<line> msg="<message>"\n
+//! ```
+//!
+//! 有名字的线程在 `tid` 之后多一个 `thread=`；没有文件名或行号时没有对应的字段。
+//! `level` 是小写的 `error`/`warn`/`info`/`debug`/`trace`。`msg` 总是加引号，
+//! 其他的值只在含有空格、`=`、`"`、`\` 或者控制字符（或者是空的）时加引号，
+//! 引号里按 JSON 转义，所以一条记录也总是只占一行，见 [`Entry::parse_logfmt`](crate::parse::Entry::parse_logfmt)。
+//!
+//! 打开了 `kv` feature 的话，[`log::kv`] 的键值对按 `Display` 写在消息后面：文本和 logfmt 格式是
+//! ` key=value`，键和值按上面 logfmt 的规则加引号；JSON 格式是 `msg` 之后的 `"key":"value"` 字段。
+//!
+//! ```
+//! # #[cfg(feature = "kv")]
+//! # {
+//! use log::Log;
+//! use mmlog::reader::LogReader;
+//!
+//! let path = std::env::temp_dir().join("mmlog-kv.log");
+//! let logger = mmlog::Builder::new().build(&path).unwrap();
+//! let kvs = [("user", "alice"), ("note", "two words"), ("a=b", "1")];
+//! logger.log(
+//!     &log::Record::builder()
+//!         .level(log::Level::Info)
+//!         .args(format_args!("login"))
+//!         .key_values(&kvs)
+//!         .build(),
+//! );
+//! drop(logger);
+//!
+//! let reader = LogReader::open(&path).unwrap();
+//! let record = reader.records().next().unwrap();
+//! assert!(record.ends_with(r#"] login user=alice note="two words" "a=b"=1"#));
+//! # }
+//! ```
+//!
+//! 设置了 [`FLAG_ESCAPED_NEWLINES`] 的话（[`Builder::escape_newlines`](crate::Builder::escape_newlines)），
+//! 文本格式的记录里的 `\`、换行和回车写成 `\\`、`\n` 和 `\r`，消息里有换行也只占一行；
+//! 消息末尾的一个换行就是记录结尾的换行，不转义。见 [`unescape_newlines`]。
+//!
+//! 使用 [`Framing::LengthPrefixed`] 时，每条记录前面多一个小端序的 `u32` 长度（不含这 4 个字节），
+//! 记录不会跨过缓冲区末尾：放不下时剩下的部分填 0，从头开始写。长度为 0 或者剩下不到
+//! 4 个字节表示这一圈到此为止。
+//!
+//! 设置了 [`FLAG_CHECKSUM`] 的话（新建的长度前缀格式的文件都有），每条记录后面再跟一个
+//! 小端序的 `u32`，是记录内容的 CRC32C（见 [`checksum`]），前面的长度不含这 4 个字节。
+//! 掉电时写了一半的记录读的时候会被认出来，见
+//! [`LogReader::checked_records`](crate::reader::LogReader::checked_records)。
+//!
+//! 对这里的任何修改都会影响外部的解析工具。`tests/golden/` 里是每种格式写出来的文件，
+//! 改了格式的话 `tests/golden.rs` 会失败，需要升级 [`VERSION`] 并重新生成。
+
+use log::{Level, Record};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt::{self, Write};
+use std::io;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// 文件开头的 magic。
+pub const MAGIC: [u8; 4] = *b"MMLG";
+
+/// 当前的格式版本。
+pub const VERSION: u32 = 13;
+
+/// magic 在 header 中的位置。
+pub const MAGIC_POS: usize = 0;
+
+/// 格式版本（`u32`，小端序）在 header 中的位置。
+pub const VERSION_POS: usize = 4;
+
+/// 缓冲区容量（`u64`，小端序）在 header 中的位置。
+pub const CAPACITY_POS: usize = 8;
+
+/// flags（`u32`，小端序）在 header 中的位置。
+pub const FLAGS_POS: usize = 16;
+
+/// flags 里表示 [`Framing::LengthPrefixed`] 的位。
+pub const FLAG_LENGTH_PREFIXED: u32 = 1;
+
+/// flags 里表示 header 中有进程间锁的位。
+pub const FLAG_SHARED: u32 = 2;
+
+/// flags 里表示记录里的换行被转义了的位，见 [`unescape_newlines`]。
+pub const FLAG_ESCAPED_NEWLINES: u32 = 4;
+
+/// flags 里表示长度前缀格式的每条记录后面有校验和的位。
+pub const FLAG_CHECKSUM: u32 = 8;
+
+/// flags 里表示缓冲区末尾分出了一个只写 Warn/Error 记录的环的位，版本 7 开始才有，
+/// 见 [`RESERVED_POS`]。
+pub const FLAG_RESERVED: u32 = 16;
+
+/// flags 里表示缓冲区按 [`Builder::channels`](crate::Builder::channels) 分成了几个通道的位，
+/// 版本 8 开始才有，见 [`CHANNELS_POS`]。
+pub const FLAG_CHANNELS: u32 = 32;
+
+/// flags 里表示记录的时间戳是单调时钟的读数的位，版本 9 开始才有，见 [`ANCHOR_POS`]。
+pub const FLAG_MONOTONIC: u32 = 64;
+
+/// flags 里表示记录的内容加密了的位，版本 10 开始才有，见 [`KEY_CHECK_POS`]。
+pub const FLAG_ENCRYPTED: u32 = 128;
+
+/// flags 里表示文件设置了保留期限的位，版本 11 开始才有，见 [`RETENTION_POS`]。
+pub const FLAG_RETENTION: u32 = 256;
+
+/// 写指针（`usize`，本机字节序）在 header 中的位置。
+pub const OFFSET_POS: usize = 24;
+
+/// 写入过的记录数（`u64`，本机字节序）在 header 中的位置。
+pub const RECORDS_POS: usize = 32;
+
+/// 写入过的字节数（`u64`，本机字节序）在 header 中的位置。
+pub const BYTES_POS: usize = 40;
+
+/// 翻转次数（`u64`，本机字节序）在 header 中的位置。
+pub const WRAPS_POS: usize = 48;
+
+/// 缓冲区的起始位置（`u32`，小端序）在 header 中的位置，版本 5 开始才有。
+pub const DATA_POS: usize = 56;
+
+/// 进程间锁在 header 中的位置，最多占 64 个字节。
+pub const MUTEX_POS: usize = 64;
+
+/// header 的长度，版本 5 的文件缓冲区从这里开始。
+pub const HEADER_SIZE: usize = 128;
+
+/// 元数据的起始位置，版本 6 开始才有，紧跟在 header 后面。
+pub const META_POS: usize = HEADER_SIZE;
+
+/// 创建文件的进程号（`u32`，小端序）在文件中的位置。
+pub const PID_POS: usize = META_POS;
+
+/// 创建文件的进程的启动时间（`u64`，小端序，UNIX 纪元以来的纳秒数）在文件中的位置。
+pub const STARTED_POS: usize = META_POS + 8;
+
+/// 可执行文件的文件名（UTF-8，后面填 0）在文件中的位置。
+pub const EXE_POS: usize = META_POS + 16;
+
+/// 可执行文件的文件名最多占的字节数，更长的被截断。
+pub const EXE_LEN: usize = 64;
+
+/// [`Builder::tag`](crate::Builder::tag) 设置的标签（UTF-8，后面填 0）在文件中的位置。
+pub const TAG_POS: usize = EXE_POS + EXE_LEN;
+
+/// 标签最多占的字节数，更长的被截断。
+pub const TAG_LEN: usize = 64;
+
+/// 设置了 [`FLAG_RESERVED`] 的话，第二个环的长度（`u64`，小端序）在文件中的位置。
+pub const RESERVED_POS: usize = TAG_POS + TAG_LEN;
+
+/// 第二个环的写指针（`usize`，本机字节序）在文件中的位置，和 [`OFFSET_POS`] 一样。
+pub const RESERVED_OFFSET_POS: usize = RESERVED_POS + 8;
+
+/// 第二个环的翻转次数（`u64`，本机字节序）在文件中的位置，和 [`WRAPS_POS`] 一样。
+pub const RESERVED_WRAPS_POS: usize = RESERVED_POS + 16;
+
+/// 设置了 [`FLAG_CHANNELS`] 的话，通道的个数（`u32`，小端序）在文件中的位置。
+pub const CHANNELS_POS: usize = RESERVED_POS + 24;
+
+/// 设置了 [`FLAG_MONOTONIC`] 的话，换算时间戳用的锚点在文件中的位置：
+/// 墙上时间和单调时钟的读数（都是 `u64`，小端序，纳秒），见 [`Anchor`]。
+pub const ANCHOR_POS: usize = CHANNELS_POS + 8;
+
+/// 设置了 [`FLAG_ENCRYPTED`] 的话，nonce 开头的 salt 在文件中的位置，
+/// 新建文件、每次打开文件写入和 [`Logger::clear`](crate::Logger::clear) 时换一个。
+pub const SALT_POS: usize = ANCHOR_POS + 16;
+
+/// salt 的字节数。
+pub const SALT_LEN: usize = 16;
+
+/// 设置了 [`FLAG_ENCRYPTED`] 的话，检查密钥用的 tag 在文件中的位置。
+pub const KEY_CHECK_POS: usize = SALT_POS + SALT_LEN;
+
+/// 检查密钥用的 tag 的字节数。
+pub const KEY_CHECK_LEN: usize = 16;
+
+/// 设置了 [`FLAG_RETENTION`] 的话，[`Builder::retention`](crate::Builder::retention) 的保留期限
+/// （`u64`，小端序，纳秒）在文件中的位置。
+pub const RETENTION_POS: usize = KEY_CHECK_POS + KEY_CHECK_LEN;
+
+/// 设置了 [`FLAG_MONOTONIC`] 的话，写锚点时的开机 id 在文件中的位置，版本 13 开始才有。
+/// Linux 上是 `/proc/sys/kernel/random/boot_id` 的 16 个字节，读不到的话是 0，这时认不出重启。
+pub const BOOT_ID_POS: usize = RETENTION_POS + 8;
+
+/// 开机 id 的字节数。
+pub const BOOT_ID_LEN: usize = 16;
+
+/// 设置了 [`FLAG_MONOTONIC`] 的话，这次开机以来时间戳加上的偏移（`u64`，小端序，纳秒）
+/// 在文件中的位置，版本 13 开始才有，见 [`Anchor::boot_start`]。
+pub const BOOT_POS: usize = BOOT_ID_POS + BOOT_ID_LEN;
+
+/// 书签表里槽的个数，版本 12 开始才有书签表。
+pub const BOOKMARK_SLOTS: usize = 8;
+
+/// 书签表里每个槽的长度：书签名的哈希和位置，都是 `u64`。
+pub const BOOKMARK_SIZE: usize = 16;
+
+/// 加密的记录开头 nonce 的字节数：salt 加上 `u64` 的序号。
+pub const SEAL_NONCE: usize = SALT_LEN + 8;
+
+/// 加密的记录末尾 tag 的字节数。
+pub const SEAL_TAG: usize = 16;
+
+/// 元数据的长度，后面没用到的部分填 0，留给以后。没有对齐到页的话缓冲区从它后面开始。
+pub const META_SIZE: usize = 256;
+
+const _: () = assert!(BOOT_POS + 8 <= META_POS + META_SIZE);
+
+/// 通道表的起始位置，紧跟在元数据后面，每个通道占 [`CHANNEL_ENTRY_SIZE`] 字节。
+pub const CHANNEL_TABLE_POS: usize = META_POS + META_SIZE;
+
+/// 通道表里每一项的长度：名字（UTF-8，后面填 0）、在缓冲区里的起始位置和长度（`u64`，小端序）、
+/// 写指针（`usize`，本机字节序）和翻转次数（`u64`，本机字节序）。
+pub const CHANNEL_ENTRY_SIZE: usize = 64;
+
+/// 通道名最多占的字节数。
+pub const CHANNEL_NAME_LEN: usize = 32;
+
+/// 一个文件里最多有几个通道。
+pub const MAX_CHANNELS: usize = 16;
+
+/// [`Framing::LengthPrefixed`] 时每条记录前面长度的字节数。
+pub const FRAME_PREFIX: usize = mem::size_of::<u32>();
+
+/// 设置了 [`FLAG_CHECKSUM`] 时每条记录后面校验和的字节数。
+pub const FRAME_CHECKSUM: usize = mem::size_of::<u32>();
+
+/// [`Builder::banner`](crate::Builder::banner) 写的启动标记的开头，整条消息是
+/// `==== mmlog start pid=<pid> exe=<exe> <内容> ====`，target 是 `mmlog`，级别是 Info。
+pub const BANNER_START: &str = "==== mmlog start ";
+
+/// [`Builder::banner`](crate::Builder::banner) 在 logger 被 drop 时写的结束标记，就是整条消息。
+pub const BANNER_SHUTDOWN: &str = "==== mmlog clean shutdown ====";
+
+/// 记录内容的 CRC32C（Castagnoli 多项式，和 iSCSI、ext4 的一样），写在每条记录后面，
+/// 见 [`FLAG_CHECKSUM`]。打开 `hw-crc` feature 的话在支持 SSE4.2 的 x86_64 上用硬件指令计算。
+///
+/// ```
+/// assert_eq!(mmlog::format::checksum(b"123456789"), 0xe306_9283);
+/// assert_eq!(mmlog::format::checksum(b""), 0);
+/// ```
+pub fn checksum(data: &[u8]) -> u32 {
+    crate::crc::crc32c(data)
+}
+
+// 设置了 FLAG_CHECKSUM 的话每条记录后面多几个字节
+pub(crate) fn frame_trailer(checksum: bool) -> usize {
+    if checksum {
+        FRAME_CHECKSUM
+    } else {
+        0
+    }
+}
+
+/// 缓冲区里记录之间怎么分隔。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// 每条记录以换行结尾（默认），可以直接 `strings`/`grep` 文件，
+    /// 但包含换行的消息读出来会被拆成几条。
+    #[default]
+    Text,
+    /// 每条记录前面是 `u32` 长度，消息里有换行也能准确拆分。
+    LengthPrefixed,
+}
+
+impl Framing {
+    pub(crate) fn from_flags(flags: u32) -> Framing {
+        if flags & FLAG_LENGTH_PREFIXED != 0 {
+            Framing::LengthPrefixed
+        } else {
+            Framing::Text
+        }
+    }
+
+    pub(crate) fn flags(self) -> u32 {
+        match self {
+            Framing::Text => 0,
+            Framing::LengthPrefixed => FLAG_LENGTH_PREFIXED,
+        }
+    }
+}
+
+/// 逐条返回 `data` 里的记录，见 [`records`]。
+pub(crate) struct Records<'a> {
+    data: &'a [u8],
+    framing: Framing,
+    frames: Frames<'a>,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        match self.framing {
+            Framing::Text => {
+                if self.data.is_empty() {
+                    return None;
+                }
+                let end = match self.data.iter().position(|&b| b == b'\n') {
+                    Some(i) => i + 1,
+                    None => self.data.len(),
+                };
+                let (record, rest) = self.data.split_at(end);
+                self.data = rest;
+                Some(record)
+            }
+            // 校验和不对的记录跳过
+            Framing::LengthPrefixed => self.frames.find_map(|(_, record, ok)| ok.then_some(record)),
+        }
+    }
+}
+
+/// 把一段从记录开头开始的数据拆成一条条记录。
+///
+/// 文本格式的记录带着结尾的换行；长度前缀格式遇到填充（或者不完整的记录）就结束，
+/// 校验和不对的记录被跳过。
+pub(crate) fn records(data: &[u8], framing: Framing, checksum: bool) -> Records<'_> {
+    Records {
+        data,
+        framing,
+        frames: frames(data, checksum),
+    }
+}
+
+/// 逐条返回长度前缀格式的记录，见 [`frames`]。
+pub(crate) struct Frames<'a> {
+    data: &'a [u8],
+    pos: usize,
+    checksum: bool,
+}
+
+impl<'a> Iterator for Frames<'a> {
+    /// 记录的长度前缀在 `data` 里的位置、记录内容和校验和对不对。
+    type Item = (usize, &'a [u8], bool);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = &self.data[self.pos..];
+        let len = frame_len(data, self.checksum)?;
+        let record = &data[FRAME_PREFIX..FRAME_PREFIX + len];
+        let ok = !self.checksum || {
+            let mut crc = [0; FRAME_CHECKSUM];
+            crc.copy_from_slice(&data[FRAME_PREFIX + len..FRAME_PREFIX + len + FRAME_CHECKSUM]);
+            u32::from_le_bytes(crc) == checksum(record)
+        };
+        let pos = self.pos;
+        self.pos += FRAME_PREFIX + len + frame_trailer(self.checksum);
+        Some((pos, record, ok))
+    }
+}
+
+/// 把一段从记录开头开始的长度前缀格式的数据拆成一条条记录，遇到填充（或者不完整的记录）就结束。
+///
+/// 长度对得上、校验和对不上的记录照样返回，调用者决定怎么报告。
+pub(crate) fn frames(data: &[u8], checksum: bool) -> Frames<'_> {
+    Frames {
+        data,
+        pos: 0,
+        checksum,
+    }
+}
+
+/// `data` 是长度前缀格式的缓冲区里从记录开头到 offset 的一段，返回最后一条完整记录的结尾。
+///
+/// 掉电时 header 里的 offset 可能先于记录本身落盘：最后一条记录的长度前缀还是 0、
+/// 长度走不到 offset 或者校验和不对的话，它没有写完，不算数。中间的坏记录不管。
+pub(crate) fn committed(data: &[u8], checksum: bool) -> usize {
+    let mut end = 0;
+    for (pos, record, ok) in frames(data, checksum) {
+        if !ok && pos + FRAME_PREFIX + record.len() + frame_trailer(checksum) == data.len() {
+            return pos;
+        }
+        end = pos + FRAME_PREFIX + record.len() + frame_trailer(checksum);
+    }
+    end
+}
+
+/// 按时间顺序逐条返回（较旧的，较新的）两段数据里的记录，两段都从一条完整的记录开始。
+///
+/// 文本格式的记录可能跨过翻转处，这一条会被拼起来。
+pub(crate) fn joined_records<'a>(
+    old: &'a [u8],
+    new: &'a [u8],
+    framing: Framing,
+    checksum: bool,
+) -> impl Iterator<Item = Cow<'a, [u8]>> {
+    let (old, seam, new) = match framing {
+        Framing::Text if !old.is_empty() && !old.ends_with(b"\n") => {
+            let cut = old.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+            let rest = new
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(new.len(), |i| i + 1);
+            let seam = [&old[cut..], &new[..rest]].concat();
+            (&old[..cut], Some(seam), &new[rest..])
+        }
+        _ => (old, None, new),
+    };
+    records(old, framing, checksum)
+        .map(Cow::Borrowed)
+        .chain(seam.map(Cow::Owned))
+        .chain(records(new, framing, checksum).map(Cow::Borrowed))
+}
+
+/// [`joined_records`] 里最后 `n` 条记录在两段数据里的后缀，写锁里只需要复制这么多。
+///
+/// `old` 是 offset 之后的原始数据，开头可能是被覆盖了一半的记录。`new` 里不够 `n` 条、
+/// 又没法从后往前找到记录开头（长度前缀格式）的话原样返回 `old`，第三个值为真，
+/// 之后还要 [`skip_partial`](crate::mapping::skip_partial)。
+pub(crate) fn tail<'a>(
+    old: &'a [u8],
+    new: &'a [u8],
+    n: usize,
+    framing: Framing,
+    checksum: bool,
+) -> (&'a [u8], &'a [u8], bool) {
+    match framing {
+        Framing::Text => {
+            // 最后一个换行是最后一条记录的结尾，没有的话结尾的半行也算一条
+            let last = new.last().or(old.last());
+            let need = n.saturating_add((last == Some(&b'\n')) as usize);
+            let mut seen = 0;
+            for (i, &b) in new.iter().enumerate().rev() {
+                if b == b'\n' {
+                    seen += 1;
+                    if seen == need {
+                        return (&old[..0], &new[i + 1..], false);
+                    }
+                }
+            }
+            for (i, &b) in old.iter().enumerate().rev() {
+                if b == b'\n' {
+                    seen += 1;
+                    if seen == need {
+                        return (&old[i + 1..], new, false);
+                    }
+                }
+            }
+            (old, new, true)
+        }
+        Framing::LengthPrefixed => {
+            // new 从一条记录开始，只能从前往后跳
+            let starts: Vec<usize> = frames(new, checksum).map(|(p, _, _)| p).collect();
+            if starts.len() >= n {
+                return (&old[..0], &new[starts[starts.len() - n]..], false);
+            }
+            (old, new, true)
+        }
+    }
+}
+
+// 开头那条记录的长度（不含前缀和校验和），遇到填充或者不完整的记录返回 None。不检查校验和
+fn frame_len(data: &[u8], checksum: bool) -> Option<usize> {
+    let mut len = [0; FRAME_PREFIX];
+    len.copy_from_slice(data.get(..FRAME_PREFIX)?);
+    let len = u32::from_le_bytes(len) as usize;
+    let room = (data.len() - FRAME_PREFIX).checked_sub(frame_trailer(checksum))?;
+    if len == 0 || len > room {
+        return None;
+    }
+    Some(len)
+}
+
+/// 在长度前缀格式的一段数据里找第一条完整记录的位置。
+///
+/// `data` 的开头可能是被覆盖了一半的记录，但结尾一定是一条记录（或者填充）的结尾，
+/// 所以从结尾往前推算：某个位置能一条条记录地正好走到结尾（或者填充），就可能是记录的开头。
+///
+/// 上一条记录结尾的换行加上长度的低位有时也能凑出一个合法的长度，跳过一大段之后
+/// 碰巧落在某条记录的开头。这样的链条会漏掉中间的记录，所以选记录最多的那个位置。
+pub(crate) fn frame_start(data: &[u8], checksum: bool) -> usize {
+    chain_start(data, checksum, false)
+}
+
+/// 和 [`frame_start`] 一样，但 `data` 的结尾可能断在一条记录中间（文件被截断了）：
+/// 走到一条超出结尾的记录也算走到了结尾。
+pub(crate) fn frame_start_cut(data: &[u8], checksum: bool) -> usize {
+    chain_start(data, checksum, true)
+}
+
+fn chain_start(data: &[u8], checksum: bool, cut: bool) -> usize {
+    // chain[p]：从 p 开始能正好走到结尾的话，一共有几条记录
+    let mut chain: Vec<Option<usize>> = vec![None; data.len() + 1];
+    for p in (0..=data.len()).rev() {
+        chain[p] = match frame_len(&data[p..], checksum) {
+            Some(len) => chain[p + FRAME_PREFIX + len + frame_trailer(checksum)].map(|n| n + 1),
+            // 结尾、填充或者剩下不到 4 个字节
+            None if cut || data[p..].iter().take(FRAME_PREFIX).all(|&b| b == 0) => Some(0),
+            None => None,
+        };
+    }
+    // 一样多的时候取靠后的：假的链条只可能从真正的开头前面开始
+    chain
+        .iter()
+        .enumerate()
+        .filter_map(|(p, n)| n.map(|n| (n, p)))
+        .max()
+        .map_or(data.len(), |(_, p)| p)
+}
+
+/// 记录前缀里各个 level 的写法。
+pub fn level_info(l: Level) -> &'static str {
+    match l {
+        Level::Error => "E",
+        Level::Warn => "W",
+        Level::Info => "I",
+        Level::Debug => "D",
+        Level::Trace => "T",
+    }
+}
+
+/// 记录的时间戳从哪个时钟读，见 [`Builder::clock`](crate::Builder::clock)。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Clock {
+    /// 墙上时间，UNIX 纪元以来的时长（默认）。
+    #[default]
+    Realtime,
+    /// `CLOCK_MONOTONIC`，不会因为校时往回跳。读取时按文件里的 [`Anchor`] 换算成墙上时间。
+    ///
+    /// 关掉 `mmap` feature（不依赖 libc）时是这个进程里第一次读时钟以来的时长，
+    /// 每个进程都当作重新开机，之前的进程写的记录换算不了墙上时间，见 [`Anchor::boot_start`]。
+    Monotonic,
+}
+
+/// [`Clock::Monotonic`] 的文件里 `build()`/`open()` 时同时读的墙上时间和单调时钟，
+/// 见 [`LogReader::anchor`](crate::reader::LogReader::anchor)。
+///
+/// 单调时钟在重启之后从头开始，文件里只有这次开机的锚点。之前开机时写的记录的时间戳都早于
+/// `boot_start`（见 [`BOOT_POS`]），换算不了墙上时间：[`LogReader::normalize`](crate::reader::LogReader::normalize)
+/// 返回 `None`，[`LogReader::range`](crate::reader::LogReader::range) 不返回它们，
+/// [`Builder::retention`](crate::Builder::retention) 的截止时间早于这次开机的话留着它们。
+///
+/// ```
+/// use mmlog::format::Anchor;
+/// use std::time::Duration;
+///
+/// let anchor = Anchor {
+///     realtime: Duration::from_secs(1_700_000_000),
+///     monotonic: Duration::from_secs(100),
+///     boot_start: Duration::from_secs(20),
+/// };
+/// assert_eq!(anchor.to_realtime(Duration::from_secs(130)), Duration::from_secs(1_700_000_030));
+/// assert_eq!(anchor.to_realtime(Duration::from_secs(40)), Duration::from_secs(1_699_999_940));
+/// assert_eq!(anchor.to_monotonic(Duration::from_secs(1_700_000_030)), Duration::from_secs(130));
+/// assert!(anchor.same_boot(Duration::from_secs(40)));
+/// assert!(!anchor.same_boot(Duration::from_secs(10)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    /// UNIX 纪元以来的时长。
+    pub realtime: Duration,
+    /// 同一时刻单调时钟的读数，加上了 [`BOOT_POS`] 处的偏移。
+    pub monotonic: Duration,
+    /// 这次开机以来的时间戳都不早于它，就是 [`BOOT_POS`] 处的偏移。
+    pub boot_start: Duration,
+}
+
+impl Anchor {
+    // 现在的两个时钟，单调时钟加上 boot_start，墙上时间早于 UNIX 纪元的话是 0
+    pub(crate) fn now(boot_start: Duration) -> Anchor {
+        Anchor {
+            realtime: now(Clock::Realtime).unwrap_or_default(),
+            monotonic: monotonic() + boot_start,
+            boot_start,
+        }
+    }
+
+    /// 单调时钟的读数是不是这次开机以来的，不是的话换算不了墙上时间。
+    pub fn same_boot(&self, monotonic: Duration) -> bool {
+        monotonic >= self.boot_start
+    }
+
+    /// 把单调时钟的读数换算成 UNIX 纪元以来的时长。
+    pub fn to_realtime(&self, monotonic: Duration) -> Duration {
+        match monotonic.checked_sub(self.monotonic) {
+            Some(after) => self.realtime + after,
+            None => self.realtime.saturating_sub(self.monotonic - monotonic),
+        }
+    }
+
+    /// 把单调时钟的读数换算成 UNIX 纪元以来的纳秒数。和 [`Anchor::to_realtime`] 一样，
+    /// 只是不在纪元处截断，早于纪元的话是负数。
+    ///
+    /// ```
+    /// use mmlog::format::Anchor;
+    /// use std::time::Duration;
+    ///
+    /// let anchor = Anchor {
+    ///     realtime: Duration::from_secs(10),
+    ///     monotonic: Duration::from_secs(100),
+    ///     boot_start: Duration::ZERO,
+    /// };
+    /// assert_eq!(anchor.to_nanos(Duration::from_secs(130)), 40_000_000_000);
+    /// assert_eq!(anchor.to_nanos(Duration::from_secs(85)), -5_000_000_000);
+    /// ```
+    pub fn to_nanos(&self, monotonic: Duration) -> i128 {
+        self.realtime.as_nanos() as i128 + monotonic.as_nanos() as i128
+            - self.monotonic.as_nanos() as i128
+    }
+
+    // 墙上时间是 0：header 里没有写锚点，或者写的时候墙上时间早于 UNIX 纪元，换算不了
+    pub(crate) fn is_missing(&self) -> bool {
+        self.realtime.is_zero()
+    }
+
+    /// 反过来，把 UNIX 纪元以来的时长换算成单调时钟的读数。
+    pub fn to_monotonic(&self, realtime: Duration) -> Duration {
+        match realtime.checked_sub(self.realtime) {
+            Some(after) => self.monotonic + after,
+            None => self.monotonic.saturating_sub(self.realtime - realtime),
+        }
+    }
+}
+
+/// 记录前缀里时间戳的写法。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampFormat {
+    /// `1700000000.123456789s`，UNIX 纪元以来的秒数（默认）。
+    #[default]
+    UnixSecondsNanos,
+    /// `2023-11-14T22:13:20.123456789Z`，UTC 时间。
+    Rfc3339Utc,
+    /// `2023-11-15T06:13:20.123456789+08:00`，本地时间。
+    ///
+    /// 时区偏移来自 `localtime_r`，关掉 `mmap` feature（不依赖 libc）时退化成 UTC。
+    LocalTime,
+}
+
+/// 记录的写法，见 [`Builder::format_kind`](crate::Builder::format_kind)，各自的字段见模块文档。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FormatKind {
+    /// `[...]` 前缀加消息（默认）。
+    #[default]
+    Text,
+    /// 一行 JSON（JSON Lines）。
+    Json,
+    /// 一行 logfmt：`ts=... tid=... level=info target=app msg="..."`。
+    Logfmt,
+}
+
+// 同一秒内的记录共用格式化好的日期部分
+struct Cached {
+    secs: u64,
+    format: TimestampFormat,
+    // `2023-11-14T22:13:20`
+    date: String,
+    // `Z` 或者 `+08:00`
+    zone: String,
+}
+
+thread_local! {
+    static CACHED: RefCell<Option<Cached>> = const { RefCell::new(None) };
+}
+
+fn write_timestamp<W: Write>(out: &mut W, now: Duration, format: TimestampFormat) -> fmt::Result {
+    if format == TimestampFormat::UnixSecondsNanos {
+        // 不到一秒的话 Debug 会写成 `324.968µs`，单调时钟刚开始计时的时候会有
+        if now.as_secs() == 0 {
+            return write!(out, "0.{:09}s", now.subsec_nanos());
+        }
+        return write!(out, "{:?}", now);
+    }
+    let secs = now.as_secs();
+    let written = CACHED.try_with(|cached| {
+        let mut cached = cached.borrow_mut();
+        let c = cached.get_or_insert_with(|| Cached::new(secs, format));
+        if c.secs != secs || c.format != format {
+            c.set(secs, format);
+        }
+        write!(out, "{}.{:09}{}", c.date, now.subsec_nanos(), c.zone)
+    });
+    // 线程正在退出、CACHED 已经销毁了的话每次都重新算
+    written.unwrap_or_else(|_| {
+        let c = Cached::new(secs, format);
+        write!(out, "{}.{:09}{}", c.date, now.subsec_nanos(), c.zone)
+    })
+}
+
+impl Cached {
+    fn new(secs: u64, format: TimestampFormat) -> Cached {
+        let mut c = Cached {
+            secs,
+            format,
+            date: String::with_capacity(19),
+            zone: String::with_capacity(6),
+        };
+        c.set(secs, format);
+        c
+    }
+
+    // 换到下一秒时在原来的字符串里重写，不再分配
+    fn set(&mut self, secs: u64, format: TimestampFormat) {
+        let offset = match format {
+            TimestampFormat::LocalTime => local_offset(secs),
+            _ => 0,
+        };
+        self.secs = secs;
+        self.format = format;
+        self.date.clear();
+        civil_time(&mut self.date, secs as i64 + offset);
+        self.zone.clear();
+        zone(&mut self.zone, offset);
+    }
+}
+
+// 本地时间相对 UTC 的秒数
+#[cfg(all(feature = "mmap", unix))]
+fn local_offset(secs: u64) -> i64 {
+    unsafe {
+        let t = secs as libc::time_t;
+        let mut tm: libc::tm = mem::zeroed();
+        if libc::localtime_r(&t, &mut tm).is_null() {
+            return 0;
+        }
+        tm.tm_gmtoff as i64
+    }
+}
+
+#[cfg(not(all(feature = "mmap", unix)))]
+fn local_offset(_secs: u64) -> i64 {
+    0
+}
+
+fn zone(out: &mut String, offset: i64) {
+    if offset == 0 {
+        out.push('Z');
+        return;
+    }
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    let _ = write!(out, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60);
+}
+
+// 纪元以来的秒数 -> `YYYY-MM-DDTHH:MM:SS`
+fn civil_time(out: &mut String, secs: i64) {
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (y, m, d) = civil_from_days(days);
+    let _ = write!(
+        out,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        y,
+        m,
+        d,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    );
+}
+
+// 见 http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400;
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// 把前缀字段（target、文件名、线程名）里可能伪造前缀的字符转义掉：
+/// `]` 和控制字符写成 `\xNN`，ANSI 转义序列整个去掉。后面跟着 `x` 的 `\` 也写成 `\x5c`，
+/// 这样字段里本来就有的 `\xNN` 不会被 [`unsanitize`] 当成转义。
+///
+/// ```
+/// use mmlog::format::{sanitize, unsanitize};
+///
+/// assert_eq!(sanitize("app::net"), "app::net");
+/// assert_eq!(sanitize("a] b\n"), "a\\x5d b\\x0a");
+/// assert_eq!(sanitize("\x1b[31mred\x1b[0m"), "red");
+/// for field in ["a] b\n", r"C:\src\main.rs", r"\x5d", "好]\t"] {
+///     assert_eq!(unsanitize(&sanitize(field)), field);
+/// }
+/// ```
+pub fn sanitize(field: &str) -> Cow<'_, str> {
+    escape(field, false)
+}
+
+// sanitize()，spaces 的话空格也写成 `\x20`：target 是前缀的最后一个字段，靠空格和文件名分开
+pub(crate) fn escape(field: &str, spaces: bool) -> Cow<'_, str> {
+    let needs_escape = |c: char, next: Option<&char>| {
+        c == ']' || c.is_control() || (spaces && c == ' ') || (c == '\\' && next == Some(&'x'))
+    };
+    let mut chars = field.chars().peekable();
+    let clean = loop {
+        match chars.next() {
+            Some(c) if needs_escape(c, chars.peek()) => break false,
+            Some(_) => {}
+            None => break true,
+        }
+    };
+    if clean {
+        return Cow::Borrowed(field);
+    }
+    let mut out = String::with_capacity(field.len() + 8);
+    let mut chars = field.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                // CSI 序列：ESC [ 参数... 结束字节（0x40..=0x7e）
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                } else {
+                    chars.next();
+                }
+            }
+            c if needs_escape(c, chars.peek()) => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// 还原 [`sanitize`] 写成 `\xNN` 的字符，去掉的 ANSI 转义序列找不回来。
+pub fn unsanitize(field: &str) -> Cow<'_, str> {
+    if !field.contains("\\x") {
+        return Cow::Borrowed(field);
+    }
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(at) = rest.find("\\x") {
+        out.push_str(&rest[..at]);
+        let code = rest
+            .get(at + 2..at + 4)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match code {
+            Some(code) => {
+                out.push(char::from(code));
+                rest = &rest[at + 4..];
+            }
+            None => {
+                out.push_str("\\x");
+                rest = &rest[at + 2..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+/// 按 `kind` 的格式生成一条记录，参数的 `Display` 实现出错时返回 `None`。
+pub(crate) fn record(
+    record: &Record,
+    sanitize: bool,
+    kind: FormatKind,
+    ts: TimestampFormat,
+    thread_names: bool,
+    now: Duration,
+) -> Option<String> {
+    let mut msg = String::new();
+    write_record(&mut msg, record, sanitize, kind, ts, thread_names, now).ok()?;
+    if !msg.ends_with('\n') {
+        msg += "\n";
+    }
+    Some(msg)
+}
+
+/// 记录时间戳用的当前时间，墙上时间早于 UNIX 纪元时返回 `None`，单调时钟总有。
+/// [`Builder::retention`](crate::Builder::retention) 的过滤：跳过时间戳早于 `now - retention`
+/// 的记录，读不出时间戳的行（消息里的换行拆出来的）跟随前一条记录的去留。
+pub(crate) struct Expiry {
+    cutoff: Option<Duration>,
+    keep: bool,
+}
+
+impl Expiry {
+    // anchor 是单调时钟的文件的锚点：截止时间换算成单调时钟的读数再和记录比，
+    // 文件拿到重启过的机器上读也是对的。之前开机时写的记录换算不了，
+    // 截止时间早于这次开机的话不知道过没过期，都留着；晚于的话肯定过期了
+    pub(crate) fn new(retention: Option<Duration>, anchor: Option<Anchor>) -> Expiry {
+        let cutoff = retention.map(|retention| {
+            let cutoff = now(Clock::Realtime)
+                .unwrap_or_default()
+                .saturating_sub(retention);
+            match anchor {
+                Some(anchor) if !anchor.same_boot(anchor.to_monotonic(cutoff)) => Duration::ZERO,
+                Some(anchor) => anchor.to_monotonic(cutoff),
+                None => cutoff,
+            }
+        });
+        Expiry {
+            cutoff,
+            // 缓冲区开头的续行不知道属于哪条记录，不要
+            keep: cutoff.is_none(),
+        }
+    }
+
+    /// 接着已经读过的记录往下读：开头的续行属于上一次读到的最后一条，保留。
+    #[cfg_attr(not(all(feature = "mmap", unix)), allow(dead_code))]
+    pub(crate) fn resumed(mut self) -> Expiry {
+        self.keep = true;
+        self
+    }
+
+    pub(crate) fn keep(&mut self, line: &[u8]) -> bool {
+        if let Some(cutoff) = self.cutoff {
+            if let Some(ts) = timestamp(line) {
+                self.keep = ts >= cutoff;
+            }
+        }
+        self.keep
+    }
+}
+
+pub(crate) fn now(clock: Clock) -> Option<Duration> {
+    #[cfg(feature = "test-util")]
+    if let Some(now) = crate::test::frozen_now() {
+        return Some(now);
+    }
+    match clock {
+        Clock::Realtime => SystemTime::UNIX_EPOCH.elapsed().ok(),
+        Clock::Monotonic => Some(monotonic()),
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+fn monotonic() -> Duration {
+    crate::sys::monotonic()
+}
+
+// 没有 libc 的话从这个进程里第一次读时钟开始算
+#[cfg(not(all(feature = "mmap", unix)))]
+fn monotonic() -> Duration {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static BASE: OnceLock<Instant> = OnceLock::new();
+    BASE.get_or_init(Instant::now).elapsed()
+}
+
+/// 现在的开机 id，见 [`BOOT_ID_POS`]。
+#[cfg(all(feature = "mmap", unix))]
+pub(crate) fn boot_id() -> [u8; BOOT_ID_LEN] {
+    use std::sync::OnceLock;
+
+    // 形如 6a0c4e1f-...，读不到的话（不是 Linux）是 0
+    fn read() -> Option<[u8; BOOT_ID_LEN]> {
+        let text = std::fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+        let hex: Vec<u8> = text.bytes().filter(u8::is_ascii_hexdigit).collect();
+        if hex.len() != 2 * BOOT_ID_LEN {
+            return None;
+        }
+        let mut id = [0; BOOT_ID_LEN];
+        for (byte, pair) in id.iter_mut().zip(hex.chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(id)
+    }
+
+    static ID: OnceLock<[u8; BOOT_ID_LEN]> = OnceLock::new();
+    *ID.get_or_init(|| read().unwrap_or_default())
+}
+
+// 没有 libc 的话单调时钟是这个进程里的，见 monotonic()，每个进程都算一次开机
+#[cfg(not(all(feature = "mmap", unix)))]
+pub(crate) fn boot_id() -> [u8; BOOT_ID_LEN] {
+    use std::sync::OnceLock;
+
+    static ID: OnceLock<[u8; BOOT_ID_LEN]> = OnceLock::new();
+    *ID.get_or_init(crate::crypt::salt)
+}
+
+/// 按 `kind` 的格式写出一条记录，不补结尾的换行。
+pub(crate) fn write_record<W: Write>(
+    w: &mut W,
+    record: &Record,
+    sanitize: bool,
+    kind: FormatKind,
+    ts: TimestampFormat,
+    thread_names: bool,
+    now: Duration,
+) -> fmt::Result {
+    match kind {
+        FormatKind::Text => {}
+        FormatKind::Json => return write_json(w, record, ts, thread_names, now),
+        FormatKind::Logfmt => return write_logfmt(w, record, ts, thread_names, now),
+    }
+    let field = |s| {
+        if sanitize {
+            self::sanitize(s)
+        } else {
+            Cow::Borrowed(s)
+        }
+    };
+    w.write_char('[')?;
+    write_timestamp(w, now, ts)?;
+    write!(w, " {}", crate::tid())?;
+    if thread_names {
+        crate::with_thread_name(|name| match name {
+            Some(name) if sanitize => write!(w, "/{}", self::sanitize(name)),
+            Some(name) => write!(w, "/{}", name),
+            None => Ok(()),
+        })?;
+    }
+    write!(w, " {} ", level_info(record.level()))?;
+    if let (Some(file), Some(line)) = (record.file(), record.line()) {
+        write!(w, "{}:{}", field(file), line)?;
+    }
+    let target = if sanitize {
+        escape(record.target(), true)
+    } else {
+        Cow::Borrowed(record.target())
+    };
+    write!(w, " {}] {}", target, record.args())?;
+    write_kv(w, record, false)
+}
+
+// 一行 JSON，字段见模块文档。JSON 转义之后不需要 sanitize
+fn write_json<W: Write>(
+    w: &mut W,
+    record: &Record,
+    ts: TimestampFormat,
+    thread_names: bool,
+    now: Duration,
+) -> fmt::Result {
+    w.write_str("{\"ts\":\"")?;
+    write_timestamp(w, now, ts)?;
+    write!(w, "\",\"tid\":{}", crate::tid())?;
+    if thread_names {
+        crate::with_thread_name(|name| match name {
+            Some(name) => {
+                w.write_str(",\"thread\":")?;
+                json_string(w, name)
+            }
+            None => Ok(()),
+        })?;
+    }
+    write!(w, ",\"level\":\"{}\",\"target\":", record.level().as_str())?;
+    json_string(w, record.target())?;
+    w.write_str(",\"file\":")?;
+    match record.file() {
+        Some(file) => json_string(w, file)?,
+        None => w.write_str("null")?,
+    }
+    match record.line() {
+        Some(line) => write!(w, ",\"line\":{}", line)?,
+        None => w.write_str(",\"line\":null")?,
+    }
+    w.write_str(",\"msg\":\"")?;
+    // 不经过 String，边格式化边转义
+    write!(JsonEscape(&mut *w), "{}", record.args())?;
+    w.write_char('"')?;
+    write_kv(w, record, true)?;
+    w.write_char('}')
+}
+
+// 一行 logfmt，字段见模块文档。键和值需要的话加引号转义，也不需要 sanitize
+fn write_logfmt<W: Write>(
+    w: &mut W,
+    record: &Record,
+    ts: TimestampFormat,
+    thread_names: bool,
+    now: Duration,
+) -> fmt::Result {
+    w.write_str("ts=")?;
+    write_timestamp(w, now, ts)?;
+    write!(w, " tid={}", crate::tid())?;
+    if thread_names {
+        crate::with_thread_name(|name| match name {
+            Some(name) => {
+                w.write_str(" thread=")?;
+                logfmt(w, name)
+            }
+            None => Ok(()),
+        })?;
+    }
+    write!(w, " level={} target=", level_name(record.level()))?;
+    logfmt(w, record.target())?;
+    if let Some(file) = record.file() {
+        w.write_str(" file=")?;
+        logfmt(w, file)?;
+    }
+    if let Some(line) = record.line() {
+        write!(w, " line={}", line)?;
+    }
+    // 消息总是加引号，边格式化边转义，不用先看一遍内容
+    w.write_str(" msg=\"")?;
+    write!(JsonEscape(&mut *w), "{}", record.args())?;
+    w.write_char('"')?;
+    write_kv(w, record, false)
+}
+
+// logfmt 里的 level
+fn level_name(l: Level) -> &'static str {
+    match l {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+// log::kv 的键值对，写法见模块文档
+#[cfg(feature = "kv")]
+fn write_kv<W: Write>(w: &mut W, record: &Record, json: bool) -> fmt::Result {
+    use log::kv::{self, Key, Value, VisitSource};
+
+    struct Visitor<'a, W> {
+        w: &'a mut W,
+        json: bool,
+    }
+
+    impl<'kvs, W: Write> VisitSource<'kvs> for Visitor<'_, W> {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            if self.json {
+                self.w.write_char(',')?;
+                json_string(self.w, key.as_str())?;
+                self.w.write_str(":\"")?;
+                write!(JsonEscape(&mut *self.w), "{}", value)?;
+                self.w.write_char('"')?;
+            } else {
+                self.w.write_char(' ')?;
+                logfmt(self.w, key.as_str())?;
+                self.w.write_char('=')?;
+                logfmt(self.w, &value.to_string())?;
+            }
+            Ok(())
+        }
+    }
+
+    record
+        .key_values()
+        .visit(&mut Visitor { w, json })
+        .map_err(|_| fmt::Error)
+}
+
+#[cfg(not(feature = "kv"))]
+fn write_kv<W: Write>(_w: &mut W, _record: &Record, _json: bool) -> fmt::Result {
+    Ok(())
+}
+
+// 文本和 logfmt 格式里的键或值：需要的话加上引号转义，保证能按空格和 `=` 拆开
+fn logfmt<W: Write>(w: &mut W, s: &str) -> fmt::Result {
+    let plain = !s.is_empty()
+        && !s
+            .chars()
+            .any(|c| matches!(c, ' ' | '=' | '"' | '\\') || c.is_control());
+    if plain {
+        w.write_str(s)
+    } else {
+        json_string(w, s)
+    }
+}
+
+fn json_string<W: Write>(w: &mut W, s: &str) -> fmt::Result {
+    w.write_char('"')?;
+    JsonEscape(&mut *w).write_str(s)?;
+    w.write_char('"')
+}
+
+// 把写进来的内容按 JSON 字符串的规则转义
+struct JsonEscape<'a, W>(&'a mut W);
+
+impl<W: Write> Write for JsonEscape<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // 不用转义的部分整段写出
+        let mut start = 0;
+        for (i, b) in s.bytes().enumerate() {
+            let escaped = match b {
+                b'"' => "\\\"",
+                b'\\' => "\\\\",
+                b'\n' => "\\n",
+                b'\r' => "\\r",
+                b'\t' => "\\t",
+                0..=0x1f | 0x7f => "",
+                _ => continue,
+            };
+            self.0.write_str(&s[start..i])?;
+            if escaped.is_empty() {
+                write!(self.0, "\\u{:04x}", b)?;
+            } else {
+                self.0.write_str(escaped)?;
+            }
+            start = i + 1;
+        }
+        self.0.write_str(&s[start..])
+    }
+}
+
+// Builder::escape_newlines：`\` 写成 `\\`，换行和回车写成 `\n` 和 `\r`。
+// 最后一个换行是记录的结尾，先扣着，调用者最后总是补一个换行
+pub(crate) struct NewlineEscape<'a, W> {
+    w: &'a mut W,
+    newline: bool,
+}
+
+impl<'a, W: Write> NewlineEscape<'a, W> {
+    pub(crate) fn new(w: &'a mut W) -> Self {
+        NewlineEscape { w, newline: false }
+    }
+}
+
+impl<W: Write> Write for NewlineEscape<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.is_empty() {
+            return Ok(());
+        }
+        if mem::take(&mut self.newline) {
+            self.w.write_str("\\n")?;
+        }
+        let mut start = 0;
+        for (i, b) in s.bytes().enumerate() {
+            let escaped = match b {
+                b'\\' => "\\\\",
+                b'\n' if i + 1 == s.len() => {
+                    self.newline = true;
+                    ""
+                }
+                b'\n' => "\\n",
+                b'\r' => "\\r",
+                _ => continue,
+            };
+            self.w.write_str(&s[start..i])?;
+            self.w.write_str(escaped)?;
+            start = i + 1;
+        }
+        self.w.write_str(&s[start..])
+    }
+}
+
+/// 转义已经格式化好的一条记录（带着结尾的换行），不需要转义的话原样返回，不分配内存。
+pub(crate) fn escape_newlines(msg: String) -> String {
+    let body = msg.strip_suffix('\n').unwrap_or(&msg);
+    if !body.contains(['\\', '\n', '\r']) {
+        return msg;
+    }
+    let mut out = String::with_capacity(msg.len() + 16);
+    // 结尾的换行被扣着，只补一个
+    let _ = NewlineEscape::new(&mut out).write_str(&msg);
+    out.push('\n');
+    out
+}
+
+/// 还原 [`FLAG_ESCAPED_NEWLINES`] 的文件里的一条记录：`\\`、`\n` 和 `\r` 换回 `\`、换行和回车，
+/// 其他的 `\` 原样保留。[`LogReader`](crate::reader::LogReader) 已经还原过了，
+/// 这个函数用在 `grep` 之类直接从文件里拿到的行上。
+///
+/// ```
+/// use mmlog::format::unescape_newlines;
+///
+/// assert_eq!(unescape_newlines(r"a\nb\r\\n\x"), "a\nb\r\\n\\x");
+/// assert!(matches!(unescape_newlines("plain"), std::borrow::Cow::Borrowed(_)));
+/// ```
+pub fn unescape_newlines(record: &str) -> Cow<'_, str> {
+    if !record.contains('\\') {
+        return Cow::Borrowed(record);
+    }
+    let mut out = String::with_capacity(record.len());
+    let mut chars = record.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.clone().next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('\\') => out.push('\\'),
+            _ => {
+                out.push('\\');
+                continue;
+            }
+        }
+        chars.next();
+    }
+    Cow::Owned(out)
+}
+
+// 接着 String::from_utf8_lossy 的结果还原，不用转义的话不复制
+pub(crate) fn unescape_lossy(record: Cow<'_, str>) -> Cow<'_, str> {
+    match record {
+        Cow::Borrowed(record) => unescape_newlines(record),
+        Cow::Owned(record) => Cow::Owned(unescape_newlines(&record).into_owned()),
+    }
+}
+
+/// [`Builder::format`](crate::Builder::format) 设置的格式化函数。
+pub(crate) type FormatFn = dyn Fn(&mut dyn io::Write, &Record) -> io::Result<()> + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct Custom(pub(crate) Arc<FormatFn>);
+
+impl fmt::Debug for Custom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Custom(..)")
+    }
+}
+
+/// [`Builder::banner_with`](crate::Builder::banner_with) 设置的函数。
+pub(crate) type BannerFn = dyn Fn() -> String + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct Banner(pub(crate) Arc<BannerFn>);
+
+impl fmt::Debug for Banner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Banner(..)")
+    }
+}
+
+/// 用自定义的格式化函数生成一条记录，结尾同样补上换行。
+pub(crate) fn custom(record: &Record, custom: &Custom) -> io::Result<String> {
+    let mut buf = Vec::new();
+    (custom.0)(&mut buf, record)?;
+    let mut msg = match String::from_utf8(buf) {
+        Ok(msg) => msg,
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    };
+    if !msg.ends_with('\n') {
+        msg += "\n";
+    }
+    Ok(msg)
+}
+
+/// 解析记录开头的时间戳（UNIX 纪元以来的时长），不是记录开头的行返回 `None`。
+pub(crate) fn timestamp(line: &[u8]) -> Option<Duration> {
+    // 跳过可能有的 `#<序号> `
+    let line = match line.strip_prefix(b"#") {
+        Some(rest) => &rest[rest.iter().position(|&b| b == b' ')? + 1..],
+        None => line,
+    };
+    let (line, end) = if let Some(line) = line.strip_prefix(b"{\"ts\":\"") {
+        (line, b'"')
+    } else if let Some(line) = line.strip_prefix(b"ts=") {
+        (line, b' ')
+    } else {
+        (line.strip_prefix(b"[")?, b' ')
+    };
+    let end = line.iter().position(|&b| b == end)?;
+    parse_timestamp(std::str::from_utf8(&line[..end]).ok()?)
+}
+
+/// 解析 [`TimestampFormat`] 的任何一种写法，返回 UNIX 纪元以来的时长。
+///
+/// ```
+/// use mmlog::format::parse_timestamp;
+/// use std::time::Duration;
+///
+/// let t = Duration::new(1700000000, 100_000_000);
+/// assert_eq!(parse_timestamp("1700000000.1s"), Some(t));
+/// assert_eq!(parse_timestamp("2023-11-14T22:13:20.1Z"), Some(t));
+/// assert_eq!(parse_timestamp("2023-11-15T06:13:20.1+08:00"), Some(t));
+/// assert_eq!(parse_timestamp("yesterday"), None);
+/// ```
+pub fn parse_timestamp(ts: &str) -> Option<Duration> {
+    match ts.strip_suffix('s') {
+        Some(ts) => {
+            let (secs, nanos) = ts.split_once('.').unwrap_or((ts, ""));
+            Some(Duration::new(secs.parse().ok()?, nanos_of(nanos)?))
+        }
+        None => rfc3339(ts),
+    }
+}
+
+// 小数部分 -> 纳秒，最多 9 位
+fn nanos_of(frac: &str) -> Option<u32> {
+    if frac.is_empty() {
+        return Some(0);
+    }
+    if frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(frac.parse::<u32>().ok()? * 10u32.pow(9 - frac.len() as u32))
+}
+
+// `YYYY-MM-DDTHH:MM:SS[.frac](Z|±HH:MM)`
+fn rfc3339(ts: &str) -> Option<Duration> {
+    // 位数有限制，后面的乘法不会溢出
+    let num = |s: &str| -> Option<i64> {
+        if s.is_empty() || s.len() > 9 || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok()
+    };
+    let (date, time) = ts.split_once('T')?;
+    let mut date = date.splitn(3, '-');
+    let (y, m, d) = (num(date.next()?)?, num(date.next()?)?, num(date.next()?)?);
+
+    let (time, offset) = match time.strip_suffix('Z') {
+        Some(time) => (time, 0),
+        None => {
+            let at = time.rfind(['+', '-'])?;
+            let (h, min) = time[at + 1..].split_once(':')?;
+            let offset = num(h)? * 3600 + num(min)? * 60;
+            (
+                &time[..at],
+                if time.as_bytes()[at] == b'-' {
+                    -offset
+                } else {
+                    offset
+                },
+            )
+        }
+    };
+    let (time, frac) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':');
+    let (hh, mm, ss) = (num(time.next()?)?, num(time.next()?)?, num(time.next()?)?);
+
+    let secs = days_from_civil(y, m, d) * 86400 + hh * 3600 + mm * 60 + ss - offset;
+    Some(Duration::new(u64::try_from(secs).ok()?, nanos_of(frac)?))
+}
+
+/// 把记录截断到不超过 `max` 字节（包括截断提示和结尾的换行）。
+pub(crate) fn truncate(msg: &mut String, max: usize) {
+    let total = msg.len();
+    // 提示本身的长度取决于被截掉的字节数，多留几位
+    let room = max.saturating_sub(format!("...[truncated {} bytes]\n", total).len());
+    let mut cut = room;
+    while !msg.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    msg.truncate(cut);
+    let _ = writeln!(msg, "...[truncated {} bytes]", total - cut);
+}
@@ -0,0 +1,80 @@
+// 把 logger 自身的健康状况报告给 `metrics` 门面。
+// 没有打开 `metrics` feature 时这里全是空函数，会被完全优化掉。
+//
+// 这里绝对不能写日志，否则会递归回 Logger::log()。
+
+#[cfg(feature = "metrics")]
+mod imp {
+    use metrics::{Counter, Gauge};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // utilization 每写这么多条记录更新一次
+    const SAMPLE: u64 = 64;
+
+    #[derive(Debug)]
+    pub(crate) struct Health {
+        records: Counter,
+        bytes: Counter,
+        dropped: Counter,
+        flush_errors: Counter,
+        utilization: Gauge,
+        sampled: AtomicU64,
+    }
+
+    impl Health {
+        pub(crate) fn new(prefix: Option<&str>) -> Option<Health> {
+            let prefix = prefix?;
+            Some(Health {
+                records: metrics::counter!(format!("{}.records_written", prefix)),
+                bytes: metrics::counter!(format!("{}.bytes_written", prefix)),
+                dropped: metrics::counter!(format!("{}.dropped", prefix)),
+                flush_errors: metrics::counter!(format!("{}.flush_errors", prefix)),
+                utilization: metrics::gauge!(format!("{}.utilization", prefix)),
+                sampled: AtomicU64::new(0),
+            })
+        }
+
+        pub(crate) fn written(&self, bytes: usize, offset: usize, capacity: usize) {
+            self.records.increment(1);
+            self.bytes.increment(bytes as u64);
+            if self
+                .sampled
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(SAMPLE)
+            {
+                self.utilization.set(offset as f64 / capacity as f64);
+            }
+        }
+
+        pub(crate) fn dropped(&self) {
+            self.dropped.increment(1);
+        }
+
+        pub(crate) fn flush_error(&self) {
+            self.flush_errors.increment(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod imp {
+    #[derive(Debug)]
+    pub(crate) struct Health;
+
+    impl Health {
+        pub(crate) fn new(_prefix: Option<&str>) -> Option<Health> {
+            None
+        }
+
+        #[inline(always)]
+        pub(crate) fn written(&self, _bytes: usize, _offset: usize, _capacity: usize) {}
+
+        #[inline(always)]
+        pub(crate) fn dropped(&self) {}
+
+        #[inline(always)]
+        pub(crate) fn flush_error(&self) {}
+    }
+}
+
+pub(crate) use imp::Health;
@@ -1,23 +1,158 @@
-use log::{Level, Log, Metadata, Record};
-use std::ffi::{CStr, CString, NulError};
-use std::io::Write;
-use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::SystemTime;
-use std::{mem, ptr, slice};
+use adaptive::{Adaptive, Transition};
+use batch::{Batch, Batches};
+use health::Health;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use ratelimit::RateLimit;
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::ffi::NulError;
+use std::fs::File;
+use std::io::{self, Write as _};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use targets::{Admit, Targets};
+use tee::Tee;
 
+/// 和 `std::dbg!` 一样返回传进来的值，但是用 [`log::log!`] 按 `level` 写成一条记录，
+/// 文件名和行号在记录的 `file`/`line` 里。值用 `{:#?}` 输出，最长见 [`Builder::dbg_budget`]。
+///
+/// 可以一次传几个值（返回元组），末尾可以有逗号。[`dbg!`] 是 `Debug` 级别，另外还有
+/// [`trace_dbg!`]、[`info_dbg!`]、[`warn_dbg!`] 和 [`error_dbg!`]，过滤掉 `Debug` 的
+/// release 版本里也能看到。
+///
+/// ```
+/// use log::{Level, Log, Metadata, Record};
+/// use std::sync::Mutex;
+///
+/// static RECORDS: Mutex<Vec<(Level, String, u32)>> = Mutex::new(Vec::new());
+/// struct Capture;
+/// impl Log for Capture {
+///     fn enabled(&self, _: &Metadata) -> bool {
+///         true
+///     }
+///     fn log(&self, record: &Record) {
+///         let line = record.line().unwrap();
+///         RECORDS.lock().unwrap().push((record.level(), record.args().to_string(), line));
+///     }
+///     fn flush(&self) {}
+/// }
+/// log::set_logger(&Capture).unwrap();
+/// log::set_max_level(log::LevelFilter::Trace);
+///
+/// let x = mmlog::dbg_at!(Level::Info, 1 + 2);
+/// let line = line!();
+/// assert_eq!(x, 3);
+/// let pair = mmlog::warn_dbg!("a", 2,);
+/// assert_eq!(pair, ("a", 2));
+/// let single = mmlog::error_dbg!(vec![1],);
+/// assert_eq!(single, [1]);
+/// let (a, b) = (mmlog::trace_dbg!(1), mmlog::info_dbg!(2));
+/// assert_eq!(a + b, 3);
+/// mmlog::dbg!(x, pair.1);
+///
+/// let records = RECORDS.lock().unwrap();
+/// assert_eq!(records[0], (Level::Info, "1 + 2 = 3".to_string(), line - 1));
+/// assert_eq!(records[1], (Level::Warn, r#""a" = "a""#.to_string(), line + 2));
+/// assert_eq!(records[2], (Level::Warn, "2 = 2".to_string(), line + 2));
+/// assert_eq!(records[3].0, Level::Error);
+/// assert_eq!(records[3].1, "vec![1] = [\n    1,\n]");
+/// assert_eq!(records[4].0, Level::Trace);
+/// assert_eq!(records[5].0, Level::Info);
+/// assert_eq!(records[6], (Level::Debug, "x = 3".to_string(), line + 8));
+/// assert_eq!(records[7], (Level::Debug, "pair.1 = 2".to_string(), line + 8));
+/// ```
 #[macro_export]
-macro_rules! dbg {
-    ($val:expr $(,)?) => {
+macro_rules! dbg_at {
+    ($lvl:expr, $val:expr $(,)?) => {
         match $val {
             tmp => {
-                ::log::debug!("{} = {:#?}", stringify!($val), &tmp);
+                ::log::log!(
+                    $lvl,
+                    "{} = {:#}",
+                    stringify!($val),
+                    $crate::fmt::bounded(&tmp, $crate::fmt::dbg_budget())
+                );
                 tmp
             }
         }
     };
+    ($lvl:expr, $($val:expr),+ $(,)?) => {
+        ($($crate::dbg_at!($lvl, $val)),+,)
+    };
+}
+
+/// 用全局 logger 把一段二进制数据写成 `xxd` 那样的十六进制，见 [`Logger::log_bytes`]。
+///
+/// target 默认是调用处的模块，也可以像 `log!` 一样用 `target:` 指定。
+///
+/// ```
+/// use log::Level;
+///
+/// let path = std::env::temp_dir().join("mmlog-log-bytes-macro.log");
+/// let logger = mmlog::Builder::new().level(Level::Debug).init(&path).unwrap();
+/// let buf = [0xde, 0xad, 0xbe, 0xef];
+/// mmlog::log_bytes!(Level::Debug, "rx frame", &buf);
+/// mmlog::log_bytes!(target: "net", Level::Trace, "filtered", &buf);
+///
+/// let tail = logger.tail(2);
+/// assert!(tail[0].ends_with("rx frame (4 bytes)"));
+/// assert!(tail[1].starts_with("00000000: dead beef "));
+/// ```
+#[macro_export]
+macro_rules! log_bytes {
+    (target: $target:expr, $lvl:expr, $label:expr, $data:expr $(,)?) => {
+        ::log::log!(
+            target: $target,
+            $lvl,
+            "{}",
+            $crate::fmt::hex_dump($label, ::std::convert::AsRef::<[u8]>::as_ref($data))
+        )
+    };
+    ($lvl:expr, $label:expr, $data:expr $(,)?) => {
+        $crate::log_bytes!(target: ::std::module_path!(), $lvl, $label, $data)
+    };
+}
+
+/// `Debug` 级别的 [`dbg_at!`]。
+#[macro_export]
+macro_rules! dbg {
+    ($($val:expr),+ $(,)?) => {
+        $crate::dbg_at!(::log::Level::Debug, $($val),+)
+    };
+}
+
+/// `Trace` 级别的 [`dbg_at!`]。
+#[macro_export]
+macro_rules! trace_dbg {
+    ($($val:expr),+ $(,)?) => {
+        $crate::dbg_at!(::log::Level::Trace, $($val),+)
+    };
+}
+
+/// `Info` 级别的 [`dbg_at!`]。
+#[macro_export]
+macro_rules! info_dbg {
+    ($($val:expr),+ $(,)?) => {
+        $crate::dbg_at!(::log::Level::Info, $($val),+)
+    };
+}
+
+/// `Warn` 级别的 [`dbg_at!`]。
+#[macro_export]
+macro_rules! warn_dbg {
+    ($($val:expr),+ $(,)?) => {
+        $crate::dbg_at!(::log::Level::Warn, $($val),+)
+    };
+}
+
+/// `Error` 级别的 [`dbg_at!`]。
+#[macro_export]
+macro_rules! error_dbg {
     ($($val:expr),+ $(,)?) => {
-        ($($crate::dbg!($val)),+,)
+        $crate::dbg_at!(::log::Level::Error, $($val),+)
     };
 }
 
@@ -28,13 +163,98 @@ pub const MB: usize = KB * 1024;
 // pub const GB: usize = MB * 1024;
 // pub const TB: usize = GB * 1024;
 
-fn level_info(l: Level) -> &'static str {
-    match l {
-        Level::Error => "E",
-        Level::Warn => "W",
-        Level::Info => "I",
-        Level::Debug => "D",
-        Level::Trace => "T",
+// 取线程 id 可能是一次系统调用，每个线程只调用一次。fork 之后子进程里缓存的是父进程的线程 id，
+// 所以用 pthread_atfork 记下 fork 的次数，次数变了就重新取
+#[cfg(all(feature = "mmap", unix))]
+static FORKS: AtomicU64 = AtomicU64::new(0);
+
+// 建 logger 的时候登记 pthread_atfork，不能等到第一次写日志：别的线程正在 call_once 里的时候
+// fork 的话，子进程里的 Once 永远停在执行中，子进程第一次写日志就卡死了
+#[cfg(all(feature = "mmap", unix))]
+fn watch_forks() {
+    use std::sync::Once;
+
+    static ATFORK: Once = Once::new();
+    extern "C" fn forked() {
+        sys::abort_on_unwind(refresh_tid);
+    }
+    ATFORK.call_once(|| unsafe {
+        libc::pthread_atfork(None, None, Some(forked));
+    });
+}
+
+// 没有 libc 就没有 fork()
+#[cfg(not(all(feature = "mmap", unix)))]
+fn watch_forks() {}
+
+#[cfg(all(feature = "mmap", unix))]
+fn tid() -> u64 {
+    use std::cell::Cell;
+
+    thread_local! {
+        // （fork 的次数，tid）
+        static TID: Cell<(u64, u64)> = const { Cell::new((u64::MAX, 0)) };
+    }
+
+    #[cfg(feature = "test-util")]
+    if let Some(tid) = test::frozen_tid() {
+        return tid;
+    }
+    let forks = FORKS.load(Ordering::Relaxed);
+    // 线程退出时 thread_local 的析构函数里写日志，TID 可能已经销毁了，每次都取
+    TID.try_with(|cached| match cached.get() {
+        (at, tid) if at == forks => tid,
+        _ => {
+            let tid = sys::current_tid();
+            cached.set((forks, tid));
+            tid
+        }
+    })
+    .unwrap_or_else(|_| sys::current_tid())
+}
+
+// 让所有线程缓存的线程 id 失效，下次写日志时重新取
+#[cfg(all(feature = "mmap", unix))]
+fn refresh_tid() {
+    FORKS.fetch_add(1, Ordering::Relaxed);
+}
+
+// 编号不会因为 fork 变化
+#[cfg(not(all(feature = "mmap", unix)))]
+fn refresh_tid() {}
+
+// 没有 libc 的时候按线程第一次写日志的顺序编号，TID 已经销毁了的话是 0
+#[cfg(not(all(feature = "mmap", unix)))]
+fn tid() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static TID: u64 = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    #[cfg(feature = "test-util")]
+    if let Some(tid) = test::frozen_tid() {
+        return tid;
+    }
+    TID.try_with(|tid| *tid).unwrap_or(0)
+}
+
+thread_local! {
+    // 默认格式的记录先格式化到这里，见 Logger::store_scratch
+    static SCRATCH: std::cell::RefCell<String> = const { std::cell::RefCell::new(String::new()) };
+}
+
+// SCRATCH 至少有这么长
+const SCRATCH_LEN: usize = 512;
+
+// 当前线程的名字，同样每个线程只取一次。NAME 已经销毁了的话当作没有名字，
+// 这时候 std::thread::current() 也可能 panic
+fn with_thread_name<R>(f: impl FnOnce(Option<&str>) -> R) -> R {
+    thread_local! {
+        static NAME: Option<String> = std::thread::current().name().map(String::from);
+    }
+    let mut f = Some(f);
+    match NAME.try_with(|name| f.take().expect("called once")(name.as_deref())) {
+        Ok(r) => r,
+        Err(_) => f.take().expect("not called")(None),
     }
 }
 
@@ -45,294 +265,4603 @@ pub enum Error {
     #[error("C style string nul error: {0}")]
     Nul(#[from] NulError),
 
+    #[error("logger already closed")]
+    Closed,
+
+    #[error("corrupt header: {0}")]
+    CorruptHeader(String),
+
+    #[error("unsupported format version: {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("io error: {0}")]
+    StdIo(#[from] std::io::Error),
+
+    #[error("file is locked by another writer")]
+    AlreadyLocked,
+
+    #[error("set logger error: {0}")]
+    SetLogger(#[from] log::SetLoggerError),
+
+    /// 互相冲突或者超出范围的设置，[`Builder::strict`] 时也包括本来会被自动调整的设置。
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(&'static str),
+
+    /// 和 [`Error::InvalidConfig`] 一样，消息里带着出错的值，例如通道名、过滤条件里的哪一段。
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
+    /// 系统调用失败，`context` 是失败的调用，例如 `"mmap"`。
+    #[error("error: errno: {errno}, msg: {} ({context})", strerror(*.errno))]
+    Io { errno: i32, context: &'static str },
+
+    #[error(
+        "shrinking the buffer from {capacity} to {requested} bytes needs Builder::allow_shrink"
+    )]
+    WouldShrink { capacity: usize, requested: usize },
+
+    /// 没有更具体的变体的意外错误。
     #[error("error: {0}")]
     Any(String),
 }
 
 impl Error {
-    unsafe fn from_errno() -> Error {
-        let errno = *libc::__errno_location();
-        let s = CStr::from_ptr(libc::strerror(errno))
-            .to_str()
-            .expect("CStr::to_str()");
-        Error::Any(format!("errno: {}, msg: {}", errno as isize, s))
+    #[cfg(all(feature = "mmap", unix))]
+    unsafe fn from_errno(context: &'static str) -> Error {
+        Error::from_code(sys::errno(), context)
+    }
+
+    // pthread_* 直接返回错误码，不设置 errno
+    #[cfg(all(feature = "mmap", unix))]
+    fn from_code(errno: libc::c_int, context: &'static str) -> Error {
+        Error::Io { errno, context }
     }
 }
 
+// Error::Io 的 msg，和 strerror(3) 一样
+#[cfg(all(feature = "mmap", unix))]
+fn strerror(errno: i32) -> String {
+    unsafe { std::ffi::CStr::from_ptr(libc::strerror(errno)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(not(all(feature = "mmap", unix)))]
+fn strerror(errno: i32) -> String {
+    io::Error::from_raw_os_error(errno).to_string()
+}
+
+#[allow(unused_macros)]
 macro_rules! errno_try {
-    ($actual:expr, $expect:expr, $bk:block) => {{
+    ($actual:expr, $expect:expr, $context:literal, $bk:block) => {{
         let ret = $actual;
         if ret == $expect {
             $bk
-            return Err($crate::Error::from_errno());
+            return Err($crate::Error::from_errno($context));
         }
         ret
     }};
-    ($actual:expr, $expect:expr) => {
-        errno_try!($actual, $expect, {})
+    ($actual:expr, $expect:expr, $context:literal) => {
+        errno_try!($actual, $expect, $context, {})
     };
 }
 
+#[allow(unused_macros)]
+macro_rules! pthread_try {
+    ($actual:expr, $context:literal) => {{
+        let ret = $actual;
+        if ret != 0 {
+            return Err($crate::Error::from_code(ret, $context));
+        }
+    }};
+}
+
+mod adaptive;
+mod archive;
+mod batch;
+mod bookmark;
+mod callsite;
+pub mod capture;
+mod channel;
+mod crc;
+mod crypt;
+mod env;
+mod flusher;
+pub mod fmt;
+mod fork;
+pub mod format;
+mod health;
+mod mapping;
+mod merge;
+mod meta;
+pub mod parse;
+mod ratelimit;
+pub mod reader;
+mod report;
+mod rotate;
+#[cfg(all(feature = "mmap", unix))]
+mod signals;
+mod snapshot;
+mod spin;
+#[cfg(all(feature = "mmap", unix))]
+mod sys;
+mod targets;
+mod tee;
+pub mod test;
+#[cfg(feature = "tracing")]
+pub mod tracing;
+#[cfg(feature = "tui")]
+pub mod tui;
+mod writer;
+
+pub use archive::ArchivedBuffer;
+pub use bookmark::{LogicalPos, SinceBookmark};
+pub use callsite::{invalidate_callsites, Callsite};
+pub use channel::ChannelLogger;
+use flusher::Flusher;
+pub use format::{Clock, FormatKind, Framing, TimestampFormat};
+use mapping::{ChannelSpec, MapOptions, Mapping, OpenMode, ProcessLock, Ring};
+pub use merge::{merge, merge_each, MergeStats};
+#[cfg(all(feature = "mmap", unix))]
+pub use signals::Signal;
+pub use snapshot::Snapshot;
+pub use spin::LockStats;
+use spin::{LockGuard, SpinLock};
+pub use writer::RingWriter;
+
+/// 单条记录比整个缓冲区还长时的处理方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversized {
+    /// 照常绕圈写入，缓冲区里只剩下这条记录的最后一部分（默认）。
+    Wrap,
+    /// 截断到缓冲区的大小，末尾加上 `...[truncated N bytes]`。
+    Truncate,
+}
+
+/// 映射之后给内核的访问提示（`madvise`），见 [`Builder::advise`]。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Advice {
+    /// 不给提示（默认）。
+    #[default]
+    Normal,
+    /// 按顺序访问：预读得更多，读过的页也回收得更早。
+    Sequential,
+    /// 随机访问：不预读。
+    Random,
+    /// 很快就会用到：马上开始把整个文件读进内存。
+    WillNeed,
+}
+
+/// 写入的统计，见 [`Logger::stats`] 和 [`LogReader::stats`](reader::LogReader::stats)。
+///
+/// 打开 `serde` feature 之后实现了 `serde::Serialize`，可以直接导出给监控系统。
+///
+/// ```
+/// use log::Log;
+/// use std::fmt;
+///
+/// struct Broken;
+///
+/// impl fmt::Display for Broken {
+///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+///         f.write_str("half of it")?;
+///         Err(fmt::Error)
+///     }
+/// }
+///
+/// let path = std::env::temp_dir().join("mmlog-stats.log");
+/// let logger = mmlog::Builder::new().build(&path).unwrap();
+/// let log = |args: fmt::Arguments| {
+///     logger.log(&log::Record::builder().level(log::Level::Info).args(args).build())
+/// };
+/// log(format_args!("{}", Broken));
+/// let stats = logger.stats();
+/// assert_eq!(stats.records_written, 0);
+/// assert_eq!(stats.records_dropped, 1);
+/// assert_eq!((stats.capacity, stats.used_bytes_since_wrap), (512 * 1024, 0));
+///
+/// for i in 0..30000 {
+///     log(format_args!("record {}", i));
+/// }
+/// let stats = logger.stats();
+/// assert_eq!(stats.records_written, 30000);
+/// assert!(stats.wrap_count > 0);
+/// assert!(stats.used_bytes_since_wrap < stats.capacity);
+/// logger.try_flush().unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Stats {
+    /// 缓冲区的大小（不含 header）。
+    pub capacity: usize,
+    /// 上次翻转之后写了多少字节，也就是当前的 offset。
+    pub used_bytes_since_wrap: usize,
+    /// 写入过的记录数，包括 logger 自己写的提示记录。
+    pub records_written: u64,
+    /// 写入过的字节数，长度前缀格式下包括每条记录的长度和校验和。
+    pub total_bytes_written: u64,
+    /// 缓冲区翻转的次数。
+    pub wrap_count: u64,
+    /// 没有写进文件的记录数：内部错误（时钟早于 UNIX 纪元、格式化出错）、
+    /// [`Builder::rate_limit`] 压掉的、超出 [`Builder::target_quota`] 的，
+    /// 还有 `records_dropped_contention`。只在这个 `Logger` 的内存里，不保存在文件里，
+    /// [`LogReader`](reader::LogReader) 总是 0。
+    pub records_dropped: u64,
+    /// 太长被截断了的记录数（[`Builder::max_record_len`]、[`Builder::oversized`] 等），
+    /// 和 `records_dropped` 一样只在内存里。
+    pub records_truncated: u64,
+    /// [`Builder::non_blocking`] 时因为写锁被占着而丢掉的记录数，已经算在 `records_dropped` 里。
+    pub records_dropped_contention: u64,
+}
+
+impl Stats {
+    fn from_mapping(mapping: &Mapping) -> Stats {
+        let (records_written, total_bytes_written, wrap_count) = mapping.counters();
+        Stats {
+            capacity: mapping.capacity(),
+            used_bytes_since_wrap: mapping.ring_offset(Ring::Main),
+            records_written,
+            total_bytes_written,
+            wrap_count,
+            records_dropped: 0,
+            records_truncated: 0,
+            records_dropped_contention: 0,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Builder {
     size: usize,
-    level: Level,
+    // 调用过 Builder::size（或者环境变量里有），打开已有的文件时按它改大小
+    resize: bool,
+    allow_shrink: bool,
+    strict: bool,
+    level: LevelFilter,
     sync: bool,
+    track_targets: bool,
+    target_quotas: Vec<(String, u64)>,
+    lock_metrics: bool,
+    non_blocking: bool,
+    batched: Option<usize>,
+    adaptive_flush: Option<Duration>,
+    adaptive_markers: bool,
+    rate_limit: Option<(u32, Duration)>,
+    dbg_budget: Option<usize>,
+    sanitize: bool,
+    metrics_prefix: Option<String>,
+    oversized: Oversized,
+    max_record_len: Option<usize>,
+    repair: bool,
+    timestamp: TimestampFormat,
+    format: Option<format::Custom>,
+    modules: Vec<(String, LevelFilter)>,
+    framing: Framing,
+    map_options: MapOptions,
+    // Builder::channels 的名字和长度，make_sense() 时填进 map_options
+    channels: Vec<(String, usize)>,
+    lock_memory: bool,
+    sequence: bool,
+    thread_names: bool,
+    sync_on: Option<Level>,
+    flush_interval: Option<Duration>,
+    panic_hook: bool,
+    process_shared: bool,
+    fork_safe: bool,
+    banner: bool,
+    banner_with: Option<format::Banner>,
+    exclusive: Option<bool>,
+    tee_stderr: bool,
+    logcat: bool,
+    also_log: Vec<tee::Sink>,
+    format_kind: FormatKind,
+    rotate: Option<usize>,
+    #[cfg(feature = "compression")]
+    compress_rotated: bool,
+    cipher: Option<crypt::Cipher>,
+    // Builder::parse_env 的 MMLOG_FILE，代替 build() 等的参数
+    file: Option<PathBuf>,
+    // Builder::parse_env 遇到的第一个错误，build() 时返回
+    env_error: Option<String>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Builder {
     const MIN_SIZE: usize = 512 * KB;
+    // Builder::reserve_for_errors 和 Builder::channels 分出来的每个环的最小长度
+    const MIN_RING: usize = 4 * KB;
 
     pub fn new() -> Builder {
         Builder {
             size: Self::MIN_SIZE,
-            level: Level::Info,
+            resize: false,
+            allow_shrink: false,
+            strict: false,
+            level: LevelFilter::Info,
             sync: false,
+            track_targets: false,
+            target_quotas: Vec::new(),
+            lock_metrics: false,
+            non_blocking: false,
+            batched: None,
+            adaptive_flush: None,
+            adaptive_markers: false,
+            rate_limit: None,
+            dbg_budget: None,
+            sanitize: true,
+            metrics_prefix: None,
+            oversized: Oversized::Wrap,
+            max_record_len: None,
+            repair: false,
+            timestamp: TimestampFormat::UnixSecondsNanos,
+            format: None,
+            modules: Vec::new(),
+            framing: Framing::Text,
+            map_options: MapOptions::default(),
+            channels: Vec::new(),
+            lock_memory: false,
+            sequence: false,
+            thread_names: false,
+            sync_on: None,
+            flush_interval: None,
+            panic_hook: false,
+            process_shared: false,
+            fork_safe: false,
+            banner: false,
+            banner_with: None,
+            exclusive: None,
+            tee_stderr: false,
+            logcat: false,
+            also_log: Vec::new(),
+            format_kind: FormatKind::Text,
+            rotate: None,
+            #[cfg(feature = "compression")]
+            compress_rotated: false,
+            cipher: None,
+            file: None,
+            env_error: None,
+        }
+    }
+
+    /// `Builder::new().parse_env("MMLOG")`。
+    pub fn from_env() -> Builder {
+        Builder::new().parse_env("MMLOG")
+    }
+
+    /// 用环境变量覆盖已经设置的选项，运维不用重新编译就能调整：
+    ///
+    /// - `<prefix>_FILTER`，没有的话 `RUST_LOG`：见 [`Builder::filter_str`]；
+    /// - `<prefix>_LEVEL`：全局级别，`off`、`error`、`warn`、`info`、`debug`、`trace`，
+    ///   不区分大小写，和 `RUST_LOG` 里的全局级别一样，比 `<prefix>_FILTER` 里的优先；
+    /// - `<prefix>_SIZE`：[`Builder::size`]，字节数，可以带 `K` 或 `M` 后缀，比如 `8M`；
+    /// - `<prefix>_SYNC`：[`Builder::sync`]，`0`、`1`、`true` 或 `false`；
+    /// - `<prefix>_FILE`：文件的路径，设置了的话 [`Builder::build`]、[`Builder::open`]、
+    ///   [`Builder::open_or_create`] 和 [`Builder::init`] 的参数会被忽略。
+    ///
+    /// 没有设置或者是空字符串的变量不影响原来的设置。值不对的话不会被忽略，
+    /// 之后的 `build()` 等返回说明了是哪个变量的 [`Error::Config`]。
+    ///
+    /// ```
+    /// use log::Level;
+    /// use mmlog::Builder;
+    ///
+    /// std::env::remove_var("RUST_LOG");
+    /// let from = |size: &str| {
+    ///     std::env::set_var("MMLOG_DOC_SIZE", size);
+    ///     Builder::new().parse_env("MMLOG_DOC").build_anonymous()
+    /// };
+    /// assert_eq!(from("8M").unwrap().stats().capacity, 8 << 20);
+    /// assert_eq!(from("1536k").unwrap().stats().capacity, 1536 << 10);
+    /// assert_eq!(from("600000").unwrap().stats().capacity, 600000);
+    /// // 太小的和 Builder::size 一样按 512KB 算
+    /// assert_eq!(from("0").unwrap().stats().capacity, 512 << 10);
+    /// assert_eq!(from("100k").unwrap().stats().capacity, 512 << 10);
+    /// for garbage in ["garbage", "8G", "k", "-1", "1.5M", "99999999999999999999"] {
+    ///     let err = from(garbage).unwrap_err().to_string();
+    ///     assert!(err.contains(&format!("MMLOG_DOC_SIZE={}", garbage)), "{}", err);
+    /// }
+    /// std::env::remove_var("MMLOG_DOC_SIZE");
+    ///
+    /// std::env::set_var("MMLOG_DOC_LEVEL", "Debug");
+    /// std::env::set_var("MMLOG_DOC_SYNC", "true");
+    /// std::env::set_var("MMLOG_DOC_FILE", std::env::temp_dir().join("mmlog-env.log"));
+    /// let logger = Builder::new()
+    ///     .level(log::Level::Error)
+    ///     .parse_env("MMLOG_DOC")
+    ///     .build("ignored.log")
+    ///     .unwrap();
+    /// assert_eq!(logger.level(), log::Level::Debug);
+    /// assert!(std::env::temp_dir().join("mmlog-env.log").exists());
+    /// assert!(!std::path::Path::new("ignored.log").exists());
+    ///
+    /// std::env::set_var("MMLOG_DOC_SYNC", "yes");
+    /// let err = Builder::new().parse_env("MMLOG_DOC").build_anonymous().unwrap_err();
+    /// assert!(matches!(err, mmlog::Error::Config(_)));
+    /// assert!(err.to_string().contains("MMLOG_DOC_SYNC=yes"));
+    /// std::env::remove_var("MMLOG_DOC_SYNC");
+    /// std::env::remove_var("MMLOG_DOC_FILE");
+    ///
+    /// // 没有 <prefix>_FILTER 时用 RUST_LOG，全局级别以 <prefix>_LEVEL 为准
+    /// std::env::set_var("RUST_LOG", "off,app=trace");
+    /// let logger = Builder::new().parse_env("MMLOG_DOC").build_anonymous().unwrap();
+    /// assert!(logger.target_enabled(Level::Trace, "app"));
+    /// assert!(logger.target_enabled(Level::Debug, "other"));
+    /// std::env::set_var("MMLOG_DOC_FILTER", "app=off");
+    /// let logger = Builder::new().parse_env("MMLOG_DOC").build_anonymous().unwrap();
+    /// assert!(!logger.target_enabled(Level::Error, "app"));
+    /// std::env::set_var("MMLOG_DOC_FILTER", "app=loud");
+    /// let err = Builder::new().parse_env("MMLOG_DOC").build_anonymous().unwrap_err();
+    /// assert!(err.to_string().contains("MMLOG_DOC_FILTER: invalid directive \"app=loud\""));
+    /// ```
+    pub fn parse_env(mut self, prefix: &str) -> Self {
+        if let Err(e) = self.apply_env(prefix) {
+            self.env_error.get_or_insert(e);
+        }
+        self
+    }
+
+    fn apply_env(&mut self, prefix: &str) -> std::result::Result<(), String> {
+        let invalid = |key: &str, value: &str, e: String| format!("{}={}: {}", key, value, e);
+        let filter = match env::var(prefix, "FILTER")? {
+            Some(filter) => Some(filter),
+            None => env::lookup("RUST_LOG")?,
+        };
+        if let Some((key, value)) = filter {
+            *self = mem::take(self)
+                .apply_filter(&value)
+                .map_err(|e| format!("{}: {}", key, e))?;
+        }
+        if let Some((key, value)) = env::var(prefix, "LEVEL")? {
+            self.level = env::level(&value).map_err(|e| invalid(&key, &value, e))?;
+        }
+        if let Some((key, value)) = env::var(prefix, "SIZE")? {
+            self.size = env::size(&value).map_err(|e| invalid(&key, &value, e))?;
+            self.resize = true;
+        }
+        if let Some((key, value)) = env::var(prefix, "SYNC")? {
+            self.sync = env::bool(&value).map_err(|e| invalid(&key, &value, e))?;
         }
+        if let Some(file) = env::var_os(prefix, "FILE") {
+            self.file = Some(PathBuf::from(file));
+        }
+        Ok(())
     }
 
+    /// 缓冲区大小，最小 512KB，更小的按 512KB 算，[`Builder::strict`] 的话报错。
+    ///
+    /// 调用过这个方法的话，[`Builder::open`] 和 [`Builder::open_or_create`] 打开大小不同的已有文件时
+    /// 会把它改成这个大小，记录按时间顺序保留；改小需要 [`Builder::allow_shrink`]。
+    /// 没调用过的话沿用文件里的大小。
+    ///
+    /// 写到末尾的记录会在边界处拆开，后半截从缓冲区开头继续写：
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::format::{DATA_POS, OFFSET_POS};
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-size.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// let payload = "x".repeat(3000);
+    /// let mut i = 0;
+    /// let offset = loop {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{} {}", payload, i))
+    ///             .build(),
+    ///     );
+    ///     i += 1;
+    ///     if logger.stats().wrap_count == 0 {
+    ///         continue;
+    ///     }
+    ///     logger.flush();
+    ///     let file = std::fs::read(&path).unwrap();
+    ///     let mut word = [0; std::mem::size_of::<usize>()];
+    ///     word.copy_from_slice(&file[OFFSET_POS..OFFSET_POS + std::mem::size_of::<usize>()]);
+    ///     let offset = usize::from_ne_bytes(word);
+    ///     // 刚好写到末尾的话没有拆开，再写一圈
+    ///     if offset > 0 {
+    ///         break offset;
+    ///     }
+    /// };
+    /// drop(logger);
+    ///
+    /// // 最后一条记录的后半截在开头，前半截在末尾
+    /// let file = std::fs::read(&path).unwrap();
+    /// let start = u32::from_le_bytes(file[DATA_POS..DATA_POS + 4].try_into().unwrap());
+    /// let data = &file[start as usize..];
+    /// let tail = format!("{} {}\n", payload, i - 1);
+    /// assert!(offset < tail.len());
+    /// let head = &data[data.len() - (tail.len() - offset)..];
+    /// assert_eq!([head, &data[..offset]].concat(), tail.as_bytes());
+    ///
+    /// // 读回来是完整的
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let last = reader.records().last().unwrap();
+    /// assert!(last.ends_with(&tail[..tail.len() - 1]));
+    /// ```
     pub fn size(mut self, s: usize) -> Self {
         self.size = s;
+        self.resize = true;
+        self
+    }
+
+    /// 打开已有的文件时允许 [`Builder::size`] 比文件里的缓冲区小，默认不允许，
+    /// 返回 [`Error::WouldShrink`]。允许的话放不下的较旧记录被丢掉，只保留最新的。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::{Error, MB};
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-shrink.log");
+    /// let logger = mmlog::Builder::new().size(MB).build(&path).unwrap();
+    /// for i in 0..30000 {
+    ///     logger.log(&log::Record::builder().args(format_args!("record {}", i)).build());
+    /// }
+    /// drop(logger);
+    ///
+    /// let shrink = || mmlog::Builder::new().size(512 * 1024);
+    /// assert!(matches!(shrink().open(&path), Err(Error::WouldShrink { .. })));
+    ///
+    /// let logger = shrink().allow_shrink(true).open(&path).unwrap();
+    /// assert_eq!(logger.stats().capacity, 512 * 1024);
+    /// logger.log(&log::Record::builder().args(format_args!("record 30000")).build());
+    /// let mut out = Vec::new();
+    /// logger.dump_to(&mut out).unwrap();
+    /// let numbers: Vec<u32> = String::from_utf8(out)
+    ///     .unwrap()
+    ///     .lines()
+    ///     .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+    ///     .collect();
+    /// // 只剩下最新的那些，中间没有断开
+    /// assert!(numbers.len() > 1000 && numbers.len() < 30000);
+    /// assert!(numbers.windows(2).all(|w| w[1] == w[0] + 1));
+    /// assert_eq!(numbers.last(), Some(&30000));
+    /// assert_eq!(logger.stats().records_written, 30001);
+    /// ```
+    pub fn allow_shrink(mut self, enable: bool) -> Self {
+        self.allow_shrink = enable;
+        self
+    }
+
+    /// 不自动调整设置，有问题的设置在 `build()` 等时返回 [`Error::InvalidConfig`]。默认关闭，
+    /// 关闭时：
+    ///
+    /// - [`Builder::size`] 小于 512KB 的按 512KB 算；
+    /// - 有 [`Builder::channels`] 时忽略 [`Builder::size`]；
+    /// - 和 [`Builder::shared`] 一起用时忽略 [`Builder::rotate`]；
+    /// - [`Builder::flush_interval`] 可以是 0，后台线程一直在同步；
+    /// - 不是 [`Framing::Text`] 和 [`FormatKind::Text`] 的话忽略 [`Builder::escape_newlines`]。
+    ///
+    /// 本来就会报错的设置（例如 [`Builder::reserve_for_errors`] 的比例不对）也返回 [`Error::InvalidConfig`]。
+    ///
+    /// ```
+    /// use mmlog::{Error, KB};
+    /// use std::time::Duration;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-strict.log");
+    /// let logger = mmlog::Builder::new().size(64 * KB).build(&path).unwrap();
+    /// assert_eq!(logger.stats().capacity, 512 * KB);
+    /// drop(logger);
+    ///
+    /// let err = mmlog::Builder::new()
+    ///     .size(64 * KB)
+    ///     .strict(true)
+    ///     .build(&path)
+    ///     .unwrap_err();
+    /// assert!(matches!(err, Error::InvalidConfig(_)));
+    /// assert!(err.to_string().starts_with("invalid configuration: "));
+    ///
+    /// let err = mmlog::Builder::new()
+    ///     .strict(true)
+    ///     .flush_interval(Duration::ZERO)
+    ///     .build(&path)
+    ///     .unwrap_err();
+    /// assert!(matches!(err, Error::InvalidConfig(_)));
+    /// ```
+    pub fn strict(mut self, enable: bool) -> Self {
+        self.strict = enable;
+        self
+    }
+
+    /// 新建文件时把缓冲区末尾的 `fraction` 分出来单独作为一个环，只写 `Warn` 和 `Error`
+    /// 的记录（包括 logger 自己的提示），大量的 `Info`、`Trace` 只会覆盖前面那个环，
+    /// 冲不掉之前的错误。默认是 0，只有一个环。
+    ///
+    /// 两个环各自翻转，[`Logger::dump_to`]、[`Logger::tail`]、[`Logger::snapshot`] 和
+    /// [`LogReader`](reader::LogReader) 按时间戳把两边的记录合在一起，没有时间戳的记录跟着
+    /// 同一个环里的上一条。打开已有的文件时以文件的 header 为准，这里的设置不起作用；
+    /// 分了环的文件不能用 [`Builder::size`] 改大小。[`Stats::used_bytes_since_wrap`] 是前一个环的。
+    ///
+    /// `fraction` 要在 `[0, 1)` 之间，而且分出来的两个环都至少有 4 KiB，否则 `build()`
+    /// 返回 [`Error::InvalidConfig`]。
+    ///
+    /// ```
+    /// use log::{Level, Log};
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-reserve.log");
+    /// let logger = mmlog::Builder::new().reserve_for_errors(0.25).build(&path).unwrap();
+    /// let log = |level, i: u32| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(level)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     )
+    /// };
+    /// log(Level::Error, 0);
+    /// for i in 1..100000 {
+    ///     log(Level::Info, i);
+    /// }
+    /// log(Level::Warn, 100000);
+    /// let number = |r: &str| -> u32 { r.rsplit(' ').next().unwrap().parse().unwrap() };
+    ///
+    /// // 前面的环翻转了很多次，错误还在，按时间排在最前面
+    /// let mut out = Vec::new();
+    /// logger.dump_to(&mut out).unwrap();
+    /// let dump = String::from_utf8(out).unwrap();
+    /// let numbers: Vec<u32> = dump.lines().map(number).collect();
+    /// assert_eq!(numbers[0], 0);
+    /// assert!(numbers.len() < 100001);
+    /// assert!(numbers.windows(2).all(|w| w[0] < w[1]));
+    /// let tail: Vec<u32> = logger.tail(2).iter().map(|r| number(r)).collect();
+    /// assert_eq!(tail, [99999, 100000]);
+    /// drop(logger);
+    ///
+    /// // 读取方从 header 里知道有两个环
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let records: Vec<&str> = reader.records().collect();
+    /// assert_eq!(reader.entries().next().unwrap().level, Level::Error);
+    /// assert_eq!(records.iter().map(|r| number(r)).collect::<Vec<_>>(), numbers);
+    ///
+    /// let err = mmlog::Builder::new().reserve_for_errors(1.0).build(&path).unwrap_err();
+    /// assert!(matches!(err, mmlog::Error::InvalidConfig(_)));
+    /// ```
+    pub fn reserve_for_errors(mut self, fraction: f32) -> Self {
+        self.map_options.reserve = fraction as f64;
+        self
+    }
+
+    /// 新建文件时把缓冲区依次分成几个有名字的通道，每个通道是单独的一个环，有自己的级别，
+    /// 一个文件、一次映射就能放下访问日志和应用日志这样互不干扰的几路记录。
+    /// 缓冲区的大小是各个通道加起来的，[`Builder::size`] 不起作用。
+    ///
+    /// `log()` 写进第一个通道，别的通道用 [`Logger::channel`] 拿到的 [`ChannelLogger`] 写，
+    /// 它的级别一开始和 [`Builder::level`] 一样。[`Logger::dump_to`]、[`Logger::tail`] 和
+    /// [`LogReader::records`](reader::LogReader::records) 按时间戳把所有通道合在一起，
+    /// [`LogReader::channel`](reader::LogReader::channel) 只读一个通道。
+    /// 打开已有的文件时以文件里的通道表为准；有通道的文件不能用 [`Builder::size`] 改大小。
+    ///
+    /// 最多 16 个通道，名字不能为空、不能重复、不超过 32 个字节，每个通道至少 4 KiB（按 8 字节向下取整），
+    /// 不能和 [`Builder::reserve_for_errors`] 一起用，否则 `build()` 返回 [`Error::InvalidConfig`]。
+    ///
+    /// ```
+    /// use log::{Level, Log};
+    /// use mmlog::reader::LogReader;
+    /// use mmlog::MB;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-channels.log");
+    /// let logger = mmlog::Builder::new()
+    ///     .channels(&[("app", MB), ("access", MB / 4)])
+    ///     .build(&path)
+    ///     .unwrap();
+    /// let access = logger.channel("access").unwrap();
+    /// access.set_level(Level::Warn);
+    /// let log = |to: &dyn Log, level, msg: &str| {
+    ///     to.log(
+    ///         &log::Record::builder()
+    ///             .level(level)
+    ///             .args(format_args!("{}", msg))
+    ///             .build(),
+    ///     )
+    /// };
+    /// log(&logger, Level::Info, "app started");
+    /// log(&access, Level::Warn, "GET /slow 200");
+    /// log(&access, Level::Info, "GET / 200");
+    /// log(&logger, Level::Info, "app stopping");
+    /// assert!(logger.channel("metrics").is_none());
+    /// drop(access);
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// assert_eq!(reader.channels(), ["app", "access"]);
+    /// let message = |r: &str| r.split("] ").nth(1).unwrap().to_string();
+    /// let all: Vec<_> = reader.records().map(message).collect();
+    /// assert_eq!(all, ["app started", "GET /slow 200", "app stopping"]);
+    /// let access: Vec<_> = reader.channel("access").map(message).collect();
+    /// assert_eq!(access, ["GET /slow 200"]);
+    ///
+    /// let err = mmlog::Builder::new()
+    ///     .channels(&[("app", MB), ("app", MB)])
+    ///     .build(&path)
+    ///     .unwrap_err();
+    /// assert!(matches!(err, mmlog::Error::InvalidConfig(_)));
+    /// ```
+    pub fn channels(mut self, channels: &[(&str, usize)]) -> Self {
+        self.channels = channels
+            .iter()
+            .map(|&(name, capacity)| (name.to_string(), capacity))
+            .collect();
         self
     }
 
     pub fn level(mut self, l: Level) -> Self {
-        self.level = l;
+        self.level = l.to_level_filter();
+        self
+    }
+
+    /// 单独设置某个模块（target 前缀）的级别，可以调用多次，
+    /// 最长的匹配优先，都不匹配时使用 [`Builder::level`]。
+    ///
+    /// ```
+    /// use log::{Level, LevelFilter};
+    ///
+    /// let logger = mmlog::Builder::new()
+    ///     .level(Level::Warn)
+    ///     .module_level("myapp", LevelFilter::Debug)
+    ///     .module_level("myapp::db", LevelFilter::Error)
+    ///     .build(std::env::temp_dir().join("mmlog-module-level.log"))
+    ///     .unwrap();
+    ///
+    /// assert!(logger.target_enabled(Level::Debug, "myapp"));
+    /// assert!(logger.target_enabled(Level::Debug, "myapp::http"));
+    /// assert!(!logger.target_enabled(Level::Trace, "myapp::http"));
+    /// assert!(!logger.target_enabled(Level::Warn, "myapp::db"));
+    /// assert!(logger.target_enabled(Level::Error, "myapp::db::pool"));
+    /// assert!(!logger.target_enabled(Level::Info, "myapplication"));
+    /// assert!(logger.target_enabled(Level::Warn, "hyper::proto"));
+    /// ```
+    pub fn module_level(mut self, prefix: &str, level: LevelFilter) -> Self {
+        self.modules.retain(|(p, _)| p != prefix);
+        self.modules.push((prefix.to_string(), level));
+        // 长的在前，按顺序找到的第一个就是最长的匹配
+        self.modules
+            .sort_by_key(|(p, _)| std::cmp::Reverse(p.len()));
         self
     }
 
+    /// 用 `RUST_LOG` 的写法设置 [`Builder::level`] 和 [`Builder::module_level`]：
+    /// 逗号分隔的 `target=level`，不带 `=` 的级别是全局级别，不带 `=` 的 target
+    /// 表示这个 target 的全部级别。级别可以是 `off`，不区分大小写；后面的覆盖前面的。
+    /// 不支持 `env_logger` 的 `/regex` 过滤，出错时返回指出了是哪一段的 [`Error::Config`]。
+    ///
+    /// ```
+    /// use log::{Level, LevelFilter};
+    ///
+    /// let logger = mmlog::Builder::new()
+    ///     .filter_str("warn,myapp=trace,hyper::proto=off, myapp::db = ERROR")
+    ///     .unwrap()
+    ///     .build_anonymous()
+    ///     .unwrap();
+    /// assert_eq!(logger.level(), Level::Warn);
+    /// assert!(logger.target_enabled(Level::Trace, "myapp::http"));
+    /// assert!(!logger.target_enabled(Level::Warn, "myapp::db::pool"));
+    /// assert!(!logger.target_enabled(Level::Error, "hyper::proto::h2"));
+    /// assert!(logger.target_enabled(Level::Warn, "hyper::client"));
+    /// assert!(!logger.target_enabled(Level::Info, "hyper"));
+    ///
+    /// let logger = mmlog::Builder::new()
+    ///     .filter_str("off,myapp")
+    ///     .unwrap()
+    ///     .build_anonymous()
+    ///     .unwrap();
+    /// assert_eq!(logger.level_filter(), LevelFilter::Off);
+    /// assert!(!logger.target_enabled(Level::Error, "hyper"));
+    /// assert!(logger.target_enabled(Level::Trace, "myapp"));
+    ///
+    /// for (spec, bad) in [("info,app=loud", "app=loud"), ("=debug", "=debug"), ("app/foo", "app/foo")] {
+    ///     let err = mmlog::Builder::new().filter_str(spec).unwrap_err().to_string();
+    ///     assert!(err.contains(&format!("{:?}", bad)), "{}", err);
+    /// }
+    /// ```
+    pub fn filter_str(self, spec: &str) -> Result<Builder> {
+        self.apply_filter(spec).map_err(Error::Config)
+    }
+
+    fn apply_filter(mut self, spec: &str) -> std::result::Result<Builder, String> {
+        for (target, level) in env::directives(spec)? {
+            match target {
+                Some(target) => self = self.module_level(target, level),
+                None => self.level = level,
+            }
+        }
+        Ok(self)
+    }
+
     pub fn sync(mut self, enable: bool) -> Self {
         self.sync = enable;
         self
     }
 
-    fn make_sense(&mut self) {
-        if self.size < Self::MIN_SIZE {
-            self.size = Self::MIN_SIZE;
-        }
+    /// 写入 `level` 及更严重的记录之后立刻同步到磁盘（`MS_SYNC`），
+    /// 其他记录仍然按 [`Builder::sync`] 的设置在 `flush()` 和 drop 时同步。
+    ///
+    /// 只同步上次同步之后写过的页，见 [`Logger::sync_now`]。
+    pub fn sync_on(mut self, level: Level) -> Self {
+        self.sync_on = Some(level);
+        self
     }
 
-    pub fn build<P: AsRef<Path>>(mut self, name: P) -> Result<Logger> {
-        self.make_sense();
-        Logger::new(name, self.size, self.level, self.sync)
+    /// 启动一个后台线程，每隔 `interval` 把写过的页同步到磁盘（按 [`Builder::sync`]
+    /// 的设置选择 `MS_SYNC` 或 `MS_ASYNC`），写日志的线程不用再等同步。
+    /// 没有新的写入时后台线程什么都不做。
+    ///
+    /// `Logger` drop 时先等后台线程退出，再做最后一次同步。
+    ///
+    /// 同步失败的话下个周期再试，第一次失败时在下一条记录之前写一条 target 为 `mmlog` 的
+    /// `Error` 级别提示（没有下一条的话在 `flush()`、`close()` 或者 drop 时写），一直失败不会重复写。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-flush-interval.log");
+    /// let logger = Arc::new(
+    ///     mmlog::Builder::new()
+    ///         .flush_interval(Duration::from_millis(1))
+    ///         .build(&path)
+    ///         .unwrap(),
+    /// );
+    /// let threads: Vec<_> = (0..4)
+    ///     .map(|t| {
+    ///         let logger = logger.clone();
+    ///         std::thread::spawn(move || {
+    ///             for i in 0..5000 {
+    ///                 logger.log(
+    ///                     &log::Record::builder()
+    ///                         .level(log::Level::Info)
+    ///                         .args(format_args!("thread {} record {}", t, i))
+    ///                         .build(),
+    ///                 );
+    ///             }
+    ///         })
+    ///     })
+    ///     .collect();
+    /// // 其他线程还在写，最后一个放手的线程负责 drop
+    /// drop(logger);
+    /// for thread in threads {
+    ///     thread.join().unwrap();
+    /// }
+    ///
+    /// let reader = mmlog::reader::LogReader::open(&path).unwrap();
+    /// assert_eq!(reader.stats().records_written, 20000);
+    /// assert!(reader.records().all(|r| r.contains("] thread ")));
+    /// ```
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = Some(interval);
+        self
     }
 
-    pub fn open<P: AsRef<Path>>(mut self, name: P) -> Result<Logger> {
-        self.make_sense();
-        Logger::open(name, self.size, self.level, self.sync)
+    /// 允许多个进程同时写同一个文件：在 header 里放一个进程间共享的 robust mutex，
+    /// 每次写入都要先拿到它。持有锁的进程死掉了的话，下一个拿锁的进程直接接手。
+    /// macOS 等没有 robust mutex 的系统上只是普通的进程间锁，写日志时被杀掉的进程会让别的进程一直等下去。
+    ///
+    /// 和 [`Builder::framing`] 一样只对新建的文件有效，之后用 [`Builder::open`]
+    /// 打开这个文件的进程都会使用这把锁；fork 出来的子进程可以直接继续用父进程的 `Logger`。
+    /// 有别的进程正在写的文件不要再用 [`Builder::build`] 重新创建。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-shared.log");
+    /// let logger = mmlog::Builder::new()
+    ///     .size(2 * mmlog::MB)
+    ///     .shared(true)
+    ///     .build(&path)
+    ///     .unwrap();
+    /// let payload = "x".repeat(100);
+    /// let write = |who: &str| {
+    ///     for i in 0..2000 {
+    ///         logger.log(
+    ///             &log::Record::builder()
+    ///                 .level(log::Level::Info)
+    ///                 .args(format_args!("{} {} {}", who, i, payload))
+    ///                 .build(),
+    ///         );
+    ///     }
+    /// };
+    ///
+    /// let pid = unsafe { libc::fork() };
+    /// if pid == 0 {
+    ///     write("child");
+    ///     unsafe { libc::_exit(0) };
+    /// }
+    /// write("parent");
+    /// unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) };
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// assert_eq!(reader.stats().records_written, 4000);
+    /// let mut next = [0, 0];
+    /// for record in reader.records() {
+    ///     let mut fields = record.split("] ").nth(1).unwrap().split(' ');
+    ///     let who = (fields.next() == Some("child")) as usize;
+    ///     let i: u32 = fields.next().unwrap().parse().unwrap();
+    ///     assert_eq!(fields.next(), Some(payload.as_str()));
+    ///     assert_eq!(i, next[who]);
+    ///     next[who] += 1;
+    /// }
+    /// assert_eq!(next, [2000, 2000]);
+    /// ```
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn shared(mut self, enable: bool) -> Self {
+        self.process_shared = enable;
+        self
     }
-}
 
-#[derive(Debug)]
-pub struct Logger {
-    addr: *mut libc::c_void,
-    size: usize,
-    level: Level,
-    spin: SpinLock,
-    sync: bool,
-}
+    /// 用 `pthread_atfork` 在每个 fork 出来的子进程里自动调用 [`Logger::post_fork_child`]。
+    /// 不打开的话，fork 时别的线程正在写日志，子进程第一次写日志就会死锁。
+    /// 最多同时有 32 个打开了这个选项的 logger，超过了返回 [`Error::InvalidConfig`]。
+    /// 子进程里的回调要是 panic 了（不应该发生），直接 `abort()`，不会展开穿过 C 的栈帧。
+    ///
+    /// 父子进程能不能同时写：
+    ///
+    /// - 文件或者 [`Builder::build_anonymous`] 加上 [`Builder::shared`]：可以，写入由进程间锁串行化。
+    /// - 没有 [`Builder::shared`]：两个进程的自旋锁各是各的，同时写会互相覆盖，
+    ///   只能有一个进程继续写（比如子进程马上 `exec`）。
+    /// - 没有 `mmap` feature：子进程拿到的是缓冲区的副本，最后写回文件的那个进程说了算。
+    ///
+    /// 另外 [`Builder::batched`] 和 [`Builder::rate_limit`] 用的是普通的 `Mutex`，
+    /// fork 时被别的线程拿着的话子进程里会死锁；[`Builder::flush_interval`]
+    /// 的后台线程不会跟到子进程里，子进程的记录在 drop 或者 `flush()` 时才同步。
+    ///
+    /// ```
+    /// use log::{Level, Log};
+    /// use mmlog::reader::LogReader;
+    /// use mmlog::KB;
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::thread;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-fork-safe.log");
+    /// let _ = std::fs::remove_file(&path);
+    /// let logger = mmlog::Builder::new()
+    ///     .channels(&[("app", 64 * KB), ("noise", 64 * KB)])
+    ///     .shared(true)
+    ///     .fork_safe(true)
+    ///     .build(&path)
+    ///     .unwrap();
+    /// let log = |to: &dyn Log, msg: &str| {
+    ///     to.log(&log::Record::builder().level(Level::Info).args(format_args!("{}", msg)).build())
+    /// };
+    /// let noise = logger.channel("noise").unwrap();
+    /// let stop = AtomicBool::new(false);
+    /// // 另一个线程一直在写，fork 时常常正拿着写锁
+    /// let children: Vec<i32> = thread::scope(|s| {
+    ///     s.spawn(|| {
+    ///         while !stop.load(Ordering::Relaxed) {
+    ///             log(&noise, "noise");
+    ///         }
+    ///     });
+    ///     let children = (0..20)
+    ///         .map(|_| {
+    ///             let pid = unsafe { libc::fork() };
+    ///             if pid == 0 {
+    ///                 // 父进程的线程没来得及放开的锁由子进程接管，照常写
+    ///                 log(&logger, "from child");
+    ///                 unsafe { libc::_exit(0) };
+    ///             }
+    ///             let mut status = -1;
+    ///             unsafe { libc::waitpid(pid, &mut status, 0) };
+    ///             assert_eq!(status, 0);
+    ///             pid
+    ///         })
+    ///         .collect();
+    ///     stop.store(true, Ordering::Relaxed);
+    ///     children
+    /// });
+    /// drop(noise);
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let records: Vec<_> = reader.channel("app").collect();
+    /// assert_eq!(records.len(), children.len());
+    /// // 子进程里的线程 id 是子进程自己的
+    /// for (record, child) in records.iter().zip(&children) {
+    ///     assert!(record.ends_with("] from child"));
+    ///     assert!(record.contains(&format!(" {} I ", child)));
+    /// }
+    /// ```
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn fork_safe(mut self, enable: bool) -> Self {
+        self.fork_safe = enable;
+        self
+    }
 
-impl Logger {
-    const HEADER_SIZE: usize = mem::size_of::<usize>();
-    const EMPTY_STRING: String = String::new();
-
-    fn new<P: AsRef<Path>>(name: P, size: usize, level: Level, sync: bool) -> Result<Logger> {
-        let logger = Self::open_inner(
-            name,
-            size,
-            level,
-            sync,
-            libc::O_CREAT | libc::O_RDWR | libc::O_TRUNC,
-        )?;
-        logger.set_offset(0);
-        Ok(logger)
+    /// 打开文件时加上 `flock(LOCK_EX | LOCK_NB)`，一直持有到 `Logger` 被 drop
+    /// （[`Logger::swap_file`] 之后旧文件的锁由 [`ArchivedBuffer`] 持有），
+    /// 已经有别的 `exclusive` 的 logger 在写这个文件时返回 [`Error::AlreadyLocked`]。
+    ///
+    /// [`Builder::build`] 默认打开，[`Builder::open`]、[`Builder::open_or_create`] 默认不打开。
+    /// [`LogReader`](reader::LogReader) 和 `mmlog-cat` 不加锁，可以读正在写的文件。
+    /// 多个进程通过 [`Builder::shared`] 写同一个文件时，之后打开它的进程不要设置这个选项。
+    ///
+    /// ```
+    /// let path = std::env::temp_dir().join("mmlog-exclusive.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// let again = mmlog::Builder::new().exclusive(true).open(&path);
+    /// assert!(matches!(again, Err(mmlog::Error::AlreadyLocked)));
+    /// drop(logger);
+    /// assert!(mmlog::Builder::new().exclusive(true).open(&path).is_ok());
+    /// ```
+    pub fn exclusive(mut self, enable: bool) -> Self {
+        self.exclusive = Some(enable);
+        self
     }
 
-    fn open<P: AsRef<Path>>(name: P, size: usize, level: Level, sync: bool) -> Result<Logger> {
-        Self::open_inner(name, size, level, sync, libc::O_RDWR)
+    /// [`Builder::init`] 时顺便调用 [`Logger::install_panic_hook`]。
+    pub fn panic_hook(mut self, enable: bool) -> Self {
+        self.panic_hook = enable;
+        self
     }
 
-    fn open_inner<P: AsRef<Path>>(
-        name: P,
-        size: usize,
-        level: Level,
-        sync: bool,
-        mode: libc::c_int,
-    ) -> Result<Logger> {
-        let size = size + Self::HEADER_SIZE;
-        unsafe {
-            let path = name.as_ref();
-            let cstr = CString::new(
-                path.to_str()
-                    .ok_or(Error::Any(format!("Path::to_str() -> {:?}", path)))?,
-            )?;
+    /// 每条记录写进缓冲区之后，再把同样的一行写到 stderr，开发时方便直接看。
+    pub fn tee_stderr(mut self, enable: bool) -> Self {
+        self.tee_stderr = enable;
+        self
+    }
 
-            let fd = errno_try!(libc::open(cstr.as_ptr(), mode, 0o666), -1);
-            errno_try!(libc::ftruncate(fd, size as _), -1, {
-                libc::close(fd);
-            });
-            let addr = errno_try!(
-                libc::mmap(
-                    ptr::null_mut::<libc::c_void>(),
-                    size as _,
-                    libc::PROT_WRITE | libc::PROT_READ,
-                    libc::MAP_SHARED,
-                    fd,
-                    0,
-                ),
-                libc::MAP_FAILED,
-                {
-                    libc::close(fd);
-                }
-            );
-            errno_try!(libc::close(fd), -1);
-            Ok(Logger {
-                addr,
-                size,
-                level,
-                spin: Default::default(),
-                sync,
-            })
-        }
+    /// 在 Android 上把每条记录同时写到 logcat（`__android_log_write`），
+    /// target 作为 tag（超过 23 字节的部分截掉），级别对应 `ANDROID_LOG_ERROR` 到 `ANDROID_LOG_VERBOSE`。
+    ///
+    /// 在释放写锁之后调用。其他系统上什么都不做，同样的代码可以到处编译。
+    pub fn logcat(mut self, enable: bool) -> Self {
+        self.logcat = enable;
+        self
     }
 
-    fn as_slice(&self) -> &[u8] {
-        unsafe { &slice::from_raw_parts(self.addr as _, self.size)[Self::HEADER_SIZE..] }
+    /// 每条写入的记录再交给 `sink` 处理（`sink.enabled()` 为真的话），可以调用多次。
+    ///
+    /// `sink` 在释放写锁之后才被调用，可以很慢，panic 也不会破坏缓冲区；
+    /// 被 [`Builder::target_quota`] 丢弃的记录同样会转过去。
+    ///
+    /// ```
+    /// use log::{Log, Metadata, Record};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// static SEEN: AtomicUsize = AtomicUsize::new(0);
+    ///
+    /// struct Counter;
+    ///
+    /// impl Log for Counter {
+    ///     fn enabled(&self, _: &Metadata) -> bool {
+    ///         true
+    ///     }
+    ///     fn log(&self, _: &Record) {
+    ///         SEEN.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    ///     fn flush(&self) {}
+    /// }
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-also-log.log");
+    /// let logger = mmlog::Builder::new()
+    ///     .also_log(Box::new(Counter))
+    ///     .build(&path)
+    ///     .unwrap();
+    /// logger.log(&Record::builder().level(log::Level::Info).args(format_args!("hello")).build());
+    /// logger.log(&Record::builder().level(log::Level::Trace).args(format_args!("filtered")).build());
+    /// assert_eq!(SEEN.load(Ordering::Relaxed), 1);
+    /// ```
+    pub fn also_log(mut self, sink: Box<dyn Log>) -> Self {
+        self.also_log.push(tee::Sink(Arc::from(sink)));
+        self
     }
 
-    #[allow(mutable_transmutes)]
-    unsafe fn as_mut_slice(&self) -> &mut [u8] {
-        mem::transmute(self.as_slice())
+    /// `open()` 时如果 header 里的 offset 不合法，从头开始写而不是返回
+    /// [`Error::CorruptHeader`]，并写一条 `Warn` 提示。
+    pub fn repair(mut self, enable: bool) -> Self {
+        self.repair = enable;
+        self
     }
 
-    fn offset(&self) -> usize {
-        *unsafe { mem::transmute::<_, &usize>(self.addr) }
+    /// 单条记录比整个缓冲区还长时怎么办，见 [`Oversized`]。
+    pub fn oversized(mut self, o: Oversized) -> Self {
+        self.oversized = o;
+        self
     }
 
-    fn set_offset(&self, new: usize) {
-        assert!(new <= self.size - Self::HEADER_SIZE);
-        let offset: &mut usize = unsafe { mem::transmute(self.addr) };
-        *offset = new;
+    /// 比 `len` 字节长的记录截断到 `len` 字节，末尾加上 `...[truncated N bytes]`，默认不限制。
+    ///
+    /// 一条 `debug!("{:#?}", giant_vec)` 不会再冲掉几个小时的历史。长度包括前缀（时间戳、
+    /// 线程号、级别等）、截断提示和结尾的换行，不包括 [`Builder::sequence_numbers`] 的序号；
+    /// `len` 小到放不下截断提示的话只剩下提示。截断在拿写锁之前做完，
+    /// 被截断的记录数见 [`Stats::records_truncated`]。[`RingWriter`] 写的字节不受限制。
+    ///
+    /// ```
+    /// use log::Log;
+    ///
+    /// let logger = mmlog::Builder::new().max_record_len(100).build_anonymous().unwrap();
+    /// let log = |msg: &str| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{}", msg))
+    ///             .build(),
+    ///     )
+    /// };
+    /// log("short");
+    /// log(&"好".repeat(1000));
+    ///
+    /// let tail = logger.tail(2);
+    /// assert!(tail[0].ends_with("] short"));
+    /// // 加上换行不超过 100 字节，在字符边界处截断
+    /// assert!((90..100).contains(&tail[1].len()));
+    /// assert!(tail[1].contains("好...[truncated "));
+    /// assert_eq!(logger.stats().records_truncated, 1);
+    /// ```
+    pub fn max_record_len(mut self, len: usize) -> Self {
+        self.max_record_len = Some(len);
+        self
     }
 
-    fn size(&self) -> usize {
-        self.size - Self::HEADER_SIZE
+    /// 缓冲区写满时换一个新文件，而不是翻转覆盖最旧的记录。
+    ///
+    /// 写不下的那条记录之前，在写锁内同步并解除当前的映射，把文件改名成 `name.1`
+    /// （原来的 `name.1` 改成 `name.2`，依此类推，超过 `keep` 个的删掉），
+    /// 再在原来的路径上新建文件继续写，所以不会有记录横跨两个文件。
+    /// 新文件的 header 计数接着旧文件往下数，[`Builder::sequence_numbers`] 的序号也是连续的。
+    ///
+    /// 轮转之后不会再翻转，比缓冲区还长的记录总是被截断，[`Builder::oversized`] 不起作用。
+    /// 改名或者新建文件失败的话这次在原来的文件里翻转，下一条记录之前写一条 `Error` 级别的提示。
+    /// 别的进程还映射着旧文件，所以和 [`Builder::shared`] 一起用、或者打开的是别的进程用
+    /// `shared` 建的文件时忽略这个设置，[`Builder::strict`] 的话报错。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    /// use std::path::PathBuf;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-rotate.log");
+    /// let segment = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    /// for n in 1..=5 {
+    ///     let _ = std::fs::remove_file(segment(n));
+    /// }
+    ///
+    /// let logger = mmlog::Builder::new().rotate(5).build(&path).unwrap();
+    /// for i in 0..10000 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {} {}", i, "x".repeat(200)))
+    ///             .build(),
+    ///     );
+    /// }
+    /// assert_eq!(logger.stats().records_written, 10000);
+    /// assert_eq!(logger.stats().wrap_count, 0);
+    /// drop(logger);
+    ///
+    /// // 从最旧的文件读到最新的
+    /// let mut files: Vec<_> = (1..=5).map(segment).filter(|p| p.exists()).collect();
+    /// assert!(files.len() >= 3);
+    /// files.reverse();
+    /// files.push(path.clone());
+    /// let mut next = 0;
+    /// for file in &files {
+    ///     for record in LogReader::open(file).unwrap().records() {
+    ///         let fields = record.split("] record ").nth(1).unwrap();
+    ///         let i: u32 = fields.split(' ').next().unwrap().parse().unwrap();
+    ///         assert_eq!(i, next);
+    ///         next += 1;
+    ///     }
+    /// }
+    /// assert_eq!(next, 10000);
+    /// ```
+    pub fn rotate(mut self, keep: usize) -> Self {
+        self.rotate = Some(keep);
+        self
     }
-}
 
-impl Drop for Logger {
-    fn drop(&mut self) {
-        self.flush();
-        unsafe {
-            debug_assert_ne!(libc::munmap(self.addr, self.size as _), -1);
-        }
+    /// [`Builder::rotate`] 轮转出来的文件交给后台线程用 zstd 压缩：去掉 header、
+    /// 按时间顺序写成纯文本（或者 JSON Lines）的 `name.1.zst`，再删掉原来的 `name.1`。
+    ///
+    /// 正在写的文件不会被压缩。压缩好的数据同步到磁盘之后才删除原来的文件，中途崩溃的话
+    /// 它还在，只是不会再被压缩。`Logger` drop 时会等排着队的压缩都做完。
+    /// 压缩失败的话原来的文件留着，和 [`Builder::flush_interval`] 一样在日志里写一条 `Error` 级别的提示。
+    /// `mmlog-cat --history` 可以直接读这些文件。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use std::io::Read;
+    /// use std::path::PathBuf;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-compress.log");
+    /// let segment = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    /// let logger = mmlog::Builder::new()
+    ///     .rotate(3)
+    ///     .compress_rotated(true)
+    ///     .build(&path)
+    ///     .unwrap();
+    /// for i in 0..10000 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {} {}", i, "x".repeat(200)))
+    ///             .build(),
+    ///     );
+    /// }
+    /// drop(logger);
+    ///
+    /// let mut text = String::new();
+    /// for n in (1..=3).rev() {
+    ///     assert!(!segment(n).exists());
+    ///     let file = std::fs::File::open(format!("{}.zst", segment(n).display())).unwrap();
+    ///     zstd::Decoder::new(file).unwrap().read_to_string(&mut text).unwrap();
+    /// }
+    /// let reader = mmlog::reader::LogReader::open(&path).unwrap();
+    /// let numbers: Vec<u32> = text
+    ///     .lines()
+    ///     .chain(reader.records())
+    ///     .map(|r| r.split("] record ").nth(1).unwrap().split(' ').next().unwrap())
+    ///     .map(|i| i.parse().unwrap())
+    ///     .collect();
+    /// assert!(numbers.windows(2).all(|w| w[1] == w[0] + 1));
+    /// assert_eq!(numbers.last(), Some(&9999));
+    /// ```
+    #[cfg(feature = "compression")]
+    pub fn compress_rotated(mut self, enable: bool) -> Self {
+        self.compress_rotated = enable;
+        self
     }
-}
 
-impl Log for Logger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+    /// 用 XChaCha20-Poly1305 加密每条记录的内容，文件里只有密文，见 [`format`] 模块。
+    /// 需要 `encryption` feature。
+    ///
+    /// 总是用 [`Framing::LengthPrefixed`]，每条记录多 [`format::SEAL_NONCE`] + [`format::SEAL_TAG`]
+    /// 个字节，[`Builder::max_record_len`] 和太长的记录的截断都是对明文算的，已经留出了这些字节。
+    /// [`Logger::tail`]、[`Logger::snapshot`] 这些 logger 自己的读取方法照常返回明文；
+    /// 另一个进程里要用 [`LogReader::open_encrypted`](reader::LogReader::open_encrypted)
+    /// 或者 `mmlog-cat --key-file`，[`LogReader::open`](reader::LogReader::open) 会报错。
+    ///
+    /// 打开已有的文件时密钥要对得上：文件没加密、加密了但没有调用这个方法，或者密钥不对，
+    /// 都返回 [`Error::InvalidConfig`]。不能和 [`Builder::compress_rotated`] 一起用，压缩出来的是明文。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let key = [7; 32];
+    /// let path = std::env::temp_dir().join("mmlog-encrypt.log");
+    /// let logger = mmlog::Builder::new().encrypt(key).build(&path).unwrap();
+    /// for i in 0..3 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("secret {}", i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// assert!(logger.tail(1)[0].ends_with("secret 2"));
+    /// drop(logger);
+    ///
+    /// let file = std::fs::read(&path).unwrap();
+    /// assert!(!file.windows(6).any(|w| w == b"secret"));
+    /// let reader = LogReader::open_encrypted(&path, &key).unwrap();
+    /// assert!(reader.records().last().unwrap().ends_with("secret 2"));
+    ///
+    /// assert!(LogReader::open(&path).is_err());
+    /// assert!(LogReader::open_encrypted(&path, &[8; 32]).is_err());
+    /// let err = mmlog::Builder::new().open(&path).unwrap_err();
+    /// assert!(matches!(err, mmlog::Error::InvalidConfig(_)));
+    /// ```
+    #[cfg(feature = "encryption")]
+    pub fn encrypt(mut self, key: [u8; 32]) -> Self {
+        let cipher = crypt::Cipher::new(&key);
+        self.map_options.key_check = Some(cipher.key_check());
+        self.cipher = Some(cipher);
+        self
     }
 
-    fn log(&self, record: &Record) {
-        let metadata = record.metadata();
-        if self.enabled(metadata) {
-            unsafe {
-                let mut msg = format!(
-                    "[{:?} {} {} {} {}] {}",
-                    SystemTime::UNIX_EPOCH
-                        .elapsed()
-                        .expect("SystemTime::elapsed()"),
-                    libc::gettid(),
-                    level_info(record.level()),
-                    record.file().map_or(Self::EMPTY_STRING, |f| {
-                        record
-                            .line()
-                            .map_or(Self::EMPTY_STRING, |nb| format!("{}:{}", f, nb))
-                    }),
-                    record.target(),
-                    record.args()
-                );
+    /// 记录前缀里时间戳的写法，见 [`TimestampFormat`]。
+    pub fn timestamp(mut self, format: TimestampFormat) -> Self {
+        self.timestamp = format;
+        self
+    }
 
-                if !msg.ends_with('\n') {
-                    msg += "\n";
-                }
+    /// 新建文件时记录的时间戳用哪个时钟，默认是 [`Clock::Realtime`]。
+    ///
+    /// [`Clock::Monotonic`] 的时间戳不会因为校时往回跳，header 里记着 `build()`/`open()` 时
+    /// 两个时钟的对应关系，[`LogReader`](reader::LogReader) 读出来的
+    /// [`Entry`](parse::Entry) 和 [`LogReader::range`](reader::LogReader::range) 都按它换算成
+    /// 墙上时间，见 [`format::Anchor`]。直接看文件或者 [`LogReader::records`](reader::LogReader::records)
+    /// 的话时间戳是单调时钟的读数。时间戳只能写成 [`TimestampFormat::UnixSecondsNanos`]，
+    /// 否则 `build()` 返回 [`Error::InvalidConfig`]。
+    ///
+    /// 打开已有的文件时沿用文件里的时钟，单调时钟的话换上这次打开时的锚点。重启过的话
+    /// 之前写的记录换算不了墙上时间，见 [`format::Anchor::boot_start`]。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    /// use mmlog::Clock;
+    /// use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-clock.log");
+    /// let logger = mmlog::Builder::new().clock(Clock::Monotonic).build(&path).unwrap();
+    /// let log = |msg: &str| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{}", msg))
+    ///             .build(),
+    ///     )
+    /// };
+    /// log("first");
+    /// // 换算回墙上时间会有一点误差
+    /// std::thread::sleep(Duration::from_millis(20));
+    /// let since = SystemTime::now();
+    /// std::thread::sleep(Duration::from_millis(20));
+    /// log("second");
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let anchor = reader.anchor().unwrap();
+    /// let entry = reader.entries().last().unwrap();
+    /// let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    /// assert!(now - entry.timestamp < Duration::from_secs(60));
+    /// // 文件里是单调时钟的读数
+    /// let raw = mmlog::parse::Entry::parse(reader.records().last().unwrap()).unwrap();
+    /// assert_eq!(anchor.to_realtime(raw.timestamp), entry.timestamp);
+    /// let messages: Vec<_> = reader.range(since, SystemTime::now()).map(|e| e.message).collect();
+    /// assert_eq!(messages, ["second"]);
+    ///
+    /// // 打开已有的文件不用再指定
+    /// let logger = mmlog::Builder::new().open(&path).unwrap();
+    /// assert_eq!(logger.clock(), Clock::Monotonic);
+    ///
+    /// let err = mmlog::Builder::new()
+    ///     .clock(Clock::Monotonic)
+    ///     .timestamp(mmlog::TimestampFormat::Rfc3339Utc)
+    ///     .build_anonymous()
+    ///     .unwrap_err();
+    /// assert!(matches!(err, mmlog::Error::InvalidConfig(_)));
+    /// ```
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.map_options.clock = clock;
+        self
+    }
 
-                // 锁住 offset 的变化
-                let _guard = self.spin.lock();
+    /// 每条记录写成一行 JSON 而不是 `[...]` 前缀加消息，字段见 [`format`] 模块。
+    /// 等于 `format_kind(FormatKind::Json)`，关掉的话回到 [`FormatKind::Text`]，见 [`Builder::format_kind`]。
+    /// [`LogReader`](reader::LogReader) 和 `mmlog-cat` 原样输出，还原顺序之后可以直接交给日志收集工具。
+    ///
+    /// [`Builder::format`] 优先；[`Builder::sequence_numbers`] 的 `#<序号> ` 前缀会让行不再是 JSON，不要一起用。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-json.log");
+    /// let logger = mmlog::Builder::new().json(true).build(&path).unwrap();
+    /// logger.log(
+    ///     &log::Record::builder()
+    ///         .level(log::Level::Warn)
+    ///         .target("app")
+    ///         .line(Some(7))
+    ///         .args(format_args!("say \"hi\"\n\\done"))
+    ///         .build(),
+    /// );
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let record = reader.records().next().unwrap();
+    /// assert!(record.starts_with(r#"{"ts":""#));
+    /// assert!(record.ends_with(
+    ///     r#","level":"WARN","target":"app","file":null,"line":7,"msg":"say \"hi\"\n\\done"}"#
+    /// ));
+    /// ```
+    pub fn json(mut self, enable: bool) -> Self {
+        self.format_kind = if enable {
+            FormatKind::Json
+        } else {
+            FormatKind::Text
+        };
+        self
+    }
 
-                let offset = self.offset();
-                let source = msg.as_bytes();
+    /// 记录的写法：默认的 [`FormatKind::Text`]、[`FormatKind::Json`] 或者 [`FormatKind::Logfmt`]，
+    /// 字段见 [`format`] 模块。logger 自己写的提示记录也用这个写法。
+    ///
+    /// logfmt 的记录用 [`Entry::parse_logfmt`](parse::Entry::parse_logfmt) 解析，
+    /// [`LogReader::entries`](reader::LogReader::entries) 两种写法都认得。
+    /// 和 JSON 一样，[`Builder::format`] 优先，也不要和 [`Builder::sequence_numbers`] 一起用。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    /// use mmlog::FormatKind;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-logfmt.log");
+    /// let logger = mmlog::Builder::new()
+    ///     .format_kind(FormatKind::Logfmt)
+    ///     .build(&path)
+    ///     .unwrap();
+    /// let message = "say \"hi\"\nx=1 \\done";
+    /// logger.log(
+    ///     &log::Record::builder()
+    ///         .level(log::Level::Warn)
+    ///         .target("my app")
+    ///         .line(Some(7))
+    ///         .args(format_args!("{}", message))
+    ///         .build(),
+    /// );
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let record = reader.records().next().unwrap();
+    /// assert!(record.starts_with("ts="));
+    /// assert!(record.ends_with(
+    ///     r#" level=warn target="my app" line=7 msg="say \"hi\"\nx=1 \\done""#
+    /// ));
+    /// let entry = reader.entries().next().unwrap();
+    /// assert_eq!(entry.level, log::Level::Warn);
+    /// assert_eq!(entry.target, "my app");
+    /// assert_eq!((entry.file, entry.line), (None, Some(7)));
+    /// assert_eq!(entry.message, message);
+    /// ```
+    pub fn format_kind(mut self, kind: FormatKind) -> Self {
+        self.format_kind = kind;
+        self
+    }
 
-                if offset + source.len() <= self.size() {
-                    let n = (&mut self.as_mut_slice()[offset..])
-                        .write(source)
-                        .expect("Write::write()");
-                    debug_assert_eq!(n, source.len());
-                    self.set_offset(offset + n);
-                } else {
-                    let n = (&mut self.as_mut_slice()[offset..])
-                        .write(source)
-                        .expect("Write::write()");
-                    debug_assert_eq!(n, self.size() - offset);
-                    let left = (source.len() - n) % self.size();
-                    let n = self
-                        .as_mut_slice()
-                        .write(&source[source.len() - left..])
-                        .expect("Write::write()");
-                    debug_assert_eq!(left, n);
-                    self.set_offset(left);
-                }
-            }
-        }
+    /// 用自己的格式化函数代替默认的记录格式，结尾没有换行时会自动补上。
+    ///
+    /// 格式化函数返回错误时这条记录被丢弃，见 [`Logger::format_errors`]。
+    /// logger 自己写的提示记录仍然使用默认格式。
+    /// 不以默认格式的时间戳开头的记录，[`Builder::retention`] 无法判断它们的时间。
+    pub fn format<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut dyn io::Write, &Record) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.format = Some(format::Custom(Arc::new(f)));
+        self
     }
 
-    fn flush(&self) {
-        unsafe {
-            let flags = if self.sync {
-                libc::MS_SYNC
-            } else {
-                libc::MS_ASYNC
-            };
-            debug_assert_ne!(libc::msync(self.addr, self.size as _, flags), -1);
-        }
+    /// 记录之间的分隔方式，见 [`Framing`]，默认 [`Framing::Text`]。
+    ///
+    /// 只对新建的文件有效，[`Builder::open`] 打开已有的文件时沿用文件里的设置。
+    /// [`Framing::LengthPrefixed`] 下比缓冲区还长的记录总是被截断，不受 [`Builder::oversized`] 影响。
+    /// 新建的 [`Framing::LengthPrefixed`] 文件每条记录后面多 4 个字节的校验和，
+    /// 读的时候能认出写坏了的记录，见 [`format::FLAG_CHECKSUM`]。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    /// use mmlog::Framing;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-framing.log");
+    /// let logger = mmlog::Builder::new()
+    ///     .framing(Framing::LengthPrefixed)
+    ///     .build(&path)
+    ///     .unwrap();
+    /// for i in 0..30000 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("first line\nrecord {}", i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let numbers: Vec<u32> = reader
+    ///     .records()
+    ///     .map(|r| r.rsplit(' ').next().unwrap().parse().unwrap())
+    ///     .collect();
+    /// assert!(numbers.windows(2).all(|w| w[1] == w[0] + 1));
+    /// assert_eq!(numbers.last(), Some(&29999));
+    /// ```
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
     }
-}
 
-unsafe impl Send for Logger {}
-unsafe impl Sync for Logger {}
+    /// 文本格式下把消息里的换行写成 `\n`（`\` 和回车也一样转义），一条记录只占一行，
+    /// `grep` 和 [`LogReader`](reader::LogReader) 都不会把它拆开。默认关闭，输出和以前逐字节相同。
+    ///
+    /// 是否转义记在 header 里（[`format::FLAG_ESCAPED_NEWLINES`]），打开已有的文件时以文件为准。
+    /// [`LogReader`](reader::LogReader)、[`Logger::tail`] 和 [`ArchivedBuffer::records`]
+    /// 读出来的是还原之后的记录；`dump_to()` 写出的仍然是转义过的，一行一条。
+    /// 不需要转义的记录不会分配内存。[`Framing::LengthPrefixed`]、[`Builder::json`] 和 logfmt
+    /// 本来就不会把记录拆开，这个选项对它们不起作用。[`RingWriter`] 写的字节原样写入。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-escape-newlines.log");
+    /// let logger = mmlog::Builder::new().escape_newlines(true).build(&path).unwrap();
+    /// let messages = ["panicked at src/main.rs:1\nstack backtrace:\r\n  0: main", r"C:\n\x", "ends\n"];
+    /// for msg in messages {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Error)
+    ///             .args(format_args!("{}", msg))
+    ///             .build(),
+    ///     );
+    /// }
+    /// logger.flush();
+    ///
+    /// let raw = std::fs::read(&path).unwrap();
+    /// let data = mmlog::format::HEADER_SIZE + mmlog::format::META_SIZE;
+    /// let raw = String::from_utf8_lossy(&raw[data..]);
+    /// assert_eq!(raw.trim_end_matches('\0').lines().count(), 3);
+    /// assert!(raw.contains(r"] panicked at src/main.rs:1\nstack backtrace:\r\n  0: main"));
+    ///
+    /// // 消息末尾的换行就是记录的结尾
+    /// let expected = [messages[0], messages[1], "ends"];
+    /// let reader = LogReader::open(&path).unwrap();
+    /// assert!(reader.entries().map(|e| e.message).eq(expected));
+    /// assert!(logger.tail(3).iter().map(|r| r.split_once("] ").unwrap().1).eq(expected));
+    /// ```
+    pub fn escape_newlines(mut self, enable: bool) -> Self {
+        self.map_options.escape_newlines = enable;
+        self
+    }
 
-#[derive(Debug, Default)]
-#[repr(transparent)]
-struct SpinLock(AtomicBool);
-
-impl SpinLock {
-    fn lock(&self) -> LockGuard {
-        loop {
-            if let Ok(false) =
-                self.0
-                    .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
-            {
-                break;
-            }
-        }
-        LockGuard(self)
+    /// 新建文件时写进元数据的标签，例如版本号或者部署的名字，最多 64 个字节，更长的被截断
+    /// （不会拆开字符）。文件里还会记下进程号、进程的启动时间和可执行文件的文件名，
+    /// 见 [`LogReader::metadata`](reader::LogReader::metadata) 和 `mmlog-cat --info`。
+    ///
+    /// 只对新建的文件有效，轮转和 [`Logger::swap_file`] 新建的文件也会写上。
+    ///
+    /// ```
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-tag.log");
+    /// drop(mmlog::Builder::new().tag("v1.2.3 canary").build(&path).unwrap());
+    ///
+    /// let metadata = LogReader::open(&path).unwrap().metadata().unwrap();
+    /// assert_eq!(metadata.pid, std::process::id());
+    /// assert_eq!(metadata.tag.as_deref(), Some("v1.2.3 canary"));
+    /// let exe = std::env::current_exe().unwrap();
+    /// let name = exe.file_name().unwrap().to_string_lossy();
+    /// assert!(name.starts_with(&metadata.exe));
+    /// assert!(metadata.process_started <= std::time::SystemTime::now());
+    ///
+    /// drop(mmlog::Builder::new().tag(&"标签".repeat(20)).build(&path).unwrap());
+    /// let metadata = LogReader::open(&path).unwrap().metadata().unwrap();
+    /// assert_eq!(metadata.tag, Some("标签".repeat(10) + "标"));
+    /// ```
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.map_options.tag = meta::padded(tag);
+        self
     }
 
-    fn unlock(&self) {
-        let result = self
-            .0
-            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst);
-        assert!(matches!(result, Ok(true)));
+    /// 打开文件之后写一条启动标记，logger 被 drop 时（同步之前）写一条结束标记，
+    /// 翻转过的缓冲区里也能看出每次运行从哪里开始、有没有正常退出，
+    /// 见 [`LogReader::runs`](reader::LogReader::runs)。
+    ///
+    /// 两条都是 target 为 `mmlog` 的 Info 记录，不受级别过滤的影响：
+    ///
+    /// ```text
+    /// ==== mmlog start pid=1234 exe=myapp version=0.1.0 ====
+    /// ==== mmlog clean shutdown ====
+    /// ```
+    ///
+    /// `version` 是 mmlog 的版本，换成自己的内容见 [`Builder::banner_with`]。
+    /// [`Logger::close`] 之后 drop 的话没有结束标记。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-banner.log");
+    /// let log = |logger: &mmlog::Logger, msg: &str| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{}", msg))
+    ///             .build(),
+    ///     )
+    /// };
+    /// let logger = mmlog::Builder::new()
+    ///     .banner_with(|| "app=demo commit=abc123".to_string())
+    ///     .build(&path)
+    ///     .unwrap();
+    /// log(&logger, "first run");
+    /// drop(logger);
+    ///
+    /// // 第二次运行同步过之后、没来得及 drop 就“崩溃”了
+    /// let logger = mmlog::Builder::new().banner(true).open(&path).unwrap();
+    /// log(&logger, "second run");
+    /// logger.flush();
+    /// std::mem::forget(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let runs = reader.runs();
+    /// assert_eq!(runs.len(), 2);
+    /// let banner = runs[0].banner.unwrap();
+    /// let pid = std::process::id();
+    /// assert!(banner.contains(&format!("mmlog] ==== mmlog start pid={} exe=", pid)));
+    /// assert!(banner.ends_with(" app=demo commit=abc123 ===="));
+    /// assert_eq!(runs[0].pid(), Some(pid));
+    /// assert_eq!(runs[0].records.len(), 1);
+    /// assert!(runs[0].records[0].ends_with("] first run"));
+    /// assert!(!runs[0].crashed());
+    ///
+    /// assert!(runs[1].banner.unwrap().contains(" version="));
+    /// assert!(runs[1].records[0].ends_with("] second run"));
+    /// assert!(runs[1].crashed());
+    /// ```
+    pub fn banner(mut self, enable: bool) -> Self {
+        self.banner = enable;
+        self
     }
-}
 
-#[derive(Debug)]
-#[repr(transparent)]
-struct LockGuard<'a>(&'a SpinLock);
+    /// 和 [`Builder::banner`] 一样，但是启动标记里 `exe=` 之后的内容由 `f` 生成
+    /// （比如应用自己的版本和提交号），每次打开文件时调用一次。换行会被换成空格。
+    pub fn banner_with<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.banner = true;
+        self.banner_with = Some(format::Banner(Arc::new(f)));
+        self
+    }
 
-impl<'a> Drop for LockGuard<'a> {
-    fn drop(&mut self) {
-        self.0.unlock();
+    /// 新建文件的权限，默认 `0o600`，还要去掉 umask 里的位。
+    ///
+    /// 日志里常有用户数据和 token，默认只有自己能读。只在新建文件时用到：
+    /// 打开已有的文件（包括 `build()` 截断已有的文件）不会改变它的权限。
+    /// 轮转和 [`Logger::swap_file`] 新建的文件也用这个权限。只在 Unix 上有用。
+    ///
+    /// ```
+    /// use std::os::unix::fs::PermissionsExt;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-mode.log");
+    /// let _ = std::fs::remove_file(&path);
+    /// let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    ///
+    /// drop(mmlog::Builder::new().build(&path).unwrap());
+    /// assert_eq!(mode(&path), 0o600);
+    ///
+    /// // 已有的文件保持原来的权限
+    /// std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+    /// drop(mmlog::Builder::new().open(&path).unwrap());
+    /// drop(mmlog::Builder::new().mode(0o600).build(&path).unwrap());
+    /// assert_eq!(mode(&path), 0o640);
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// drop(mmlog::Builder::new().mode(0o640).build(&path).unwrap());
+    /// assert_eq!(mode(&path), 0o640);
+    /// ```
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.map_options.mode = mode;
+        self
+    }
+
+    /// 打开之前先建好缺少的上级目录，权限见 [`Builder::dir_mode`]，已经存在的目录不动。
+    ///
+    /// ```
+    /// use std::os::unix::fs::PermissionsExt;
+    ///
+    /// let dir = std::env::temp_dir().join("mmlog-create-dirs");
+    /// let _ = std::fs::remove_dir_all(&dir);
+    /// let path = dir.join("myapp").join("current.mmlog");
+    /// assert!(mmlog::Builder::new().build(&path).is_err());
+    ///
+    /// let logger = mmlog::Builder::new()
+    ///     .create_dirs(true)
+    ///     .dir_mode(0o750)
+    ///     .build(&path)
+    ///     .unwrap();
+    /// drop(logger);
+    /// for dir in [&dir, &dir.join("myapp")] {
+    ///     assert_eq!(std::fs::metadata(dir).unwrap().permissions().mode() & 0o777, 0o750);
+    /// }
+    /// ```
+    pub fn create_dirs(mut self, enable: bool) -> Self {
+        self.map_options.create_dirs = enable;
+        self
+    }
+
+    /// [`Builder::create_dirs`] 新建的目录的权限，默认 `0o700`，同样要去掉 umask 里的位。
+    pub fn dir_mode(mut self, mode: u32) -> Self {
+        self.map_options.dir_mode = mode;
+        self
+    }
+
+    /// 新建文件时让缓冲区从单独的一页开始，header 之后填 0，见 [`format`] 模块。
+    ///
+    /// 默认 header 和最开头的记录共用一页，每次 flush 都要同步这一页。对齐之后
+    /// flush 只同步 header 所在的页和写过的页，大缓冲区放在闪存上时写放大小一些。
+    /// 文件多占一页，格式版本 5 之前的读取方打不开。打开已有的文件时沿用原来的布局，
+    /// 轮转和 [`Logger::swap_file`] 新建的文件和原来的一样。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::format::{DATA_POS, HEADER_SIZE, META_SIZE, VERSION_POS};
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let word = |file: &[u8], pos: usize| {
+    ///     u32::from_le_bytes(file[pos..pos + 4].try_into().unwrap()) as usize
+    /// };
+    /// let path = std::env::temp_dir().join("mmlog-page-aligned.log");
+    /// let logger = mmlog::Builder::new().page_aligned(true).build(&path).unwrap();
+    /// for i in 0..100 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// drop(logger);
+    ///
+    /// let file = std::fs::read(&path).unwrap();
+    /// let start = word(&file, DATA_POS);
+    /// assert!(start >= 4096 && start.is_power_of_two());
+    /// assert_eq!(file.len(), start + 512 * 1024);
+    /// assert!(file[HEADER_SIZE + META_SIZE..start].iter().all(|&b| b == 0));
+    /// assert!(file[start..].starts_with(b"["));
+    /// let reader = LogReader::open(&path).unwrap();
+    /// assert_eq!(reader.records().count(), 100);
+    ///
+    /// // 版本 4 的文件没有元数据和 DATA_POS，缓冲区紧跟在 header 后面，照样能打开
+    /// let path = std::env::temp_dir().join("mmlog-version-4.log");
+    /// drop(mmlog::Builder::new().build(&path).unwrap());
+    /// let mut file = std::fs::read(&path).unwrap();
+    /// let start = word(&file, DATA_POS);
+    /// assert!(start >= HEADER_SIZE + META_SIZE);
+    /// file.drain(HEADER_SIZE..start);
+    /// file[VERSION_POS..VERSION_POS + 4].copy_from_slice(&4u32.to_le_bytes());
+    /// file[DATA_POS..DATA_POS + 4].fill(0);
+    /// std::fs::write(&path, &file).unwrap();
+    /// let logger = mmlog::Builder::new().open(&path).unwrap();
+    /// logger.log(&log::Record::builder().args(format_args!("still works")).build());
+    /// drop(logger);
+    /// let reader = LogReader::open(&path).unwrap();
+    /// assert!(reader.records().next().unwrap().ends_with("] still works"));
+    /// assert_eq!(word(&std::fs::read(&path).unwrap(), VERSION_POS), 4);
+    /// ```
+    pub fn page_aligned(mut self, enable: bool) -> Self {
+        self.map_options.page_aligned = enable;
+        self
+    }
+
+    /// 映射时加上 `MAP_POPULATE`，一次把整个文件读进内存、建好页表，
+    /// 免得刚开始写的几千条记录碰上缺页。缓冲区越大 `build()`/`open()` 越慢。
+    /// 只有 Linux 有这个 flag，别的系统上什么都不做。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-populate.log");
+    /// let logger = mmlog::Builder::new().populate(true).build(&path).unwrap();
+    /// logger.log(&log::Record::builder().args(format_args!("hello")).build());
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// assert!(reader.records().next().unwrap().ends_with("] hello"));
+    /// ```
+    pub fn populate(mut self, enable: bool) -> Self {
+        self.map_options.populate = enable;
+        self
+    }
+
+    /// `mlock` 整个映射，内存紧张时缓冲区也不会被换出去再缺页。
+    ///
+    /// 映射比 `RLIMIT_MEMLOCK` 大（而且没有 `CAP_IPC_LOCK`）的话锁不住，照常写，
+    /// 只在日志开头写一条 Warn 级别、target 为 `mmlog` 的提示。轮转和
+    /// [`Logger::swap_file`] 新建的文件也会锁上。没有 mmap 的实现什么都不做。
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "mmap", target_os = "linux"))]
+    /// # {
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let locked_kb = || {
+    ///     let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    ///     let line = status.lines().find(|l| l.starts_with("VmLck:")).unwrap();
+    ///     line.split_whitespace().nth(1).unwrap().parse::<u64>().unwrap()
+    /// };
+    /// let before = locked_kb();
+    /// let path = std::env::temp_dir().join("mmlog-lock-memory.log");
+    /// let logger = mmlog::Builder::new().lock_memory(true).build(&path).unwrap();
+    /// let locked = locked_kb() > before;
+    /// drop(logger);
+    ///
+    /// // 锁住了就没有提示，锁不住就有
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let warning = reader.records().find(|r| r.contains("lock_memory"));
+    /// assert_eq!(warning.is_none(), locked);
+    /// # }
+    /// ```
+    pub fn lock_memory(mut self, enable: bool) -> Self {
+        self.lock_memory = enable;
+        self
+    }
+
+    /// 映射之后用 `madvise` 告诉内核会怎么访问缓冲区，默认 [`Advice::Normal`]
+    /// 不调用。只是提示，内核不支持的话什么都不做；没有 mmap 的实现也什么都不做。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    /// use mmlog::Advice;
+    ///
+    /// for advice in [Advice::Sequential, Advice::Random, Advice::WillNeed] {
+    ///     let path = std::env::temp_dir().join(format!("mmlog-advise-{:?}.log", advice));
+    ///     let logger = mmlog::Builder::new().advise(advice).build(&path).unwrap();
+    ///     logger.log(&log::Record::builder().args(format_args!("hello")).build());
+    ///     drop(logger);
+    ///     let reader = LogReader::open(&path).unwrap();
+    ///     assert_eq!(reader.records().count(), 1);
+    /// }
+    /// ```
+    pub fn advise(mut self, advice: Advice) -> Self {
+        self.map_options.advice = advice;
+        self
+    }
+
+    /// 每条记录前面加上 `#<序号> `，序号从 0 开始，保存在文件 header 里，
+    /// 重新打开之后继续递增，见 [`Logger::stats`]。
+    pub fn sequence_numbers(mut self, enable: bool) -> Self {
+        self.sequence = enable;
+        self
+    }
+
+    /// 在线程 id 后面加上线程名，写成 `<tid>/<线程名>`，没有名字的线程还是只有 tid。
+    ///
+    /// 线程名和 `sanitize` 过的文件名一样处理，但可能包含空格。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-thread-names.log");
+    /// let logger = mmlog::Builder::new().thread_names(true).build(&path).unwrap();
+    /// std::thread::scope(|s| {
+    ///     std::thread::Builder::new()
+    ///         .name("worker".to_string())
+    ///         .spawn_scoped(s, || {
+    ///             logger.log(
+    ///                 &log::Record::builder()
+    ///                     .level(log::Level::Info)
+    ///                     .args(format_args!("hello"))
+    ///                     .build(),
+    ///             );
+    ///         })
+    ///         .unwrap();
+    /// });
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let record = reader.records().next().unwrap();
+    /// assert!(record.contains("/worker I "), "{}", record);
+    /// ```
+    pub fn thread_names(mut self, enable: bool) -> Self {
+        self.thread_names = enable;
+        self
+    }
+
+    /// 按顶层 target 统计写入的字节数，见 [`Logger::top_targets`]。
+    pub fn track_targets(mut self, enable: bool) -> Self {
+        self.track_targets = enable;
+        self
+    }
+
+    /// 限制某个模块（target 前缀，和 [`Builder::module_level`] 一样按 `::` 分段匹配）
+    /// 在缓冲区每一轮（翻转一次算一轮）里最多写入的字节数，
+    /// 超出的记录会被丢弃，并写入一条提示记录。可以调用多次，最长的匹配优先。
+    pub fn target_quota(mut self, target: &str, bytes: u64) -> Self {
+        self.target_quotas.retain(|(t, _)| t != target);
+        self.target_quotas.push((target.to_string(), bytes));
+        // 长的在前，按顺序找到的第一个就是最长的匹配
+        self.target_quotas
+            .sort_by_key(|(t, _)| std::cmp::Reverse(t.len()));
+        self
+    }
+
+    /// 统计写锁的竞争情况，见 [`Logger::lock_stats`]。
+    pub fn lock_metrics(mut self, enable: bool) -> Self {
+        self.lock_metrics = enable;
+        self
+    }
+
+    /// `log()` 拿不到写锁时不等待，直接丢掉这条记录，计入 [`Stats::records_dropped_contention`]。
+    ///
+    /// 给音频线程之类宁可少一行日志也不能卡住的场合用：写锁只试一次（一次 `compare_exchange`），
+    /// [`Builder::shared`] 的进程间锁也一样。格式化仍然照常做，需要的话先用 [`Builder::level`]
+    /// 过滤掉。个别需要保证写入的记录用 [`Logger::log_blocking`]；`flush()`、[`RingWriter`]
+    /// 等其他操作照常等锁。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use std::thread;
+    ///
+    /// let logger = mmlog::Builder::new().non_blocking(true).build_anonymous().unwrap();
+    /// let log = |msg: &str| {
+    ///     logger.log(&log::Record::builder().level(log::Level::Info).args(format_args!("{}", msg)).build())
+    /// };
+    /// thread::scope(|s| {
+    ///     for _ in 0..4 {
+    ///         s.spawn(|| {
+    ///             for _ in 0..20000 {
+    ///                 log("record");
+    ///             }
+    ///         });
+    ///     }
+    /// });
+    /// // 拿不到锁的记录被丢掉，计在 records_dropped_contention 里
+    /// let stats = logger.stats();
+    /// assert_eq!(stats.records_written + stats.records_dropped_contention, 80000);
+    ///
+    /// // log_blocking() 会等到锁被放开
+    /// logger.log_blocking(
+    ///     &log::Record::builder()
+    ///         .level(log::Level::Info)
+    ///         .args(format_args!("must be written"))
+    ///         .build(),
+    /// );
+    /// assert!(logger.tail(1)[0].ends_with("] must be written"));
+    /// ```
+    pub fn non_blocking(mut self, enable: bool) -> Self {
+        self.non_blocking = enable;
+        self
+    }
+
+    /// 每个线程先把格式化好的记录攒在自己的缓冲区里，攒够 `flush_bytes` 字节才拿一次写锁，
+    /// 把整批记录写进去。很多线程同时写日志时写锁不再是瓶颈，见 `examples/contention.rs`。
+    ///
+    /// 同一个线程的记录保持顺序，不同线程的记录以批为单位交错。`flush()`、[`Logger::try_flush`]、
+    /// [`Logger::close`]、drop 和 [`Builder::panic_hook`] 会先把所有线程攒着的记录写进去，
+    /// 达到 [`Builder::sync_on`] 级别的记录也会让这个线程的这一批马上写进去。
+    /// 还没写进去的记录在 [`Logger::stats`]、[`Logger::tail`] 和文件里都看不到，进程被杀掉的话
+    /// 就丢了；[`Builder::flush_interval`] 的后台线程也不会写它们。
+    /// 整批写入时总是等锁，不受 [`Builder::non_blocking`] 影响。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use std::thread;
+    ///
+    /// let logger = mmlog::Builder::new().batched(4096).build_anonymous().unwrap();
+    /// let log = |t: u32, i: u32| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("thread {} record {}", t, i))
+    ///             .build(),
+    ///     )
+    /// };
+    /// log(0, 0);
+    /// assert_eq!(logger.stats().records_written, 0);
+    /// thread::scope(|s| {
+    ///     for t in 1..=4 {
+    ///         s.spawn(move || (0..1000).for_each(|i| log(t, i)));
+    ///     }
+    /// });
+    /// // 退出了的线程攒着的记录也会写进去
+    /// logger.flush();
+    /// assert_eq!(logger.stats().records_written, 4001);
+    ///
+    /// let records = logger.tail(4001);
+    /// for t in 1..=4 {
+    ///     let prefix = format!("] thread {} record ", t);
+    ///     let numbers: Vec<u32> = records
+    ///         .iter()
+    ///         .filter_map(|r| r.split_once(&prefix).map(|(_, i)| i.parse().unwrap()))
+    ///         .collect();
+    ///     assert_eq!(numbers, (0..1000).collect::<Vec<_>>());
+    /// }
+    /// ```
+    pub fn batched(mut self, flush_bytes: usize) -> Self {
+        self.batched = Some(flush_bytes);
+        self
+    }
+
+    /// 每条 Warn/Error 记录之后的 `window` 时间内，每写一条记录都同步一次（`MS_SYNC`），
+    /// 平时仍按 [`Builder::sync`] 的策略在 `flush()` 时同步。
+    pub fn adaptive_flush(mut self, window: Duration) -> Self {
+        self.adaptive_flush = Some(window);
+        self
+    }
+
+    /// 进入和退出 [`Builder::adaptive_flush`] 模式时各写一条标记记录。
+    pub fn adaptive_flush_markers(mut self, enable: bool) -> Self {
+        self.adaptive_markers = enable;
+        self
+    }
+
+    /// 每个调用点（记录的 `file` 和 `line`）在 `window` 时间内最多写 `per_callsite` 条记录，
+    /// 多出来的直接丢掉，一个疯狂打日志的循环不会冲掉别的历史。窗口过去之后这个调用点的下一条记录之前
+    /// 先写一条 `Warn` 级别、target 为 `mmlog` 的提示 `rate limit: suppressed N similar messages from file:line`。
+    ///
+    /// 最近的调用点记在一张固定大小（64 个槽位）的表里，同时活跃的调用点太多的话会互相挤掉，
+    /// 被挤掉的调用点有被压掉的记录的话马上写提示。表有自己的锁，在拿写锁之前判断，被压掉的记录
+    /// 不会格式化，也不会转给 [`Builder::also_log`] 等；没有 `file` 或 `line` 的记录不限流。
+    /// 不设置的话 `log()` 只多一次判断。被压掉的记录数见 [`Logger::rate_limited`]。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use std::time::Duration;
+    ///
+    /// let logger = mmlog::Builder::new()
+    ///     .rate_limit(5, Duration::from_millis(200))
+    ///     .build_anonymous()
+    ///     .unwrap();
+    /// let log = |line: u32, i: u32| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Error)
+    ///             .file(Some("src/net.rs"))
+    ///             .line(Some(line))
+    ///             .args(format_args!("connect failed {}", i))
+    ///             .build(),
+    ///     )
+    /// };
+    /// for i in 0..1000 {
+    ///     log(10, i);
+    /// }
+    /// // 别的调用点不受影响
+    /// log(20, 0);
+    /// assert_eq!(logger.stats().records_written, 6);
+    /// assert_eq!(logger.rate_limited(), 995);
+    /// assert_eq!(logger.stats().records_dropped, 995);
+    ///
+    /// std::thread::sleep(Duration::from_millis(250));
+    /// log(10, 1000);
+    /// let tail = logger.tail(2);
+    /// assert!(tail[0].ends_with("rate limit: suppressed 995 similar messages from src/net.rs:10"));
+    /// assert!(tail[1].ends_with("] connect failed 1000"));
+    /// ```
+    pub fn rate_limit(mut self, per_callsite: u32, window: Duration) -> Self {
+        self.rate_limit = Some((per_callsite, window));
+        self
+    }
+
+    /// [`dbg!`] 单个值最多输出的字节数，默认 64 KB，见 [`fmt::bounded`]。
+    ///
+    /// 这是全局设置，在 `build()`/`open()` 时生效。
+    pub fn dbg_budget(mut self, bytes: usize) -> Self {
+        self.dbg_budget = Some(bytes);
+        self
+    }
+
+    /// 保留期限：读出和导出记录时跳过时间戳早于 `now - retention` 的记录，`now` 是读的时候的时间。
+    ///
+    /// 保留期限写在新建的文件的元数据里（见 [`format::RETENTION_POS`]），[`Builder::open`]
+    /// 打开已有的文件时沿用文件里的设置。[`LogReader`](reader::LogReader)（包括
+    /// [`LogReader::follow`](reader::LogReader::follow)）、[`Logger::dump_to`]、[`Logger::snapshot`]、
+    /// [`ArchivedBuffer`] 和 `mmlog-cat` 都看不到过期的记录，只有
+    /// [`LogReader::open_ignoring_retention`](reader::LogReader::open_ignoring_retention) 和
+    /// `mmlog-cat --ignore-retention` 例外。过期的记录仍然留在缓冲区里，直到被覆盖。
+    ///
+    /// 读不出时间戳的行（没转义的换行拆出来的）跟随前一条记录的去留。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    /// use std::time::Duration;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-retention.log");
+    /// let logger = mmlog::Builder::new()
+    ///     .retention(Duration::from_secs(24 * 3600))
+    ///     .build(&path)
+    ///     .unwrap();
+    /// let log = |msg: &str| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{}", msg))
+    ///             .build(),
+    ///     )
+    /// };
+    /// mmlog::test::freeze(Duration::from_secs(1_700_000_000), 1);
+    /// log("two days ago");
+    /// mmlog::test::freeze(Duration::from_secs(1_700_000_000 + 2 * 24 * 3600), 1);
+    /// log("now");
+    /// logger.flush();
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// assert!(reader.records().map(|r| r.rsplit_once("] ").unwrap().1).eq(["now"]));
+    /// let reader = LogReader::open_ignoring_retention(&path, None).unwrap();
+    /// assert_eq!(reader.records().count(), 2);
+    /// mmlog::test::thaw();
+    /// ```
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.map_options.retention = Some(retention);
+        self
+    }
+
+    /// 转义 target 和文件名里的 `]`、控制字符和 ANSI 转义序列，防止伪造记录前缀，默认开启。
+    /// 消息内容不受影响。
+    pub fn sanitize(mut self, enable: bool) -> Self {
+        self.sanitize = enable;
+        self
+    }
+
+    /// 通过 `metrics` 门面报告 logger 自身的状况：`<prefix>.records_written`、
+    /// `<prefix>.bytes_written`、`<prefix>.dropped`、`<prefix>.flush_errors`
+    /// 和 `<prefix>.utilization`。`<prefix>.dropped` 和 [`Stats::records_dropped`]
+    /// 算的是同样的记录。
+    ///
+    /// 指标在 `build()`/`open()` 时注册，所以要先安装好 recorder。
+    #[cfg(feature = "metrics")]
+    pub fn emit_metrics(mut self, prefix: &str) -> Self {
+        self.metrics_prefix = Some(prefix.to_string());
+        self
+    }
+
+    fn make_sense(&mut self) -> Result<()> {
+        if let Some(e) = self.env_error.take() {
+            return Err(Error::Config(e));
+        }
+        if self.strict {
+            self.reject_adjustments()?;
+        }
+        if self.size < Self::MIN_SIZE {
+            self.size = Self::MIN_SIZE;
+        }
+        let reserve = self.map_options.reserve;
+        if reserve != 0.0 {
+            let reserved = (self.size as f64 * reserve).round() as usize / 8 * 8;
+            if !(0.0..1.0).contains(&reserve)
+                || reserved < Self::MIN_RING
+                || self.size - reserved < Self::MIN_RING
+            {
+                return Err(Error::InvalidConfig(
+                    "reserve_for_errors doesn't leave two rings of at least 4 KiB",
+                ));
+            }
+        }
+        if !self.channels.is_empty() {
+            self.channel_table()?;
+        }
+        if self.process_shared {
+            self.rotate = None;
+        }
+        if self.map_options.clock == Clock::Monotonic
+            && self.timestamp != TimestampFormat::UnixSecondsNanos
+        {
+            return Err(Error::InvalidConfig(
+                "clock(Monotonic) only works with TimestampFormat::UnixSecondsNanos",
+            ));
+        }
+        if self.cipher.is_some() {
+            #[cfg(feature = "compression")]
+            if self.compress_rotated {
+                return Err(Error::InvalidConfig(
+                    "compress_rotated would store encrypted records in plain text",
+                ));
+            }
+            self.framing = Framing::LengthPrefixed;
+        }
+        if self.framing != Framing::Text || self.format_kind != FormatKind::Text {
+            self.map_options.escape_newlines = false;
+        }
+        Ok(())
+    }
+
+    // Builder::strict：make_sense() 会悄悄调整的设置
+    fn reject_adjustments(&self) -> Result<()> {
+        if !self.channels.is_empty() && self.resize {
+            return Err(Error::InvalidConfig("size has no effect with channels"));
+        }
+        if self.channels.is_empty() && self.size < Self::MIN_SIZE {
+            return Err(Error::InvalidConfig("size is below the 512 KiB minimum"));
+        }
+        if self.process_shared && self.rotate.is_some() {
+            return Err(Error::InvalidConfig("rotate can't be combined with shared"));
+        }
+        if self.flush_interval == Some(Duration::ZERO) {
+            return Err(Error::InvalidConfig(
+                "flush_interval must be greater than zero",
+            ));
+        }
+        if self.map_options.escape_newlines
+            && (self.framing != Framing::Text || self.format_kind != FormatKind::Text)
+        {
+            return Err(Error::InvalidConfig(
+                "escape_newlines only works with Framing::Text and FormatKind::Text",
+            ));
+        }
+        Ok(())
+    }
+
+    // Builder::channels：检查名字和长度，填好通道表，缓冲区的大小是加起来的
+    fn channel_table(&mut self) -> Result<()> {
+        if self.map_options.reserve != 0.0 {
+            return Err(Error::InvalidConfig(
+                "channels can't be combined with reserve_for_errors",
+            ));
+        }
+        if self.channels.len() > format::MAX_CHANNELS {
+            return Err(Error::InvalidConfig("at most 16 channels are allowed"));
+        }
+        let mut table = [None; format::MAX_CHANNELS];
+        for (i, (name, capacity)) in self.channels.iter().enumerate() {
+            if name.is_empty() || name.len() > format::CHANNEL_NAME_LEN || name.contains('\0') {
+                return Err(Error::InvalidConfig(
+                    "channel names must be 1 to 32 bytes without NUL",
+                ));
+            }
+            if self.channels[..i].iter().any(|(other, _)| other == name) {
+                return Err(Error::InvalidConfig("duplicate channel name"));
+            }
+            if *capacity < Self::MIN_RING {
+                return Err(Error::InvalidConfig("each channel needs at least 4 KiB"));
+            }
+            table[i] = Some(ChannelSpec {
+                name: meta::padded(name),
+                capacity: capacity / 8 * 8,
+            });
+        }
+        self.size = table.iter().flatten().map(|c| c.capacity).sum();
+        self.map_options.channels = table;
+        Ok(())
+    }
+
+    // Builder::parse_env 设置了文件的话用它
+    fn path<'a>(&'a self, name: &'a Path) -> &'a Path {
+        self.file.as_deref().unwrap_or(name)
+    }
+
+    /// 新建（或截断）文件开始写。
+    ///
+    /// 文件的空间在这里就分配好（`posix_fallocate`，文件系统不支持的话写 0），
+    /// 磁盘不够时返回 `ErrorKind::StorageFull` 的 [`Error::StdIo`]，而不是写到一半收到 `SIGBUS`：
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "mmap", target_os = "linux"))]
+    /// # {
+    /// use std::ffi::CString;
+    ///
+    /// // 需要挂载一个很小的 tmpfs，没有权限的话跳过
+    /// let dir = std::env::temp_dir().join("mmlog-tiny-fs");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let target = CString::new(dir.to_str().unwrap()).unwrap();
+    /// let mounted = unsafe {
+    ///     libc::mount(
+    ///         c"tmpfs".as_ptr(),
+    ///         target.as_ptr(),
+    ///         c"tmpfs".as_ptr(),
+    ///         0,
+    ///         c"size=256k".as_ptr().cast(),
+    ///     ) == 0
+    /// };
+    /// if mounted {
+    ///     let result = mmlog::Builder::new().build(dir.join("full.log"));
+    ///     unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) };
+    ///     match result {
+    ///         Err(mmlog::Error::StdIo(e)) => assert_eq!(e.kind(), std::io::ErrorKind::StorageFull),
+    ///         other => panic!("expected ENOSPC, got {:?}", other.map(|_| ())),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// 路径不要求是 UTF-8：
+    ///
+    /// ```
+    /// use std::ffi::OsStr;
+    /// use std::os::unix::ffi::OsStrExt;
+    ///
+    /// let path = std::env::temp_dir().join(OsStr::from_bytes(b"mmlog-\xff\xfe.log"));
+    /// drop(mmlog::Builder::new().build(&path).unwrap());
+    /// assert!(mmlog::Builder::new().open(&path).is_ok());
+    ///
+    /// // 中间有 0 的路径不行
+    /// let nul = std::env::temp_dir().join(OsStr::from_bytes(b"mmlog-\0.log"));
+    /// assert!(mmlog::Builder::new().build(&nul).is_err());
+    /// ```
+    pub fn build<P: AsRef<Path>>(mut self, name: P) -> Result<Logger> {
+        self.make_sense()?;
+        Logger::new(self.path(name.as_ref()), &self)
+    }
+
+    /// 打开已有的文件，从上次的位置继续写。
+    ///
+    /// 没有调用过 [`Builder::size`] 的话缓冲区大小以文件 header 里记录的为准。调用过、
+    /// 而且和文件里的不一样的话，按时间顺序把记录搬到一个新大小的文件里，改名替换原来的文件，
+    /// 计数接着往下数；改小见 [`Builder::allow_shrink`]。[`Builder::shared`] 的文件
+    /// 可能还有别的进程在写，不能改大小，返回 [`Error::InvalidConfig`]。
+    ///
+    /// [`Framing::LengthPrefixed`] 的文件在掉电或者被杀掉时，header 里的 offset 可能已经越过了
+    /// 最后一条记录，记录本身却没有写完。打开时从头检查到 offset 为止的记录，最后一条不完整
+    /// （或者校验和不对）的话把它清零，offset 退回到上一条记录的结尾，再写一条 `Warn` 提示；
+    /// [`LogReader`](reader::LogReader) 读到这样的文件也会跳过这一条，不当作坏记录：
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::format::{DATA_POS, FRAME_CHECKSUM, FRAME_PREFIX, OFFSET_POS};
+    /// use mmlog::reader::LogReader;
+    /// use mmlog::Framing;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-torn.log");
+    /// let builder = || mmlog::Builder::new().framing(Framing::LengthPrefixed);
+    /// let log = |logger: &mmlog::Logger, i: u32| {
+    ///     logger.log(&log::Record::builder().args(format_args!("record {}", i)).build())
+    /// };
+    /// let logger = builder().build(&path).unwrap();
+    /// (0..3).for_each(|i| log(&logger, i));
+    /// drop(logger);
+    ///
+    /// // 模拟崩溃：offset 已经越过了第 4 条记录，记录只写了长度前缀和一部分内容
+    /// const WORD: usize = std::mem::size_of::<usize>();
+    /// let mut file = std::fs::read(&path).unwrap();
+    /// let offset = usize::from_ne_bytes(file[OFFSET_POS..OFFSET_POS + WORD].try_into().unwrap());
+    /// let start = u32::from_le_bytes(file[DATA_POS..DATA_POS + 4].try_into().unwrap()) as usize;
+    /// let at = start + offset;
+    /// file[at..at + FRAME_PREFIX].copy_from_slice(&100u32.to_le_bytes());
+    /// file[at + FRAME_PREFIX..at + FRAME_PREFIX + 10].copy_from_slice(b"[17000000.");
+    /// let torn = offset + FRAME_PREFIX + 100 + FRAME_CHECKSUM;
+    /// file[OFFSET_POS..OFFSET_POS + WORD].copy_from_slice(&torn.to_ne_bytes());
+    /// std::fs::write(&path, &file).unwrap();
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// assert_eq!(reader.checked_records().count(), 3);
+    /// assert!(reader.checked_records().all(|r| r.is_ok()));
+    ///
+    /// // 接着上一条完整的记录写，而不是写在半条记录后面
+    /// let logger = builder().open(&path).unwrap();
+    /// log(&logger, 3);
+    /// drop(logger);
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let records: Vec<&str> = reader.records().collect();
+    /// assert_eq!(records.len(), 5);
+    /// assert!(records[3].ends_with(&format!("offset rolled back from {} to {}", torn, offset)));
+    /// assert!(records[4].ends_with("record 3"));
+    /// assert!(reader.checked_records().all(|r| r.is_ok()));
+    /// ```
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::{Framing, MB};
+    ///
+    /// for framing in [Framing::Text, Framing::LengthPrefixed] {
+    ///     let path = std::env::temp_dir().join(format!("mmlog-grow-{:?}.log", framing));
+    ///     let builder = || mmlog::Builder::new().framing(framing);
+    ///     let log = |logger: &mmlog::Logger, i| {
+    ///         logger.log(&log::Record::builder().args(format_args!("record {}", i)).build())
+    ///     };
+    ///     let numbers = |logger: &mmlog::Logger| -> Vec<u32> {
+    ///         let mut out = Vec::new();
+    ///         logger.dump_to(&mut out).unwrap();
+    ///         String::from_utf8(out)
+    ///             .unwrap()
+    ///             .lines()
+    ///             .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+    ///             .collect()
+    ///     };
+    ///     // 写够好几圈，保证翻转过
+    ///     let logger = builder().build(&path).unwrap();
+    ///     (0..30000).for_each(|i| log(&logger, i));
+    ///     let before = numbers(&logger);
+    ///     drop(logger);
+    ///
+    ///     // 不调用 size() 的话沿用文件里的大小
+    ///     let logger = builder().open(&path).unwrap();
+    ///     assert_eq!(logger.stats().capacity, 512 * 1024);
+    ///     drop(logger);
+    ///
+    ///     let logger = builder().size(2 * MB).open(&path).unwrap();
+    ///     assert_eq!(logger.stats().capacity, 2 * MB);
+    ///     assert_eq!(logger.stats().records_written, 30000);
+    ///     assert_eq!(numbers(&logger), before);
+    ///     (30000..40000).for_each(|i| log(&logger, i));
+    ///     let after = numbers(&logger);
+    ///     assert!(after.len() > before.len());
+    ///     assert!(after.windows(2).all(|w| w[1] == w[0] + 1));
+    ///     assert_eq!(after.last(), Some(&39999));
+    /// }
+    /// ```
+    pub fn open<P: AsRef<Path>>(mut self, name: P) -> Result<Logger> {
+        self.make_sense()?;
+        Logger::open(self.path(name.as_ref()), &self)
+    }
+
+    /// 文件存在时和 [`Builder::open`] 一样从上次的位置继续写，否则新建，
+    /// 不会截断已有的文件。
+    ///
+    /// 新建时使用 [`Builder::size`]，已有的文件和 [`Builder::open`] 一样，
+    /// 调用过 [`Builder::size`] 的话改成那个大小。几个线程或者进程同时调用时只有一个会新建，
+    /// 别的打开它建好的文件。
+    pub fn open_or_create<P: AsRef<Path>>(mut self, name: P) -> Result<Logger> {
+        self.make_sense()?;
+        Logger::open_or_create(self.path(name.as_ref()), &self)
+    }
+
+    /// 只读打开已有的文件，返回一份 [`LogReader`](reader::LogReader) 快照，用来在只有读权限的账号下分析日志。
+    ///
+    /// 文件用 `O_RDONLY` 打开，`PROT_READ | MAP_PRIVATE` 映射，不会 `ftruncate`、不加锁，
+    /// 也不会写回任何东西，文件不存在时不会新建。除了 [`Builder::parse_env`] 的 `<prefix>_FILE`
+    /// 之外，别的设置都不起作用，格式和大小以文件里的 header 为准。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use std::os::unix::fs::PermissionsExt;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-readonly.log");
+    /// let _ = std::fs::remove_file(&path);
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// logger.log(&log::Record::builder().args(format_args!("hello")).build());
+    /// drop(logger);
+    ///
+    /// std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o400)).unwrap();
+    /// let before = std::fs::read(&path).unwrap();
+    /// let reader = mmlog::Builder::new().size(mmlog::MB).open_readonly(&path).unwrap();
+    /// assert!(reader.records().next().unwrap().ends_with("] hello"));
+    /// assert_eq!(reader.stats().capacity, 512 * 1024);
+    /// drop(reader);
+    /// assert_eq!(std::fs::read(&path).unwrap(), before);
+    ///
+    /// let missing = std::env::temp_dir().join("mmlog-readonly-missing.log");
+    /// assert!(mmlog::Builder::new().open_readonly(&missing).is_err());
+    /// assert!(!missing.exists());
+    /// ```
+    ///
+    /// 返回的 `LogReader` 没有实现 [`Log`]，不能装成全局 logger，往只读的文件里写日志在编译时就会报错：
+    ///
+    /// ```compile_fail
+    /// let reader = mmlog::Builder::new().open_readonly("app.log").unwrap();
+    /// log::set_logger(Box::leak(Box::new(reader))).unwrap();
+    /// ```
+    pub fn open_readonly<P: AsRef<Path>>(mut self, name: P) -> Result<reader::LogReader> {
+        self.make_sense()?;
+        reader::LogReader::open_with(self.path(name.as_ref()), self.cipher.clone())
+    }
+
+    /// 不用文件，写到一块 [`Builder::size`] 大小的匿名共享内存里（没有 mmap 的实现是堆内存），
+    /// header 和文件里的一样，翻转、格式、[`Logger::stats`]、[`Logger::tail`]、
+    /// [`Logger::dump_to`] 都照常工作。`flush()` 什么都不做，drop 时直接释放。
+    ///
+    /// 适合测试和不需要留下文件的场合。[`Builder::rotate`] 在这里没有意义，会被忽略；
+    /// [`Builder::shared`] 的话 fork 出来的子进程也能写。
+    ///
+    /// ```
+    /// use log::Log;
+    ///
+    /// let logger = mmlog::Builder::new().build_anonymous().unwrap();
+    /// let payload = "x".repeat(1000);
+    /// for i in 0..1000 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{} {}", payload, i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// logger.flush();
+    ///
+    /// let stats = logger.stats();
+    /// assert_eq!(stats.records_written, 1000);
+    /// assert!(stats.wrap_count > 0);
+    /// let tail = logger.tail(2);
+    /// assert!(tail[0].ends_with(" 998") && tail[1].ends_with(" 999"));
+    ///
+    /// let mut out = Vec::new();
+    /// logger.dump_to(&mut out).unwrap();
+    /// let out = String::from_utf8(out).unwrap();
+    /// assert!(out.lines().count() < 1000);
+    /// assert!(out.ends_with(&format!("] {} 999\n", payload)));
+    /// ```
+    pub fn build_anonymous(mut self) -> Result<Logger> {
+        self.make_sense()?;
+        self.rotate = None;
+        let mapping = Mapping::anonymous(
+            self.size,
+            self.framing,
+            self.process_shared,
+            &self.map_options,
+        )?;
+        Logger::with_mapping(mapping, &self, false)
+    }
+
+    /// 用 [`Builder::open_or_create`] 打开文件，并安装成全局 logger，
+    /// 最大级别设成 [`Builder::level`] 和 [`Builder::module_level`] 里最详细的那个。
+    ///
+    /// 返回的引用可以用来 `flush()`。已经安装过 logger 时返回 [`Error::SetLogger`]。
+    pub fn init<P: AsRef<Path>>(self, name: P) -> Result<&'static Logger> {
+        let panic_hook = self.panic_hook;
+        let logger: &'static Logger = Box::leak(Box::new(self.open_or_create(name)?));
+        if let Err(e) = log::set_logger(logger) {
+            // 没装上，没有别人持有这个引用
+            unsafe { drop(Box::from_raw(logger as *const Logger as *mut Logger)) };
+            return Err(e.into());
+        }
+        logger.installed.store(true, Ordering::Relaxed);
+        log::set_max_level(logger.max_level());
+        // 之前缓存的是没有 logger 时的结果
+        invalidate_callsites();
+        if panic_hook {
+            logger.install_panic_hook();
+        }
+        Ok(logger)
+    }
+}
+
+/// 写入 mmap 缓冲区的 logger。
+///
+/// 默认格式的记录先在写锁外格式化到每个线程复用的缓冲区里，不用每条记录分配内存。
+/// 日志参数的 `Display` 实现里也可以再写日志。
+#[derive(Debug)]
+pub struct Logger {
+    shared: Arc<Shared>,
+    flusher: Option<Flusher>,
+    // 登记在 fork 模块里，见 Builder::fork_safe
+    fork_safe: bool,
+    // 开始时和 drop 时写标记，见 Builder::banner
+    banner: bool,
+    banner_with: Option<format::Banner>,
+    capacity: usize,
+    // 最小的那个环的长度，一条记录最长多少按它算，见 Builder::reserve_for_errors
+    ring_capacity: usize,
+    // 文件里的通道和它们的级别（LevelFilter as usize），第一个通道的级别就是 level，
+    // 这里的不用，见 Builder::channels
+    channels: Vec<(String, AtomicUsize)>,
+    // 文件里有没有进程间锁，见 Builder::shared
+    process_shared: bool,
+    // 是否持有文件的 flock，见 Builder::exclusive
+    exclusive: bool,
+    // LevelFilter as usize
+    level: AtomicUsize,
+    modules: Vec<(String, LevelFilter)>,
+    // 是否通过 Builder::init() 安装成了全局 logger
+    installed: AtomicBool,
+    sanitize: bool,
+    format_kind: FormatKind,
+    oversized: Oversized,
+    max_record_len: Option<usize>,
+    // Builder::rotate 保留几个旧文件
+    rotate: Option<usize>,
+    framing: Framing,
+    // 轮转和 swap_file() 新建文件时用，page_aligned 跟着打开的文件
+    map_options: MapOptions,
+    // 见 Builder::lock_memory
+    lock_memory: bool,
+    // 见 Builder::escape_newlines，跟着打开的文件
+    escape: bool,
+    sequence: bool,
+    // 能不能格式化到每个线程复用的 SCRATCH 里：自定义格式、长度前缀、截断、配额和轮转
+    // 都要改格式化好的记录，只能走每条分配一个 String 的路子；tee 到 stderr 也要用到这个 String
+    direct: bool,
+    timestamp: TimestampFormat,
+    clock: Clock,
+    // Builder::encrypt 的密钥，和文件里的对得上
+    cipher: Option<crypt::Cipher>,
+    thread_names: bool,
+    format: Option<format::Custom>,
+    format_errors: AtomicU64,
+    // 因为内部错误丢掉的记录，见 Stats::records_dropped
+    internal_dropped: AtomicU64,
+    records_truncated: AtomicU64,
+    // 见 Builder::non_blocking
+    non_blocking: bool,
+    contention_dropped: AtomicU64,
+    batches: Option<Batches>,
+    published: Published,
+    sync: bool,
+    sync_on: Option<Level>,
+    generation: AtomicUsize,
+    targets: Option<Box<Targets>>,
+    adaptive: Option<Adaptive>,
+    rate_limit: Option<RateLimit>,
+    health: Option<Health>,
+    tee: Option<Tee>,
+    #[cfg(feature = "compression")]
+    compressor: Option<rotate::Compressor>,
+}
+
+// 写锁和它保护的映射，Builder::flush_interval 的后台线程也要用
+#[derive(Debug)]
+struct Shared {
+    spin: SpinLock,
+    // 只能在持有写锁时访问，close() 之后为 None
+    mapping: UnsafeCell<Option<Mapping>>,
+    // 后台线程和轮转的错误，见 report 模块
+    reports: Arc<report::Reports>,
+}
+
+impl Shared {
+    // 进程内的自旋锁，文件里有进程间锁的话再拿上它
+    fn lock(&self) -> Guard<'_> {
+        let spin = self.spin.lock();
+        let process = unsafe { self.mapping() }.and_then(|m| m.lock_process());
+        Guard {
+            _process: process,
+            _spin: spin,
+        }
+    }
+
+    // Builder::non_blocking：自旋锁和进程间锁都只试一次，哪个拿不到都返回 None
+    fn try_lock(&self) -> Option<Guard<'_>> {
+        let spin = self.spin.try_lock()?;
+        let process = match unsafe { self.mapping() } {
+            Some(mapping) => mapping.try_lock_process().ok()?,
+            None => None,
+        };
+        Some(Guard {
+            _process: process,
+            _spin: spin,
+        })
+    }
+
+    /// 调用者必须持有写锁
+    unsafe fn mapping(&self) -> Option<&Mapping> {
+        (*self.mapping.get()).as_ref()
+    }
+
+    // fork 出来的子进程里只剩调用 fork() 的线程，别的线程拿着的自旋锁永远不会放开了
+    fn post_fork_child(&self) {
+        self.spin.reset();
+        let _spin = self.spin.lock();
+        if let Some(mapping) = unsafe { self.mapping() } {
+            mapping.revalidate_process_lock();
+        }
+    }
+}
+
+// 按声明的顺序释放：先进程间锁，再自旋锁
+struct Guard<'a> {
+    _process: Option<ProcessLock>,
+    _spin: LockGuard<'a>,
+}
+
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+// Logger::stats() 读的计数：每次在写锁里改了 header 之后复制一份，读的时候不用拿写锁，
+// 也不怕映射在 swap_file()、轮转或者 close() 时被解除
+#[derive(Debug, Default)]
+struct Published {
+    offset: AtomicUsize,
+    records: AtomicU64,
+    bytes: AtomicU64,
+    wraps: AtomicU64,
+}
+
+impl Published {
+    // 调用者必须持有写锁，None 表示已经 close() 了
+    fn store(&self, mapping: Option<&Mapping>) {
+        let (offset, (records, bytes, wraps)) = match mapping {
+            Some(mapping) => (mapping.ring_offset(Ring::Main), mapping.counters()),
+            None => (0, (0, 0, 0)),
+        };
+        self.offset.store(offset, Ordering::Relaxed);
+        self.records.store(records, Ordering::Relaxed);
+        self.bytes.store(bytes, Ordering::Relaxed);
+        self.wraps.store(wraps, Ordering::Relaxed);
+    }
+}
+
+impl Logger {
+    fn new<P: AsRef<Path>>(name: P, builder: &Builder) -> Result<Logger> {
+        // 新建的文件 offset 就是 0，open_inner() 里可能已经写了提示记录，不能再清零
+        Self::open_inner(name, builder, OpenMode::Create)
+    }
+
+    fn open<P: AsRef<Path>>(name: P, builder: &Builder) -> Result<Logger> {
+        Self::open_inner(name, builder, OpenMode::Open)
+    }
+
+    fn open_or_create<P: AsRef<Path>>(name: P, builder: &Builder) -> Result<Logger> {
+        Self::open_inner(name, builder, OpenMode::OpenOrCreate)
+    }
+
+    fn open_inner<P: AsRef<Path>>(name: P, builder: &Builder, mode: OpenMode) -> Result<Logger> {
+        let exclusive = builder.exclusive.unwrap_or(mode == OpenMode::Create);
+        let mapping = if mode == OpenMode::OpenOrCreate {
+            mapping::open_or_create(
+                name.as_ref(),
+                builder.size,
+                builder.framing,
+                builder.process_shared,
+                exclusive,
+                &builder.map_options,
+            )?
+        } else {
+            Mapping::open(
+                name.as_ref(),
+                builder.size,
+                builder.framing,
+                builder.process_shared,
+                exclusive,
+                mode,
+                &builder.map_options,
+            )?
+        };
+        // 改大小之前先确认密钥对得上
+        check_key(&mapping, builder)?;
+        let capacity = mapping.capacity();
+        if !builder.resize || capacity == builder.size {
+            return Self::with_mapping(mapping, builder, exclusive);
+        }
+        if capacity > builder.size && !builder.allow_shrink {
+            return Err(Error::WouldShrink {
+                capacity,
+                requested: builder.size,
+            });
+        }
+        if mapping.shared() {
+            return Err(Error::InvalidConfig(
+                "can't resize a file shared between processes",
+            ));
+        }
+        if mapping.reserved() > 0 {
+            return Err(Error::InvalidConfig(
+                "can't resize a file with a reserved ring",
+            ));
+        }
+        if mapping.channels().next().is_some() {
+            return Err(Error::InvalidConfig("can't resize a file with channels"));
+        }
+        let mapping = mapping::resize(mapping, builder.size, exclusive, &builder.map_options)?;
+        Self::with_mapping(mapping, builder, exclusive)
+    }
+
+    fn with_mapping(mapping: Mapping, builder: &Builder, exclusive: bool) -> Result<Logger> {
+        watch_forks();
+        // 打开的是别的进程建的共享文件的话也不能轮转：别的进程还映射着原来的文件，
+        // 进程间锁也在它的 header 里。make_sense() 只看得到 Builder::shared
+        let process_shared = mapping.shared();
+        if process_shared && builder.rotate.is_some() && builder.strict {
+            return Err(Error::InvalidConfig("rotate can't be combined with shared"));
+        }
+        let rotate = builder.rotate.filter(|_| !process_shared);
+        // 修好的 offset，logger 建好之后再写提示
+        let mut repaired = Vec::new();
+        for ring in mapping.rings() {
+            let (offset, size) = (mapping.ring_offset(ring), mapping.ring_size(ring));
+            if offset > size {
+                if !builder.repair {
+                    return Err(Error::CorruptHeader(format!(
+                        "offset {} is beyond the buffer size {}",
+                        offset, size
+                    )));
+                }
+                mapping.in_ring(ring, |mapping| mapping.set_offset(0));
+                repaired.push((offset, size));
+            }
+        }
+        // 崩溃时没写完的记录，logger 建好之后再写提示
+        let torn: Vec<_> = {
+            let _lock = mapping.lock_process();
+            if mapping.clock() == Clock::Monotonic {
+                // 重启过的话要找出文件里最晚的时间戳，加密了的记录先解密
+                mapping.refresh_anchor(|| {
+                    let halves = mapping.rings().map(|ring| mapping.halves(ring));
+                    merge::rings(
+                        halves,
+                        mapping.framing(),
+                        mapping.checksum(),
+                        builder.cipher.as_ref(),
+                    )
+                    .iter()
+                    .filter_map(|record| format::timestamp(record))
+                    .max()
+                });
+            }
+            if mapping.key_check().is_some() {
+                mapping.refresh_salt();
+            }
+            mapping
+                .rings()
+                .filter_map(|ring| mapping.roll_back_torn(ring))
+                .collect()
+        };
+        if let Some(budget) = builder.dbg_budget {
+            fmt::set_dbg_budget(budget);
+        }
+        // 锁不住也照常写，logger 建好之后再写提示
+        let locked = if builder.lock_memory {
+            mapping.lock_memory()
+        } else {
+            Ok(())
+        };
+        let framing = mapping.framing();
+        let escape = mapping.escaped();
+        let map_options = MapOptions {
+            page_aligned: mapping.page_aligned(),
+            escape_newlines: escape,
+            // 轮转和 swap_file() 出来的新文件和这个一样分环、分通道
+            reserve: mapping.reserved() as f64 / mapping.capacity() as f64,
+            channels: mapping.channel_table(),
+            clock: mapping.clock(),
+            key_check: mapping.key_check(),
+            boot_offset: mapping.boot_offset(),
+            ..builder.map_options
+        };
+        let channels = mapping
+            .channels()
+            .map(|(name, _)| (name.to_string(), AtomicUsize::new(builder.level as usize)))
+            .collect();
+        let capacity = mapping.capacity();
+        let ring_capacity = mapping
+            .rings()
+            .map(|ring| mapping.ring_size(ring))
+            .min()
+            .unwrap_or(capacity);
+        let published = Published::default();
+        published.store(Some(&mapping));
+        let reports = Arc::new(report::Reports::default());
+        #[cfg(feature = "compression")]
+        let compressor = match rotate {
+            Some(keep) if builder.compress_rotated => Some(rotate::Compressor::spawn(
+                mapping.path(),
+                keep,
+                reports.clone(),
+            )?),
+            _ => None,
+        };
+        let shared = Arc::new(Shared {
+            spin: SpinLock::new(builder.lock_metrics),
+            mapping: UnsafeCell::new(Some(mapping)),
+            reports,
+        });
+        let flusher = match builder.flush_interval {
+            Some(interval) => Some(Flusher::spawn(shared.clone(), interval, builder.sync)?),
+            None => None,
+        };
+        // 登记是最后一个会失败的步骤：前面失败的话 shared 被释放，登记表里就留下了悬空的指针。
+        // 这里失败的话 flusher 被 drop 时自己停下来
+        if builder.fork_safe && !fork::register(&shared) {
+            return Err(Error::InvalidConfig("too many fork_safe loggers"));
+        }
+        let logger = Logger {
+            capacity,
+            ring_capacity,
+            channels,
+            framing,
+            map_options,
+            lock_memory: builder.lock_memory,
+            escape,
+            process_shared,
+            exclusive,
+            sequence: builder.sequence,
+            direct: builder.format.is_none()
+                && framing == Framing::Text
+                && builder.oversized == Oversized::Wrap
+                && builder.max_record_len.is_none()
+                && rotate.is_none()
+                && builder.target_quotas.is_empty()
+                && !builder.tee_stderr,
+            shared,
+            flusher,
+            fork_safe: builder.fork_safe,
+            banner: builder.banner,
+            banner_with: builder.banner_with.clone(),
+            level: AtomicUsize::new(builder.level as usize),
+            installed: AtomicBool::new(false),
+            modules: builder.modules.clone(),
+            sanitize: builder.sanitize,
+            format_kind: builder.format_kind,
+            timestamp: builder.timestamp,
+            clock: map_options.clock,
+            cipher: builder.cipher.clone(),
+            thread_names: builder.thread_names,
+            format: builder.format.clone(),
+            format_errors: AtomicU64::new(0),
+            internal_dropped: AtomicU64::new(0),
+            records_truncated: AtomicU64::new(0),
+            non_blocking: builder.non_blocking,
+            contention_dropped: AtomicU64::new(0),
+            batches: builder.batched.map(Batches::new),
+            published,
+            oversized: builder.oversized,
+            max_record_len: builder.max_record_len,
+            rotate,
+            sync: builder.sync,
+            sync_on: builder.sync_on,
+            generation: AtomicUsize::new(0),
+            targets: Targets::new(builder.track_targets, &builder.target_quotas).map(Box::new),
+            adaptive: builder
+                .adaptive_flush
+                .map(|window| Adaptive::new(window, builder.adaptive_markers)),
+            rate_limit: builder
+                .rate_limit
+                .map(|(budget, window)| RateLimit::new(budget, window)),
+            health: Health::new(builder.metrics_prefix.as_deref()),
+            tee: Tee::new(builder.tee_stderr, builder.logcat, &builder.also_log),
+            #[cfg(feature = "compression")]
+            compressor,
+        };
+        if let Err(e) = locked {
+            logger.warn(format_args!("lock_memory: mlock failed, {}", e));
+        }
+        for (offset, size) in repaired {
+            logger.warn(format_args!(
+                "repaired a corrupt header: offset {} was beyond the buffer size {}, reset to 0",
+                offset, size
+            ));
+        }
+        for (from, to) in torn {
+            logger.warn(format_args!(
+                "recovered from a torn record: offset rolled back from {} to {}",
+                from, to
+            ));
+        }
+        logger.start_banner();
+        Ok(logger)
+    }
+
+    // 开始标记，新建的 logger 和 swap_file() 换上的新文件都写一条，见 Builder::banner
+    fn start_banner(&self) {
+        if !self.banner {
+            return;
+        }
+        let content = match &self.banner_with {
+            Some(banner) => (banner.0)().replace(['\n', '\r'], " "),
+            None => format!("version={}", env!("CARGO_PKG_VERSION")),
+        };
+        self.notice(
+            Level::Info,
+            format_args!(
+                "{}pid={} exe={} {} ====",
+                format::BANNER_START,
+                std::process::id(),
+                meta::exe_name(),
+                content
+            ),
+        );
+    }
+
+    /// 调用者必须持有写锁
+    unsafe fn mapping(&self) -> Option<&Mapping> {
+        self.shared.mapping()
+    }
+
+    /// 同步并解除映射，之后的记录都会被丢弃，`flush()` 也不再做任何事。
+    ///
+    /// 重复调用会返回 [`Error::Closed`]。
+    pub fn close(&self) -> Result<()> {
+        self.write_batches();
+        let mapping = {
+            let _guard = self.shared.lock();
+            unsafe { self.write_reports_locked() };
+            self.published.store(None);
+            unsafe { (*self.shared.mapping.get()).take() }
+        };
+        match mapping {
+            Some(mapping) => mapping.sync_dirty(self.sync),
+            None => Err(Error::Closed),
+        }
+    }
+
+    /// 换一个新文件继续写，旧的映射交给返回的 [`ArchivedBuffer`]，
+    /// 可以慢慢地压缩、上传，不会影响新的日志写入。
+    ///
+    /// 切换在写锁内完成，不会有记录横跨两个文件。新文件和新建的时候一样写入进程号、程序名等元数据，
+    /// 打开了 [`Builder::banner`] 的话也会先写一条开始标记。
+    pub fn swap_file<P: AsRef<Path>>(&self, new_path: P) -> Result<ArchivedBuffer> {
+        let fresh = Mapping::open(
+            new_path.as_ref(),
+            self.capacity,
+            self.framing,
+            self.process_shared,
+            self.exclusive,
+            OpenMode::Create,
+            &self.map_options,
+        )?;
+        fresh.set_offset(0);
+        let locked = if self.lock_memory {
+            fresh.lock_memory()
+        } else {
+            Ok(())
+        };
+
+        let old = {
+            let _guard = self.shared.lock();
+            let old = match unsafe { (*self.shared.mapping.get()).as_mut() } {
+                Some(mapping) => mem::replace(mapping, fresh),
+                None => return Err(Error::Closed),
+            };
+            self.published.store(unsafe { self.mapping() });
+            old
+        };
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = locked {
+            self.warn(format_args!("lock_memory: mlock failed, {}", e));
+        }
+        self.start_banner();
+        Ok(ArchivedBuffer::new(old, self.cipher.clone()))
+    }
+
+    /// 在原来的路径上新建一个文件继续写，用于 logrotate 把文件改名挪走之后，见
+    /// [`Logger::install_signal_handlers`]。
+    ///
+    /// 路径上已经没有文件，或者只有一个空文件（logrotate 的 `create`）时才新建，计数接着往下数，
+    /// 和 [`Builder::rotate`] 一样；原来的文件还在原地（不是空的）的话什么都不做。
+    /// 切换在写锁内完成，正在写的记录要么在旧文件里（随后同步），要么在新文件里。
+    /// [`Builder::shared`] 的文件、匿名映射返回 [`Error::InvalidConfig`]，`close()` 之后返回 [`Error::Closed`]。
+    pub fn reopen(&self) -> Result<()> {
+        if self.process_shared {
+            return Err(Error::InvalidConfig("reopen can't be combined with shared"));
+        }
+        let path = {
+            let _guard = self.shared.lock();
+            let mapping = unsafe { self.mapping() }.ok_or(Error::Closed)?;
+            mapping.path().to_path_buf()
+        };
+        if path.as_os_str().is_empty() {
+            return Err(Error::InvalidConfig("reopen needs a file"));
+        }
+        if std::fs::metadata(&path).is_ok_and(|m| m.len() > 0) {
+            return Ok(());
+        }
+        let fresh = Mapping::open(
+            &path,
+            self.capacity,
+            self.framing,
+            self.process_shared,
+            self.exclusive,
+            OpenMode::Create,
+            &self.map_options,
+        )?;
+        fresh.set_offset(0);
+        let locked = if self.lock_memory {
+            fresh.lock_memory()
+        } else {
+            Ok(())
+        };
+
+        let old = {
+            let _guard = self.shared.lock();
+            let old = match unsafe { (*self.shared.mapping.get()).as_mut() } {
+                Some(mapping) => {
+                    fresh.set_counters(mapping.counters());
+                    mem::replace(mapping, fresh)
+                }
+                None => return Err(Error::Closed),
+            };
+            self.published.store(unsafe { self.mapping() });
+            old
+        };
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = locked {
+            self.warn(format_args!("lock_memory: mlock failed, {}", e));
+        }
+        let result = old.sync_dirty(true);
+        self.sync_result(&result);
+        result
+    }
+
+    /// 清空缓冲区，从头开始写，不用重新映射或者截断文件。
+    ///
+    /// 在写锁内把 offset 和 header 里的计数（记录数、字节数、翻转次数，也就是
+    /// [`Builder::sequence_numbers`] 的序号）清零，然后同步 header；正在写的记录要么在
+    /// 清空之前写完，要么写在清空之后。`zero` 的话把整个缓冲区也清零，缓冲区很大时要花些时间；
+    /// 不清零的话旧数据还在文件里，但读取时会被忽略，直到下一次翻转把它们覆盖掉。
+    /// [`Builder::target_quota`] 的额度也重新开始计算。`close()` 之后返回 [`Error::Closed`]。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    /// use std::thread;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-clear.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// let log = |i: u32| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     )
+    /// };
+    /// for i in 0..30000 {
+    ///     log(i);
+    /// }
+    /// assert!(logger.stats().wrap_count > 0);
+    ///
+    /// logger.clear(false).unwrap();
+    /// assert_eq!(logger.stats().records_written, 0);
+    /// assert!(logger.tail(10).is_empty());
+    /// log(0);
+    /// logger.flush();
+    /// let reader = LogReader::open(&path).unwrap();
+    /// assert_eq!(reader.records().count(), 1);
+    /// assert_eq!((reader.stats().records_written, reader.stats().wrap_count), (1, 0));
+    ///
+    /// // 和 log() 同时调用也不会留下半条记录
+    /// thread::scope(|s| {
+    ///     s.spawn(|| {
+    ///         for i in 0..20000 {
+    ///             log(i);
+    ///         }
+    ///     });
+    ///     for _ in 0..100 {
+    ///         logger.clear(false).unwrap();
+    ///     }
+    /// });
+    /// log(20000);
+    /// logger.flush();
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let numbers: Vec<u32> = reader
+    ///     .records()
+    ///     .map(|r| r.strip_prefix('[').unwrap().rsplit(' ').next().unwrap().parse().unwrap())
+    ///     .collect();
+    /// if reader.stats().wrap_count == 0 {
+    ///     assert_eq!(numbers.len() as u64, reader.stats().records_written);
+    /// }
+    /// assert!(numbers.windows(2).all(|w| w[1] == w[0] + 1));
+    /// assert_eq!(numbers.last(), Some(&20000));
+    ///
+    /// logger.clear(true).unwrap();
+    /// drop(logger);
+    /// let file = std::fs::read(&path).unwrap();
+    /// let data = mmlog::format::HEADER_SIZE + mmlog::format::META_SIZE;
+    /// assert!(file[data..].iter().all(|&b| b == 0));
+    /// ```
+    pub fn clear(&self, zero: bool) -> Result<()> {
+        let _guard = self.shared.lock();
+        let mapping = unsafe { self.mapping() }.ok_or(Error::Closed)?;
+        for ring in mapping.rings() {
+            mapping.in_ring(ring, |mapping| {
+                mapping.set_offset(0);
+                if zero {
+                    unsafe { mapping.zero_at(0, mapping.size()) };
+                    mapping.touch(0, mapping.size());
+                }
+            });
+        }
+        mapping.set_counters((0, 0, 0));
+        // 序号从 0 重新数，nonce 不能和之前的重复
+        if self.cipher.is_some() {
+            mapping.refresh_salt();
+        }
+        self.published.store(Some(mapping));
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        mapping.sync_dirty(self.sync)
+    }
+
+    /// 按时间顺序把缓冲区里的全部记录写成纯文本，返回写出的字节数。
+    ///
+    /// 只在复制缓冲区时拿一下写锁，写出时不影响新的日志。[`Logger::clear`]、[`Builder::rotate`]
+    /// 的轮转和 [`Logger::swap_file`] 也在写锁里，复制到的要么全是之前的记录，要么全是之后的。
+    /// 翻转处被覆盖了一半的那条记录会被跳过，长度前缀会被去掉，和 [`ArchivedBuffer::dump_to`] 一样。`close()` 之后返回 [`Error::Closed`]。
+    ///
+    /// ```
+    /// use log::Log;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-dump.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// for i in 0..30000 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// let mut out = Vec::new();
+    /// let n = logger.dump_to(&mut out).unwrap();
+    /// assert_eq!(n, out.len() as u64);
+    ///
+    /// let text = String::from_utf8(out).unwrap();
+    /// assert!(text.lines().all(|line| line.starts_with('[')));
+    /// let numbers: Vec<u32> = text
+    ///     .lines()
+    ///     .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+    ///     .collect();
+    /// assert!(numbers.windows(2).all(|w| w[1] == w[0] + 1));
+    /// assert_eq!(numbers.last(), Some(&29999));
+    /// ```
+    pub fn dump_to<W: io::Write>(&self, w: W) -> Result<u64> {
+        let snapshot = {
+            let _guard = self.shared.lock();
+            Snapshot::copy(
+                unsafe { self.mapping() }.ok_or(Error::Closed)?,
+                self.cipher.as_ref(),
+            )
+        };
+        snapshot.write_to(w)
+    }
+
+    /// 新建（或截断）`path`，把 [`Logger::dump_to`] 的结果写进去。
+    pub fn dump_to_file<P: AsRef<Path>>(&self, path: P) -> Result<u64> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        let n = self.dump_to(&mut file)?;
+        file.flush()?;
+        Ok(n)
+    }
+
+    /// 复制一份缓冲区里还读得到的记录，见 [`Snapshot`]，可以在后台线程里慢慢上传。
+    ///
+    /// 写锁只在复制时拿着：分配好一个正好放得下的 `Vec<u8>`，最多两次 `memcpy`，
+    /// 拆分记录和写出都在锁外面做，不会像在 [`Logger::dump_to`] 的写出过程中那样卡住别的线程。
+    /// `close()` 之后返回空的快照。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use std::io::{self, Write};
+    /// use std::thread;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// // 很慢的上传
+    /// struct Slow(Vec<u8>);
+    ///
+    /// impl Write for Slow {
+    ///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///         thread::sleep(Duration::from_micros(100));
+    ///         self.0.extend_from_slice(buf);
+    ///         Ok(buf.len())
+    ///     }
+    ///     fn flush(&mut self) -> io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let logger = mmlog::Builder::new().build_anonymous().unwrap();
+    /// let log = |i: u32| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     )
+    /// };
+    /// for i in 0..100 {
+    ///     log(i);
+    /// }
+    /// let snapshot = logger.snapshot();
+    /// // 没翻转过，复制的只有写过的部分
+    /// assert_eq!(snapshot.len(), logger.stats().used_bytes_since_wrap);
+    /// assert_eq!(snapshot.records().count(), 100);
+    /// assert_eq!(snapshot.entries().last().unwrap().message, "record 99");
+    ///
+    /// for i in 100..30000 {
+    ///     log(i);
+    /// }
+    /// let (slowest, upload) = thread::scope(|s| {
+    ///     let upload = s.spawn(|| {
+    ///         let snapshot = logger.snapshot();
+    ///         let mut out = Slow(Vec::new());
+    ///         snapshot.write_to(&mut out).unwrap();
+    ///         (snapshot, out.0)
+    ///     });
+    ///     let mut slowest = Duration::ZERO;
+    ///     let mut i = 30000;
+    ///     while !upload.is_finished() {
+    ///         let start = Instant::now();
+    ///         log(i);
+    ///         slowest = slowest.max(start.elapsed());
+    ///         i += 1;
+    ///     }
+    ///     (slowest, upload.join().unwrap())
+    /// });
+    /// // 上传花了很久，写日志只等过复制的那一下
+    /// assert!(slowest < Duration::from_millis(50), "{:?}", slowest);
+    ///
+    /// let (snapshot, uploaded) = upload;
+    /// let text = String::from_utf8(uploaded).unwrap();
+    /// assert!(text.lines().eq(snapshot.records()));
+    /// let numbers: Vec<u32> = snapshot
+    ///     .records()
+    ///     .map(|r| r.rsplit(' ').next().unwrap().parse().unwrap())
+    ///     .collect();
+    /// assert!(numbers.windows(2).all(|w| w[1] == w[0] + 1));
+    /// assert!(*numbers.last().unwrap() >= 29999);
+    /// ```
+    pub fn snapshot(&self) -> Snapshot {
+        let _guard = self.shared.lock();
+        match unsafe { self.mapping() } {
+            Some(mapping) => Snapshot::copy(mapping, self.cipher.as_ref()),
+            None => Snapshot::empty(),
+        }
+    }
+
+    /// 名字是 `name` 的书签的位置，没有设置过的话是 `LogicalPos(0)`，文件的最开头。
+    ///
+    /// 书签记在文件的书签表里（见 [`format::BOOKMARK_SLOTS`]），和 header 一起写回文件，
+    /// 进程重启之后 [`Builder::open`] 同一个文件还在，用来在进程里接着读新写入的记录，
+    /// 见 [`Logger::read_since_bookmark`]。一个文件最多有 [`format::BOOKMARK_SLOTS`] 个书签，
+    /// 只能用在只有一个环的文件里（没有 [`Builder::reserve_for_errors`]、[`Builder::channels`]），
+    /// 版本 12 之前创建的文件没有书签表。不满足的话返回 [`Error::InvalidConfig`]
+    /// （版本不够的话是 [`Error::Config`]），`close()` 之后返回 [`Error::Closed`]。
+    pub fn bookmark(&self, name: &str) -> Result<LogicalPos> {
+        let _guard = self.shared.lock();
+        let mapping = unsafe { self.mapping() }.ok_or(Error::Closed)?;
+        bookmark::check(mapping)?;
+        let pos = mapping.bookmark(bookmark::hash(name))?;
+        Ok(LogicalPos(pos.unwrap_or(0)))
+    }
+
+    /// 把名字是 `name` 的书签设成 `pos`，`pos` 应该是 [`Logger::bookmark`] 或者
+    /// [`SinceBookmark::end`] 返回的、记录边界上的位置。还没有这个书签的话占一个空的槽，
+    /// 槽都用完了、或者 `pos` 在现在写到的位置之后的话返回 [`Error::InvalidConfig`]，别的要求和
+    /// [`Logger::bookmark`] 一样。
+    pub fn set_bookmark(&self, name: &str, pos: LogicalPos) -> Result<()> {
+        let _guard = self.shared.lock();
+        let mapping = unsafe { self.mapping() }.ok_or(Error::Closed)?;
+        bookmark::check(mapping)?;
+        if pos.0 > mapping.position(Ring::Main).1 {
+            return Err(Error::InvalidConfig(
+                "the bookmark position is past the end of the log",
+            ));
+        }
+        mapping.set_bookmark(bookmark::hash(name), pos.0)
+    }
+
+    /// 名字是 `name` 的书签之后写入的完整记录，`advance` 的话同时把书签移到读到的最后一条之后，
+    /// 下次只读这之后的。要求和 [`Logger::bookmark`] 一样。
+    ///
+    /// 书签之后的记录在读到之前已经被覆盖了的话（缓冲区绕过了书签，或者 [`Logger::clear`]、
+    /// 轮转过），[`SinceBookmark::gap`] 为真，读到的是现在缓冲区里所有的记录。
+    /// 写锁只在复制书签之后的数据时拿着，拆分和转换成 `String` 在锁外面做。
+    ///
+    /// ```
+    /// use log::Log;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-bookmark.log");
+    /// let log = |logger: &mmlog::Logger, msg: &str| {
+    ///     logger.log(&log::Record::builder().level(log::Level::Error).args(format_args!("{}", msg)).build());
+    /// };
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// log(&logger, "disk full");
+    /// let seen = logger.read_since_bookmark("errors-ui", true).unwrap();
+    /// assert!(seen.records[0].ends_with("] disk full"));
+    /// assert!(!seen.gap);
+    /// log(&logger, "disk still full");
+    /// drop(logger);
+    ///
+    /// // 重启之后接着上次读到的地方
+    /// let logger = mmlog::Builder::new().open(&path).unwrap();
+    /// let seen = logger.read_since_bookmark("errors-ui", true).unwrap();
+    /// assert_eq!(seen.records.len(), 1);
+    /// assert!(seen.records[0].ends_with("] disk still full"));
+    /// assert_eq!(logger.bookmark("errors-ui").unwrap(), seen.end);
+    /// assert!(logger.read_since_bookmark("errors-ui", true).unwrap().records.is_empty());
+    /// ```
+    pub fn read_since_bookmark(&self, name: &str, advance: bool) -> Result<SinceBookmark> {
+        let (snapshot, gap, end) = {
+            let _guard = self.shared.lock();
+            let mapping = unsafe { self.mapping() }.ok_or(Error::Closed)?;
+            bookmark::check(mapping)?;
+            let hash = bookmark::hash(name);
+            let cursor = mapping.bookmark(hash)?.unwrap_or(0);
+            let (intact, end) = bookmark::intact(mapping, cursor);
+            let snapshot = if intact {
+                Snapshot::since(mapping, cursor, self.cipher.as_ref())
+            } else {
+                Snapshot::copy(mapping, self.cipher.as_ref())
+            };
+            if advance {
+                mapping.set_bookmark(hash, end)?;
+            }
+            (snapshot, !intact, end)
+        };
+        Ok(SinceBookmark {
+            records: snapshot.records().map(|r| r.into_owned()).collect(),
+            gap,
+            end: LogicalPos(end),
+        })
+    }
+
+    /// 按时间顺序返回最后 `n` 条完整的记录（不含结尾的换行），缓冲区里不够的话有几条返回几条。
+    ///
+    /// 从 offset 往前找记录的边界，最多绕过翻转处一次。写锁只在找边界和复制这几条记录时拿着，
+    /// 拆分和转换成 `String` 在锁外面做。`close()` 之后返回空的 `Vec`。
+    ///
+    /// ```
+    /// use log::Log;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-tail.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// let log = |i| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     )
+    /// };
+    /// for i in 0..3 {
+    ///     log(i);
+    /// }
+    /// assert_eq!(logger.tail(10).len(), 3);
+    ///
+    /// // 写够好几圈，保证翻转过
+    /// for i in 3..30000 {
+    ///     log(i);
+    /// }
+    /// let tail = logger.tail(5);
+    /// let numbers: Vec<u32> = tail
+    ///     .iter()
+    ///     .map(|r| r.rsplit(' ').next().unwrap().parse().unwrap())
+    ///     .collect();
+    /// assert_eq!(numbers, [29995, 29996, 29997, 29998, 29999]);
+    /// assert_eq!(logger.tail(0), Vec::<String>::new());
+    /// ```
+    pub fn tail(&self, n: usize) -> Vec<String> {
+        if n == 0 {
+            return Vec::new();
+        }
+        // 每个环最后 n 条记录所在的（较旧的，较新的）两段，old 的开头可能是半条记录
+        let (rings, framing, checksum) = {
+            let _guard = self.shared.lock();
+            let mapping = match unsafe { self.mapping() } {
+                Some(mapping) => mapping,
+                None => return Vec::new(),
+            };
+            let (framing, checksum) = (mapping.framing(), mapping.checksum());
+            let rings: Vec<_> = mapping
+                .rings()
+                .map(|ring| {
+                    let data = mapping.slice(ring);
+                    let (new, old) = data.split_at(mapping.ring_offset(ring).min(data.len()));
+                    // 没翻转过的话 offset 之后没有记录，见 Mapping::halves()
+                    let old = if mapping.wrapped(ring) {
+                        old
+                    } else {
+                        &old[..0]
+                    };
+                    let (old, new, partial) = format::tail(old, new, n, framing, checksum);
+                    (old.to_vec(), new.to_vec(), partial)
+                })
+                .collect();
+            (rings, framing, checksum)
+        };
+
+        let halves = rings.iter().map(|(old, new, partial)| {
+            let old = if *partial {
+                mapping::skip_partial(old, framing, checksum)
+            } else {
+                old
+            };
+            (old, &new[..])
+        });
+        let mut records: Vec<String> =
+            merge::rings(halves, framing, checksum, self.cipher.as_ref())
+                .into_iter()
+                .map(|record| {
+                    let record = record.strip_suffix(b"\n").unwrap_or(&record);
+                    let record = String::from_utf8_lossy(record);
+                    if self.escape {
+                        format::unescape_newlines(&record).into_owned()
+                    } else {
+                        record.into_owned()
+                    }
+                })
+                .collect();
+        records.drain(..records.len().saturating_sub(n));
+        records
+    }
+
+    /// 按写入字节数从大到小返回前 `k` 个顶层 target（近似值），
+    /// 需要 [`Builder::track_targets`]。
+    pub fn top_targets(&self, k: usize) -> Vec<(String, u64)> {
+        match &self.targets {
+            Some(targets) => {
+                let _guard = self.shared.lock();
+                unsafe { targets.top(k) }
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// 不用构造 [`Metadata`] 就能判断某个 target 的记录是否会被写入。
+    pub fn target_enabled(&self, level: Level, target: &str) -> bool {
+        match self
+            .modules
+            .iter()
+            .find(|(prefix, _)| targets::in_module(prefix, target))
+        {
+            Some((_, max)) => level <= *max,
+            None => level <= self.level_filter(),
+        }
+    }
+
+    /// 当前的全局级别。[`Builder::filter_str`] 把它设成了 `off` 的话返回 `Level::Error`，
+    /// 要区分的话用 [`Logger::level_filter`]。
+    pub fn level(&self) -> Level {
+        self.level_filter().to_level().unwrap_or(Level::Error)
+    }
+
+    /// 当前的全局级别，[`Builder::filter_str`] 可以把它设成 `off`。
+    pub fn level_filter(&self) -> LevelFilter {
+        match self.level.load(Ordering::Relaxed) {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    /// 运行时调整全局级别，[`Builder::module_level`] 设置的模块不受影响。
+    ///
+    /// 通过 [`Builder::init`] 安装的 logger 会同时更新 `log::max_level`。
+    pub fn set_level(&self, level: Level) {
+        self.level.store(level as usize, Ordering::Relaxed);
+        if self.installed.load(Ordering::Relaxed) {
+            log::set_max_level(self.max_level());
+        }
+        invalidate_callsites();
+    }
+
+    /// 记录的时间戳用的时钟，打开已有的文件的话是文件里的，见 [`Builder::clock`]。
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    // 所有级别里最详细的那个
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, l)| *l)
+            .fold(self.level_filter(), cmp::max)
+    }
+
+    /// 当前是否处在 [`Builder::adaptive_flush`] 的积极同步窗口内。
+    pub fn aggressive_flush_active(&self) -> bool {
+        self.adaptive.as_ref().is_some_and(|a| a.active())
+    }
+
+    /// 进入积极同步窗口的次数。
+    pub fn aggressive_flush_episodes(&self) -> u64 {
+        self.adaptive.as_ref().map_or(0, |a| a.episodes())
+    }
+
+    /// 写锁的竞争统计，需要 [`Builder::lock_metrics`]。
+    pub fn lock_stats(&self) -> Option<LockStats> {
+        self.shared.spin.stats()
+    }
+
+    /// 在 fork 出来的子进程里、写日志之前调用：放开 fork 时别的线程拿着的写锁，
+    /// 让缓存的线程 id 失效，[`Builder::shared`] 的话再检查一遍进程间锁。
+    /// 在父进程里或者 fork 之后再调用会把正在写的线程的锁放开。
+    ///
+    /// 自己调用的话要保证子进程在这之前没有写过日志；[`Builder::fork_safe`] 会用
+    /// `pthread_atfork` 自动在子进程里调用它。
+    pub fn post_fork_child(&self) {
+        self.shared.post_fork_child();
+        refresh_tid();
+    }
+
+    /// 名字是 `name` 的通道，文件里没有这个通道的话返回 `None`，见 [`Builder::channels`]。
+    pub fn channel(&self, name: &str) -> Option<ChannelLogger<'_>> {
+        let i = self.channels.iter().position(|(n, _)| n == name)?;
+        Some(ChannelLogger::new(self, i))
+    }
+
+    // 第一个通道（没有通道的话就是整个 logger）按 target_enabled() 判断，别的通道只看自己的级别
+    pub(crate) fn channel_enabled(&self, channel: usize, metadata: &Metadata) -> bool {
+        if channel == 0 {
+            self.enabled(metadata)
+        } else {
+            metadata.level() <= self.channel_level(channel)
+        }
+    }
+
+    pub(crate) fn channel_level(&self, channel: usize) -> LevelFilter {
+        if channel == 0 {
+            return self.level_filter();
+        }
+        match self.channels[channel].1.load(Ordering::Relaxed) {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    pub(crate) fn set_channel_level(&self, channel: usize, level: Level) {
+        if channel == 0 {
+            self.set_level(level);
+        } else {
+            self.channels[channel]
+                .1
+                .store(level as usize, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn channel_name(&self, channel: usize) -> &str {
+        &self.channels[channel].0
+    }
+
+    /// 把原始的字节直接写进缓冲区，见 [`RingWriter`]。
+    pub fn writer(&self) -> RingWriter<'_> {
+        RingWriter::new(self)
+    }
+
+    /// 写入的统计，保存在文件 header 里，重新打开之后继续累加；
+    /// [`Logger::swap_file`] 换上的新文件从 0 开始。`close()` 之后除了 [`Stats::capacity`] 和
+    /// 只在内存里的 [`Stats::records_dropped`] 等计数都返回 0。
+    ///
+    /// 不拿写锁，读的是这个 `Logger` 每次写完之后复制出来的计数，可以频繁调用。
+    /// 各个字段是分别读的，和正在写的记录同时调用时可能有的已经算上了这条记录、有的还没有。
+    /// [`Builder::shared`] 的文件里其他进程写的记录要等这个进程下一次写入之后才算进来。
+    pub fn stats(&self) -> Stats {
+        let published = &self.published;
+        let contention = self.contention_dropped.load(Ordering::Relaxed);
+        Stats {
+            capacity: self.capacity,
+            used_bytes_since_wrap: published.offset.load(Ordering::Relaxed),
+            records_written: published.records.load(Ordering::Relaxed),
+            total_bytes_written: published.bytes.load(Ordering::Relaxed),
+            wrap_count: published.wraps.load(Ordering::Relaxed),
+            records_dropped: self.internal_dropped.load(Ordering::Relaxed)
+                + contention
+                + self.rate_limited()
+                + self.quota_dropped(),
+            records_truncated: self.records_truncated.load(Ordering::Relaxed),
+            records_dropped_contention: contention,
+        }
+    }
+
+    /// 和 [`Log::flush`] 一样把缓冲区同步到文件（打开了 [`Builder::sync`] 的话等待写完），
+    /// 但是返回同步的结果。`close()` 之后返回 [`Error::Closed`]。
+    pub fn try_flush(&self) -> Result<()> {
+        self.write_batches();
+        let _guard = self.shared.lock();
+        unsafe { self.write_reports_locked() };
+        let mapping = unsafe { self.mapping() }.ok_or(Error::Closed)?;
+        let result = mapping.sync_dirty(self.sync);
+        self.sync_result(&result);
+        result
+    }
+
+    /// 在已有的 panic hook 之前插入一步：把 panic 的消息、位置和 backtrace 作为
+    /// `Error` 级别、target 为 `panic` 的记录写入，然后同步到磁盘（`MS_SYNC`），
+    /// 即使 `panic = "abort"` 或者进程随后被杀掉，崩溃前的日志也已经落盘。
+    ///
+    /// panic 发生在持有写锁的时候（比如参数的 `Display` 实现里）不会死锁：
+    /// 等一小会儿还拿不到锁的话不写这条记录，只同步。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use std::fmt;
+    /// use std::panic;
+    ///
+    /// struct Panicky;
+    ///
+    /// impl fmt::Display for Panicky {
+    ///     fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+    ///         panic!("panicked while holding the lock")
+    ///     }
+    /// }
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-panic.log");
+    /// let logger: &'static mmlog::Logger =
+    ///     Box::leak(Box::new(mmlog::Builder::new().build(&path).unwrap()));
+    /// logger.install_panic_hook();
+    ///
+    /// assert!(panic::catch_unwind(|| panic!("boom")).is_err());
+    /// // 写锁内的 panic 不会死锁
+    /// let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{}", Panicky))
+    ///             .build(),
+    ///     )
+    /// }));
+    /// assert!(result.is_err());
+    /// let _ = panic::take_hook();
+    ///
+    /// let reader = mmlog::reader::LogReader::open(&path).unwrap();
+    /// assert!(reader.records().any(|r| r.contains(" E ") && r.ends_with(": boom")));
+    /// ```
+    pub fn install_panic_hook(&'static self) {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let payload = info
+                .payload()
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| info.payload().downcast_ref::<String>().map(|s| s.as_str()))
+                .unwrap_or("Box<dyn Any>");
+            let location = info
+                .location()
+                .map_or_else(String::new, |l| format!(" at {}:{}", l.file(), l.line()));
+            self.panicked(format_args!(
+                "panicked{}: {}\n{}",
+                location,
+                payload,
+                std::backtrace::Backtrace::force_capture()
+            ));
+            previous(info);
+        }));
+    }
+
+    /// 收到 `reopen` 时调用 [`Logger::reopen`]，配合 logrotate 的 `postrotate`
+    /// （`kill -HUP`）使用；收到 `flush` 时同步到文件，和 [`Logger::try_flush`] 一样。
+    /// 两个可以是同一个信号，先 reopen 再同步。需要 `mmap` feature，只支持 Unix。
+    ///
+    /// 信号处理函数只往一个管道里写一个字节，真正的工作在后台线程 `mmlog-signals` 里做，
+    /// `reopen()` 失败的话写一条 `Warn` 记录。一个进程里只能装一次，再装返回 [`Error::InvalidConfig`]。
+    /// 信号处理函数要是 panic 了（不应该发生），直接 `abort()`，不会展开穿过 C 的栈帧。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::Signal;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let dir = std::env::temp_dir().join("mmlog-signals");
+    /// let _ = std::fs::remove_dir_all(&dir);
+    /// let path = dir.join("app.log");
+    /// let logger: &'static mmlog::Logger = Box::leak(Box::new(
+    ///     mmlog::Builder::new().create_dirs(true).build(&path).unwrap(),
+    /// ));
+    /// logger.install_signal_handlers(Signal::Hup, Signal::Usr1).unwrap();
+    /// assert!(logger
+    ///     .install_signal_handlers(Signal::Hup, Signal::Usr1)
+    ///     .is_err());
+    /// let log = |msg: &str| {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{}", msg))
+    ///             .build(),
+    ///     )
+    /// };
+    ///
+    /// log("before rotation");
+    /// // logrotate 先改名，再发 SIGHUP
+    /// let moved = dir.join("app.log.1");
+    /// std::fs::rename(&path, &moved).unwrap();
+    /// unsafe { libc::raise(libc::SIGHUP) };
+    /// // 后台线程换好文件之后新文件里还没有记录
+    /// let start = Instant::now();
+    /// while !logger.tail(1).is_empty() {
+    ///     assert!(start.elapsed() < Duration::from_secs(10));
+    ///     std::thread::sleep(Duration::from_millis(10));
+    /// }
+    /// assert!(path.exists());
+    /// log("after rotation");
+    /// unsafe { libc::raise(libc::SIGUSR1) };
+    ///
+    /// let old = mmlog::reader::LogReader::open(&moved).unwrap();
+    /// assert!(old.records().last().unwrap().ends_with("before rotation"));
+    /// let new = mmlog::reader::LogReader::open(&path).unwrap();
+    /// let records: Vec<_> = new.records().collect();
+    /// assert_eq!(records.len(), 1);
+    /// assert!(records[0].ends_with("after rotation"));
+    /// assert_eq!(new.stats().records_written, 2);
+    /// ```
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn install_signal_handlers(&'static self, reopen: Signal, flush: Signal) -> Result<()> {
+        signals::install(self, reopen, flush)
+    }
+
+    // panic hook 里调用：写入一条记录并同步
+    fn panicked(&self, args: std::fmt::Arguments) {
+        match self.shared.spin.try_lock_for(Duration::from_millis(100)) {
+            Some(guard) => {
+                // 锁是空的，放开之后走正常的写入流程
+                drop(guard);
+                self.log_blocking(
+                    &Record::builder()
+                        .level(Level::Error)
+                        .target("panic")
+                        .args(args)
+                        .build(),
+                );
+                // 别的线程可能正拿着自己的 Batch，不等它
+                if let Some(batches) = &self.batches {
+                    for batch in batches.drain() {
+                        if let Ok(mut batch) = batch.try_lock() {
+                            self.write_batch(&mut batch);
+                        }
+                    }
+                }
+                let _ = self.sync_now();
+            }
+            None => {
+                // 多半是这个线程自己在持有写锁时 panic 了，锁永远不会放开，
+                // 别的线程也就不可能换掉映射，不加锁同步是安全的
+                if let Some(mapping) = unsafe { self.mapping() } {
+                    let _ = mapping.sync(true);
+                }
+            }
+        }
+    }
+
+    /// 不管 [`Builder::sync`] 怎么设置，立刻把上次同步之后写过的页同步到磁盘，
+    /// 等待写完（`MS_SYNC`）。`close()` 之后返回 [`Error::Closed`]。
+    pub fn sync_now(&self) -> Result<()> {
+        let _guard = self.shared.lock();
+        let mapping = unsafe { self.mapping() }.ok_or(Error::Closed)?;
+        let result = mapping.sync_dirty(true);
+        self.sync_result(&result);
+        result
+    }
+
+    /// [`Builder::format`] 设置的格式化函数返回错误的次数，这些记录同样算在
+    /// [`Stats::records_dropped`] 里。
+    pub fn format_errors(&self) -> u64 {
+        self.format_errors.load(Ordering::Relaxed)
+    }
+
+    /// 被 [`Builder::rate_limit`] 压掉的记录数，也算在 [`Stats::records_dropped`] 里。
+    pub fn rate_limited(&self) -> u64 {
+        self.rate_limit.as_ref().map_or(0, |r| r.suppressed())
+    }
+
+    /// 因为超出 [`Builder::target_quota`] 而被丢弃的记录数，也算在 [`Stats::records_dropped`] 里。
+    pub fn quota_dropped(&self) -> u64 {
+        self.targets.as_ref().map_or(0, |t| t.dropped())
+    }
+
+    // logger 自己写的提示记录，时钟出错时没有
+    fn marker(&self, args: std::fmt::Arguments) -> Option<String> {
+        self.marker_at(Level::Warn, args)
+    }
+
+    // 记录的时间戳，单调时钟的话加上文件里的偏移，见 format::BOOT_POS
+    fn now(&self) -> Option<Duration> {
+        Some(format::now(self.clock)? + self.map_options.boot_offset)
+    }
+
+    fn marker_at(&self, level: Level, args: std::fmt::Arguments) -> Option<String> {
+        let marker = format::record(
+            &Record::builder()
+                .level(level)
+                .target("mmlog")
+                .args(args)
+                .build(),
+            false,
+            self.format_kind,
+            self.timestamp,
+            self.thread_names,
+            self.now()?,
+        )?;
+        Some(if self.escape {
+            format::escape_newlines(marker)
+        } else {
+            marker
+        })
+    }
+
+    // 在写锁外写一条 marker() 提示
+    fn warn(&self, args: std::fmt::Arguments) {
+        self.notice(Level::Warn, args);
+    }
+
+    fn notice(&self, level: Level, args: std::fmt::Arguments) {
+        if let Some(marker) = self.marker_at(level, args) {
+            let _guard = self.shared.lock();
+            unsafe { self.write_locked(ring(level, 0), marker.as_bytes()) };
+        }
+    }
+
+    // 因为内部错误丢掉了一条记录
+    fn dropped(&self) {
+        self.internal_dropped.fetch_add(1, Ordering::Relaxed);
+        if let Some(health) = &self.health {
+            health.dropped();
+        }
+    }
+
+    fn sync_result(&self, result: &Result<()>) {
+        if result.is_err() {
+            if let Some(health) = &self.health {
+                health.flush_error();
+            }
+        }
+    }
+
+    // 把后台线程和轮转攒下的错误写成 Error 级别的提示，见 report 模块。调用者必须持有写锁
+    unsafe fn write_reports_locked(&self) {
+        for message in self.shared.reports.take() {
+            if let Some(marker) = self.marker_at(Level::Error, format_args!("{}", message)) {
+                self.write_locked(ring(Level::Error, 0), marker.as_bytes());
+            }
+        }
+    }
+
+    // 调用者必须持有写锁。Builder::rotate 的话写不下时会先换成新文件，
+    // 之前拿到的 &Mapping 都不能再用
+    //
+    // 记录正好写到缓冲区末尾时 offset 回到 0（而不是停在 size()），算作翻转一次，
+    // 所以 offset 总是小于 size()。写进 ring，见 ring()
+    unsafe fn write_locked(&self, ring: Ring, source: &[u8]) {
+        let mut mapping = match self.mapping() {
+            Some(mapping) => mapping,
+            None => return,
+        };
+        mapping.select(ring);
+        let prefixed;
+        let source = if self.sequence {
+            let (seq, _, _) = mapping.counters();
+            prefixed = [format!("#{} ", seq).as_bytes(), source].concat();
+            &prefixed[..]
+        } else {
+            source
+        };
+        let sealed;
+        let source = match &self.cipher {
+            Some(cipher) => {
+                let (seq, _, _) = mapping.counters();
+                sealed = cipher.seal(mapping.salt(), seq, source);
+                &sealed[..]
+            }
+            None => source,
+        };
+        if let Some(keep) = self.rotate {
+            let len = match self.framing {
+                Framing::Text => source.len(),
+                Framing::LengthPrefixed => {
+                    format::FRAME_PREFIX + source.len() + format::frame_trailer(mapping.checksum())
+                }
+            };
+            if mapping.offset() + len >= mapping.size() {
+                mapping = match self.rotate_locked(keep) {
+                    Some(mapping) => mapping,
+                    None => return,
+                };
+                mapping.select(ring);
+            }
+        }
+
+        let mut offset = mapping.offset();
+        if offset == mapping.size() {
+            // 旧版本写出的文件可能停在末尾
+            offset = 0;
+        }
+
+        let mut len = source.len();
+        let (end, wraps) = if self.framing == Framing::LengthPrefixed {
+            len += format::FRAME_PREFIX + format::frame_trailer(mapping.checksum());
+            self.write_frame(mapping, offset, source)
+        } else if offset + source.len() < mapping.size() {
+            mapping.write_at(offset, source);
+            (offset + source.len(), 0)
+        } else if offset + source.len() == mapping.size() {
+            mapping.write_at(offset, source);
+            (0, 1)
+        } else if source.len() < mapping.size() {
+            let (head, tail) = source.split_at(mapping.size() - offset);
+            mapping.write_at(offset, head);
+            mapping.write_at(0, tail);
+            (tail.len(), 1)
+        } else {
+            // 比整个缓冲区还长：最终只有最后 size() 个字节留在缓冲区里，
+            // 位置和逐字节绕圈写入的结果一样
+            let size = mapping.size();
+            let end = (offset + source.len()) % size;
+            let last = &source[source.len() - size..];
+            let (a, b) = last.split_at(size - end);
+            mapping.write_at(end, a);
+            mapping.write_at(0, b);
+            (end, (offset + source.len()) / size)
+        };
+
+        self.written(mapping, offset, end, len, wraps);
+        mapping.select(Ring::Main);
+    }
+
+    // Builder::rotate：同步并解除当前的映射，旧文件依次改名，在原来的路径上新建一个文件，
+    // 计数接着往下数。调用者必须持有写锁。
+    //
+    // 改名或者新建失败的话重新打开原来的文件，这条记录在原地翻转；还是打不开就和 close() 之后一样
+    unsafe fn rotate_locked(&self, keep: usize) -> Option<&Mapping> {
+        let slot = &mut *self.shared.mapping.get();
+        // 调用者的 Guard 里的进程间锁在这个映射里，不能解除映射，见 with_mapping()
+        debug_assert!(!slot.as_ref()?.shared());
+        let old = slot.take()?;
+        let path = old.path().to_path_buf();
+        let counters = old.counters();
+        self.sync_result(&old.sync_dirty(true));
+        drop(old);
+
+        // 已经在写锁里了，锁不住内存的话不再提示
+        let open = |mode| {
+            let mapping = Mapping::open(
+                &path,
+                self.capacity,
+                self.framing,
+                self.process_shared,
+                self.exclusive,
+                mode,
+                &self.map_options,
+            )?;
+            if self.lock_memory {
+                let _ = mapping.lock_memory();
+            }
+            Ok(mapping)
+        };
+        let fresh = self
+            .shift(&path, keep)
+            .map_err(Error::from)
+            .and_then(|_| open(OpenMode::Create));
+        *slot = match fresh {
+            Ok(fresh) => {
+                fresh.set_offset(0);
+                fresh.set_counters(counters);
+                Some(fresh)
+            }
+            Err(e) => {
+                self.shared.reports.push(format!(
+                    "rotate: can't start a new file, keep writing to {}: {}",
+                    path.display(),
+                    e
+                ));
+                self.sync_result(&Err(e));
+                open(OpenMode::OpenOrCreate).ok()
+            }
+        };
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.published.store(slot.as_ref());
+        slot.as_ref()
+    }
+
+    // Builder::compress_rotated 的话改名之后交给后台线程压缩
+    fn shift(&self, path: &Path, keep: usize) -> io::Result<()> {
+        #[cfg(feature = "compression")]
+        if let Some(compressor) = &self.compressor {
+            return compressor.shift(path);
+        }
+        rotate::shift(path, keep)
+    }
+
+    // 从 start 开始、到 end 为止的一条记录写完之后更新各种计数，最后才移动 offset，
+    // 见 Mapping::position()
+    fn written(&self, mapping: &Mapping, start: usize, end: usize, len: usize, wraps: usize) {
+        mapping.count(len, wraps);
+        mapping.set_offset(end);
+        self.published.store(Some(mapping));
+        if wraps > 0 {
+            self.generation.fetch_add(wraps, Ordering::Relaxed);
+            mapping.touch(0, mapping.size());
+        } else {
+            mapping.touch(start, end);
+        }
+        if let Some(flusher) = &self.flusher {
+            flusher.dirty();
+        }
+        if let Some(health) = &self.health {
+            health.written(len, end, mapping.size());
+        }
+    }
+
+    // 写锁，blocking 为假的话只试一次，拿不到时记下丢掉了一条记录，见 Builder::non_blocking
+    fn lock_or_drop(&self, blocking: bool) -> Option<Guard<'_>> {
+        if blocking {
+            return Some(self.shared.lock());
+        }
+        let guard = self.shared.try_lock();
+        if guard.is_none() {
+            self.contention_dropped.fetch_add(1, Ordering::Relaxed);
+            if let Some(health) = &self.health {
+                health.dropped();
+            }
+        }
+        guard
+    }
+
+    /// 把一段二进制数据写成 `xxd` 那样的十六进制，作为一条 `level` 级别、`target` 的记录，
+    /// 格式见 [`fmt::hex_dump`]。
+    ///
+    /// 和 `log()` 一样先看 `enabled()`，默认格式下直接格式化到缓冲区里，不分配内存；
+    /// 太长的话按 [`Builder::max_record_len`] 截断。全局 logger 用 [`log_bytes!`]。
+    ///
+    /// ```
+    /// use log::Level;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-log-bytes.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// let frame: Vec<u8> = (0..40).collect();
+    /// logger.log_bytes(Level::Info, "net", "rx frame", &frame);
+    /// // 默认的 Info 级别过滤掉了 Debug
+    /// logger.log_bytes(Level::Debug, "net", "tx frame", b"ping\n");
+    ///
+    /// let tail = logger.tail(4);
+    /// assert!(tail[0].ends_with("net] rx frame (40 bytes)"));
+    /// assert_eq!(tail[1], "00000000: 0001 0203 0405 0607 0809 0a0b 0c0d 0e0f  ................");
+    /// assert_eq!(tail[2], "00000010: 1011 1213 1415 1617 1819 1a1b 1c1d 1e1f  ................");
+    /// assert_eq!(tail[3], "00000020: 2021 2223 2425 2627                       !\"#$%&'");
+    /// drop(logger);
+    ///
+    /// // 截断
+    /// let logger = mmlog::Builder::new().max_record_len(120).build(&path).unwrap();
+    /// logger.log_bytes(Level::Warn, "net", "big", &[0xff; 256]);
+    /// assert!(logger.tail(1)[0].contains("[truncated"));
+    /// ```
+    pub fn log_bytes(&self, level: Level, target: &str, label: &str, data: &[u8]) {
+        self.log(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("{}", fmt::hex_dump(label, data)))
+                .build(),
+        );
+    }
+
+    /// 和 `log()` 一样，但是打开了 [`Builder::non_blocking`] 也会等写锁，保证记录被写入。
+    pub fn log_blocking(&self, record: &Record) {
+        self.log_with(record, 0, true);
+    }
+
+    // 写进第 channel 个通道，见 Logger::channel
+    pub(crate) fn log_with(&self, record: &Record, channel: usize, blocking: bool) {
+        if !self.channel_enabled(channel, record.metadata()) {
+            return;
+        }
+        if let (Some(limit), Some(file), Some(line)) =
+            (&self.rate_limit, record.file(), record.line())
+        {
+            let (admit, summary) = limit.admit(file, line);
+            if let Some(s) = summary {
+                self.warn(format_args!(
+                    "rate limit: suppressed {} similar messages from {}:{}",
+                    s.suppressed, s.file, s.line
+                ));
+            }
+            if !admit {
+                if let Some(health) = &self.health {
+                    health.dropped();
+                }
+                return;
+            }
+        }
+        let formatted = self.store(record, channel, blocking);
+        // 已经释放了写锁
+        if let Some(tee) = &self.tee {
+            tee.forward(record, formatted.as_deref());
+        }
+    }
+
+    // 把记录写进缓冲区，返回格式化好的那一行（直接格式化到缓冲区里的话没有）。
+    // 别的通道的记录不攒批，见 Builder::batched
+    fn store(&self, record: &Record, channel: usize, blocking: bool) -> Option<String> {
+        // 时钟早于 UNIX 纪元
+        let now = match self.now() {
+            Some(now) => now,
+            None => {
+                self.dropped();
+                return None;
+            }
+        };
+        if self.direct && self.store_scratch(record, channel, blocking, now).is_some() {
+            return None;
+        }
+
+        let formatted = match &self.format {
+            None => format::record(
+                record,
+                self.sanitize,
+                self.format_kind,
+                self.timestamp,
+                self.thread_names,
+                now,
+            ),
+            Some(custom) => format::custom(record, custom).ok().or_else(|| {
+                self.format_errors.fetch_add(1, Ordering::Relaxed);
+                None
+            }),
+        };
+        let mut msg = match formatted {
+            Some(msg) if self.escape => format::escape_newlines(msg),
+            Some(msg) => msg,
+            None => {
+                self.dropped();
+                return None;
+            }
+        };
+        let max = match self.framing {
+            // 一个空文件要放得下
+            Framing::Text if self.rotate.is_some() => self.max_record(),
+            Framing::Text if self.oversized == Oversized::Truncate => self.ring_capacity,
+            Framing::Text => usize::MAX,
+            Framing::LengthPrefixed => self.max_frame(),
+        };
+        let max = self.max_record_len.map_or(max, |len| cmp::min(len, max));
+        if msg.len() > max {
+            format::truncate(&mut msg, max);
+            self.records_truncated.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.store_formatted(record.level(), channel, record.target(), &msg, blocking);
+        Some(msg)
+    }
+
+    // 默认格式：在锁外格式化到这个线程的 SCRATCH 里，拿到写锁后再复制进去，不用每条记录分配一次。
+    // 参数的 Display 实现里又写了日志的话 SCRATCH 正借着，嵌套的那条返回 None，
+    // 走分配 String 的路子；线程正在退出、SCRATCH 已经销毁了也一样
+    fn store_scratch(
+        &self,
+        record: &Record,
+        channel: usize,
+        blocking: bool,
+        now: Duration,
+    ) -> Option<()> {
+        SCRATCH
+            .try_with(|scratch| {
+                let mut scratch = scratch.try_borrow_mut().ok()?;
+                scratch.clear();
+                // 第一次就留够常见的记录的长度，不然比之前的记录长一点就要重新分配
+                scratch.reserve(SCRATCH_LEN);
+                let result = if self.escape {
+                    self.write_record(&mut format::NewlineEscape::new(&mut *scratch), record, now)
+                } else {
+                    self.write_record(&mut *scratch, record, now)
+                };
+                // 参数的 Display 实现出错时丢掉这条记录
+                if result.is_err() {
+                    self.dropped();
+                    return Some(());
+                }
+                if !scratch.ends_with('\n') {
+                    scratch.push('\n');
+                }
+                self.store_formatted(record.level(), channel, record.target(), &scratch, blocking);
+                Some(())
+            })
+            .ok()
+            .flatten()
+    }
+
+    // 写入格式化好的一条记录，Builder::batched 的话先攒进这个线程的 Batch 里。
+    // 别的通道的记录不攒批
+    fn store_formatted(
+        &self,
+        level: Level,
+        channel: usize,
+        target: &str,
+        msg: &str,
+        blocking: bool,
+    ) {
+        if let Some(batches) = self.batches.as_ref().filter(|_| channel == 0) {
+            let batched = batches.with_local(|batch| {
+                let mut batch = batch::lock(batch);
+                batch.push(level, target, msg);
+                self.batch_pushed(&mut batch, level);
+            });
+            if batched.is_some() {
+                return;
+            }
+            // 线程正在退出、Batch 已经从 thread_local 里拿掉了的话直接写，
+            // 先把它还攒着的记录写进去，保持先后顺序
+            self.write_batches();
+        }
+        unsafe { self.store_locked(level, channel, target, msg, blocking) };
+    }
+
+    // Builder::rotate 的文本格式下一条记录最长多少字节，留出序号的位置
+    fn max_record(&self) -> usize {
+        self.ring_capacity - 1 - if self.sequence { 22 } else { 0 }
+    }
+
+    // 长度前缀格式下一条记录最长多少字节
+    fn max_frame(&self) -> usize {
+        // 留出序号的位置：`#` + u64 最多 20 位 + 空格
+        // Builder::rotate 的话再留一个字节，正好写满会翻转
+        // 加密的话还有 nonce 和 tag
+        let reserved = format::FRAME_PREFIX
+            + format::FRAME_CHECKSUM
+            + if self.sequence { 22 } else { 0 }
+            + if self.cipher.is_some() {
+                format::SEAL_NONCE + format::SEAL_TAG
+            } else {
+                0
+            }
+            + self.rotate.is_some() as usize;
+        cmp::min(self.ring_capacity - reserved, u32::MAX as usize)
+    }
+
+    // 写入已经格式化好的一条记录，配额满了的话丢弃
+    unsafe fn store_locked(
+        &self,
+        level: Level,
+        channel: usize,
+        target: &str,
+        msg: &str,
+        blocking: bool,
+    ) {
+        // 锁住 offset 的变化
+        let _guard = match self.lock_or_drop(blocking) {
+            Some(guard) => guard,
+            None => return,
+        };
+        self.write_record_locked(level, channel, target, msg);
+    }
+
+    // 攒够了或者是 sync_on 级别的记录就写进去
+    fn batch_pushed(&self, batch: &mut Batch, level: Level) {
+        let batches = self.batches.as_ref().expect("batched");
+        if batch.len() >= batches.flush_bytes() || self.sync_on.is_some_and(|l| level <= l) {
+            self.write_batch(batch);
+        }
+    }
+
+    // Builder::batched：拿一次写锁把这一批记录都写进去
+    fn write_batch(&self, batch: &mut Batch) {
+        if batch.len() == 0 {
+            return;
+        }
+        let _guard = self.shared.lock();
+        for (level, target, msg) in batch.records() {
+            unsafe { self.write_record_locked(level, 0, target, msg) };
+        }
+        batch.clear();
+    }
+
+    // 把所有线程攒着的记录写进去，见 Builder::batched
+    fn write_batches(&self) {
+        if let Some(batches) = &self.batches {
+            for batch in batches.drain() {
+                self.write_batch(&mut batch::lock(&batch));
+            }
+        }
+    }
+
+    // 配额、adaptive_flush 和 sync_on 都按一条条记录算，channel 见 ring()。调用者必须持有写锁
+    unsafe fn write_record_locked(&self, level: Level, channel: usize, target: &str, msg: &str) {
+        if self.mapping().is_none() {
+            return;
+        }
+        self.write_reports_locked();
+
+        if let Some(targets) = &self.targets {
+            let generation = self.generation.load(Ordering::Relaxed);
+            match targets.admit(target, msg.len(), generation) {
+                Admit::Write => {}
+                Admit::Drop => {
+                    if let Some(health) = &self.health {
+                        health.dropped();
+                    }
+                    return;
+                }
+                Admit::Exceeded { target, limit } => {
+                    if let Some(health) = &self.health {
+                        health.dropped();
+                    }
+                    let marker = self.marker(format_args!(
+                        "quota exceeded: target {} wrote more than {} bytes, dropping its records",
+                        target, limit
+                    ));
+                    if let Some(marker) = marker {
+                        self.write_locked(ring(Level::Warn, channel), marker.as_bytes());
+                    }
+                    return;
+                }
+            }
+        }
+
+        let sync = self.adapt(level) || self.sync_on.is_some_and(|sync_on| level <= sync_on);
+        self.write_locked(ring(level, channel), msg.as_bytes());
+
+        if let (true, Some(mapping)) = (sync, self.mapping()) {
+            self.sync_result(&mapping.sync_dirty(true));
+        }
+    }
+
+    fn write_record<W: std::fmt::Write>(
+        &self,
+        w: &mut W,
+        record: &Record,
+        now: Duration,
+    ) -> std::fmt::Result {
+        format::write_record(
+            w,
+            record,
+            self.sanitize,
+            self.format_kind,
+            self.timestamp,
+            self.thread_names,
+            now,
+        )
+    }
+
+    // Builder::adaptive_flush：需要的话写入标记记录，返回这条记录之后是否要同步。
+    // 调用者必须持有写锁
+    unsafe fn adapt(&self, level: Level) -> bool {
+        let adaptive = match &self.adaptive {
+            Some(adaptive) => adaptive,
+            None => return false,
+        };
+        let (transition, sync) = adaptive.observe(level);
+        if adaptive.markers() {
+            let marker = match transition {
+                Transition::Stay => None,
+                Transition::Enter => self.marker(format_args!("entering aggressive flush mode")),
+                Transition::Leave => self.marker(format_args!("leaving aggressive flush mode")),
+            };
+            if let Some(marker) = marker {
+                self.write_locked(ring(Level::Warn, 0), marker.as_bytes());
+            }
+        }
+        sync
+    }
+
+    // 长度前缀格式：记录不跨过末尾，放不下时剩下的部分填 0，从头开始写。文件里有
+    // FLAG_CHECKSUM 的话后面跟着校验和。调用者保证整条记录不超过缓冲区大小，
+    // 返回新的 offset 和翻转的次数
+    unsafe fn write_frame(
+        &self,
+        mapping: &Mapping,
+        mut offset: usize,
+        source: &[u8],
+    ) -> (usize, usize) {
+        let size = mapping.size();
+        let checksum = mapping.checksum();
+        let total = format::FRAME_PREFIX + source.len() + format::frame_trailer(checksum);
+        let mut wraps = 0;
+        if offset + total > size {
+            mapping.zero_at(offset, size - offset);
+            offset = 0;
+            wraps += 1;
+        }
+        // 长度前缀最后写：崩溃时没写完的记录要么前缀还是 0，要么校验和对不上，
+        // 打开时能认出来，见 Mapping::roll_back_torn()
+        mapping.write_at(offset + format::FRAME_PREFIX, source);
+        if checksum {
+            let crc = format::checksum(source).to_le_bytes();
+            mapping.write_at(offset + format::FRAME_PREFIX + source.len(), &crc);
+        }
+        mapping.write_at(offset, &(source.len() as u32).to_le_bytes());
+        offset += total;
+        if offset == size {
+            offset = 0;
+            wraps += 1;
+        }
+        (offset, wraps)
+    }
+}
+
+// 文件加没加密、密钥对不对得上 Builder::encrypt
+fn check_key(mapping: &Mapping, builder: &Builder) -> Result<()> {
+    let message = match (mapping.key_check(), builder.map_options.key_check) {
+        (Some(file), Some(key)) if file != key => "wrong key for the encrypted file",
+        (Some(_), None) => "the file is encrypted, open it with Builder::encrypt",
+        (None, Some(_)) => "Builder::encrypt on a file that isn't encrypted",
+        _ => return Ok(()),
+    };
+    Err(Error::InvalidConfig(message))
+}
+
+// Builder::reserve_for_errors 的文件里 Warn 和 Error 写进第二个环，
+// Builder::channels 的文件里写进第 channel 个通道
+fn ring(level: Level, channel: usize) -> Ring {
+    if channel > 0 {
+        Ring::Channel(channel)
+    } else if level <= Level::Warn {
+        Ring::Severe
+    } else {
+        Ring::Main
+    }
+}
+
+impl Drop for Logger {
+    fn drop(&mut self) {
+        if self.fork_safe {
+            fork::unregister(&self.shared);
+        }
+        // 先停掉后台线程，之后只有这里会访问映射
+        if let Some(flusher) = &mut self.flusher {
+            flusher.stop();
+        }
+        self.write_batches();
+        // 等压缩线程把排着队的做完，它的错误也要写进去
+        #[cfg(feature = "compression")]
+        self.compressor.take();
+        {
+            let _guard = self.shared.lock();
+            unsafe { self.write_reports_locked() };
+        }
+        if self.banner {
+            self.notice(Level::Info, format_args!("{}", format::BANNER_SHUTDOWN));
+        }
+        // 已经 close() 过的话什么都不做
+        if let Some(mapping) = unsafe { (*self.shared.mapping.get()).take() } {
+            let _ = mapping.sync_dirty(self.sync);
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.target_enabled(metadata.level(), metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        self.log_with(record, 0, !self.non_blocking);
+    }
+
+    /// 同步失败时什么都不报告，需要知道结果的话用 [`Logger::try_flush`]。
+    fn flush(&self) {
+        let _ = self.try_flush();
+        if let Some(tee) = &self.tee {
+            tee.flush();
+        }
+    }
+}
+
+unsafe impl Send for Logger {}
+unsafe impl Sync for Logger {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Barrier};
+    use std::thread;
+    use std::time::Instant;
+
+    const ATTEMPTS: usize = 1000;
+
+    fn log(logger: &Logger, i: usize) {
+        logger.log(
+            &Record::builder()
+                .level(Level::Info)
+                .args(format_args!("record {}", i))
+                .build(),
+        );
+    }
+
+    // holder 的写锁在另一个线程里拿着的时候，logger 的 log() 马上返回，记录计入丢掉的
+    fn contended(logger: &Logger, holder: &Logger) {
+        let before = logger.stats();
+        let locked = Barrier::new(2);
+        let (release, released) = mpsc::channel::<()>();
+        let mut times = thread::scope(|s| {
+            let locked = &locked;
+            s.spawn(move || {
+                let _guard = holder.shared.lock();
+                locked.wait();
+                // log() 卡住的话一秒之后放开，让测试失败而不是一直等着
+                let _ = released.recv_timeout(Duration::from_secs(1));
+            });
+            locked.wait();
+            let times: Vec<Duration> = (0..ATTEMPTS)
+                .map(|i| {
+                    let start = Instant::now();
+                    log(logger, i);
+                    start.elapsed()
+                })
+                .collect();
+            release.send(()).unwrap();
+            times
+        });
+        times.sort();
+        assert!(
+            times[ATTEMPTS / 2] < Duration::from_micros(50),
+            "{:?}",
+            times
+        );
+        assert!(
+            times[ATTEMPTS - 1] < Duration::from_millis(100),
+            "{:?}",
+            times
+        );
+        let after = logger.stats();
+        assert_eq!(
+            after.records_dropped_contention - before.records_dropped_contention,
+            ATTEMPTS as u64
+        );
+        assert_eq!(
+            after.records_dropped - before.records_dropped,
+            ATTEMPTS as u64
+        );
+        assert_eq!(after.records_written, before.records_written);
+
+        // 锁放开了就照常写
+        log(logger, ATTEMPTS);
+        assert_eq!(logger.stats().records_written, before.records_written + 1);
+    }
+
+    #[test]
+    fn non_blocking_under_contention() {
+        let logger = Builder::new().non_blocking(true).build_anonymous().unwrap();
+        contended(&logger, &logger);
+    }
+
+    // 另一个 logger 拿着文件里的进程间锁，自旋锁拿得到也不等
+    #[cfg(all(feature = "mmap", unix))]
+    #[test]
+    fn non_blocking_under_process_lock_contention() {
+        let path = std::env::temp_dir().join("mmlog-unit-non-blocking.log");
+        let _ = std::fs::remove_file(&path);
+        let logger = Builder::new()
+            .shared(true)
+            .non_blocking(true)
+            .build(&path)
+            .unwrap();
+        let holder = Builder::new().shared(true).open(&path).unwrap();
+        contended(&logger, &holder);
     }
 }
@@ -0,0 +1,498 @@
+use super::{MapOptions, OpenMode, Region, Ring, HEADER_SIZE};
+use crate::format::{Framing, MUTEX_POS};
+use crate::{sys, Advice, Error, Result};
+use std::cell::Cell;
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::mem::{self, ManuallyDrop};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::{ptr, slice};
+
+// 进程间锁放在 header 里 MUTEX_POS 之后的 64 个字节
+const _: () = assert!(mem::size_of::<libc::pthread_mutex_t>() <= HEADER_SIZE - MUTEX_POS);
+
+// 一段 mmap 出来的内存，析构时 munmap
+#[derive(Debug)]
+pub(crate) struct Mapping {
+    addr: *mut libc::c_void,
+    size: usize,
+    // 缓冲区在文件里的起始位置，见 format::DATA_POS
+    start: usize,
+    path: PathBuf,
+    // 上次 sync_dirty() 之后写过的范围，见 Mapping::touch()
+    pub(super) dirty: Cell<(usize, usize)>,
+    // 持有 flock 的文件描述符，drop 时关闭，见 open() 的 exclusive
+    locked: Option<libc::c_int>,
+    // 文件里的环和当前选中的环，见 Mapping::select()
+    pub(super) regions: Vec<Region>,
+    pub(super) ring: Cell<Ring>,
+}
+
+impl Mapping {
+    /// `Create` 时新建（或截断）文件并写入 header；`Open` 时沿用文件里的容量，
+    /// 忽略 `capacity`；`OpenOrCreate` 只在文件是新建的（长度为 0）时写入 header；
+    /// `ReadOnly` 用 `O_RDONLY` 打开、`PROT_READ | MAP_PRIVATE` 映射，不改文件的大小，
+    /// 不能调用写入相关的方法，见 [`Mapping::read_only`](super::Mapping::read_only)。新建的文件使用 `framing`、
+    /// `options.page_aligned` 和 `options.mode`，`shared` 的话在 header 里初始化进程间锁。
+    /// 映射之后按 `options` 预读、`madvise`，`madvise` 失败了不算错误。
+    ///
+    /// `exclusive` 时在映射之前先对文件加 `flock(LOCK_EX | LOCK_NB)`，别的进程持有锁的话
+    /// 返回 [`Error::AlreadyLocked`]，锁一直保持到 `Mapping` 被 drop。
+    pub(crate) fn open(
+        path: &Path,
+        capacity: usize,
+        framing: Framing,
+        shared: bool,
+        exclusive: bool,
+        mode: OpenMode,
+        options: &MapOptions,
+    ) -> Result<Mapping> {
+        let start = super::data_start(options);
+        if mode != OpenMode::ReadOnly {
+            super::create_dirs(path, options)?;
+        }
+        // Create 也不用 O_TRUNC，拿到锁之后才截断
+        let flags = match mode {
+            OpenMode::Create => libc::O_CREAT | libc::O_RDWR,
+            OpenMode::Open => libc::O_RDWR,
+            OpenMode::OpenOrCreate => libc::O_CREAT | libc::O_RDWR,
+            OpenMode::ReadOnly => libc::O_RDONLY,
+        };
+        let prot = match mode {
+            OpenMode::ReadOnly => libc::PROT_READ,
+            _ => libc::PROT_WRITE | libc::PROT_READ,
+        };
+        let (mut mapping, fresh) = unsafe {
+            // 路径不一定是 UTF-8，直接用原始的字节
+            let cstr = CString::new(path.as_os_str().as_bytes())?;
+
+            // 只有新建文件时才用到权限，已有的文件不变
+            let fd = errno_try!(
+                libc::open(cstr.as_ptr(), flags, options.mode as libc::c_uint),
+                -1,
+                "open"
+            );
+            if exclusive && libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) == -1 {
+                let err = if sys::errno() == libc::EWOULDBLOCK {
+                    Error::AlreadyLocked
+                } else {
+                    Error::from_errno("flock")
+                };
+                libc::close(fd);
+                return Err(err);
+            }
+            if mode == OpenMode::Create {
+                errno_try!(libc::ftruncate(fd, 0), -1, "ftruncate", {
+                    libc::close(fd);
+                });
+            }
+            let mut len = 0;
+            if mode != OpenMode::Create {
+                let mut stat: libc::stat = std::mem::zeroed();
+                errno_try!(libc::fstat(fd, &mut stat), -1, "fstat", {
+                    libc::close(fd);
+                });
+                len = stat.st_size as usize;
+            }
+            // 不存在的文件由 mapping::open_or_create() 建好再链接过来，这里只会遇到已有的空文件
+            let fresh = mode == OpenMode::Create || (mode == OpenMode::OpenOrCreate && len == 0);
+            let size = if fresh {
+                let size = capacity + start;
+                errno_try!(libc::ftruncate(fd, size as _), -1, "ftruncate", {
+                    libc::close(fd);
+                });
+                size
+            } else if len < HEADER_SIZE {
+                libc::close(fd);
+                return Err(Error::CorruptHeader("file too short".to_string()));
+            } else {
+                len
+            };
+            if mode != OpenMode::ReadOnly {
+                if let Err(e) = reserve(fd, size, fresh) {
+                    libc::close(fd);
+                    return Err(e);
+                }
+            }
+            let populate = if options.populate {
+                sys::MAP_POPULATE
+            } else {
+                0
+            };
+            // 只读的话用私有映射，反正不会写，也就不会有写时复制：Linux 上没被复制过的页
+            // 一直是页缓存里的那一页，写入方之后写的内容照样看得到，LogReader::follow 靠的就是这个
+            let share = match mode {
+                OpenMode::ReadOnly => libc::MAP_PRIVATE,
+                _ => libc::MAP_SHARED,
+            };
+            let addr = errno_try!(
+                libc::mmap(
+                    ptr::null_mut::<libc::c_void>(),
+                    size as _,
+                    prot,
+                    share | populate,
+                    fd,
+                    0,
+                ),
+                libc::MAP_FAILED,
+                "mmap",
+                {
+                    libc::close(fd);
+                }
+            );
+            let mapping = Mapping {
+                addr,
+                size,
+                start,
+                path: path.to_path_buf(),
+                dirty: Cell::new((0, 0)),
+                locked: if exclusive { Some(fd) } else { None },
+                regions: Vec::new(),
+                ring: Cell::new(Ring::Main),
+            };
+            if !exclusive {
+                errno_try!(libc::close(fd), -1, "close");
+            }
+            advise(addr, size, options.advice);
+            (mapping, fresh)
+        };
+
+        if fresh {
+            super::init_header(
+                mapping.file_mut(),
+                capacity,
+                start,
+                framing,
+                shared,
+                options,
+            );
+            if shared {
+                unsafe { mapping.init_mutex()? };
+            }
+        } else {
+            mapping.start = super::check_header(mapping.file())?;
+        }
+        mapping.regions = super::regions(mapping.file(), mapping.capacity());
+        Ok(mapping)
+    }
+
+    /// 不对应文件的匿名映射（`MAP_SHARED | MAP_ANONYMOUS`），header 和文件里的一样，
+    /// fork 出来的子进程也能写，见 [`Builder::build_anonymous`](crate::Builder::build_anonymous)。
+    pub(crate) fn anonymous(
+        capacity: usize,
+        framing: Framing,
+        shared: bool,
+        options: &MapOptions,
+    ) -> Result<Mapping> {
+        let start = super::data_start(options);
+        let size = capacity + start;
+        let populate = if options.populate {
+            sys::MAP_POPULATE
+        } else {
+            0
+        };
+        let mut mapping = unsafe {
+            let addr = errno_try!(
+                libc::mmap(
+                    ptr::null_mut::<libc::c_void>(),
+                    size as _,
+                    libc::PROT_WRITE | libc::PROT_READ,
+                    libc::MAP_SHARED | libc::MAP_ANONYMOUS | populate,
+                    -1,
+                    0,
+                ),
+                libc::MAP_FAILED,
+                "mmap"
+            );
+            advise(addr, size, options.advice);
+            Mapping {
+                addr,
+                size,
+                start,
+                path: PathBuf::new(),
+                dirty: Cell::new((0, 0)),
+                locked: None,
+                regions: Vec::new(),
+                ring: Cell::new(Ring::Main),
+            }
+        };
+        super::init_header(
+            mapping.file_mut(),
+            capacity,
+            start,
+            framing,
+            shared,
+            options,
+        );
+        if shared {
+            unsafe { mapping.init_mutex()? };
+        }
+        mapping.regions = super::regions(mapping.file(), mapping.capacity());
+        Ok(mapping)
+    }
+
+    /// 文件内容的一份拷贝，放在匿名的私有映射里，`data` 不到 `size` 的部分是 0，
+    /// 见 [`Mapping::read_lenient`](super::Mapping::read_lenient)。
+    pub(crate) fn copied(path: &Path, data: &[u8], size: usize) -> Result<Mapping> {
+        let mut mapping = unsafe {
+            let addr = errno_try!(
+                libc::mmap(
+                    ptr::null_mut::<libc::c_void>(),
+                    size as _,
+                    libc::PROT_WRITE | libc::PROT_READ,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                ),
+                libc::MAP_FAILED,
+                "mmap"
+            );
+            Mapping {
+                addr,
+                size,
+                start: 0,
+                path: path.to_path_buf(),
+                dirty: Cell::new((0, 0)),
+                locked: None,
+                regions: Vec::new(),
+                ring: Cell::new(Ring::Main),
+            }
+        };
+        mapping.file_mut()[..data.len()].copy_from_slice(data);
+        mapping.start = super::check_header(mapping.file())?;
+        mapping.regions = super::regions(mapping.file(), mapping.capacity());
+        Ok(mapping)
+    }
+
+    // 新建文件时初始化进程间锁：PTHREAD_PROCESS_SHARED 加上 PTHREAD_MUTEX_ROBUST，
+    // 持有锁的进程死掉之后别的进程还能拿到。macOS 等没有 robust mutex，见 sys::set_robust()
+    unsafe fn init_mutex(&self) -> Result<()> {
+        let mut attr: libc::pthread_mutexattr_t = mem::zeroed();
+        pthread_try!(
+            libc::pthread_mutexattr_init(&mut attr),
+            "pthread_mutexattr_init"
+        );
+        let result = (|| {
+            pthread_try!(
+                libc::pthread_mutexattr_setpshared(&mut attr, sys::PTHREAD_PROCESS_SHARED),
+                "pthread_mutexattr_setpshared"
+            );
+            pthread_try!(sys::set_robust(&mut attr), "pthread_mutexattr_setrobust");
+            pthread_try!(
+                libc::pthread_mutex_init(self.mutex(), &attr),
+                "pthread_mutex_init"
+            );
+            Ok(())
+        })();
+        libc::pthread_mutexattr_destroy(&mut attr);
+        result
+    }
+
+    fn mutex(&self) -> *mut libc::pthread_mutex_t {
+        unsafe { self.addr.add(MUTEX_POS) as *mut libc::pthread_mutex_t }
+    }
+
+    /// header 里有进程间锁的话拿到它，释放返回的 [`ProcessLock`] 时解锁。
+    ///
+    /// 持有锁的进程死掉了的话直接接手：offset 和计数只在一条记录写完之后才更新，
+    /// 写了一半的记录在 offset 之后，下一条记录会覆盖它。
+    pub(crate) fn lock_process(&self) -> Option<ProcessLock> {
+        if !self.shared() {
+            return None;
+        }
+        let mutex = self.mutex();
+        Self::acquired(mutex, unsafe { libc::pthread_mutex_lock(mutex) })
+    }
+
+    /// 和 [`Mapping::lock_process`] 一样，但是锁在别的进程手里的话不等，返回 `Err(())`。
+    pub(crate) fn try_lock_process(&self) -> std::result::Result<Option<ProcessLock>, ()> {
+        if !self.shared() {
+            return Ok(None);
+        }
+        let mutex = self.mutex();
+        match unsafe { libc::pthread_mutex_trylock(mutex) } {
+            libc::EBUSY => Err(()),
+            ret => Ok(Self::acquired(mutex, ret)),
+        }
+    }
+
+    fn acquired(mutex: *mut libc::pthread_mutex_t, ret: libc::c_int) -> Option<ProcessLock> {
+        match ret {
+            0 => Some(ProcessLock(mutex)),
+            libc::EOWNERDEAD => {
+                unsafe { sys::make_consistent(mutex) };
+                Some(ProcessLock(mutex))
+            }
+            // ENOTRECOVERABLE 之类的，没有别的办法，不加锁写
+            _ => None,
+        }
+    }
+
+    /// fork 出来的子进程里检查一遍进程间锁：持有锁的进程死了的话接手再放开，
+    /// 已经是 `ENOTRECOVERABLE` 的话重新初始化。锁在别的进程手里（`EBUSY`）是正常的，
+    /// 它会自己放开。调用者必须持有自旋锁。
+    pub(crate) fn revalidate_process_lock(&self) {
+        if !self.shared() {
+            return;
+        }
+        let mutex = self.mutex();
+        match unsafe { libc::pthread_mutex_trylock(mutex) } {
+            libc::EBUSY => {}
+            libc::ENOTRECOVERABLE => {
+                let _ = unsafe { self.init_mutex() };
+            }
+            ret => drop(Self::acquired(mutex, ret)),
+        }
+    }
+
+    // 包括 header 在内的整个文件
+    fn file(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.addr as *const u8, self.size) }
+    }
+
+    // 只在 open() 里还没有别的引用时用到
+    fn file_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.addr as *mut u8, self.size) }
+    }
+
+    pub(crate) fn header(&self) -> &[u8] {
+        &self.file()[..HEADER_SIZE]
+    }
+
+    // 整个缓冲区，两个环的话包括两个
+    pub(super) fn region(&self) -> &[u8] {
+        &self.file()[self.start..]
+    }
+
+    // 缓冲区前面的部分：header、元数据和填充
+    pub(super) fn prefix(&self) -> &[u8] {
+        &self.file()[..self.start]
+    }
+
+    // 整个文件的开头，按页对齐
+    pub(super) fn base(&self) -> *mut u8 {
+        self.addr as *mut u8
+    }
+
+    pub(super) fn start(&self) -> usize {
+        self.start
+    }
+
+    /// 整个缓冲区的长度，两个环的话是加起来的。
+    pub(crate) fn capacity(&self) -> usize {
+        self.size - self.start
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn sync(&self, sync: bool) -> Result<()> {
+        self.msync(0, self.size, sync)
+    }
+
+    /// `mlock` 整个映射，munmap 时自动解锁，见 [`Builder::lock_memory`](crate::Builder::lock_memory)。
+    pub(crate) fn lock_memory(&self) -> Result<()> {
+        unsafe { errno_try!(libc::mlock(self.addr, self.size as _), -1, "mlock") };
+        Ok(())
+    }
+
+    /// 只同步 header 和 [`Mapping::touch`] 标记过的页，调用者必须持有写锁。
+    pub(crate) fn sync_dirty(&self, sync: bool) -> Result<()> {
+        #[cfg(feature = "test-util")]
+        if let Some(e) = crate::test::failed_sync() {
+            return Err(e.into());
+        }
+        let (start, end) = self.dirty.get();
+        if start >= end {
+            return self.msync(0, HEADER_SIZE, sync);
+        }
+        let page = super::page_size();
+        let start = (self.start + start) / page * page;
+        if start >= page {
+            // 和 header 不在同一页，中间没写过的不用管
+            self.msync(0, HEADER_SIZE, sync)?;
+            self.msync(start, self.start + end, sync)?;
+        } else {
+            self.msync(0, self.start + end, sync)?;
+        }
+        self.dirty.set((0, 0));
+        Ok(())
+    }
+
+    // 同步文件里的 [start, end)，start 必须按页对齐
+    fn msync(&self, start: usize, end: usize, sync: bool) -> Result<()> {
+        let flags = if sync { libc::MS_SYNC } else { libc::MS_ASYNC };
+        unsafe {
+            errno_try!(
+                libc::msync(self.addr.add(start), (end - start) as _, flags),
+                -1,
+                "msync"
+            );
+        }
+        Ok(())
+    }
+}
+
+// ftruncate() 出来的是稀疏文件，磁盘满了之后再写到还没分配的页会收到 SIGBUS，
+// 所以映射之前先把空间分配好，ENOSPC 在这里就返回。不支持 fallocate 的文件系统上
+// 新建的文件直接写 0，已有的文件不能写，只好算了
+unsafe fn reserve(fd: libc::c_int, size: usize, fresh: bool) -> Result<()> {
+    match sys::fallocate(fd, size) {
+        0 => Ok(()),
+        // ZFS 之类的返回 EINVAL
+        libc::EOPNOTSUPP | libc::EINVAL if fresh => Ok(zero_fill(fd, size)?),
+        libc::EOPNOTSUPP | libc::EINVAL => Ok(()),
+        code => Err(io::Error::from_raw_os_error(code).into()),
+    }
+}
+
+fn zero_fill(fd: libc::c_int, size: usize) -> io::Result<()> {
+    // fd 还是调用者的，不能在这里关闭
+    let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    let zeros = [0; 64 * 1024];
+    let mut pos = 0;
+    while pos < size {
+        let n = zeros.len().min(size - pos);
+        file.write_all_at(&zeros[..n], pos as u64)?;
+        pos += n;
+    }
+    Ok(())
+}
+
+// 只是提示，失败了也不影响
+unsafe fn advise(addr: *mut libc::c_void, size: usize, advice: Advice) {
+    let advice = match advice {
+        Advice::Normal => return,
+        Advice::Sequential => libc::MADV_SEQUENTIAL,
+        Advice::Random => libc::MADV_RANDOM,
+        Advice::WillNeed => libc::MADV_WILLNEED,
+    };
+    libc::madvise(addr, size as _, advice);
+}
+
+/// 见 [`Mapping::lock_process`]。
+pub(crate) struct ProcessLock(*mut libc::pthread_mutex_t);
+
+impl Drop for ProcessLock {
+    fn drop(&mut self) {
+        unsafe { libc::pthread_mutex_unlock(self.0) };
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // 失败了也没什么能做的
+        unsafe {
+            libc::munmap(self.addr, self.size as _);
+            if let Some(fd) = self.locked {
+                // 关闭之后 flock 自动释放
+                libc::close(fd);
+            }
+        }
+    }
+}
@@ -0,0 +1,1178 @@
+// 非 Unix 系统上没有 mmap 实现，即使打开了 mmap feature 也用 std_file
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
+#[cfg(not(all(feature = "mmap", unix)))]
+mod std_file;
+
+#[cfg(all(feature = "mmap", unix))]
+pub(crate) use mmap::{Mapping, ProcessLock};
+#[cfg(not(all(feature = "mmap", unix)))]
+pub(crate) use std_file::{Mapping, ProcessLock};
+
+use crate::crypt;
+use crate::format::{
+    self, Anchor, Clock, Framing, ANCHOR_POS, BOOKMARK_SIZE, BOOKMARK_SLOTS, BOOT_ID_LEN,
+    BOOT_ID_POS, BOOT_POS, BYTES_POS, CAPACITY_POS, CHANNELS_POS, CHANNEL_ENTRY_SIZE,
+    CHANNEL_NAME_LEN, CHANNEL_TABLE_POS, DATA_POS, FLAGS_POS, FLAG_CHANNELS, FLAG_CHECKSUM,
+    FLAG_ENCRYPTED, FLAG_ESCAPED_NEWLINES, FLAG_LENGTH_PREFIXED, FLAG_MONOTONIC, FLAG_RESERVED,
+    FLAG_RETENTION, FLAG_SHARED, KEY_CHECK_LEN, KEY_CHECK_POS, MAGIC, MAGIC_POS, MAX_CHANNELS,
+    META_SIZE, OFFSET_POS, RECORDS_POS, RESERVED_OFFSET_POS, RESERVED_POS, RESERVED_WRAPS_POS,
+    RETENTION_POS, SALT_LEN, SALT_POS, TAG_LEN, VERSION, VERSION_POS, WRAPS_POS,
+};
+use crate::meta;
+use crate::reader::FileMetadata;
+use crate::{Advice, Error, Result};
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// 两种实现的文件格式完全一样，见 crate::format
+pub(crate) use crate::format::HEADER_SIZE;
+
+/// 新建文件的布局、权限和映射的方式，见 [`Builder::page_aligned`](crate::Builder::page_aligned)、
+/// [`Builder::mode`](crate::Builder::mode)、[`Builder::create_dirs`](crate::Builder::create_dirs)、
+/// [`Builder::populate`](crate::Builder::populate) 和 [`Builder::advise`](crate::Builder::advise)。
+/// 最后两个只对 mmap 实现有用。
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MapOptions {
+    // 只影响新建的文件，已有的文件沿用原来的布局和权限
+    pub(crate) page_aligned: bool,
+    pub(crate) mode: u32,
+    pub(crate) create_dirs: bool,
+    pub(crate) dir_mode: u32,
+    pub(crate) populate: bool,
+    pub(crate) advice: Advice,
+    // 新建文件时在 header 里设置 FLAG_ESCAPED_NEWLINES，见 Builder::escape_newlines
+    pub(crate) escape_newlines: bool,
+    // 新建文件时写进元数据，见 Builder::tag
+    pub(crate) tag: [u8; TAG_LEN],
+    // 新建文件时分给 Warn/Error 的比例，0 表示只有一个环，见 Builder::reserve_for_errors
+    pub(crate) reserve: f64,
+    // 新建文件时的通道，都是 None 的话没有通道，见 Builder::channels
+    pub(crate) channels: [Option<ChannelSpec>; MAX_CHANNELS],
+    // 新建文件时记录的时间戳用哪个时钟，见 Builder::clock
+    pub(crate) clock: Clock,
+    // 新建文件时加密记录的话是密钥的 key check，见 Builder::encrypt
+    pub(crate) key_check: Option<[u8; KEY_CHECK_LEN]>,
+    // 新建文件时写进元数据的保留期限，见 Builder::retention
+    pub(crate) retention: Option<std::time::Duration>,
+    // 单调时钟的新文件的时间戳的偏移，轮转、swap_file() 出来的文件沿用原来的，见 format::BOOT_POS
+    pub(crate) boot_offset: std::time::Duration,
+}
+
+/// [`Builder::channels`](crate::Builder::channels) 的一个通道：名字（后面填 0）和长度。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ChannelSpec {
+    pub(crate) name: [u8; CHANNEL_NAME_LEN],
+    pub(crate) capacity: usize,
+}
+
+impl Default for MapOptions {
+    fn default() -> Self {
+        MapOptions {
+            page_aligned: false,
+            mode: 0o600,
+            create_dirs: false,
+            dir_mode: 0o700,
+            populate: false,
+            advice: Advice::Normal,
+            escape_newlines: false,
+            tag: [0; TAG_LEN],
+            reserve: 0.0,
+            channels: [None; MAX_CHANNELS],
+            clock: Clock::Realtime,
+            key_check: None,
+            retention: None,
+            boot_offset: std::time::Duration::ZERO,
+        }
+    }
+}
+
+/// `create_dirs` 的话先建好上级目录，已经存在的目录不动。
+pub(crate) fn create_dirs(path: &Path, options: &MapOptions) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if options.create_dirs && !parent.as_os_str().is_empty() => parent,
+        _ => return Ok(()),
+    };
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, options.dir_mode);
+    builder.create(parent)?;
+    Ok(())
+}
+
+// 版本 4 没有 DATA_POS，缓冲区紧跟在 header 后面，别的都一样
+const MIN_VERSION: u32 = 4;
+
+/// 新建文件时缓冲区的起始位置，见 [`Builder::page_aligned`](crate::Builder::page_aligned)。
+/// 有通道的话元数据后面是通道表，再后面是书签表。
+pub(crate) fn data_start(options: &MapOptions) -> usize {
+    let end = bookmarks_pos(options.channels.iter().flatten().count()) + BOOKMARKS_LEN;
+    if options.page_aligned {
+        end.next_multiple_of(page_size())
+    } else {
+        end
+    }
+}
+
+// 书签表的长度
+const BOOKMARKS_LEN: usize = BOOKMARK_SLOTS * BOOKMARK_SIZE;
+
+// 版本 12 开始书签表紧跟在通道表后面
+fn bookmarks_pos(channels: usize) -> usize {
+    CHANNEL_TABLE_POS + channels * CHANNEL_ENTRY_SIZE
+}
+
+#[cfg(all(feature = "mmap", unix))]
+pub(crate) fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+// 没有 mmap 的话对齐没什么用，按最常见的 4KB，文件拿到别的机器上也能打开
+#[cfg(not(all(feature = "mmap", unix)))]
+pub(crate) fn page_size() -> usize {
+    4096
+}
+
+// 新建文件时写入 header 和元数据，缓冲区从 start 开始，进程间锁由调用者初始化。
+// 长度前缀格式的记录总是带校验和
+pub(crate) fn init_header(
+    file: &mut [u8],
+    capacity: usize,
+    start: usize,
+    framing: Framing,
+    shared: bool,
+    options: &MapOptions,
+) {
+    let checksum = framing == Framing::LengthPrefixed;
+    let escaped = options.escape_newlines;
+    // 按 8 字节取整，取整之后是 0 的话只有一个环
+    let reserved = (capacity as f64 * options.reserve).round() as usize / 8 * 8;
+    let channels = options.channels.iter().flatten().count();
+    let monotonic = options.clock == Clock::Monotonic;
+    let flags = framing.flags()
+        | if checksum { FLAG_CHECKSUM } else { 0 }
+        | if shared { FLAG_SHARED } else { 0 }
+        | if escaped { FLAG_ESCAPED_NEWLINES } else { 0 }
+        | if reserved > 0 { FLAG_RESERVED } else { 0 }
+        | if channels > 0 { FLAG_CHANNELS } else { 0 }
+        | if monotonic { FLAG_MONOTONIC } else { 0 }
+        | if options.key_check.is_some() {
+            FLAG_ENCRYPTED
+        } else {
+            0
+        }
+        | if options.retention.is_some() {
+            FLAG_RETENTION
+        } else {
+            0
+        };
+    file[MAGIC_POS..MAGIC_POS + 4].copy_from_slice(&MAGIC);
+    file[VERSION_POS..VERSION_POS + 4].copy_from_slice(&VERSION.to_le_bytes());
+    file[CAPACITY_POS..CAPACITY_POS + 8].copy_from_slice(&(capacity as u64).to_le_bytes());
+    file[FLAGS_POS..FLAGS_POS + 4].copy_from_slice(&flags.to_le_bytes());
+    // offset 和各个计数都从 0 开始，header 和缓冲区之间也填 0
+    file[OFFSET_POS..start].fill(0);
+    file[DATA_POS..DATA_POS + 4].copy_from_slice(&(start as u32).to_le_bytes());
+    meta::write(file, &options.tag);
+    file[RESERVED_POS..RESERVED_POS + 8].copy_from_slice(&(reserved as u64).to_le_bytes());
+    file[CHANNELS_POS..CHANNELS_POS + 4].copy_from_slice(&(channels as u32).to_le_bytes());
+    if monotonic {
+        let anchor = Anchor::now(options.boot_offset);
+        file[ANCHOR_POS..ANCHOR_POS + 16].copy_from_slice(&anchor_bytes(anchor));
+        file[BOOT_ID_POS..BOOT_ID_POS + BOOT_ID_LEN].copy_from_slice(&format::boot_id());
+        file[BOOT_POS..BOOT_POS + 8]
+            .copy_from_slice(&(anchor.boot_start.as_nanos() as u64).to_le_bytes());
+    }
+    if let Some(check) = options.key_check {
+        file[SALT_POS..SALT_POS + SALT_LEN].copy_from_slice(&crypt::salt());
+        file[KEY_CHECK_POS..KEY_CHECK_POS + KEY_CHECK_LEN].copy_from_slice(&check);
+    }
+    if let Some(retention) = options.retention {
+        let nanos = u64::try_from(retention.as_nanos()).unwrap_or(u64::MAX);
+        file[RETENTION_POS..RETENTION_POS + 8].copy_from_slice(&nanos.to_le_bytes());
+    }
+    // 通道首尾相接，调用者保证加起来正好是 capacity
+    let mut at = 0;
+    for (i, channel) in options.channels.iter().flatten().enumerate() {
+        let entry = CHANNEL_TABLE_POS + i * CHANNEL_ENTRY_SIZE;
+        file[entry..entry + CHANNEL_NAME_LEN].copy_from_slice(&channel.name);
+        file[entry + 32..entry + 40].copy_from_slice(&(at as u64).to_le_bytes());
+        file[entry + 40..entry + 48].copy_from_slice(&(channel.capacity as u64).to_le_bytes());
+        at += channel.capacity;
+    }
+}
+
+// 文件里 ANCHOR_POS 处的 16 个字节
+fn anchor_bytes(anchor: Anchor) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&(anchor.realtime.as_nanos() as u64).to_le_bytes());
+    bytes[8..].copy_from_slice(&(anchor.monotonic.as_nanos() as u64).to_le_bytes());
+    bytes
+}
+
+fn read_u64(file: &[u8], pos: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&file[pos..pos + 8]);
+    u64::from_le_bytes(word)
+}
+
+fn read_u32(file: &[u8], pos: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&file[pos..pos + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn channel_count(file: &[u8]) -> usize {
+    let mut count = [0; 4];
+    count.copy_from_slice(&file[CHANNELS_POS..CHANNELS_POS + 4]);
+    u32::from_le_bytes(count) as usize
+}
+
+/// 文件里的一个环：它在缓冲区里的位置和长度，以及它的 offset 和翻转次数在文件里的位置。
+#[derive(Debug, Clone)]
+pub(crate) struct Region {
+    ring: Ring,
+    start: usize,
+    len: usize,
+    offset_pos: usize,
+    wraps_pos: usize,
+    // 通道名，不是通道的话是空的
+    name: String,
+}
+
+// 按 header 分出来的环，至少有一个。file 的 header 已经检查过了
+pub(crate) fn regions(file: &[u8], capacity: usize) -> Vec<Region> {
+    let flags = header_flags(file);
+    if flags & FLAG_CHANNELS != 0 {
+        return (0..channel_count(file))
+            .map(|i| {
+                let entry = CHANNEL_TABLE_POS + i * CHANNEL_ENTRY_SIZE;
+                let name = &file[entry..entry + CHANNEL_NAME_LEN];
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                Region {
+                    ring: Ring::Channel(i),
+                    start: read_u64(file, entry + 32) as usize,
+                    len: read_u64(file, entry + 40) as usize,
+                    offset_pos: entry + 48,
+                    wraps_pos: entry + 56,
+                    name: String::from_utf8_lossy(&name[..end]).into_owned(),
+                }
+            })
+            .collect();
+    }
+    let reserved = if flags & FLAG_RESERVED != 0 {
+        read_u64(file, RESERVED_POS) as usize
+    } else {
+        0
+    };
+    let mut regions = vec![Region {
+        ring: Ring::Main,
+        start: 0,
+        len: capacity - reserved,
+        offset_pos: OFFSET_POS,
+        wraps_pos: WRAPS_POS,
+        name: String::new(),
+    }];
+    if reserved > 0 {
+        regions.push(Region {
+            ring: Ring::Severe,
+            start: capacity - reserved,
+            len: reserved,
+            offset_pos: RESERVED_OFFSET_POS,
+            wraps_pos: RESERVED_WRAPS_POS,
+            name: String::new(),
+        });
+    }
+    regions
+}
+
+// 检查已有文件的 header，返回缓冲区的起始位置
+pub(crate) fn check_header(file: &[u8]) -> Result<usize> {
+    if file.len() < HEADER_SIZE {
+        return Err(Error::CorruptHeader("file too short".to_string()));
+    }
+    if file[MAGIC_POS..MAGIC_POS + 4] != MAGIC {
+        // 最早的格式只有一个 offset，看看是不是这种文件
+        let legacy = {
+            let mut word = [0; mem::size_of::<usize>()];
+            word.copy_from_slice(&file[..mem::size_of::<usize>()]);
+            usize::from_ne_bytes(word) <= file.len() - mem::size_of::<usize>()
+        };
+        return Err(Error::CorruptHeader(if legacy {
+            "legacy mmlog file without magic, recreate it with Builder::build()".to_string()
+        } else {
+            "not an mmlog file".to_string()
+        }));
+    }
+
+    let mut version = [0; 4];
+    version.copy_from_slice(&file[VERSION_POS..VERSION_POS + 4]);
+    let version = u32::from_le_bytes(version);
+    if !(MIN_VERSION..=VERSION).contains(&version) {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let start = if version < 5 {
+        HEADER_SIZE
+    } else {
+        let mut start = [0; 4];
+        start.copy_from_slice(&file[DATA_POS..DATA_POS + 4]);
+        u32::from_le_bytes(start) as usize
+    };
+    // 版本 6 开始 header 后面是元数据
+    let min = if version < 6 {
+        HEADER_SIZE
+    } else {
+        HEADER_SIZE + META_SIZE
+    };
+    // 版本 12 开始后面还有书签表
+    let min = if version < 12 {
+        min
+    } else {
+        let channels = if header_flags(file) & FLAG_CHANNELS != 0 {
+            channel_count(file)
+        } else {
+            0
+        };
+        bookmarks_pos(channels.min(MAX_CHANNELS)) + BOOKMARKS_LEN
+    };
+    if start < min || start % 8 != 0 || start > file.len() {
+        return Err(Error::CorruptHeader(format!("bad data start {}", start)));
+    }
+
+    let flags = header_flags(file);
+    let known = FLAG_LENGTH_PREFIXED
+        | FLAG_SHARED
+        | FLAG_ESCAPED_NEWLINES
+        | FLAG_CHECKSUM
+        | if version < 7 { 0 } else { FLAG_RESERVED }
+        | if version < 8 { 0 } else { FLAG_CHANNELS }
+        | if version < 9 { 0 } else { FLAG_MONOTONIC }
+        | if version < 10 { 0 } else { FLAG_ENCRYPTED }
+        | if version < 11 { 0 } else { FLAG_RETENTION };
+    if flags & !known != 0 {
+        return Err(Error::CorruptHeader(format!("unknown flags {:#x}", flags)));
+    }
+    // 加密的记录靠长度前缀和校验和拆开
+    let framed = FLAG_LENGTH_PREFIXED | FLAG_CHECKSUM;
+    if flags & FLAG_ENCRYPTED != 0 && flags & framed != framed {
+        return Err(Error::CorruptHeader(format!(
+            "encrypted records without length prefixes and checksums, flags {:#x}",
+            flags
+        )));
+    }
+
+    let mut capacity = [0; 8];
+    capacity.copy_from_slice(&file[CAPACITY_POS..CAPACITY_POS + 8]);
+    let capacity = u64::from_le_bytes(capacity) as usize;
+    if capacity.checked_add(start) != Some(file.len()) {
+        return Err(Error::CorruptHeader(format!(
+            "capacity {} doesn't match the file length {}",
+            capacity,
+            file.len()
+        )));
+    }
+    if flags & FLAG_RESERVED != 0 {
+        let reserved = read_u64(file, RESERVED_POS) as usize;
+        if start < HEADER_SIZE + META_SIZE || reserved == 0 || reserved >= capacity {
+            return Err(Error::CorruptHeader(format!(
+                "bad reserved ring size {}",
+                reserved
+            )));
+        }
+    }
+    if flags & FLAG_CHANNELS != 0 {
+        check_channels(file, start, capacity, flags)?;
+    }
+    // Mapping::position 是翻转次数 × 环的长度 + offset，坏文件里的翻转次数不能让它溢出。
+    // 计数和 offset 一样是本机字节序
+    for region in regions(file, capacity) {
+        let mut wraps = [0; 8];
+        wraps.copy_from_slice(&file[region.wraps_pos..region.wraps_pos + 8]);
+        let wraps = u64::from_ne_bytes(wraps);
+        let len = region.len as u64;
+        if wraps
+            .checked_mul(len)
+            .and_then(|n| n.checked_add(len))
+            .is_none()
+        {
+            return Err(Error::CorruptHeader(format!(
+                "wrap count {} of a {} byte ring overflows",
+                wraps, region.len
+            )));
+        }
+    }
+    Ok(start)
+}
+
+// 通道表在 data 之前，通道首尾相接、正好铺满缓冲区，名字都是 UTF-8
+fn check_channels(file: &[u8], start: usize, capacity: usize, flags: u32) -> Result<()> {
+    let count = channel_count(file);
+    if flags & FLAG_RESERVED != 0
+        || !(1..=MAX_CHANNELS).contains(&count)
+        || start < CHANNEL_TABLE_POS + count * CHANNEL_ENTRY_SIZE
+    {
+        return Err(Error::CorruptHeader(format!(
+            "bad channel table with {} channels",
+            count
+        )));
+    }
+    let mut at = 0;
+    for region in regions(file, capacity) {
+        if region.start != at || region.len == 0 || region.len > capacity - at {
+            return Err(Error::CorruptHeader(format!(
+                "channel {:?} at {} with capacity {} doesn't fit the buffer",
+                region.name, region.start, region.len
+            )));
+        }
+        at += region.len;
+    }
+    let names = CHANNEL_TABLE_POS..CHANNEL_TABLE_POS + count * CHANNEL_ENTRY_SIZE;
+    let bad_name = file[names]
+        .chunks(CHANNEL_ENTRY_SIZE)
+        .any(|entry| std::str::from_utf8(&entry[..CHANNEL_NAME_LEN]).is_err());
+    if at != capacity || bad_name {
+        return Err(Error::CorruptHeader("bad channel table".to_string()));
+    }
+    Ok(())
+}
+
+/// [`Builder::reserve_for_errors`](crate::Builder::reserve_for_errors) 分出来的两个环，
+/// 或者 [`Builder::channels`](crate::Builder::channels) 的通道。文件里没有的环都是第一个环：
+/// 只有一个环的文件 `Severe` 就是 `Main`，有通道的文件 `Main` 和 `Severe` 都是第一个通道。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Ring {
+    Main,
+    // 缓冲区末尾只写 Warn/Error 的那个
+    Severe,
+    // 通道表里的第几个
+    Channel(usize),
+}
+
+fn header_flags(file: &[u8]) -> u32 {
+    let mut flags = [0; 4];
+    flags.copy_from_slice(&file[FLAGS_POS..FLAGS_POS + 4]);
+    u32::from_le_bytes(flags)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OpenMode {
+    // 创建或者截断
+    Create,
+    // 打开已有的文件，沿用里面的 offset
+    Open,
+    // 文件不存在（或者长度为 0）时创建，否则和 Open 一样，不会截断
+    OpenOrCreate,
+    // 只读打开已有的文件，不能写入，也不会写回
+    ReadOnly,
+}
+
+impl Mapping {
+    /// 只读打开已有的文件，[`LogReader`](crate::reader::LogReader) 用的就是这个：
+    /// 只需要读权限，不加锁，不建目录，不会 `ftruncate` 或者分配空间，也不会写回。
+    /// 格式、大小都以文件里的 header 为准。
+    pub(crate) fn read_only(path: &Path) -> Result<Mapping> {
+        Mapping::open(
+            path,
+            0,
+            Framing::Text,
+            false,
+            false,
+            OpenMode::ReadOnly,
+            &MapOptions::default(),
+        )
+    }
+
+    /// 和 [`Mapping::read_only`] 一样，但文件比 header 里说的短（比如传输到一半断了）也能打开：
+    /// 整个读进内存，缺的部分是 0。header 和元数据必须是全的。
+    /// 返回映射和文件实际的长度，没有被截断的话就是映射的长度。
+    pub(crate) fn read_lenient(path: &Path) -> Result<(Mapping, usize)> {
+        let data = std::fs::read(path)?;
+        let size = match declared_len(&data)? {
+            Some(size) if size > data.len() => size,
+            _ => return Ok((Mapping::read_only(path)?, data.len())),
+        };
+        Ok((Mapping::copied(path, &data, size)?, data.len()))
+    }
+
+    /// 整个文件的长度：header、元数据加上缓冲区。
+    pub(crate) fn file_len(&self) -> usize {
+        self.start() + self.capacity()
+    }
+
+    /// 文件只有前 `file_len` 个字节的话，`ring` 里还剩几个字节，见 [`Mapping::read_lenient`]。
+    pub(crate) fn ring_present(&self, ring: Ring, file_len: usize) -> usize {
+        let (start, len) = self.bounds(ring);
+        file_len.saturating_sub(self.start() + start).min(len)
+    }
+
+    /// 文件里的环，按在缓冲区里的顺序：只有一个的话是 `Main`，分了环的话是 `Main` 和 `Severe`，
+    /// 有通道的话是每个通道。
+    pub(crate) fn rings(&self) -> impl Iterator<Item = Ring> + '_ {
+        self.regions.iter().map(|region| region.ring)
+    }
+
+    /// 通道的名字和长度，没有通道的话是空的。
+    pub(crate) fn channels(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.regions
+            .iter()
+            .filter(|region| matches!(region.ring, Ring::Channel(_)))
+            .map(|region| (region.name.as_str(), region.len))
+    }
+
+    /// 之后 [`Mapping::size`]、[`Mapping::offset`]、[`Mapping::write_at`] 这些写入用的方法
+    /// 都针对 `ring`，直到再次调用。调用者必须持有写锁，写完之后换回 `Main`。
+    /// 读取用的 [`Mapping::halves`] 和 [`Mapping::position`] 直接指定环，不受影响。
+    pub(crate) fn select(&self, ring: Ring) {
+        self.ring.set(ring);
+    }
+
+    /// 在 `ring` 上调用 `f`，之后换回原来的环，要求和 [`Mapping::select`] 一样。
+    pub(crate) fn in_ring<R>(&self, ring: Ring, f: impl FnOnce(&Mapping) -> R) -> R {
+        struct Restore<'a>(&'a Mapping, Ring);
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                self.0.select(self.1);
+            }
+        }
+        let _restore = Restore(self, self.ring.get());
+        self.select(ring);
+        f(self)
+    }
+
+    /// 文件里的通道表，新建同样分通道的文件用。
+    pub(crate) fn channel_table(&self) -> [Option<ChannelSpec>; MAX_CHANNELS] {
+        let mut table = [None; MAX_CHANNELS];
+        for (slot, (name, capacity)) in table.iter_mut().zip(self.channels()) {
+            *slot = Some(ChannelSpec {
+                name: meta::padded(name),
+                capacity,
+            });
+        }
+        table
+    }
+
+    // 文件里没有的环都是第一个，见 Ring
+    fn lookup(&self, ring: Ring) -> &Region {
+        let i = match ring {
+            Ring::Main => 0,
+            Ring::Severe => 1,
+            Ring::Channel(i) => i,
+        };
+        match self.regions.get(i) {
+            Some(region) if region.ring == ring => region,
+            _ => &self.regions[0],
+        }
+    }
+
+    /// `ring` 在缓冲区里的起始位置和长度。
+    pub(crate) fn bounds(&self, ring: Ring) -> (usize, usize) {
+        let region = self.lookup(ring);
+        (region.start, region.len)
+    }
+
+    /// 当前的环的长度。
+    pub(crate) fn size(&self) -> usize {
+        self.ring_size(self.ring.get())
+    }
+
+    /// `ring` 的长度。
+    pub(crate) fn ring_size(&self, ring: Ring) -> usize {
+        self.bounds(ring).1
+    }
+
+    /// `ring` 的全部数据。
+    pub(crate) fn slice(&self, ring: Ring) -> &[u8] {
+        let (start, len) = self.bounds(ring);
+        &self.region()[start..start + len]
+    }
+
+    /// 第二个环的长度，没有分环的话是 0。
+    pub(crate) fn reserved(&self) -> usize {
+        match self.regions.get(1) {
+            Some(region) if region.ring == Ring::Severe => region.len,
+            _ => 0,
+        }
+    }
+
+    // 环的 offset。两种实现的 base() 都至少按 8 字节对齐，OFFSET_POS、RESERVED_OFFSET_POS
+    // 和通道表里的 offset 也是
+    fn offset_word(&self, ring: Ring) -> &AtomicUsize {
+        let pos = self.lookup(ring).offset_pos;
+        unsafe { &*(self.base().add(pos) as *const AtomicUsize) }
+    }
+
+    fn wraps_pos(&self, ring: Ring) -> usize {
+        self.lookup(ring).wraps_pos
+    }
+
+    /// 当前的环里下一条记录的写入位置。
+    ///
+    /// 和 [`Mapping::set_offset`] 配对：看到新的 offset 就一定能看到之前写入的记录，
+    /// 别的进程映射的同一个文件也一样。
+    pub(crate) fn offset(&self) -> usize {
+        self.ring_offset(self.ring.get())
+    }
+
+    /// `ring` 里下一条记录的写入位置。
+    pub(crate) fn ring_offset(&self, ring: Ring) -> usize {
+        self.offset_word(ring).load(Ordering::Acquire)
+    }
+
+    /// 记录写完、[`Mapping::count`] 之后调用，调用者必须持有写锁。
+    pub(crate) fn set_offset(&self, new: usize) {
+        assert!(new <= self.size());
+        self.offset_word(self.ring.get())
+            .store(new, Ordering::Release);
+    }
+
+    /// `ring` 的 offset 和它在这个环写入过的所有数据里的位置（翻转次数 × 环的长度 + offset）。
+    ///
+    /// 写入方先更新计数再更新 offset，所以读到的翻转次数至少和 offset 一样新；
+    /// 前后两次读到的 offset 不一样的话说明中间有写入，重来。
+    pub(crate) fn position(&self, ring: Ring) -> (usize, u64) {
+        loop {
+            let offset = self.ring_offset(ring);
+            let wraps = self.counter(self.wraps_pos(ring));
+            if self.ring_offset(ring) == offset {
+                // 打开时 check_header 检查过不会溢出，之后被别的进程写坏了也不 panic
+                let pos = wraps
+                    .saturating_mul(self.ring_size(ring) as u64)
+                    .saturating_add(offset as u64);
+                return (offset, pos);
+            }
+        }
+    }
+
+    // header 里的计数，和 offset 一样是原子的，别的线程（进程）可以同时读
+    fn counter_word(&self, pos: usize) -> &AtomicU64 {
+        unsafe { &*(self.base().add(pos) as *const AtomicU64) }
+    }
+
+    fn counter(&self, pos: usize) -> u64 {
+        self.counter_word(pos).load(Ordering::Relaxed)
+    }
+
+    fn set_counter(&self, pos: usize, value: u64) {
+        self.counter_word(pos).store(value, Ordering::Relaxed)
+    }
+
+    // 当前的环的开头，见 Mapping::write_at()
+    fn data(&self) -> *mut u8 {
+        unsafe {
+            self.base()
+                .add(self.start() + self.bounds(self.ring.get()).0)
+        }
+    }
+
+    /// 缓冲区是否从单独的一页开始，见 [`Builder::page_aligned`](crate::Builder::page_aligned)。
+    pub(crate) fn page_aligned(&self) -> bool {
+        self.start() > HEADER_SIZE
+    }
+
+    /// 按时间顺序返回 `ring` 里（较旧的，较新的）两段数据，都从一条完整的记录开始。
+    ///
+    /// 翻转处被覆盖了一半的那条记录会被丢掉。没翻转过的话 offset 之后没有记录：
+    /// 全是 0，或者是 `Logger::clear()` 留下的旧数据。
+    pub(crate) fn halves(&self, ring: Ring) -> (&[u8], &[u8]) {
+        let data = self.slice(ring);
+        let (new, old) = data.split_at(self.ring_offset(ring).min(data.len()));
+        if !self.wrapped(ring) {
+            return (&old[..0], new);
+        }
+        (skip_partial(old, self.framing(), self.checksum()), new)
+    }
+
+    /// 长度前缀格式的 `ring` 里 offset 之前的最后一条记录没有写完的话（见 `format::committed()`），
+    /// 把它清零，offset 退回到上一条记录的结尾，返回（原来的 offset，新的 offset）。
+    /// 打开文件时调用，调用者必须持有写锁。
+    pub(crate) fn roll_back_torn(&self, ring: Ring) -> Option<(usize, usize)> {
+        if self.framing() != Framing::LengthPrefixed {
+            return None;
+        }
+        let offset = self.ring_offset(ring).min(self.ring_size(ring));
+        let end = format::committed(&self.slice(ring)[..offset], self.checksum());
+        if end == offset {
+            return None;
+        }
+        self.in_ring(ring, |mapping| {
+            unsafe { mapping.zero_at(end, offset - end) };
+            mapping.touch(end, offset);
+            mapping.set_offset(end);
+        });
+        Some((offset, end))
+    }
+
+    /// `ring` 有没有翻转过。
+    pub(crate) fn wrapped(&self, ring: Ring) -> bool {
+        self.counter(self.wraps_pos(ring)) > 0
+    }
+
+    /// header 里的计数：（记录数，字节数，翻转次数），几个环的话是加起来的。
+    pub(crate) fn counters(&self) -> (u64, u64, u64) {
+        let wraps = self
+            .regions
+            .iter()
+            .map(|region| self.counter(region.wraps_pos))
+            .sum();
+        (self.counter(RECORDS_POS), self.counter(BYTES_POS), wraps)
+    }
+
+    /// 接着别的文件的计数往下数，用于轮转出来的新文件，调用者必须持有写锁。
+    /// 翻转次数都算在第一个环上。
+    pub(crate) fn set_counters(&self, (records, bytes, wraps): (u64, u64, u64)) {
+        self.set_counter(RECORDS_POS, records);
+        self.set_counter(BYTES_POS, bytes);
+        for (i, region) in self.regions.iter().enumerate() {
+            self.set_counter(region.wraps_pos, if i == 0 { wraps } else { 0 });
+        }
+    }
+
+    /// 写入一条记录之后、[`Mapping::set_offset`] 之前更新 header 里的计数，调用者必须持有写锁。
+    /// 翻转次数算在当前的环上。
+    pub(crate) fn count(&self, bytes: usize, wraps: usize) {
+        self.set_counter(RECORDS_POS, self.counter(RECORDS_POS) + 1);
+        self.set_counter(BYTES_POS, self.counter(BYTES_POS) + bytes as u64);
+        if wraps > 0 {
+            let pos = self.wraps_pos(self.ring.get());
+            self.set_counter(pos, self.counter(pos) + wraps as u64);
+        }
+    }
+
+    /// 把 `source` 复制到缓冲区里的 `pos` 处，不能跨过末尾。
+    ///
+    /// 缓冲区只通过裸指针写入，不会从 `&self` 得到 `&mut [u8]`。
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须持有写锁，而且这段时间里没有别的线程在读缓冲区。
+    pub(crate) unsafe fn write_at(&self, pos: usize, source: &[u8]) {
+        assert!(pos + source.len() <= self.size());
+        ptr::copy_nonoverlapping(source.as_ptr(), self.data().add(pos), source.len());
+    }
+
+    /// 把缓冲区里从 `pos` 开始的 `len` 个字节清零，要求和 [`Mapping::write_at`] 一样。
+    pub(crate) unsafe fn zero_at(&self, pos: usize, len: usize) {
+        assert!(pos + len <= self.size());
+        ptr::write_bytes(self.data().add(pos), 0, len);
+    }
+
+    /// 标记当前的环里写过的 `[start, end)`，下次 `sync_dirty()` 时同步。
+    /// 和之前标记的合并成一段，调用者必须持有写锁。
+    pub(crate) fn touch(&self, start: usize, end: usize) {
+        let at = self.bounds(self.ring.get()).0;
+        let (start, end) = (at + start, at + end);
+        let (lo, hi) = self.dirty.get();
+        if lo >= hi {
+            self.dirty.set((start, end));
+        } else {
+            self.dirty.set((lo.min(start), hi.max(end)));
+        }
+    }
+
+    /// 文件里记录的分隔方式。
+    pub(crate) fn framing(&self) -> Framing {
+        Framing::from_flags(header_flags(self.header()))
+    }
+
+    /// header 里有没有进程间锁。
+    pub(crate) fn shared(&self) -> bool {
+        header_flags(self.header()) & FLAG_SHARED != 0
+    }
+
+    /// 记录里的换行有没有被转义，见 [`Builder::escape_newlines`](crate::Builder::escape_newlines)。
+    pub(crate) fn escaped(&self) -> bool {
+        header_flags(self.header()) & FLAG_ESCAPED_NEWLINES != 0
+    }
+
+    /// 创建文件时写入的元数据，版本 6 之前的文件没有。
+    pub(crate) fn metadata(&self) -> Option<FileMetadata> {
+        let mut version = [0; 4];
+        version.copy_from_slice(&self.header()[VERSION_POS..VERSION_POS + 4]);
+        if u32::from_le_bytes(version) < 6 {
+            return None;
+        }
+        Some(meta::read(self.prefix()))
+    }
+
+    /// 记录的时间戳用的哪个时钟，见 [`Builder::clock`](crate::Builder::clock)。
+    pub(crate) fn clock(&self) -> Clock {
+        if header_flags(self.header()) & FLAG_MONOTONIC != 0 {
+            Clock::Monotonic
+        } else {
+            Clock::Realtime
+        }
+    }
+
+    /// [`Clock::Monotonic`] 的文件里换算时间戳用的锚点，别的文件没有。
+    pub(crate) fn anchor(&self) -> Option<Anchor> {
+        if self.clock() != Clock::Monotonic {
+            return None;
+        }
+        let prefix = self.prefix();
+        Some(Anchor {
+            realtime: std::time::Duration::from_nanos(read_u64(prefix, ANCHOR_POS)),
+            monotonic: std::time::Duration::from_nanos(read_u64(prefix, ANCHOR_POS + 8)),
+            boot_start: self.boot_offset(),
+        })
+    }
+
+    /// 时间戳的偏移，版本 13 之前的文件和不是单调时钟的文件是 0，见 [`format::BOOT_POS`]。
+    pub(crate) fn boot_offset(&self) -> std::time::Duration {
+        if self.boot_id().is_none() {
+            return std::time::Duration::ZERO;
+        }
+        std::time::Duration::from_nanos(read_u64(self.prefix(), BOOT_POS))
+    }
+
+    /// 写锚点时的开机 id，版本 13 之前的文件和不是单调时钟的文件没有，见 [`format::BOOT_ID_POS`]。
+    pub(crate) fn boot_id(&self) -> Option<[u8; BOOT_ID_LEN]> {
+        if self.clock() != Clock::Monotonic || read_u32(self.header(), VERSION_POS) < 13 {
+            return None;
+        }
+        let mut id = [0; BOOT_ID_LEN];
+        id.copy_from_slice(&self.prefix()[BOOT_ID_POS..BOOT_ID_POS + BOOT_ID_LEN]);
+        Some(id)
+    }
+
+    /// 换上现在的锚点，[`Clock::Monotonic`] 的文件每次打开时调用。调用者必须持有写锁，
+    /// 只读打开的文件不能调用。
+    ///
+    /// 文件里的开机 id 和现在的不一样的话是重启过，单调时钟从头开始了：偏移挪到 `latest()`
+    /// （文件里最晚的时间戳）和原来的锚点之后，之前写的记录都早于这次开机。
+    pub(crate) fn refresh_anchor(&self, latest: impl FnOnce() -> Option<std::time::Duration>) {
+        let id = format::boot_id();
+        let offset = match (self.anchor(), self.boot_id()) {
+            (Some(anchor), Some(old)) if old != [0; BOOT_ID_LEN] && old != id => {
+                let latest = latest().map_or(anchor.monotonic, |ts| ts.max(anchor.monotonic));
+                latest + std::time::Duration::from_nanos(1)
+            }
+            _ => self.boot_offset(),
+        };
+        self.set_anchor(Anchor::now(offset), id);
+    }
+
+    /// 直接写入锚点和开机 id，版本 13 之前的文件只写锚点。要求和 [`Mapping::refresh_anchor`] 一样。
+    pub(crate) fn set_anchor(&self, anchor: Anchor, id: [u8; BOOT_ID_LEN]) {
+        let bytes = anchor_bytes(anchor);
+        let boot = self.boot_id().is_some();
+        // 和 write_at() 一样通过裸指针写
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), self.base().add(ANCHOR_POS), 16);
+            if boot {
+                let offset = (anchor.boot_start.as_nanos() as u64).to_le_bytes();
+                ptr::copy_nonoverlapping(id.as_ptr(), self.base().add(BOOT_ID_POS), BOOT_ID_LEN);
+                ptr::copy_nonoverlapping(offset.as_ptr(), self.base().add(BOOT_POS), 8);
+            }
+        }
+    }
+
+    /// 加密了的文件里检查密钥用的 tag，没加密的话是 `None`，见 [`format::KEY_CHECK_POS`]。
+    pub(crate) fn key_check(&self) -> Option<[u8; KEY_CHECK_LEN]> {
+        if header_flags(self.header()) & FLAG_ENCRYPTED == 0 {
+            return None;
+        }
+        let mut check = [0; KEY_CHECK_LEN];
+        check.copy_from_slice(&self.prefix()[KEY_CHECK_POS..KEY_CHECK_POS + KEY_CHECK_LEN]);
+        Some(check)
+    }
+
+    /// 创建文件时设置的保留期限，见 [`format::RETENTION_POS`]。
+    pub(crate) fn retention(&self) -> Option<std::time::Duration> {
+        if header_flags(self.header()) & FLAG_RETENTION == 0 {
+            return None;
+        }
+        Some(std::time::Duration::from_nanos(read_u64(
+            self.prefix(),
+            RETENTION_POS,
+        )))
+    }
+
+    /// 加密记录用的 nonce 开头的 salt，见 [`format::SALT_POS`]。
+    pub(crate) fn salt(&self) -> [u8; SALT_LEN] {
+        let mut salt = [0; SALT_LEN];
+        salt.copy_from_slice(&self.prefix()[SALT_POS..SALT_POS + SALT_LEN]);
+        salt
+    }
+
+    /// 换一个 salt，打开文件和计数清零之后调用，要求和 [`Mapping::refresh_anchor`] 一样。
+    pub(crate) fn refresh_salt(&self) {
+        let salt = crypt::salt();
+        unsafe { ptr::copy_nonoverlapping(salt.as_ptr(), self.base().add(SALT_POS), SALT_LEN) };
+    }
+
+    /// 名字的哈希是 `hash` 的书签的位置，没有设置过的话是 `None`，见 [`format::BOOKMARK_SLOTS`]。
+    /// 版本 12 之前的文件没有书签表，返回错误。
+    pub(crate) fn bookmark(&self, hash: u64) -> Result<Option<u64>> {
+        let table = self.bookmark_table()?;
+        let prefix = self.prefix();
+        Ok((0..BOOKMARK_SLOTS)
+            .map(|i| table + i * BOOKMARK_SIZE)
+            .find(|&slot| read_u64(prefix, slot) == hash)
+            .map(|slot| read_u64(prefix, slot + 8)))
+    }
+
+    /// 设置书签，还没有的话占一个空的槽，槽都用完了的话返回错误。
+    /// 要求和 [`Mapping::refresh_anchor`] 一样。
+    pub(crate) fn set_bookmark(&self, hash: u64, pos: u64) -> Result<()> {
+        let table = self.bookmark_table()?;
+        let prefix = self.prefix();
+        let slots = (0..BOOKMARK_SLOTS).map(|i| table + i * BOOKMARK_SIZE);
+        let slot = match slots.clone().find(|&slot| read_u64(prefix, slot) == hash) {
+            Some(slot) => slot,
+            None => slots
+                .into_iter()
+                .find(|&slot| read_u64(prefix, slot) == 0)
+                .ok_or(Error::InvalidConfig("all bookmark slots are in use"))?,
+        };
+        let mut bytes = [0; BOOKMARK_SIZE];
+        bytes[..8].copy_from_slice(&hash.to_le_bytes());
+        bytes[8..].copy_from_slice(&pos.to_le_bytes());
+        // 和 write_at() 一样通过裸指针写
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), self.base().add(slot), BOOKMARK_SIZE) };
+        Ok(())
+    }
+
+    // 书签表在文件中的位置
+    fn bookmark_table(&self) -> Result<usize> {
+        let version = read_u32(self.header(), VERSION_POS);
+        if version < 12 {
+            return Err(Error::Config(format!(
+                "the file has format version {}, bookmarks need version 12",
+                version
+            )));
+        }
+        Ok(bookmarks_pos(self.channels().count()))
+    }
+
+    /// 长度前缀格式的记录后面有没有校验和，见 [`format::FLAG_CHECKSUM`]。
+    pub(crate) fn checksum(&self) -> bool {
+        header_flags(self.header()) & FLAG_CHECKSUM != 0
+    }
+}
+
+/// 把已有的文件改成 `capacity` 大小，见 [`Builder::size`](crate::Builder::size)。
+///
+/// 按时间顺序把记录从头排好写进 `<path>.resize`，同步之后改名替换原来的文件，
+/// 中途出错的话原来的文件不变。放不下的话只保留最新的记录。计数照旧，
+/// 元数据里的进程号、启动时间和程序名换成这次打开的；布局、时钟、密钥、标签、保留期限和权限
+/// 都沿用原来的文件，`options` 里的这些设置只对新建的文件有效，这里不用。
+/// 长度前缀格式的记录去掉填充，原来没有校验和的补上。
+pub(crate) fn resize(
+    old: Mapping,
+    capacity: usize,
+    exclusive: bool,
+    options: &MapOptions,
+) -> Result<Mapping> {
+    let framing = old.framing();
+    if old.rings().count() > 1 || old.channels().next().is_some() {
+        return Err(Error::InvalidConfig(
+            "can't resize a file with more than one ring",
+        ));
+    }
+    let (first, second) = old.halves(Ring::Main);
+    let mut records: Vec<Vec<u8>> = Vec::new();
+    for half in [first, second] {
+        match framing {
+            // 翻转处拆开的那条记录在两段里各有一半，接上就行
+            Framing::Text => match records.last_mut() {
+                Some(last) if !last.ends_with(b"\n") => {
+                    let end = half
+                        .iter()
+                        .position(|&b| b == b'\n')
+                        .map_or(half.len(), |i| i + 1);
+                    last.extend_from_slice(&half[..end]);
+                    records.extend(
+                        half[end..]
+                            .split_inclusive(|&b| b == b'\n')
+                            .map(<[u8]>::to_vec),
+                    );
+                }
+                _ => records.extend(half.split_inclusive(|&b| b == b'\n').map(<[u8]>::to_vec)),
+            },
+            Framing::LengthPrefixed => {
+                for (pos, record, ok) in format::frames(half, old.checksum()) {
+                    let mut frame = (record.len() as u32).to_le_bytes().to_vec();
+                    frame.extend_from_slice(record);
+                    // 校验和不对的记录原样保留，不能替它重新算
+                    let crc = if old.checksum() && !ok {
+                        let at = pos + format::FRAME_PREFIX + record.len();
+                        half[at..at + format::FRAME_CHECKSUM].to_vec()
+                    } else {
+                        format::checksum(record).to_le_bytes().to_vec()
+                    };
+                    frame.extend_from_slice(&crc);
+                    records.push(frame);
+                }
+            }
+        }
+    }
+
+    // 留一个字节，offset 总是小于缓冲区大小
+    let mut len: usize = records.iter().map(Vec::len).sum();
+    let mut skip = 0;
+    while len >= capacity {
+        len -= records[skip].len();
+        skip += 1;
+    }
+    let (records_written, bytes, mut wraps) = old.counters();
+    // 丢了记录的话让读取方知道前面有被覆盖的记录，见 LogReader::overwritten
+    if skip > 0 {
+        wraps = wraps.max(1);
+    }
+
+    let path = old.path().to_path_buf();
+    let permissions = fs::metadata(&path)?.permissions();
+    let options = MapOptions {
+        page_aligned: old.page_aligned(),
+        escape_newlines: old.escaped(),
+        // 版本 6 之前的文件没有元数据，用这次的
+        tag: old.metadata().map_or(options.tag, |metadata| {
+            meta::padded(&metadata.tag.unwrap_or_default())
+        }),
+        reserve: 0.0,
+        channels: [None; MAX_CHANNELS],
+        clock: old.clock(),
+        key_check: old.key_check(),
+        retention: old.retention(),
+        boot_offset: old.boot_offset(),
+        ..*options
+    };
+    // 打开文件时看原来的锚点和开机 id 认出重启，不能换成现在的。
+    // 版本 13 之前的文件不知道是哪次开机写的，开机 id 填 0
+    let anchor = old
+        .anchor()
+        .map(|anchor| (anchor, old.boot_id().unwrap_or_default()));
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".resize");
+    drop(old);
+    let fresh = Mapping::open(
+        Path::new(&tmp),
+        capacity,
+        framing,
+        false,
+        exclusive,
+        OpenMode::Create,
+        &options,
+    )?;
+    let mut offset = 0;
+    for record in &records[skip..] {
+        // 还没有别人能看到这个文件
+        unsafe { fresh.write_at(offset, record) };
+        offset += record.len();
+    }
+    fresh.set_offset(offset);
+    fresh.set_counters((records_written, bytes, wraps));
+    if let Some((anchor, id)) = anchor {
+        fresh.set_anchor(anchor, id);
+    }
+    fresh.sync(true)?;
+    drop(fresh);
+    // 新建时的权限受 umask 影响，直接照抄
+    fs::set_permissions(&tmp, permissions)?;
+    fs::rename(&tmp, &path)?;
+    Mapping::open(
+        &path,
+        capacity,
+        framing,
+        false,
+        exclusive,
+        OpenMode::Open,
+        &options,
+    )
+}
+
+/// 文件存在时和 `OpenMode::Open` 一样打开，不存在时新建，见
+/// [`Builder::open_or_create`](crate::Builder::open_or_create)。
+///
+/// 新建的文件先在 `<path>.create<pid>-<n>` 里写好 header，再用硬链接放到 `path` 上，
+/// 同时打开的别的线程和进程看到的总是完整的文件；链接的时候别人抢先建好了的话就打开别人的。
+/// 长度为 0 的文件（logrotate 的 `create`）就地初始化。
+pub(crate) fn open_or_create(
+    path: &Path,
+    capacity: usize,
+    framing: Framing,
+    shared: bool,
+    exclusive: bool,
+    options: &MapOptions,
+) -> Result<Mapping> {
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+    let open = |path: &Path, mode| {
+        Mapping::open(path, capacity, framing, shared, exclusive, mode, options)
+    };
+    loop {
+        match fs::metadata(path) {
+            Ok(meta) if meta.len() == 0 => return open(path, OpenMode::OpenOrCreate),
+            Ok(_) => return open(path, OpenMode::Open),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        create_dirs(path, options)?;
+        let mut tmp = path.to_path_buf().into_os_string();
+        tmp.push(format!(
+            ".create{}-{}",
+            std::process::id(),
+            CREATED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let tmp = Path::new(&tmp);
+        let linked = Mapping::open(
+            tmp,
+            capacity,
+            framing,
+            shared,
+            false,
+            OpenMode::Create,
+            options,
+        )
+        .map(|fresh| {
+            // std_file 实现在 drop 时写回
+            drop(fresh);
+            fs::hard_link(tmp, path)
+        });
+        let _ = fs::remove_file(tmp);
+        match linked? {
+            Ok(()) => return open(path, OpenMode::Open),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// header 里说的文件长度（数据开始的位置加上容量），不是 mmlog 文件的话交给 check_header 报错，
+// 返回 None。连数据开始之前的部分都不全的话没法读
+fn declared_len(file: &[u8]) -> Result<Option<usize>> {
+    if file.len() < HEADER_SIZE || file[MAGIC_POS..MAGIC_POS + 4] != MAGIC {
+        return Ok(None);
+    }
+    let version = read_u32(file, VERSION_POS);
+    let start = if version < 5 {
+        HEADER_SIZE
+    } else {
+        read_u32(file, DATA_POS) as usize
+    };
+    if start > file.len() {
+        return Err(Error::CorruptHeader(format!(
+            "the file is cut off at {} bytes, before the data starts at {}",
+            file.len(),
+            start
+        )));
+    }
+    Ok((read_u64(file, CAPACITY_POS) as usize).checked_add(start))
+}
+
+// 跳过开头被覆盖了一半的记录，全是 0（还没写到过）的话返回空
+pub(crate) fn skip_partial(data: &[u8], framing: Framing, checksum: bool) -> &[u8] {
+    match framing {
+        Framing::Text => match data.first() {
+            Some(0) | None => &data[..0],
+            Some(_) => match data.iter().position(|&b| b == b'\n') {
+                Some(i) => &data[i + 1..],
+                None => &data[..0],
+            },
+        },
+        Framing::LengthPrefixed => &data[format::frame_start(data, checksum)..],
+    }
+}
@@ -0,0 +1,279 @@
+use super::{MapOptions, OpenMode, Region, Ring, HEADER_SIZE};
+use crate::format::Framing;
+use crate::{Error, Result};
+use std::cell::Cell;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+// 不用 mmap 的实现：缓冲区放在进程内存里，sync() 时写回文件。
+// 崩溃时没来得及 sync 的记录会丢失，文件格式和 mmap 实现完全一样。
+#[derive(Debug)]
+pub(crate) struct Mapping {
+    // 整个文件，实际分配的是 Box<[u64]>，drop 时释放。和 mmap 实现一样只通过裸指针写入
+    buf: NonNull<[u8]>,
+    // 只读或者匿名的话为 None，不写回
+    file: Option<File>,
+    path: PathBuf,
+    // 缓冲区在文件里的起始位置，见 format::DATA_POS
+    start: usize,
+    // 上次 sync_dirty() 之后写过的范围，见 Mapping::touch()
+    pub(super) dirty: Cell<(usize, usize)>,
+    // 文件里的环和当前选中的环，见 Mapping::select()
+    pub(super) regions: Vec<Region>,
+    pub(super) ring: Cell<Ring>,
+}
+
+impl Mapping {
+    /// 没有 mmap 的话别的进程看不到这里的写入，`shared` 只是记在 header 里，
+    /// `options` 里的 `populate` 和 `advice` 没有用，权限只在 Unix 上有用。
+    /// `exclusive` 时用 [`File::try_lock`] 加锁，锁跟着 `file` 一起释放。
+    pub(crate) fn open(
+        path: &Path,
+        capacity: usize,
+        framing: Framing,
+        shared: bool,
+        exclusive: bool,
+        mode: OpenMode,
+        options: &MapOptions,
+    ) -> Result<Mapping> {
+        if mode != OpenMode::ReadOnly {
+            super::create_dirs(path, options)?;
+        }
+        // 拿到锁之后才截断
+        let mut open = OpenOptions::new();
+        open.read(true)
+            .write(mode != OpenMode::ReadOnly)
+            .create(mode == OpenMode::Create || mode == OpenMode::OpenOrCreate);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut open, options.mode);
+        let file = open.open(path)?;
+        if exclusive {
+            file.try_lock().map_err(|e| match e {
+                TryLockError::WouldBlock => Error::AlreadyLocked,
+                TryLockError::Error(e) => e.into(),
+            })?;
+        }
+        if mode == OpenMode::Create {
+            file.set_len(0)?;
+        }
+
+        let len = file.metadata()?.len() as usize;
+        let (mut words, len, start) =
+            if mode == OpenMode::Create || (mode == OpenMode::OpenOrCreate && len == 0) {
+                let start = super::data_start(options);
+                let size = capacity + start;
+                file.set_len(size as u64)?;
+                let mut words = zeroed(size);
+                let bytes = as_bytes_mut(&mut words, size);
+                super::init_header(bytes, capacity, start, framing, shared, options);
+                // 整个写一遍，磁盘不够的话现在就报错，而不是等到 sync 的时候
+                write_all_at(&file, bytes, 0)?;
+                (words, size, start)
+            } else {
+                let mut words = zeroed(len);
+                read_exact_at(&file, as_bytes_mut(&mut words, len))?;
+                let start = super::check_header(as_bytes_mut(&mut words, len))?;
+                (words, len, start)
+            };
+        let regions = super::regions(as_bytes_mut(&mut words, len), len - start);
+        Ok(Mapping {
+            buf: NonNull::slice_from_raw_parts(NonNull::from(Box::leak(words)).cast(), len),
+            file: (mode != OpenMode::ReadOnly).then_some(file),
+            path: path.to_path_buf(),
+            start,
+            dirty: Cell::new((0, 0)),
+            regions,
+            ring: Cell::new(Ring::Main),
+        })
+    }
+
+    /// 不对应任何文件，见 [`Builder::build_anonymous`](crate::Builder::build_anonymous)。
+    pub(crate) fn anonymous(
+        capacity: usize,
+        framing: Framing,
+        shared: bool,
+        options: &MapOptions,
+    ) -> Result<Mapping> {
+        let start = super::data_start(options);
+        let size = capacity + start;
+        let mut words = zeroed(size);
+        super::init_header(
+            as_bytes_mut(&mut words, size),
+            capacity,
+            start,
+            framing,
+            shared,
+            options,
+        );
+        let regions = super::regions(as_bytes_mut(&mut words, size), capacity);
+        Ok(Mapping {
+            buf: NonNull::slice_from_raw_parts(NonNull::from(Box::leak(words)).cast(), size),
+            file: None,
+            path: PathBuf::new(),
+            start,
+            dirty: Cell::new((0, 0)),
+            regions,
+            ring: Cell::new(Ring::Main),
+        })
+    }
+
+    /// 文件内容的一份拷贝，`data` 不到 `size` 的部分是 0，
+    /// 见 [`Mapping::read_lenient`](super::Mapping::read_lenient)。
+    pub(crate) fn copied(path: &Path, data: &[u8], size: usize) -> Result<Mapping> {
+        let mut words = zeroed(size);
+        let bytes = as_bytes_mut(&mut words, size);
+        bytes[..data.len()].copy_from_slice(data);
+        let start = super::check_header(bytes)?;
+        let regions = super::regions(bytes, size - start);
+        Ok(Mapping {
+            buf: NonNull::slice_from_raw_parts(NonNull::from(Box::leak(words)).cast(), size),
+            file: None,
+            path: path.to_path_buf(),
+            start,
+            dirty: Cell::new((0, 0)),
+            regions,
+            ring: Cell::new(Ring::Main),
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { self.buf.as_ref() }
+    }
+
+    // 整个文件的开头，按 u64 对齐
+    pub(super) fn base(&self) -> *mut u8 {
+        self.buf.as_ptr() as *mut u8
+    }
+
+    pub(crate) fn header(&self) -> &[u8] {
+        &self.bytes()[..HEADER_SIZE]
+    }
+
+    // 整个缓冲区，两个环的话包括两个
+    pub(super) fn region(&self) -> &[u8] {
+        &self.bytes()[self.start..]
+    }
+
+    // 缓冲区前面的部分：header、元数据和填充
+    pub(super) fn prefix(&self) -> &[u8] {
+        &self.bytes()[..self.start]
+    }
+
+    pub(super) fn start(&self) -> usize {
+        self.start
+    }
+
+    /// 整个缓冲区的长度，两个环的话是加起来的。
+    pub(crate) fn capacity(&self) -> usize {
+        self.bytes().len() - self.start
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn sync(&self, sync: bool) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        write_all_at(file, self.bytes(), 0)?;
+        if sync {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// 缓冲区是普通的堆内存，不锁。
+    pub(crate) fn lock_memory(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// 不支持进程间锁，总是返回 `None`。
+    pub(crate) fn lock_process(&self) -> Option<ProcessLock> {
+        None
+    }
+
+    pub(crate) fn try_lock_process(&self) -> std::result::Result<Option<ProcessLock>, ()> {
+        Ok(None)
+    }
+
+    pub(crate) fn revalidate_process_lock(&self) {}
+
+    /// 只写回缓冲区前面的部分和 [`Mapping::touch`] 标记过的部分，调用者必须持有写锁。
+    /// 第二个环和通道的 offset 不在 header 里，所以 header 后面的元数据和通道表也要写。
+    pub(crate) fn sync_dirty(&self, sync: bool) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        #[cfg(feature = "test-util")]
+        if let Some(e) = crate::test::failed_sync() {
+            return Err(e.into());
+        }
+        write_all_at(file, self.prefix(), 0)?;
+        let (start, end) = self.dirty.get();
+        if start < end {
+            let offset = self.start + start;
+            write_all_at(file, &self.bytes()[offset..self.start + end], offset)?;
+        }
+        if sync {
+            file.sync_data()?;
+        }
+        self.dirty.set((0, 0));
+        Ok(())
+    }
+}
+
+// 没有 mmap 时不会有进程间锁
+pub(crate) enum ProcessLock {}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // mmap 实现在 munmap 时由内核写回，这里只能自己写
+        if let Some(file) = &self.file {
+            let _ = write_all_at(file, self.bytes(), 0);
+        }
+        let words =
+            ptr::slice_from_raw_parts_mut(self.base() as *mut u64, self.buf.len().div_ceil(8));
+        unsafe { drop(Box::from_raw(words)) };
+    }
+}
+
+// 按 u64 分配，header 里的 offset 和计数都是对齐的
+fn zeroed(len: usize) -> Box<[u64]> {
+    vec![0; len.div_ceil(8)].into_boxed_slice()
+}
+
+fn as_bytes_mut(words: &mut [u64], len: usize) -> &mut [u8] {
+    assert!(len <= words.len() * 8);
+    unsafe { slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, len) }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8]) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, 0)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: usize) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset as u64)
+}
+
+#[cfg(not(unix))]
+fn read_exact_at(file: &File, buf: &mut [u8]) -> std::io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut file = file;
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(buf)
+}
+
+#[cfg(not(unix))]
+fn write_all_at(file: &File, buf: &[u8], offset: usize) -> std::io::Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.write_all(buf)
+}
@@ -0,0 +1,239 @@
+use crate::crypt::Cipher;
+use crate::format::{self, Framing};
+use crate::parse::Entry;
+use crate::reader::LogReader;
+use crate::Result;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// 把几个日志文件的记录按时间戳合并成一个流，每一行前面加上来源文件的文件名：
+///
+/// ```text
+/// worker-1.log: [1700000000.5s 42 I  app] hello
+/// ```
+///
+/// 每个文件先按 [`LogReader`] 的顺序读出来，时间戳用 [`LogReader::normalize`] 换算成
+/// UNIX 纪元以来的纳秒数再比较，所以几个文件的时间戳写法（[`TimestampFormat`](crate::format::TimestampFormat)）、
+/// 记录格式和时钟（[`Clock::Monotonic`](crate::Clock::Monotonic) 的文件按 [`LogReader::anchor`] 换算）
+/// 都可以不一样，输出的记录还是原样的。解析不出时间戳的记录（自定义格式、被拆成几行的消息）
+/// 紧跟在同一个文件里的上一条记录后面输出，文件开头的这种记录排在最前面。
+/// 时间戳相同的记录按参数里文件的顺序输出。
+///
+/// 换算不了时间戳的文件（见 [`LogReader::normalizable`]）不参与按时间合并：
+/// 按参数里的顺序跟在最后，各自按文件里的顺序输出，[`MergeStats::warnings`] 里有一条说明。
+///
+/// ```
+/// use log::Log;
+///
+/// let dir = std::env::temp_dir();
+/// let (a, b) = (dir.join("mmlog-merge-a.log"), dir.join("mmlog-merge-b.log"));
+/// let la = mmlog::Builder::new().build(&a).unwrap();
+/// let lb = mmlog::Builder::new().build(&b).unwrap();
+/// for i in 0..10 {
+///     let logger = if i % 3 == 0 { &la } else { &lb };
+///     logger.log(
+///         &log::Record::builder()
+///             .level(log::Level::Info)
+///             .args(format_args!("record {}", i))
+///             .build(),
+///     );
+/// }
+/// drop((la, lb));
+///
+/// let mut out = Vec::new();
+/// let stats = mmlog::merge(&[a.as_path(), b.as_path()], &mut out).unwrap();
+/// assert_eq!(stats.records, 10);
+/// assert!(stats.warnings.is_empty());
+/// let out = String::from_utf8(out).unwrap();
+/// let lines: Vec<_> = out.lines().collect();
+/// assert_eq!(lines.len(), 10);
+/// for (i, line) in lines.iter().enumerate() {
+///     let source = if i % 3 == 0 { "mmlog-merge-a.log: [" } else { "mmlog-merge-b.log: [" };
+///     assert!(line.starts_with(source));
+///     assert!(line.ends_with(&format!("] record {}", i)));
+/// }
+/// ```
+pub fn merge<W: io::Write>(paths: &[&Path], mut out: W) -> Result<MergeStats> {
+    let names: Vec<_> = paths.iter().map(|path| file_name(path)).collect();
+    let stats = merge_each(paths, |i, _, record| {
+        for line in record.split('\n') {
+            writeln!(out, "{}: {}", names[i], line)?;
+        }
+        Ok(())
+    })?;
+    out.flush()?;
+    Ok(stats)
+}
+
+/// 和 [`merge`] 一样的顺序，不输出文本，而是把每条记录交给 `each`：来源文件在 `paths` 里的下标、
+/// 读这个文件的 [`LogReader`]（用来 [`normalize`](LogReader::normalize) 时间戳）和记录本身，
+/// [`merge`] 拆成几行输出的记录在这里是一整条。`each` 返回错误的话停下，返回这个错误。
+///
+/// ```
+/// use log::Log;
+///
+/// let dir = std::env::temp_dir();
+/// let (a, b) = (dir.join("mmlog-merge-each-a.log"), dir.join("mmlog-merge-each-b.log"));
+/// let la = mmlog::Builder::new().build(&a).unwrap();
+/// let lb = mmlog::Builder::new().build(&b).unwrap();
+/// for (i, logger) in [&la, &lb, &la].into_iter().enumerate() {
+///     logger.log(&log::Record::builder().args(format_args!("line {}", i)).build());
+/// }
+/// drop((la, lb));
+///
+/// let mut seen = Vec::new();
+/// let stats = mmlog::merge_each(&[a.as_path(), b.as_path()], |i, reader, record| {
+///     assert!(reader.normalize(record).is_some());
+///     seen.push((i, record.rsplit("] ").next().unwrap().to_string()));
+///     Ok(())
+/// })
+/// .unwrap();
+/// assert_eq!(stats.records, 3);
+/// assert_eq!(seen, [(0, "line 0".into()), (1, "line 1".into()), (0, "line 2".into())]);
+/// ```
+pub fn merge_each<F>(paths: &[&Path], mut each: F) -> Result<MergeStats>
+where
+    F: FnMut(usize, &LogReader, &str) -> io::Result<()>,
+{
+    let readers = paths
+        .iter()
+        .map(LogReader::open)
+        .collect::<Result<Vec<_>>>()?;
+    let mut stats = MergeStats::default();
+    let mut sources: Vec<_> = readers.iter().map(|r| groups(r).peekable()).collect();
+
+    // (时间戳，第几个文件)，取出最小的那个输出，再放进去这个文件的下一条
+    let mut heap = BinaryHeap::new();
+    for (i, source) in sources.iter_mut().enumerate() {
+        if !readers[i].normalizable() {
+            stats.warnings.push(format!(
+                "{}: monotonic timestamps without an anchor, appended in file order",
+                file_name(paths[i])
+            ));
+            continue;
+        }
+        if let Some((ts, _)) = source.peek() {
+            heap.push(Reverse((*ts, i)));
+        }
+    }
+    let mut write = |i: usize, records: Vec<&str>| -> io::Result<()> {
+        for record in records {
+            each(i, &readers[i], record)?;
+            stats.records += 1;
+        }
+        Ok(())
+    };
+    while let Some(Reverse((_, i))) = heap.pop() {
+        let (_, records) = sources[i].next().expect("peeked");
+        write(i, records)?;
+        if let Some((ts, _)) = sources[i].peek() {
+            heap.push(Reverse((*ts, i)));
+        }
+    }
+    for (i, reader) in readers.iter().enumerate() {
+        if !reader.normalizable() {
+            write(i, reader.records().collect())?;
+        }
+    }
+    Ok(stats)
+}
+
+// 输出和警告里用的来源文件名
+fn file_name(path: &Path) -> Cow<'_, str> {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => path.as_os_str().to_string_lossy(),
+    }
+}
+
+/// [`merge`] 的结果。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeStats {
+    /// 输出了多少条记录，拆成几行输出的记录算一条。
+    pub records: usize,
+    /// 没能按时间合并的文件，每个一条说明，开头是文件名。
+    pub warnings: Vec<String>,
+}
+
+// 有时间戳的记录带上后面没有时间戳的记录，文件开头没有时间戳的记录排在最前面
+fn groups(reader: &LogReader) -> impl Iterator<Item = (i128, Vec<&str>)> {
+    let mut records = reader.records().peekable();
+    std::iter::from_fn(move || {
+        let first = records.next()?;
+        let ts = reader.normalize(first).unwrap_or(i128::MIN);
+        let mut group = vec![first];
+        while let Some(record) = records.next_if(|r| timestamp(r).is_none()) {
+            group.push(record);
+        }
+        Some((ts, group))
+    })
+}
+
+pub(crate) fn timestamp(record: &str) -> Option<Duration> {
+    match Entry::parse_any(record) {
+        Some(entry) => Some(entry.timestamp),
+        None => format::timestamp(record.as_bytes()),
+    }
+}
+
+/// 按时间戳把各自按时间排好的几列记录合成一列，见
+/// [`Builder::reserve_for_errors`](crate::Builder::reserve_for_errors) 和
+/// [`Builder::channels`](crate::Builder::channels)。
+/// 和 `groups()` 一样没有时间戳的记录跟着同一列里的上一条，时间戳相同的话前面的列在前。
+pub(crate) fn interleave<T>(columns: Vec<Vec<T>>, ts: impl Fn(&T) -> Option<Duration>) -> Vec<T> {
+    if columns.len() == 1 {
+        return columns.into_iter().next().unwrap_or_default();
+    }
+    let mut columns: Vec<_> = columns
+        .into_iter()
+        .map(|column| {
+            let mut groups: Vec<(Duration, Vec<T>)> = Vec::new();
+            for item in column {
+                match (ts(&item), groups.last_mut()) {
+                    (None, Some((_, group))) => group.push(item),
+                    (ts, _) => groups.push((ts.unwrap_or_default(), vec![item])),
+                }
+            }
+            groups.into_iter().peekable()
+        })
+        .collect();
+    let mut out = Vec::new();
+    loop {
+        let next = columns
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, column)| column.peek().map(|(ts, _)| (*ts, i)))
+            .min();
+        match next.and_then(|(_, i)| columns[i].next()) {
+            Some((_, group)) => out.extend(group),
+            None => return out,
+        }
+    }
+}
+
+/// 把每个环的（较旧的，较新的）两段里的记录按时间合在一起，见 `format::joined_records()`。
+/// 加密了的文件先解密，tag 对不上的记录和校验和不对的一样跳过。
+pub(crate) fn rings<'a>(
+    halves: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    framing: Framing,
+    checksum: bool,
+    cipher: Option<&Cipher>,
+) -> Vec<Cow<'a, [u8]>> {
+    let columns = halves
+        .into_iter()
+        .map(|(old, new)| {
+            let records = format::joined_records(old, new, framing, checksum);
+            match cipher {
+                Some(cipher) => records
+                    .filter_map(|record| cipher.open(&record).map(Cow::Owned))
+                    .collect(),
+                None => records.collect(),
+            }
+        })
+        .collect();
+    interleave(columns, |line: &Cow<[u8]>| format::timestamp(line))
+}
@@ -0,0 +1,88 @@
+// 版本 6 开始 header 后面的元数据，见 format 模块和 LogReader::metadata
+
+use crate::format::{EXE_LEN, EXE_POS, PID_POS, STARTED_POS, TAG_LEN, TAG_POS};
+use crate::reader::FileMetadata;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 新建文件时写入元数据，file 至少到 META_POS + META_SIZE，那里已经填好了 0
+pub(crate) fn write(file: &mut [u8], tag: &[u8; TAG_LEN]) {
+    let started = process_started()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let exe = exe_name();
+    file[PID_POS..PID_POS + 4].copy_from_slice(&std::process::id().to_le_bytes());
+    file[STARTED_POS..STARTED_POS + 8].copy_from_slice(&started.to_le_bytes());
+    file[EXE_POS..EXE_POS + EXE_LEN].copy_from_slice(&padded::<EXE_LEN>(&exe));
+    file[TAG_POS..TAG_POS + TAG_LEN].copy_from_slice(tag);
+}
+
+pub(crate) fn read(file: &[u8]) -> FileMetadata {
+    let mut pid = [0; 4];
+    pid.copy_from_slice(&file[PID_POS..PID_POS + 4]);
+    let mut started = [0; 8];
+    started.copy_from_slice(&file[STARTED_POS..STARTED_POS + 8]);
+    let tag = string(&file[TAG_POS..TAG_POS + TAG_LEN]);
+    FileMetadata {
+        pid: u32::from_le_bytes(pid),
+        process_started: UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(started)),
+        exe: string(&file[EXE_POS..EXE_POS + EXE_LEN]),
+        tag: (!tag.is_empty()).then_some(tag),
+    }
+}
+
+// std::env::current_exe() 的文件名，拿不到的话是空的
+pub(crate) fn exe_name() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_default()
+}
+
+// 截断到 N 个字节以内（不拆开字符），后面填 0
+pub(crate) fn padded<const N: usize>(s: &str) -> [u8; N] {
+    let mut end = s.len().min(N);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut buf = [0; N];
+    buf[..end].copy_from_slice(&s.as_bytes()[..end]);
+    buf
+}
+
+fn string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+// Linux 上从 /proc 算出进程的启动时间，别的系统（或者读不到的话）用这个进程里第一次
+// 新建文件的时间
+fn process_started() -> SystemTime {
+    static FIRST: OnceLock<SystemTime> = OnceLock::new();
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(started) = proc_started() {
+        return started;
+    }
+    *FIRST.get_or_init(SystemTime::now)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn proc_started() -> Option<SystemTime> {
+    use std::fs;
+
+    // 进程名里可能有空格和括号，从最后一个 `)` 后面数：state 是第 3 个字段，
+    // starttime（开机以来的时钟周期数）是第 22 个
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    let rest = stat.get(stat.rfind(')')? + 2..)?;
+    let ticks: u64 = rest.split(' ').nth(19)?.parse().ok()?;
+    let boot: u64 = fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    // /proc 里的时钟周期是 USER_HZ，除了 alpha 和 ia64 都是 100
+    Some(UNIX_EPOCH + Duration::from_secs(boot) + Duration::from_millis(ticks * 10))
+}
@@ -0,0 +1,348 @@
+//! 把默认格式的记录解析回各个字段，格式见 [`format`](crate::format) 模块。
+//!
+//! 依赖 [`sanitize`](crate::format::sanitize)：`<file>`、`<target>` 和线程名里不会出现 `]`，
+//! 所以第一个 `]` 就是前缀的结尾，消息里有多少 `]` 都没关系；`<target>` 里也没有空格。
+//! 解析出来的文件名和 target 用 [`unsanitize`](crate::format::unsanitize) 还原过。
+//! 关掉了 [`Builder::sanitize`](crate::Builder::sanitize) 的文件不保证能正确解析。
+//! logfmt 格式（[`FormatKind::Logfmt`](crate::FormatKind::Logfmt)）的记录见 [`Entry::parse_logfmt`]，
+//! JSON 格式（[`Builder::json`](crate::Builder::json)）的记录请直接用 JSON 解析器。
+//!
+//! 这里的函数对任何输入都不会 panic：解析不了的返回 `None`，
+//! [`LogReader::items`](crate::reader::LogReader::items) 把它们作为续行或者 [`Item::Malformed`] 返回。
+
+use crate::format::{parse_timestamp, unsanitize, Anchor};
+use log::Level;
+use std::borrow::Cow;
+use std::iter::Fuse;
+use std::time::Duration;
+
+/// 文本格式下一条记录最多接这么多字节的续行，再多的作为 [`Item::Malformed`] 返回。
+pub const MAX_CONTINUATION: usize = 64 * 1024;
+
+/// 一条记录的各个字段。
+///
+/// ```
+/// use mmlog::parse::Entry;
+/// use std::time::Duration;
+///
+/// let entry = Entry::parse("[1700000000.5s 42 W src/main.rs:7 app::net] bad [frame] ]").unwrap();
+/// assert_eq!(entry.timestamp, Duration::new(1700000000, 500_000_000));
+/// assert_eq!(entry.tid, 42);
+/// assert_eq!(entry.level, log::Level::Warn);
+/// assert_eq!(entry.file.as_deref(), Some("src/main.rs"));
+/// assert_eq!(entry.line, Some(7));
+/// assert_eq!(entry.target, "app::net");
+/// assert_eq!(entry.message, "bad [frame] ]");
+///
+/// // 没有文件名和行号，线程名里有空格
+/// let entry = Entry::parse("#3 [2023-11-14T22:13:20.1Z 42/pool W 1 I  app] hi").unwrap();
+/// assert_eq!(entry.timestamp, Duration::new(1700000000, 100_000_000));
+/// assert_eq!(entry.level, log::Level::Info);
+/// assert_eq!((entry.file, entry.line), (None, None));
+/// assert_eq!(entry.target, "app");
+///
+/// // 转义过的文件名和 target 还原成原来的样子
+/// let entry = Entry::parse(r"[1700000000.5s 42 E C:\x5cx\x5d.rs:1 a\x5d\x20[fake] hi").unwrap();
+/// assert_eq!(entry.file.as_deref(), Some(r"C:\x].rs"));
+/// assert_eq!(entry.target, "a] [fake");
+/// assert_eq!(entry.message, "hi");
+///
+/// assert!(Entry::parse("not a record").is_none());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// UNIX 纪元以来的时长。[`Clock::Monotonic`](crate::Clock::Monotonic) 的文件里直接解析出来的是
+    /// 单调时钟的读数，见 [`Entry::anchored`]。
+    pub timestamp: Duration,
+    pub tid: u64,
+    pub level: Level,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub target: String,
+    /// 前缀之后的全部内容（包括 `kv` 的键值对），不含结尾的换行。
+    pub message: String,
+}
+
+impl Entry {
+    /// 解析一条记录，可以带着 [`Builder::sequence_numbers`](crate::Builder::sequence_numbers)
+    /// 的 `#<序号> ` 前缀。不是默认格式的行返回 `None`。
+    ///
+    /// [`Builder::escape_newlines`](crate::Builder::escape_newlines) 的文件里直接拿到的行要先用
+    /// [`unescape_newlines`](crate::format::unescape_newlines) 还原，
+    /// [`LogReader`](crate::reader::LogReader) 读出来的记录已经还原过了。
+    pub fn parse(record: &str) -> Option<Entry> {
+        let record = unprefixed(record)?;
+        let (prefix, message) = record.strip_prefix('[')?.split_once(']')?;
+        let message = message.strip_prefix(' ')?;
+
+        let (timestamp, rest) = prefix.split_once(' ')?;
+        let timestamp = parse_timestamp(timestamp)?;
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let tid = rest[..digits].parse().ok()?;
+        let rest = &rest[digits..];
+        // 线程名里可能有空格，甚至像 ` W ` 这样的片段，取最后一个 ` <level> `；
+        // 文件名里也有的话就分不清了
+        let rest = match rest.strip_prefix('/') {
+            Some(name) => {
+                let at = name
+                    .char_indices()
+                    .map(|(i, _)| i)
+                    .rfind(|&i| level_at(&name[i..]).is_some())?;
+                &name[at..]
+            }
+            None => rest,
+        };
+        let level = level_at(rest)?;
+
+        // 剩下 `<file>:<line> <target>`，target 里没有空格，文件名里可能有
+        let (location, target) = rest[3..].rsplit_once(' ')?;
+        let (file, line) = match location {
+            "" => (None, None),
+            location => {
+                let (file, line) = location.rsplit_once(':')?;
+                (
+                    Some(unsanitize(file).into_owned()),
+                    Some(line.parse().ok()?),
+                )
+            }
+        };
+        Some(Entry {
+            timestamp,
+            tid,
+            level,
+            file,
+            line,
+            target: unsanitize(target).into_owned(),
+            message: message.to_string(),
+        })
+    }
+
+    /// 解析一条 logfmt 格式的记录，字段见 [`format`](crate::format) 模块，
+    /// 和 [`Entry::parse`] 一样可以带着 `#<序号> ` 前缀。
+    ///
+    /// `ts`、`tid`、`level`、`target` 和 `msg` 缺了哪个都返回 `None`；带引号的值按 JSON 转义还原。
+    /// `msg` 之后的 `kv` 键值对原样接在 `message` 后面，和默认格式一样。
+    ///
+    /// ```
+    /// use mmlog::parse::Entry;
+    /// use std::time::Duration;
+    ///
+    /// let line = r#"ts=1700000000.5s tid=42 thread="io pool" level=warn target=app::net file=src/main.rs line=7 msg="bad \"frame\"\nretrying" peer=10.0.0.1"#;
+    /// let entry = Entry::parse_logfmt(line).unwrap();
+    /// assert_eq!(entry.timestamp, Duration::new(1700000000, 500_000_000));
+    /// assert_eq!(entry.tid, 42);
+    /// assert_eq!(entry.level, log::Level::Warn);
+    /// assert_eq!(entry.file.as_deref(), Some("src/main.rs"));
+    /// assert_eq!(entry.line, Some(7));
+    /// assert_eq!(entry.target, "app::net");
+    /// assert_eq!(entry.message, "bad \"frame\"\nretrying peer=10.0.0.1");
+    ///
+    /// assert!(Entry::parse_logfmt("ts=1700000000.5s level=info msg=hi").is_none());
+    /// assert!(Entry::parse_logfmt("[1700000000.5s 42 W  app] hi").is_none());
+    /// ```
+    pub fn parse_logfmt(record: &str) -> Option<Entry> {
+        let mut rest = unprefixed(record)?;
+        let (mut timestamp, mut tid, mut level, mut target) = (None, None, None, None);
+        let (mut file, mut line) = (None, None);
+        let message = loop {
+            let (key, after) = rest.split_once('=')?;
+            let (value, after) = logfmt_value(after)?;
+            match key {
+                "ts" => timestamp = Some(parse_timestamp(&value)?),
+                "tid" => tid = Some(value.parse().ok()?),
+                "thread" => {}
+                "level" => level = Some(value.parse().ok()?),
+                "target" => target = Some(value.into_owned()),
+                "file" => file = Some(value.into_owned()),
+                "line" => line = Some(value.parse().ok()?),
+                "msg" => break value.into_owned() + after,
+                _ => return None,
+            }
+            rest = after.strip_prefix(' ')?;
+        };
+        Some(Entry {
+            timestamp: timestamp?,
+            tid: tid?,
+            level: level?,
+            file,
+            line,
+            target: target?,
+            message,
+        })
+    }
+
+    /// 按 [`Clock::Monotonic`](crate::Clock::Monotonic) 的文件里的锚点把 `timestamp` 换算成
+    /// UNIX 纪元以来的时长，`anchor` 是 `None`（墙上时间的文件）的话原样返回。
+    /// 之前开机时写的记录换算不了（见 [`Anchor::same_boot`]），也原样返回。
+    /// [`LogReader::entries`](crate::reader::LogReader::entries) 读出来的已经换算过了。
+    ///
+    /// ```
+    /// use mmlog::format::Anchor;
+    /// use mmlog::parse::Entry;
+    /// use std::time::Duration;
+    ///
+    /// let anchor = Anchor {
+    ///     realtime: Duration::from_secs(1_700_000_000),
+    ///     monotonic: Duration::from_secs(100),
+    ///     boot_start: Duration::from_secs(20),
+    /// };
+    /// let entry = Entry::parse("[130.5s 42 I  app] hi").unwrap().anchored(Some(&anchor));
+    /// assert_eq!(entry.timestamp, Duration::new(1_700_000_030, 500_000_000));
+    /// let entry = Entry::parse("[10.5s 42 I  app] hi").unwrap().anchored(Some(&anchor));
+    /// assert_eq!(entry.timestamp, Duration::new(10, 500_000_000));
+    /// ```
+    pub fn anchored(mut self, anchor: Option<&Anchor>) -> Entry {
+        match anchor {
+            Some(anchor) if anchor.same_boot(self.timestamp) => {
+                self.timestamp = anchor.to_realtime(self.timestamp);
+            }
+            _ => {}
+        }
+        self
+    }
+
+    // 默认格式或者 logfmt，LogReader 这些读取方用
+    pub(crate) fn parse_any(record: &str) -> Option<Entry> {
+        Entry::parse(record).or_else(|| Entry::parse_logfmt(record))
+    }
+}
+
+/// [`LogReader::items`](crate::reader::LogReader::items) 逐条返回的东西。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    /// 解析好的一条记录。文本格式下紧跟在后面、不是记录开头的行（没转义的换行、
+    /// [`Builder::format`](crate::Builder::format) 写的行）用 `\n` 接在 `entry.message` 后面，
+    /// `continued` 是接上的行数。
+    Entry { entry: Entry, continued: usize },
+    /// 解析不了、又接不到前一条记录后面的行，原样返回：文件开头（前一条被覆盖了）的续行、
+    /// 超过了 [`MAX_CONTINUATION`] 的续行、含有 NUL 的行（写了一半的记录），
+    /// 以及 [`Framing::LengthPrefixed`](crate::Framing::LengthPrefixed) 文件里解析不了的记录。
+    Malformed(String),
+}
+
+// 把一条条记录变成 Item，`continuation` 是文本格式：只有这时候一条记录可能被拆成几行
+pub(crate) struct Items<I: Iterator> {
+    records: Fuse<I>,
+    continuation: bool,
+    // 看过、不是续行的下一条
+    peeked: Option<(I::Item, Option<Entry>)>,
+}
+
+pub(crate) fn items<I>(records: I, continuation: bool) -> Items<I>
+where
+    I: Iterator,
+    I::Item: AsRef<str>,
+{
+    Items {
+        records: records.fuse(),
+        continuation,
+        peeked: None,
+    }
+}
+
+impl<I> Items<I>
+where
+    I: Iterator,
+    I::Item: AsRef<str>,
+{
+    fn pull(&mut self) -> Option<(I::Item, Option<Entry>)> {
+        self.peeked.take().or_else(|| {
+            let record = self.records.next()?;
+            let entry = Entry::parse_any(record.as_ref());
+            Some((record, entry))
+        })
+    }
+}
+
+impl<I> Iterator for Items<I>
+where
+    I: Iterator,
+    I::Item: AsRef<str>,
+{
+    type Item = Item;
+
+    fn next(&mut self) -> Option<Item> {
+        let (record, entry) = self.pull()?;
+        let mut entry = match entry {
+            Some(entry) => entry,
+            None => return Some(Item::Malformed(record.as_ref().to_string())),
+        };
+        let (mut continued, mut added) = (0, 0);
+        while self.continuation {
+            match self.pull() {
+                Some((line, None))
+                    if !line.as_ref().contains('\0')
+                        && added + line.as_ref().len() < MAX_CONTINUATION =>
+                {
+                    let line = line.as_ref();
+                    entry.message.push('\n');
+                    entry.message.push_str(line);
+                    added += line.len() + 1;
+                    continued += 1;
+                }
+                next => {
+                    self.peeked = next;
+                    break;
+                }
+            }
+        }
+        Some(Item::Entry { entry, continued })
+    }
+}
+
+// 去掉结尾的换行和可能有的 `#<序号> `
+fn unprefixed(record: &str) -> Option<&str> {
+    let record = record.strip_suffix('\n').unwrap_or(record);
+    Some(match record.strip_prefix('#') {
+        Some(rest) => rest.split_once(' ')?.1,
+        None => record,
+    })
+}
+
+// logfmt 里 `=` 之后的一个值和剩下的部分：带引号的话按 JSON 转义还原，否则到下一个空格为止
+fn logfmt_value(s: &str) -> Option<(Cow<'_, str>, &str)> {
+    let quoted = match s.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => {
+            let end = s.find(' ').unwrap_or(s.len());
+            return Some((Cow::Borrowed(&s[..end]), &s[end..]));
+        }
+    };
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((Cow::Owned(value), &quoted[i + 1..])),
+            '\\' => value.push(match chars.next()?.1 {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => {
+                    let hex = quoted.get(i + 2..i + 6)?;
+                    chars.nth(3)?;
+                    char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+                }
+                c @ ('"' | '\\' | '/') => c,
+                _ => return None,
+            }),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+// `s` 以 ` <level> ` 开头的话返回 level
+fn level_at(s: &str) -> Option<Level> {
+    let level = match s.as_bytes() {
+        [b' ', level, b' ', ..] => *level,
+        _ => return None,
+    };
+    Some(match level {
+        b'E' => Level::Error,
+        b'W' => Level::Warn,
+        b'I' => Level::Info,
+        b'D' => Level::Debug,
+        b'T' => Level::Trace,
+        _ => return None,
+    })
+}
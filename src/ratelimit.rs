@@ -0,0 +1,134 @@
+use crate::batch;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Builder::rate_limit：按调用点（file, line）限流。表很小、大小固定，有自己的锁，
+// 在拿写锁之前判断，不会拉长写锁的临界区。
+const SLOTS: usize = 64;
+// 从哈希的位置往后找几个槽位，都被别的调用点占着的话换掉窗口开始得最早的那个
+const PROBE: usize = 4;
+// 文件名只用来写提示，超长的截断
+const FILE_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    used: bool,
+    hash: u64,
+    line: u32,
+    file: [u8; FILE_LEN],
+    len: usize,
+    // 当前窗口开始的时间，RateLimit::base 以来的纳秒数
+    start: u64,
+    count: u32,
+    suppressed: u64,
+}
+
+impl Slot {
+    const EMPTY: Slot = Slot {
+        used: false,
+        hash: 0,
+        line: 0,
+        file: [0; FILE_LEN],
+        len: 0,
+        start: 0,
+        count: 0,
+        suppressed: 0,
+    };
+
+    fn summary(&self) -> Option<Summary> {
+        (self.suppressed > 0).then(|| Summary {
+            file: String::from_utf8_lossy(&self.file[..self.len]).into_owned(),
+            line: self.line,
+            suppressed: self.suppressed,
+        })
+    }
+}
+
+/// 某个调用点在上一个窗口里被压掉了多少条记录。
+pub(crate) struct Summary {
+    pub(crate) file: String,
+    pub(crate) line: u32,
+    pub(crate) suppressed: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimit {
+    budget: u32,
+    window: u64,
+    base: Instant,
+    slots: Mutex<[Slot; SLOTS]>,
+    suppressed: AtomicU64,
+}
+
+impl RateLimit {
+    pub(crate) fn new(budget: u32, window: Duration) -> RateLimit {
+        RateLimit {
+            budget,
+            window: window.as_nanos() as u64,
+            base: Instant::now(),
+            slots: Mutex::new([Slot::EMPTY; SLOTS]),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// 返回这条记录能不能写，以及需不需要先写一条提示：这个调用点的窗口过去了，
+    /// 或者它的槽位被别的调用点换掉了，之前又有被压掉的记录。
+    pub(crate) fn admit(&self, file: &str, line: u32) -> (bool, Option<Summary>) {
+        let mut hasher = DefaultHasher::new();
+        (file, line).hash(&mut hasher);
+        let hash = hasher.finish();
+        let now = self.base.elapsed().as_nanos() as u64;
+
+        let mut slots = batch::lock(&self.slots);
+        let home = hash as usize % SLOTS;
+        let probe = (home..home + PROBE).map(|i| i % SLOTS);
+        let found = probe
+            .clone()
+            .find(|&i| slots[i].used && slots[i].hash == hash && slots[i].line == line);
+        let (i, mut summary) = match found {
+            Some(i) => (i, None),
+            None => {
+                let i = probe
+                    .min_by_key(|&i| (slots[i].used, slots[i].start))
+                    .expect("PROBE > 0");
+                // 空的槽位没有被压掉的记录
+                let evicted = slots[i].summary();
+                let mut len = file.len().min(FILE_LEN);
+                while !file.is_char_boundary(len) {
+                    len -= 1;
+                }
+                let slot = &mut slots[i];
+                *slot = Slot::EMPTY;
+                slot.used = true;
+                slot.hash = hash;
+                slot.line = line;
+                slot.file[..len].copy_from_slice(&file.as_bytes()[..len]);
+                slot.len = len;
+                slot.start = now;
+                (i, evicted)
+            }
+        };
+
+        let slot = &mut slots[i];
+        if now - slot.start >= self.window {
+            summary = summary.or(slot.summary());
+            slot.start = now;
+            slot.count = 0;
+            slot.suppressed = 0;
+        }
+        if slot.count >= self.budget {
+            slot.suppressed += 1;
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            return (false, summary);
+        }
+        slot.count += 1;
+        (true, summary)
+    }
+
+    pub(crate) fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+}
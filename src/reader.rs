@@ -0,0 +1,1557 @@
+//! 读取日志文件，按时间顺序还原记录。
+//!
+//! 写满之后缓冲区会从头覆盖，直接 `cat` 文件看到的是先新后旧、在翻转处断开的内容，
+//! [`LogReader`] 按 header 里的 offset 把两段重新拼起来。两种 [`Framing`] 都支持。
+
+use crate::crypt::Cipher;
+use crate::format::{self, Anchor, Expiry, Framing};
+use crate::mapping::{self, Mapping, Ring};
+use crate::merge;
+use crate::parse::{self, Entry, Item};
+use crate::{Error, Result, Stats};
+use std::borrow::Cow;
+#[cfg(all(feature = "mmap", unix))]
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::path::Path;
+#[cfg(all(feature = "mmap", unix))]
+use std::thread;
+#[cfg(all(feature = "mmap", unix))]
+use std::time::Instant;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 日志文件在打开那一刻的快照。
+///
+/// 文件可以同时被另一个进程写着：打开时只读映射文件、取一次 offset 并复制数据，
+/// 复制期间被覆盖的部分会被丢掉，之后的写入不会影响已经打开的 `LogReader`。
+/// 文件设置了 [`Builder::retention`](crate::Builder::retention) 的话，打开时已经过期的记录读不到，
+/// 见 [`LogReader::open_ignoring_retention`]。
+///
+/// ```
+/// use log::Log;
+/// use mmlog::reader::LogReader;
+///
+/// let path = std::env::temp_dir().join("mmlog-reader.log");
+/// let logger = mmlog::Builder::new().build(&path).unwrap();
+/// // 写够好几圈，保证翻转过
+/// for i in 0..30000 {
+///     logger.log(
+///         &log::Record::builder()
+///             .level(log::Level::Info)
+///             .args(format_args!("record {}", i))
+///             .build(),
+///     );
+/// }
+/// drop(logger);
+///
+/// let reader = LogReader::open(&path).unwrap();
+/// let numbers: Vec<u32> = reader
+///     .records()
+///     .map(|r| r.rsplit(' ').next().unwrap().parse().unwrap())
+///     .collect();
+/// assert!(numbers.len() > 1000 && numbers.len() < 30000);
+/// assert!(numbers.windows(2).all(|w| w[1] == w[0] + 1));
+/// assert_eq!(numbers.last(), Some(&29999));
+/// ```
+#[derive(Debug)]
+#[cfg_attr(not(all(feature = "mmap", unix)), allow(dead_code))]
+pub struct LogReader {
+    mapping: Mapping,
+    // 所有记录首尾相接，ends 是每条记录的结尾，rings 是每条记录在 Mapping::rings() 里的第几个环
+    text: String,
+    ends: Vec<usize>,
+    rings: Vec<u8>,
+    // 校验和不对的记录，和它前面有几条好的记录
+    corrupt: Vec<(usize, ReadError)>,
+    stats: Stats,
+    // Follow 在每个环里读到了哪里，见 Mapping::position()
+    cursor: Vec<u64>,
+    // 文本格式下每个环里还没读到换行的半条记录
+    pending: Vec<Vec<u8>>,
+    // 上次 poll() 发现落后太多，下次从最旧的记录重新开始
+    resync: bool,
+    // 加密了的文件的密钥，见 LogReader::open_encrypted
+    cipher: Option<Cipher>,
+    // 文件应该有和实际有的字节数，只有 LogReader::open_lenient 打开被截断的文件时才不一样
+    expected_len: usize,
+    file_len: usize,
+    // 文件里的保留期限，LogReader::open_ignoring_retention 打开的话是 None
+    retention: Option<Duration>,
+}
+
+impl LogReader {
+    /// 只读打开文件，只需要读权限，见 [`Builder::open_readonly`](crate::Builder::open_readonly)。
+    ///
+    /// [`Builder::encrypt`](crate::Builder::encrypt) 加密了的文件要用 [`LogReader::open_encrypted`]，
+    /// 这里返回 [`Error::InvalidConfig`]。
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<LogReader> {
+        LogReader::open_with(path, None)
+    }
+
+    /// 和 [`LogReader::open`] 一样，但文件比 header 里说的短也能打开，比如从手机上拉下来、
+    /// 传到一半断了的文件。header 和它后面的元数据必须是全的，否则返回 [`Error::CorruptHeader`]。
+    ///
+    /// 文件里没有的部分当作一段缺口：缺口两边的记录照常读出来，跨过缺口的记录不和另一边的数据拼在一起，
+    /// [`LogReader::checked_records`] 在原来的位置返回 [`ReadError::Truncated`]。
+    /// 文本格式的文件翻转过的话，缺口后面第一行读不出时间戳的话也算跨过缺口的记录。
+    /// [`LogReader::summary`] 里有应该有和实际有的字节数。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::{LogReader, ReadError};
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-open-lenient.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// for i in 0..100 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// drop(logger);
+    ///
+    /// // 截到第 51 条记录中间
+    /// let data = std::fs::read(&path).unwrap();
+    /// let cut = data.windows(9).position(|w| w == b"record 50").unwrap() + 5;
+    /// std::fs::write(&path, &data[..cut]).unwrap();
+    ///
+    /// assert!(LogReader::open(&path).is_err());
+    /// let reader = LogReader::open_lenient(&path).unwrap();
+    /// assert_eq!(reader.records().count(), 50);
+    /// assert!(matches!(
+    ///     reader.checked_records().last(),
+    ///     Some(Err(ReadError::Truncated { .. }))
+    /// ));
+    /// let summary = reader.summary();
+    /// assert!(summary.truncated());
+    /// assert_eq!((summary.expected_bytes, summary.present_bytes), (data.len() as u64, cut as u64));
+    /// ```
+    pub fn open_lenient<P: AsRef<Path>>(path: P) -> Result<LogReader> {
+        let (mapping, file_len) = Mapping::read_lenient(path.as_ref())?;
+        LogReader::with_mapping(mapping, file_len, None, true)
+    }
+
+    /// 打开 [`Builder::encrypt`](crate::Builder::encrypt) 加密了的文件，记录读出来的时候解密。
+    /// 需要 `encryption` feature。
+    ///
+    /// 密钥不对、文件没加密的话返回 [`Error::InvalidConfig`]。被改过的记录（tag 对不上）和校验和不对的记录
+    /// 一样不在 [`LogReader::records`] 里，[`LogReader::checked_records`] 在原来的位置返回
+    /// [`ReadError::Tampered`]。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::{LogReader, ReadError};
+    /// use std::io::{Seek, SeekFrom, Write};
+    ///
+    /// let key = [3; 32];
+    /// let path = std::env::temp_dir().join("mmlog-open-encrypted.log");
+    /// let logger = mmlog::Builder::new().encrypt(key).build(&path).unwrap();
+    /// for i in 0..3 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// drop(logger);
+    ///
+    /// // 改掉第二条记录密文里的一个字节，再把校验和改对：只有 tag 认得出来
+    /// let mut data = std::fs::read(&path).unwrap();
+    /// let at = mmlog::format::DATA_POS;
+    /// let data_start = u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+    /// let first = u32::from_le_bytes(data[data_start..data_start + 4].try_into().unwrap()) as usize;
+    /// let frame = data_start + 4 + first + 4;
+    /// let len = u32::from_le_bytes(data[frame..frame + 4].try_into().unwrap()) as usize;
+    /// data[frame + 4 + mmlog::format::SEAL_NONCE] ^= 1;
+    /// let crc = mmlog::format::checksum(&data[frame + 4..frame + 4 + len]);
+    /// data[frame + 4 + len..frame + 8 + len].copy_from_slice(&crc.to_le_bytes());
+    /// let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    /// file.seek(SeekFrom::Start(0)).unwrap();
+    /// file.write_all(&data).unwrap();
+    /// drop(file);
+    ///
+    /// let reader = LogReader::open_encrypted(&path, &key).unwrap();
+    /// let records: Vec<_> = reader.checked_records().collect();
+    /// assert_eq!(records.len(), 3);
+    /// assert!(records[0].unwrap().ends_with("record 0"));
+    /// assert!(matches!(records[1], Err(ReadError::Tampered { .. })));
+    /// assert!(records[2].unwrap().ends_with("record 2"));
+    /// ```
+    #[cfg(feature = "encryption")]
+    pub fn open_encrypted<P: AsRef<Path>>(path: P, key: &[u8; 32]) -> Result<LogReader> {
+        LogReader::open_with(path, Some(Cipher::new(key)))
+    }
+
+    /// 和 [`LogReader::open`]（有 `key` 的话是 `LogReader::open_encrypted`）一样，但不管
+    /// [`Builder::retention`](crate::Builder::retention)，过期了、还没被覆盖的记录也读得到。
+    /// 只给内部排查问题用，读出来的记录不要导出。
+    pub fn open_ignoring_retention<P: AsRef<Path>>(
+        path: P,
+        key: Option<&[u8; 32]>,
+    ) -> Result<LogReader> {
+        let cipher = match key {
+            #[cfg(feature = "encryption")]
+            Some(key) => Some(Cipher::new(key)),
+            #[cfg(not(feature = "encryption"))]
+            Some(_) => {
+                return Err(Error::InvalidConfig(
+                    "reading encrypted files needs the encryption feature",
+                ))
+            }
+            None => None,
+        };
+        let mapping = Mapping::read_only(path.as_ref())?;
+        let file_len = mapping.file_len();
+        LogReader::with_mapping(mapping, file_len, cipher, false)
+    }
+
+    // cipher 要和文件对得上：加密了的文件要有，没加密的不能有
+    pub(crate) fn open_with<P: AsRef<Path>>(path: P, cipher: Option<Cipher>) -> Result<LogReader> {
+        let mapping = Mapping::read_only(path.as_ref())?;
+        let file_len = mapping.file_len();
+        LogReader::with_mapping(mapping, file_len, cipher, true)
+    }
+
+    // 文件只有前 file_len 个字节，见 LogReader::open_lenient。
+    // retention 的话跳过 Builder::retention 过期了的记录
+    fn with_mapping(
+        mapping: Mapping,
+        file_len: usize,
+        cipher: Option<Cipher>,
+        retention: bool,
+    ) -> Result<LogReader> {
+        match (mapping.key_check(), cipher.as_ref().map(Cipher::key_check)) {
+            (Some(file), Some(key)) if file != key => {
+                return Err(Error::InvalidConfig("wrong key for the encrypted file"))
+            }
+            (Some(_), None) => {
+                return Err(Error::InvalidConfig(
+                    "the file is encrypted, open it with LogReader::open_encrypted",
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(Error::InvalidConfig("the file isn't encrypted"));
+            }
+            _ => {}
+        }
+        for ring in mapping.rings() {
+            let offset = mapping.ring_offset(ring);
+            if offset > mapping.ring_size(ring) {
+                return Err(Error::CorruptHeader(format!(
+                    "offset {} is beyond the buffer size {}",
+                    offset,
+                    mapping.ring_size(ring)
+                )));
+            }
+        }
+        let stats = Stats::from_mapping(&mapping);
+        let retention = mapping.retention().filter(|_| retention);
+        let mut expiry = Expiry::new(retention, mapping.anchor());
+
+        let mut text = String::new();
+        let mut ends = Vec::new();
+        let mut rings = Vec::new();
+        let mut corrupt = Vec::new();
+        let cursor = snapshot(
+            &mapping,
+            cipher.as_ref(),
+            file_len,
+            |ring, record| match record {
+                Ok(record) if !expiry.keep(record.as_bytes()) => {}
+                Ok(record) => {
+                    text.push_str(&record);
+                    ends.push(text.len());
+                    rings.push(ring as u8);
+                }
+                Err(e) => corrupt.push((ends.len(), e)),
+            },
+        );
+        Ok(LogReader {
+            pending: vec![Vec::new(); cursor.len()],
+            text,
+            ends,
+            rings,
+            corrupt,
+            stats,
+            cursor,
+            resync: false,
+            cipher,
+            expected_len: mapping.file_len(),
+            file_len,
+            retention,
+            mapping,
+        })
+    }
+
+    /// 按时间顺序逐条返回记录（不含结尾的换行）。
+    ///
+    /// [`Framing::LengthPrefixed`] 的记录可以包含换行，文本格式下一行就是一条。
+    /// 校验和不对的记录不在这里，见 [`LogReader::checked_records`]。
+    pub fn records(&self) -> impl Iterator<Item = &str> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        starts
+            .zip(self.ends.iter().copied())
+            .map(move |(start, end)| &self.text[start..end])
+    }
+
+    /// 文件里的通道，按通道表里的顺序，见 [`Builder::channels`](crate::Builder::channels)。
+    /// 没有通道的话是空的。
+    pub fn channels(&self) -> Vec<&str> {
+        self.mapping.channels().map(|(name, _)| name).collect()
+    }
+
+    /// 和 [`LogReader::records`] 一样，但只有通道 `name` 里的记录，没有这个通道的话什么都没有。
+    pub fn channel<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> {
+        // 通道在 Mapping::rings() 里的顺序就是通道表里的顺序
+        let ring = self.mapping.channels().position(|(n, _)| n == name);
+        self.records()
+            .zip(self.rings.iter())
+            .filter(move |(_, &r)| Some(r as usize) == ring)
+            .map(|(record, _)| record)
+    }
+
+    /// 和 [`LogReader::records`] 一样，但校验和不对的记录（掉电时写了一半、文件被改过）
+    /// 在原来的位置返回 [`ReadError::BadChecksum`]。
+    ///
+    /// 只有带校验和的 [`Framing::LengthPrefixed`] 文件（见 [`format::FLAG_CHECKSUM`]）会有错误，
+    /// 长度前缀本身坏掉的话和以前一样，这一段后面的记录都读不到。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::{LogReader, ReadError};
+    /// use std::io::{Read, Seek, SeekFrom, Write};
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-checked.log");
+    /// let logger = mmlog::Builder::new()
+    ///     .framing(mmlog::Framing::LengthPrefixed)
+    ///     .build(&path)
+    ///     .unwrap();
+    /// for i in 0..3 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// drop(logger);
+    ///
+    /// // 改掉第二条记录里的一个字节
+    /// let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    /// let mut data = Vec::new();
+    /// file.read_to_end(&mut data).unwrap();
+    /// let pos = data.windows(8).position(|w| w == b"record 1").unwrap();
+    /// file.seek(SeekFrom::Start(pos as u64)).unwrap();
+    /// file.write_all(b"R").unwrap();
+    /// drop(file);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let records: Vec<_> = reader.checked_records().collect();
+    /// assert_eq!(records.len(), 3);
+    /// assert!(records[0].unwrap().ends_with("record 0"));
+    /// assert!(matches!(records[1], Err(ReadError::BadChecksum { .. })));
+    /// assert!(records[2].unwrap().ends_with("record 2"));
+    /// assert_eq!(reader.records().count(), 2);
+    /// ```
+    pub fn checked_records(&self) -> impl Iterator<Item = std::result::Result<&str, ReadError>> {
+        let mut records = self.records();
+        let mut corrupt = self.corrupt.iter().peekable();
+        let mut seen = 0;
+        std::iter::from_fn(move || {
+            if let Some((_, e)) = corrupt.next_if(|(before, _)| *before == seen) {
+                return Some(Err(*e));
+            }
+            seen += 1;
+            records.next().map(Ok)
+        })
+    }
+
+    /// 按时间顺序把快照里的记录写成纯文本，每条后面跟一个换行，返回写出的字节数。
+    ///
+    /// 和 [`Logger::dump_to`](crate::Logger::dump_to) 一样，但可以在另一个进程里对着正在写的文件用。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-reader-dump.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// for i in 0..100 {
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("record {}", i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// // 关掉 mmap feature 的话 flush() 之前文件里还没有内容
+    /// logger.flush();
+    ///
+    /// let dump = std::env::temp_dir().join("mmlog-reader-dump.txt");
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let n = reader.dump_to_file(&dump).unwrap();
+    /// let text = std::fs::read_to_string(&dump).unwrap();
+    /// assert_eq!(n, text.len() as u64);
+    /// assert_eq!(text.lines().count(), 100);
+    /// assert!(text.lines().eq(reader.records()));
+    /// ```
+    pub fn dump_to<W: Write>(&self, mut w: W) -> Result<u64> {
+        let mut n = 0;
+        for record in self.records() {
+            w.write_all(record.as_bytes())?;
+            w.write_all(b"\n")?;
+            n += record.len() as u64 + 1;
+        }
+        Ok(n)
+    }
+
+    /// 新建（或截断）`path`，把 [`LogReader::dump_to`] 的结果写进去。
+    pub fn dump_to_file<P: AsRef<Path>>(&self, path: P) -> Result<u64> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let n = self.dump_to(&mut file)?;
+        file.flush()?;
+        Ok(n)
+    }
+
+    /// 按时间顺序逐条返回解析好的记录，默认格式和 logfmt 格式的都认得，
+    /// 见 [`Entry::parse`] 和 [`Entry::parse_logfmt`]。解析不了的行接在前一条的消息后面，
+    /// 接不上的被跳过，见 [`LogReader::items`]。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-entries.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// logger.log(
+    ///     &log::Record::builder()
+    ///         .level(log::Level::Error)
+    ///         .target("app::db")
+    ///         .file(Some("src/db.rs"))
+    ///         .line(Some(12))
+    ///         .args(format_args!("query [users] failed"))
+    ///         .build(),
+    /// );
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let entry = reader.entries().next().unwrap();
+    /// assert_eq!(entry.level, log::Level::Error);
+    /// assert_eq!(entry.file.as_deref(), Some("src/db.rs"));
+    /// assert_eq!(entry.line, Some(12));
+    /// assert_eq!(entry.target, "app::db");
+    /// assert_eq!(entry.message, "query [users] failed");
+    /// ```
+    pub fn entries(&self) -> impl Iterator<Item = Entry> + '_ {
+        self.items().filter_map(|item| match item {
+            Item::Entry { entry, .. } => Some(entry),
+            Item::Malformed(_) => None,
+        })
+    }
+
+    /// 和 [`LogReader::entries`] 一样，但解析不了的行也在原来的位置：文本格式下
+    /// 不是记录开头的行接在前一条记录的消息后面（最多 [`MAX_CONTINUATION`](parse::MAX_CONTINUATION) 字节），
+    /// 接不上的作为 [`Item::Malformed`] 返回，见 [`Item`]。不管文件里是什么都不会 panic。
+    ///
+    /// ```
+    /// use mmlog::parse::Item;
+    /// use mmlog::reader::LogReader;
+    /// use std::io::{Seek, SeekFrom, Write};
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-items.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// log::Log::log(
+    ///     &logger,
+    ///     &log::Record::builder()
+    ///         .level(log::Level::Error)
+    ///         .args(format_args!("panicked\n  0: main"))
+    ///         .build(),
+    /// );
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let items: Vec<_> = reader.items().collect();
+    /// assert!(matches!(
+    ///     &items[..],
+    ///     [Item::Entry { entry, continued: 1 }] if entry.message == "panicked\n  0: main"
+    /// ));
+    /// let summary = reader.summary();
+    /// assert_eq!((summary.well_formed, summary.continuation, summary.malformed), (1, 1, 0));
+    /// ```
+    pub fn items(&self) -> impl Iterator<Item = Item> + '_ {
+        let anchor = self.anchor();
+        let continuation = self.mapping.framing() == Framing::Text;
+        parse::items(self.records(), continuation).map(move |item| match item {
+            Item::Entry { entry, continued } => Item::Entry {
+                entry: entry.anchored(anchor.as_ref()),
+                continued,
+            },
+            item => item,
+        })
+    }
+
+    /// [`Clock::Monotonic`](crate::Clock::Monotonic) 的文件里换算时间戳用的锚点，别的文件是 `None`。
+    ///
+    /// [`LogReader::entries`] 和 [`LogReader::range`] 已经换算过了，[`LogReader::records`]
+    /// 里的是原样的单调时钟读数，见 [`Builder::clock`](crate::Builder::clock)。
+    pub fn anchor(&self) -> Option<Anchor> {
+        self.mapping.anchor()
+    }
+
+    /// 记录的时间戳能不能换算成墙上时间。只有 [`Clock::Monotonic`](crate::Clock::Monotonic)
+    /// 的文件里锚点是 0（header 里没写，或者写的时候墙上时间早于 UNIX 纪元）时不能，
+    /// 这时 [`LogReader::normalize`] 总是返回 `None`。
+    pub fn normalizable(&self) -> bool {
+        !self.anchor().is_some_and(|anchor| anchor.is_missing())
+    }
+
+    /// 把一条记录的时间戳换算成 UNIX 纪元以来的纳秒数，[`mmlog::merge`](crate::merge) 和
+    /// [`LogReader::seek_time`] 都按它比较。
+    ///
+    /// 默认格式、JSON 和 logfmt 的记录都行，时间戳可以是 [`TimestampFormat`](crate::format::TimestampFormat)
+    /// 的任何一种写法，本地时间按里面写的时区换算；单调时钟的文件按 [`LogReader::anchor`] 换算，
+    /// 早于纪元的话是负数。解析不出时间戳、文件不是 [`LogReader::normalizable`]、或者是之前开机时写的
+    /// （见 [`Anchor::same_boot`]）的话返回 `None`。
+    ///
+    /// ```
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-normalize.log");
+    /// drop(mmlog::Builder::new().build(&path).unwrap());
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let t = 1_700_000_000_100_000_000;
+    /// assert_eq!(reader.normalize("[1700000000.1s 1 I  app] hi"), Some(t));
+    /// assert_eq!(reader.normalize("[2023-11-15T06:13:20.1+08:00 1 I  app] hi"), Some(t));
+    /// assert_eq!(reader.normalize(r#"{"ts":"2023-11-14T22:13:20.1Z","tid":1}"#), Some(t));
+    /// assert_eq!(reader.normalize("  at main.rs:7"), None);
+    /// ```
+    pub fn normalize(&self, record: &str) -> Option<i128> {
+        let ts = merge::timestamp(record)?;
+        match self.anchor() {
+            None => Some(ts.as_nanos() as i128),
+            Some(anchor) if anchor.is_missing() || !anchor.same_boot(ts) => None,
+            Some(anchor) => Some(anchor.to_nanos(ts)),
+        }
+    }
+
+    /// [`LogReader::records`] 里第一条不早于 `at` 的记录是第几条（从 0 开始），都早于 `at` 的话是记录数。
+    ///
+    /// 时间戳按 [`LogReader::normalize`] 换算，解析不出时间戳的记录跟着前一条。
+    /// 文件不是 [`LogReader::normalizable`] 的话返回 `None`。
+    pub fn seek_time(&self, at: SystemTime) -> Option<usize> {
+        if !self.normalizable() {
+            return None;
+        }
+        let at = match at.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_nanos() as i128,
+            Err(before) => -(before.duration().as_nanos() as i128),
+        };
+        let mut last = i128::MIN;
+        let mut count = 0;
+        for (i, record) in self.records().enumerate() {
+            if let Some(ts) = self.normalize(record) {
+                last = ts;
+            }
+            if last >= at {
+                return Some(i);
+            }
+            count = i + 1;
+        }
+        Some(count)
+    }
+
+    /// 包含 `pattern` 的记录，每条带上前后最多 `context` 条记录，按时间顺序排列，和 `grep -C` 一样。
+    ///
+    /// 只比较子串，不是正则表达式。两条命中的记录离得近、上下文重叠的话不重复：
+    /// 前一条的 [`Match::after`] 到下一条命中的记录为止，下一条的 [`Match::before`]
+    /// 从前一条的上下文结束的地方开始，依次输出每个 `Match` 的三部分就是 `grep -C` 的结果，
+    /// [`Match::start`] 和前一个的 [`Match::end`] 不相等的地方是 `grep` 输出 `--` 的地方。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-grep.log");
+    /// let logger = mmlog::Builder::new().build(&path).unwrap();
+    /// for i in 0..20 {
+    ///     let msg = if i == 5 || i == 7 || i == 15 { "hit" } else { "miss" };
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{} {}", msg, i))
+    ///             .build(),
+    ///     );
+    /// }
+    /// drop(logger);
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let matches = reader.grep("hit", 2);
+    /// let positions: Vec<_> = matches.iter().map(|m| (m.start(), m.position, m.end())).collect();
+    /// // 5 和 7 的上下文连在一起，15 单独一段
+    /// assert_eq!(positions, [(3, 5, 7), (7, 7, 10), (13, 15, 18)]);
+    /// assert!(matches[1].before.is_empty());
+    /// assert!(matches[2].record.ends_with("hit 15"));
+    /// ```
+    pub fn grep(&self, pattern: &str, context: usize) -> Vec<Match<'_>> {
+        let hits: Vec<usize> = (0..self.ends.len())
+            .filter(|&i| self.record(i).contains(pattern))
+            .collect();
+        let records = |range: std::ops::Range<usize>| range.map(|i| self.record(i)).collect();
+        let mut matches = Vec::with_capacity(hits.len());
+        // 前一段上下文结束的位置
+        let mut end = 0;
+        for (n, &position) in hits.iter().enumerate() {
+            let start = position.saturating_sub(context).max(end);
+            let next = hits.get(n + 1).copied().unwrap_or(self.ends.len());
+            end = position
+                .saturating_add(context)
+                .saturating_add(1)
+                .min(next)
+                .min(self.ends.len());
+            matches.push(Match {
+                position,
+                record: self.record(position),
+                before: records(start..position),
+                after: records(position + 1..end),
+            });
+        }
+        matches
+    }
+
+    /// 创建文件时写进去的元数据：哪个进程、哪个可执行文件、什么时候启动的，
+    /// 见 [`Builder::tag`](crate::Builder::tag)。版本 6 之前的文件没有，返回 `None`。
+    pub fn metadata(&self) -> Option<FileMetadata> {
+        self.mapping.metadata()
+    }
+
+    /// 按 [`Builder::banner`](crate::Builder::banner) 的启动标记和结束标记把记录分成每次运行，
+    /// 按时间顺序排列。
+    ///
+    /// 第一个启动标记之前的记录（启动标记被覆盖了，或者那次运行没有打开 `banner`）单独算一次，
+    /// 它的 [`Run::banner`] 是 `None`。只认默认格式的记录，JSON 格式的文件只有一次运行。
+    /// 只要位置的话见 [`LogReader::sessions`]。
+    pub fn runs(&self) -> Vec<Run<'_>> {
+        self.sessions()
+            .iter()
+            .map(|session| {
+                let start = session.start + usize::from(!session.partial);
+                let end = session.end - usize::from(session.clean);
+                Run {
+                    banner: (!session.partial).then(|| self.record(session.start)),
+                    records: (start..end).map(|i| self.record(i)).collect(),
+                    clean: session.clean,
+                }
+            })
+            .collect()
+    }
+
+    /// [`Builder::open`](crate::Builder::open) 反复打开的同一个文件里的每次运行，按时间顺序排列，
+    /// 和 [`LogReader::runs`] 的分法一样，只是记下位置、进程号和启动时间，不收集记录。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    ///
+    /// let path = std::env::temp_dir().join("mmlog-sessions.log");
+    /// for run in ["first", "second"] {
+    ///     let logger = mmlog::Builder::new().banner(true).open_or_create(&path).unwrap();
+    ///     logger.log(
+    ///         &log::Record::builder()
+    ///             .level(log::Level::Info)
+    ///             .args(format_args!("{} run", run))
+    ///             .build(),
+    ///     );
+    /// }
+    ///
+    /// let reader = LogReader::open(&path).unwrap();
+    /// let sessions = reader.sessions();
+    /// let last = sessions.last().unwrap();
+    /// assert_eq!(last.pid, Some(std::process::id()));
+    /// assert!(last.clean && !last.partial);
+    /// let records: Vec<_> = reader.records_in_session(sessions.len() - 1).collect();
+    /// assert_eq!(records.len(), 3);
+    /// assert!(records[0].contains("==== mmlog start pid="));
+    /// assert!(records[1].ends_with("] second run"));
+    /// assert!(records[2].ends_with("] ==== mmlog clean shutdown ===="));
+    /// ```
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let mut sessions = Vec::new();
+        let mut session = SessionInfo::partial(0);
+        let anchor = self.anchor();
+        for (i, record) in self.records().enumerate() {
+            match marker(record) {
+                Some(Marker::Start) => {
+                    if i > session.start {
+                        session.end = i;
+                        sessions.push(session);
+                    }
+                    // 之前开机时写的启动标记换算不了墙上时间
+                    let entry = Entry::parse_any(record).filter(|entry| {
+                        anchor.is_none_or(|anchor| anchor.same_boot(entry.timestamp))
+                    });
+                    let entry = entry.map(|e| e.anchored(anchor.as_ref()));
+                    session = SessionInfo {
+                        pid: banner_pid(record),
+                        started: entry.map(|entry| UNIX_EPOCH + entry.timestamp),
+                        partial: false,
+                        ..SessionInfo::partial(i)
+                    };
+                }
+                Some(Marker::Shutdown) => {
+                    session.end = i + 1;
+                    session.clean = true;
+                    sessions.push(mem::replace(&mut session, SessionInfo::partial(i + 1)));
+                }
+                None => {}
+            }
+        }
+        if self.ends.len() > session.start {
+            session.end = self.ends.len();
+            sessions.push(session);
+        }
+        sessions
+    }
+
+    /// [`LogReader::sessions`] 里第 `index` 次运行（从 0 开始）的记录，包括启动标记和结束标记，
+    /// 没有这么多次运行的话什么都没有。每次调用都要重新找一遍运行的边界。
+    pub fn records_in_session(&self, index: usize) -> impl Iterator<Item = &str> {
+        let range = match self.sessions().get(index) {
+            Some(session) => session.start..session.end,
+            None => 0..0,
+        };
+        range.map(move |i| self.record(i))
+    }
+
+    /// 时间戳在 `[since, until)` 之间的记录，按时间顺序排列，和 [`LogReader::records`] 一样不含结尾的换行。
+    ///
+    /// [`Framing::LengthPrefixed`] 的文件每条都是完整的记录，二分查找开始和结束的位置；
+    /// 文本格式的文件里可能有消息里的换行拆出来的行，逐条比较。
+    /// 读不出时间戳的记录（比如这样拆出来的行）前后最近的两条有时间戳的记录都在范围内的话才算在内。
+    /// 单调时钟的文件里之前开机时写的记录换算不了墙上时间，总是不在范围内。
+    pub fn records_between(
+        &self,
+        since: SystemTime,
+        until: SystemTime,
+    ) -> impl Iterator<Item = &str> {
+        self.between(since, until).map(|(record, _)| record)
+    }
+
+    /// 和 [`LogReader::records_between`] 一样，返回解析好的记录。
+    ///
+    /// 解析不了的记录也在里面：`message` 是整条记录，`timestamp` 是它自己的或者前一条的时间戳，
+    /// `level` 是 Info，其余字段是空的。
+    ///
+    /// ```
+    /// use log::Log;
+    /// use mmlog::reader::LogReader;
+    /// use mmlog::Framing;
+    /// use std::thread;
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// for framing in [Framing::Text, Framing::LengthPrefixed] {
+    ///     let path = std::env::temp_dir().join(format!("mmlog-range-{:?}.log", framing));
+    ///     let logger = mmlog::Builder::new().framing(framing).build(&path).unwrap();
+    ///     let log = |msg: &str| {
+    ///         logger.log(
+    ///             &log::Record::builder()
+    ///                 .level(log::Level::Warn)
+    ///                 .args(format_args!("{}", msg))
+    ///                 .build(),
+    ///         );
+    ///         thread::sleep(Duration::from_millis(20));
+    ///     };
+    ///     log("before");
+    ///     let since = SystemTime::now();
+    ///     log("first\ncontinued");
+    ///     log("second");
+    ///     let until = SystemTime::now();
+    ///     log("after");
+    ///     drop(logger);
+    ///
+    ///     let reader = LogReader::open(&path).unwrap();
+    ///     let messages: Vec<_> = reader.range(since, until).map(|e| e.message).collect();
+    ///     match framing {
+    ///         // 拆出来的那一行前后都在范围内
+    ///         Framing::Text => assert_eq!(messages, ["first", "continued", "second"]),
+    ///         Framing::LengthPrefixed => assert_eq!(messages, ["first\ncontinued", "second"]),
+    ///     }
+    ///     assert_eq!(reader.range(until, SystemTime::now()).count(), 1);
+    ///     assert_eq!(reader.range(until, since).count(), 0);
+    /// }
+    /// ```
+    pub fn range(&self, since: SystemTime, until: SystemTime) -> impl Iterator<Item = Entry> + '_ {
+        let anchor = self.anchor();
+        self.between(since, until).map(move |(record, timestamp)| {
+            Entry::parse_any(record)
+                .unwrap_or_else(|| Entry {
+                    timestamp,
+                    tid: 0,
+                    level: log::Level::Info,
+                    file: None,
+                    line: None,
+                    target: String::new(),
+                    message: record.to_string(),
+                })
+                .anchored(anchor.as_ref())
+        })
+    }
+
+    // 单调时钟的文件先把 since 和 until 换算成文件里的时钟，记录的时间戳原样比较。
+    // 之前开机时写的记录不知道是什么时候的，都不要
+    fn between(&self, since: SystemTime, until: SystemTime) -> Between<'_> {
+        let mut since = since.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut until = until.duration_since(UNIX_EPOCH).unwrap_or_default();
+        if let Some(anchor) = self.anchor() {
+            since = anchor.to_monotonic(since).max(anchor.boot_start);
+            until = anchor.to_monotonic(until).max(anchor.boot_start);
+        }
+        let (start, end) = if self.mapping.framing() == Framing::LengthPrefixed {
+            // 读不出时间戳的记录按它后面第一条有时间戳的记录算，后面都没有的话算作无穷大
+            let before = |i: usize, t: Duration| self.time_after(i).is_some_and(|(_, at)| at < t);
+            (
+                partition_point(self.ends.len(), |i| before(i, since)),
+                partition_point(self.ends.len(), |i| before(i, until)),
+            )
+        } else {
+            (0, self.ends.len())
+        };
+        Between {
+            reader: self,
+            since,
+            until,
+            i: start,
+            end: end.max(start),
+            prev: (0..start).rev().find_map(|i| self.time(i)),
+            next: (0, None),
+        }
+    }
+
+    fn record(&self, i: usize) -> &str {
+        let start = if i == 0 { 0 } else { self.ends[i - 1] };
+        &self.text[start..self.ends[i]]
+    }
+
+    fn time(&self, i: usize) -> Option<Duration> {
+        format::timestamp(self.record(i).as_bytes())
+    }
+
+    // 从第 i 条开始第一条有时间戳的记录
+    fn time_after(&self, i: usize) -> Option<(usize, Duration)> {
+        (i..self.ends.len()).find_map(|j| Some((j, self.time(j)?)))
+    }
+
+    /// 快照时 header 里的统计。
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// 读到了多少，见 [`Summary`]。要把所有记录解析一遍，见 [`LogReader::items`]。
+    pub fn summary(&self) -> Summary {
+        let mut summary = Summary {
+            expected_bytes: self.expected_len as u64,
+            present_bytes: self.file_len as u64,
+            records: self.ends.len(),
+            corrupt: self.corrupt.len(),
+            ..Summary::default()
+        };
+        for item in parse::items(self.records(), self.mapping.framing() == Framing::Text) {
+            match item {
+                Item::Entry { continued, .. } => {
+                    summary.well_formed += 1;
+                    summary.continuation += continued;
+                }
+                Item::Malformed(_) => summary.malformed += 1,
+            }
+        }
+        summary
+    }
+
+    /// 已经被覆盖、读不到的较旧记录的条数。
+    ///
+    /// 文本格式下包含换行的记录会被当成几条，这时结果偏小。
+    /// 没翻转过的文件总是 0：[`Builder::rotate`](crate::Builder::rotate) 出来的文件的计数
+    /// 包含了之前那些文件里的记录，它们没有被覆盖。
+    pub fn overwritten(&self) -> u64 {
+        if self.stats.wrap_count == 0 {
+            return 0;
+        }
+        self.stats
+            .records_written
+            .saturating_sub((self.ends.len() + self.corrupt.len()) as u64)
+    }
+
+    /// 从快照结束的位置开始跟踪之后写入的记录，见 [`Follow`]。
+    ///
+    /// 读到的位置记在 `LogReader` 里，`Follow` drop 之后再调用会接着上次的位置。
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn follow(&mut self) -> Follow<'_> {
+        Follow { reader: self }
+    }
+}
+
+unsafe impl Send for LogReader {}
+unsafe impl Sync for LogReader {}
+
+// Follow::next_batch() 轮询 offset 的间隔
+#[cfg(all(feature = "mmap", unix))]
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// 跟踪正在被写入的日志文件，类似 `tail -f`，见 [`LogReader::follow`]。
+///
+/// 只读 header 里的 offset 和翻转次数，不需要写入方配合，可以在另一个进程里用。
+/// 两次读取之间写入的数据超过整个缓冲区的话，中间的记录已经被覆盖了：这时返回
+/// [`Lagged`]，下一次从当时最旧的记录重新开始，和广播 channel 的接收方一样。
+/// 正在写的那一条记录还没有更新 offset，所以正好落后一整圈时读到的最旧的记录可能不完整。
+///
+/// ```
+/// use log::Log;
+/// use mmlog::reader::{Lagged, LogReader};
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let path = std::env::temp_dir().join("mmlog-follow.log");
+/// let logger = mmlog::Builder::new().size(4 << 20).build(&path).unwrap();
+/// let log = |i: u32| {
+///     logger.log(
+///         &log::Record::builder()
+///             .level(log::Level::Info)
+///             .args(format_args!("record {}", i))
+///             .build(),
+///     )
+/// };
+/// let number = |r: &String| -> u32 { r.rsplit(' ').next().unwrap().parse().unwrap() };
+///
+/// let mut reader = LogReader::open(&path).unwrap();
+/// thread::scope(|s| {
+///     s.spawn(|| {
+///         for i in 0..20000 {
+///             log(i);
+///         }
+///     });
+///     let mut follow = reader.follow();
+///     let mut next = 0;
+///     while next < 20000 {
+///         match follow.next_batch(Duration::from_secs(10)) {
+///             Ok(records) => {
+///                 assert!(!records.is_empty());
+///                 for record in &records {
+///                     assert_eq!(number(record), next);
+///                     next += 1;
+///                 }
+///             }
+///             // 跟丢了就从下一批里最旧的那条接着数
+///             Err(Lagged(_)) => {
+///                 let records = follow.poll().unwrap();
+///                 next = number(&records[0]);
+///                 for record in &records {
+///                     assert_eq!(number(record), next);
+///                     next += 1;
+///                 }
+///             }
+///         }
+///     }
+/// });
+///
+/// // 一次写入超过整个缓冲区：先报告落后了多少字节，再从最旧的记录开始
+/// let mut follow = reader.follow();
+/// for i in 0..200000 {
+///     log(i);
+/// }
+/// let Err(Lagged(skipped)) = follow.poll() else { panic!("should have lagged") };
+/// assert!(skipped > 0);
+/// let records = follow.poll().unwrap();
+/// assert!(records.len() < 200000);
+/// assert_eq!(records.last().map(number), Some(199999));
+/// assert!(records.windows(2).all(|w| number(&w[1]) == number(&w[0]) + 1));
+/// ```
+#[cfg(all(feature = "mmap", unix))]
+#[derive(Debug)]
+pub struct Follow<'a> {
+    reader: &'a mut LogReader,
+}
+
+/// [`Follow`] 落后了一整圈以上，里面是读不到了的字节数。
+#[cfg(all(feature = "mmap", unix))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lagged(pub u64);
+
+#[cfg(all(feature = "mmap", unix))]
+impl fmt::Display for Lagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "follower lagged, {} bytes were overwritten", self.0)
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl std::error::Error for Lagged {}
+
+/// 创建文件时写进去的元数据，见 [`LogReader::metadata`] 和 [`format`] 模块。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// 创建文件的进程号。
+    pub pid: u32,
+    /// 那个进程的启动时间。Linux 以外的系统上是进程里第一次新建日志文件的时间。
+    pub process_started: SystemTime,
+    /// `std::env::current_exe()` 的文件名，最多 64 个字节，拿不到的话是空的。
+    pub exe: String,
+    /// [`Builder::tag`](crate::Builder::tag) 设置的标签。
+    pub tag: Option<String>,
+}
+
+// 第一个让 before(i) 不成立的 i，before 必须是先真后假的
+fn partition_point(len: usize, before: impl Fn(usize) -> bool) -> usize {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if before(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+// LogReader::records_between 的迭代器，返回记录和它（或者前一条记录）的时间戳
+struct Between<'a> {
+    reader: &'a LogReader,
+    since: Duration,
+    until: Duration,
+    i: usize,
+    end: usize,
+    // 前一条有时间戳的记录的时间
+    prev: Option<Duration>,
+    // 上次找到的下一条有时间戳的记录，没有的话下标是 usize::MAX
+    next: (usize, Option<Duration>),
+}
+
+impl<'a> Iterator for Between<'a> {
+    type Item = (&'a str, Duration);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let within = |t: Option<Duration>| t.is_some_and(|t| self.since <= t && t < self.until);
+        while self.i < self.end {
+            let i = self.i;
+            self.i += 1;
+            let record = self.reader.record(i);
+            match self.reader.time(i) {
+                Some(t) => {
+                    self.prev = Some(t);
+                    if within(Some(t)) {
+                        return Some((record, t));
+                    }
+                }
+                None => {
+                    if self.next.0 <= i {
+                        self.next = match self.reader.time_after(i + 1) {
+                            Some((j, t)) => (j, Some(t)),
+                            None => (usize::MAX, None),
+                        };
+                    }
+                    if within(self.prev) && within(self.next.1) {
+                        return self.prev.map(|t| (record, t));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// [`LogReader::runs`] 分出来的一次运行。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Run<'a> {
+    /// 启动标记那条记录，已经被覆盖了的话是 `None`。
+    pub banner: Option<&'a str>,
+    /// 启动标记和结束标记之间的记录，不含这两条标记。
+    pub records: Vec<&'a str>,
+    /// 有没有结束标记。
+    pub clean: bool,
+}
+
+impl Run<'_> {
+    /// 没有结束标记：进程崩溃了、被杀掉了，或者最后一次运行还在写。
+    pub fn crashed(&self) -> bool {
+        !self.clean
+    }
+
+    /// 启动标记里的进程号。
+    pub fn pid(&self) -> Option<u32> {
+        banner_pid(self.banner?)
+    }
+}
+
+/// [`LogReader::sessions`] 找到的一次运行。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// 第一条记录在 [`LogReader::records`] 里的位置（从 0 开始），有启动标记的话就是启动标记。
+    pub start: usize,
+    /// 最后一条记录之后的位置，有结束标记的话包括结束标记，
+    /// 所以一次运行的 `end` 就是下一次运行的 `start`。
+    pub end: usize,
+    /// 启动标记里的进程号。
+    pub pid: Option<u32>,
+    /// 启动标记的时间戳，单调时钟的文件里之前开机时写的换算不了墙上时间，是 `None`。
+    pub started: Option<SystemTime>,
+    /// 有没有结束标记，没有的话进程崩溃了、被杀掉了，或者最后一次运行还在写。
+    pub clean: bool,
+    /// 没有启动标记，只剩下后面一部分：启动标记被环形缓冲区覆盖了，或者那次运行没有打开
+    /// [`Builder::banner`](crate::Builder::banner)。`pid` 和 `started` 都是 `None`。
+    pub partial: bool,
+}
+
+impl SessionInfo {
+    // 从 start 开始、还没看到启动标记的一次运行
+    fn partial(start: usize) -> SessionInfo {
+        SessionInfo {
+            start,
+            end: start,
+            pid: None,
+            started: None,
+            clean: false,
+            partial: true,
+        }
+    }
+}
+
+/// [`LogReader::grep`] 找到的一条记录和它的上下文。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'a> {
+    /// 命中的记录在 [`LogReader::records`] 里是第几条（从 0 开始）。
+    pub position: usize,
+    /// 命中的记录。
+    pub record: &'a str,
+    /// 它前面的上下文，按时间顺序排列，已经在前一个 `Match` 里的不算。
+    pub before: Vec<&'a str>,
+    /// 它后面的上下文，到下一条命中的记录为止。
+    pub after: Vec<&'a str>,
+}
+
+impl Match<'_> {
+    /// 上下文第一条记录的位置。
+    pub fn start(&self) -> usize {
+        self.position - self.before.len()
+    }
+
+    /// 上下文最后一条记录之后的位置。
+    pub fn end(&self) -> usize {
+        self.position + 1 + self.after.len()
+    }
+}
+
+enum Marker {
+    Start,
+    Shutdown,
+}
+
+// 启动标记里的进程号
+fn banner_pid(banner: &str) -> Option<u32> {
+    let rest = &banner[banner.find(format::BANNER_START)? + format::BANNER_START.len()..];
+    rest.strip_prefix("pid=")?.split(' ').next()?.parse().ok()
+}
+
+// 只看 logger 自己写的记录，先用子串过滤掉绝大多数记录，不用每条都解析
+fn marker(record: &str) -> Option<Marker> {
+    if !record.contains("==== mmlog ") {
+        return None;
+    }
+    let entry = Entry::parse_any(record)?;
+    if entry.target != "mmlog" {
+        return None;
+    }
+    if entry.message == format::BANNER_SHUTDOWN {
+        Some(Marker::Shutdown)
+    } else if entry.message.starts_with(format::BANNER_START) {
+        Some(Marker::Start)
+    } else {
+        None
+    }
+}
+
+/// [`LogReader::checked_records`] 读到的坏记录。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ReadError {
+    /// 记录内容和后面的校验和对不上，`offset` 是这条记录（的长度前缀）在缓冲区里的位置。
+    #[error("bad checksum in the record at offset {offset}")]
+    BadChecksum { offset: usize },
+    /// 加密了的记录的 tag 对不上：校验和是对的，但内容被改过，见 [`LogReader::open_encrypted`]。
+    #[error("the encrypted record at offset {offset} failed authentication")]
+    Tampered { offset: usize },
+    /// 记录跨过了文件被截断的地方，只剩下一部分，`offset` 是剩下的部分在缓冲区里的位置，
+    /// 见 [`LogReader::open_lenient`]。
+    #[error("the record at offset {offset} is cut off by the end of the file")]
+    Truncated { offset: usize },
+}
+
+/// [`LogReader::summary`]：文件应该有多长、实际有多长，读出了多少条记录。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// header 里说的文件长度：header、元数据加上缓冲区。
+    pub expected_bytes: u64,
+    /// 文件实际的长度，只有 [`LogReader::open_lenient`] 打开的文件会比 `expected_bytes` 短。
+    pub present_bytes: u64,
+    /// [`LogReader::records`] 里的记录数。
+    pub records: usize,
+    /// [`LogReader::checked_records`] 里的坏记录数，包括跨过截断处的。
+    pub corrupt: usize,
+    /// [`LogReader::items`] 里解析好的记录数。
+    pub well_formed: usize,
+    /// 接在这些记录后面的续行数。
+    pub continuation: usize,
+    /// [`Item::Malformed`] 的条数。
+    pub malformed: usize,
+}
+
+impl Summary {
+    /// 文件是不是被截断了。
+    pub fn truncated(&self) -> bool {
+        self.present_bytes < self.expected_bytes
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Follow<'_> {
+    /// 返回上次调用之后新写入的完整记录（不含结尾的换行），没有的话立即返回空的 `Vec`。
+    ///
+    /// [`Builder::reserve_for_errors`](crate::Builder::reserve_for_errors) 的两个环和
+    /// [`Builder::channels`](crate::Builder::channels) 的各个通道分别读，按时间合在一起，
+    /// 任何一个环落后了一整圈都返回 [`Lagged`]。
+    pub fn poll(&mut self) -> std::result::Result<Vec<String>, Lagged> {
+        if self.reader.resync {
+            return Ok(self.reader.resync());
+        }
+        let rings: Vec<Ring> = self.reader.mapping.rings().collect();
+        let mut columns = Vec::new();
+        for (i, ring) in rings.into_iter().enumerate() {
+            columns.push(self.poll_ring(i, ring)?);
+        }
+        let mut records = merge::interleave(columns, |r: &String| format::timestamp(r.as_bytes()));
+        // 接着上次读到的地方，开头的续行属于上次的最后一条
+        let reader = &self.reader;
+        let mut expiry = Expiry::new(reader.retention, reader.mapping.anchor()).resumed();
+        records.retain(|record| expiry.keep(record.as_bytes()));
+        Ok(records)
+    }
+
+    // ring 是 Mapping::rings() 里的第 i 个
+    fn poll_ring(&mut self, i: usize, ring: Ring) -> std::result::Result<Vec<String>, Lagged> {
+        let reader = &mut *self.reader;
+        let mapping = &reader.mapping;
+        let size = mapping.ring_size(ring);
+        let (_, pos) = mapping.position(ring);
+        if pos == reader.cursor[i] {
+            return Ok(Vec::new());
+        }
+        // pos 变小了说明文件被重新创建了
+        if pos < reader.cursor[i] || pos - reader.cursor[i] > size as u64 {
+            return Err(reader.lagged(i, ring, pos));
+        }
+
+        let start = (reader.cursor[i] % size as u64) as usize;
+        let len = (pos - reader.cursor[i]) as usize;
+        let first = len.min(size - start);
+        let data = mapping.slice(ring);
+        let chunk = [&data[start..start + first], &data[..len - first]].concat();
+        // 复制期间写入方又绕了一圈的话，复制的这段可能已经被覆盖了
+        let (_, after) = mapping.position(ring);
+        if after - reader.cursor[i] > size as u64 {
+            return Err(reader.lagged(i, ring, after));
+        }
+        reader.cursor[i] = pos;
+
+        if mapping.framing() == Framing::LengthPrefixed {
+            // 记录不会跨过末尾，两段分别拆开就行。校验和不对的记录跳过
+            let (head, tail) = chunk.split_at(first);
+            let checksum = mapping.checksum();
+            let cipher = reader.cipher.as_ref();
+            let records = [head, tail]
+                .iter()
+                .flat_map(|segment| format::records(segment, Framing::LengthPrefixed, checksum))
+                .filter_map(|record| match cipher {
+                    // tag 对不上的记录也跳过
+                    Some(cipher) => Some(Cow::Owned(cipher.open(record)?)),
+                    None => Some(Cow::Borrowed(record)),
+                })
+                .map(|record| decode(mapping, trim_newline(&record)).into_owned())
+                .collect();
+            return Ok(records);
+        }
+
+        let pending = &mut reader.pending[i];
+        pending.extend_from_slice(&chunk);
+        let complete = match pending.iter().rposition(|&b| b == b'\n') {
+            Some(i) => i + 1,
+            None => return Ok(Vec::new()),
+        };
+        let records = pending[..complete]
+            .split_inclusive(|&b| b == b'\n')
+            .map(|line| decode(mapping, trim_newline(line)).into_owned())
+            .collect();
+        pending.drain(..complete);
+        Ok(records)
+    }
+
+    /// 和 [`Follow::poll`] 一样，但没有新的记录时等待，最多等 `timeout`。
+    pub fn next_batch(&mut self, timeout: Duration) -> std::result::Result<Vec<String>, Lagged> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let records = self.poll()?;
+            let now = Instant::now();
+            if !records.is_empty() || now >= deadline {
+                return Ok(records);
+            }
+            thread::sleep(POLL_INTERVAL.min(deadline - now));
+        }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl LogReader {
+    // 落后太多，丢掉手上的半条记录，下次所有的环都从最旧的记录开始
+    fn lagged(&mut self, i: usize, ring: Ring, pos: u64) -> Lagged {
+        let size = self.mapping.ring_size(ring) as u64;
+        let skipped = pos.saturating_sub(self.cursor[i] + size);
+        self.pending.iter_mut().for_each(Vec::clear);
+        self.resync = true;
+        Lagged(skipped)
+    }
+
+    fn resync(&mut self) -> Vec<String> {
+        let mut records = Vec::new();
+        let mut expiry = Expiry::new(self.retention, self.mapping.anchor());
+        self.cursor = snapshot(
+            &self.mapping,
+            self.cipher.as_ref(),
+            self.file_len,
+            |_, record| match record {
+                Ok(record) if expiry.keep(record.as_bytes()) => records.push(record.into_owned()),
+                _ => {}
+            },
+        );
+        self.resync = false;
+        records
+    }
+}
+
+// 复制一份缓冲区，按时间顺序把每条记录（不含结尾的换行，还原了转义的换行）和它在
+// Mapping::rings() 里的第几个环交给 each，返回复制开始时每个环的 offset 的位置（见 Mapping::position）。
+// 复制期间写进来的记录留给 Follow 读。几个环的话分别复制，按时间合在一起。
+// 文件只有前 file_len 个字节的话见 snapshot_truncated()
+fn snapshot(
+    mapping: &Mapping,
+    cipher: Option<&Cipher>,
+    file_len: usize,
+    mut each: impl FnMut(usize, std::result::Result<Cow<'_, str>, ReadError>),
+) -> Vec<u64> {
+    let rings: Vec<Ring> = mapping.rings().collect();
+    if let [ring] = rings[..] {
+        return vec![snapshot_ring(mapping, ring, cipher, file_len, |record| {
+            each(0, record)
+        })];
+    }
+    let mut cursor = Vec::new();
+    let mut columns = Vec::new();
+    for (i, ring) in rings.into_iter().enumerate() {
+        let mut records = Vec::new();
+        cursor.push(snapshot_ring(mapping, ring, cipher, file_len, |record| {
+            records.push((i, record.map(Cow::into_owned)));
+        }));
+        columns.push(records);
+    }
+    let ts = |(_, record): &(usize, std::result::Result<String, ReadError>)| {
+        format::timestamp(record.as_ref().ok()?.as_bytes())
+    };
+    for (i, record) in merge::interleave(columns, ts) {
+        each(i, record.map(Cow::Owned));
+    }
+    cursor
+}
+
+fn snapshot_ring(
+    mapping: &Mapping,
+    ring: Ring,
+    cipher: Option<&Cipher>,
+    file_len: usize,
+    mut each: impl FnMut(std::result::Result<Cow<'_, str>, ReadError>),
+) -> u64 {
+    let present = mapping.ring_present(ring, file_len);
+    if present < mapping.ring_size(ring) {
+        snapshot_truncated(mapping, ring, cipher, present, &mut each);
+        return mapping.position(ring).1;
+    }
+    let (framing, checksum) = (mapping.framing(), mapping.checksum());
+    let (offset, pos) = mapping.position(ring);
+    let data = mapping.slice(ring).to_vec();
+    let (_, after) = mapping.position(ring);
+    // ReadError 里的位置从整个缓冲区的开头算
+    let (base, _) = mapping.bounds(ring);
+    let size = data.len();
+    // 旧文件可能停在末尾
+    let offset = offset % size.max(1);
+
+    // 复制期间写入方从 offset 开始覆盖了 written 个字节
+    let written = after.saturating_sub(pos).min(size as u64) as usize;
+    let (old, new) = if pos < size as u64 && offset + written <= size {
+        // 还没翻转过，offset 之后全是 0 或者 Logger::clear() 留下的旧数据
+        (&data[..0], &data[..offset])
+    } else if offset + written <= size {
+        (
+            mapping::skip_partial(&data[offset + written..], framing, checksum),
+            &data[..offset],
+        )
+    } else {
+        // 复制期间翻转了，旧的那一半全被覆盖了，新的一半开头也是
+        let overwritten = (offset + written - size).min(offset);
+        (
+            &data[..0],
+            mapping::skip_partial(&data[overwritten..offset], framing, checksum),
+        )
+    };
+    if framing == Framing::LengthPrefixed {
+        // 记录不跨过翻转处，两段分别拆开。old 总是到缓冲区末尾，new 总是到 offset。
+        // new 最后没写完的记录（崩溃时 offset 先落了盘）跳过，不算坏记录
+        let start = offset - new.len();
+        let new = &new[..format::committed(new, checksum)];
+        for (segment, start) in [(old, size - old.len()), (new, start)] {
+            frames(mapping, segment, base + start, cipher, &mut each);
+        }
+        return pos;
+    }
+    for record in format::joined_records(old, new, framing, checksum) {
+        each(Ok(decode(mapping, trim_newline(&record))));
+    }
+    pos
+}
+
+// 长度前缀格式的一段数据里的记录，start 是这一段在整个缓冲区里的位置，返回最后一条记录的结尾
+fn frames<'a>(
+    mapping: &Mapping,
+    segment: &'a [u8],
+    start: usize,
+    cipher: Option<&Cipher>,
+    each: &mut impl FnMut(std::result::Result<Cow<'a, str>, ReadError>),
+) -> usize {
+    let checksum = mapping.checksum();
+    let mut end = 0;
+    for (p, record, ok) in format::frames(segment, checksum) {
+        let offset = start + p;
+        each(match cipher {
+            _ if !ok => Err(ReadError::BadChecksum { offset }),
+            Some(cipher) => match cipher.open(record) {
+                Some(plain) => Ok(Cow::Owned(
+                    decode(mapping, trim_newline(&plain)).into_owned(),
+                )),
+                None => Err(ReadError::Tampered { offset }),
+            },
+            None => Ok(decode(mapping, trim_newline(record))),
+        });
+        end = p + format::FRAME_PREFIX + record.len() + format::frame_trailer(checksum);
+    }
+    end
+}
+
+// 被截断的文件（见 LogReader::open_lenient）里只剩前 present 个字节的 ring。文件不会再变，
+// 不用管写入方。按时间顺序是 offset 之后的旧数据、缺的那一段、offset 之前的新数据，
+// 没翻转过的话只有新数据，而且缺口在它后面。跨过缺口的记录报告成 ReadError::Truncated，
+// 不和缺口另一边的数据拼起来
+fn snapshot_truncated<'a>(
+    mapping: &'a Mapping,
+    ring: Ring,
+    cipher: Option<&Cipher>,
+    present: usize,
+    each: &mut impl FnMut(std::result::Result<Cow<'a, str>, ReadError>),
+) {
+    let (framing, checksum) = (mapping.framing(), mapping.checksum());
+    let data = mapping.slice(ring);
+    let (base, _) = mapping.bounds(ring);
+    let offset = mapping.ring_offset(ring) % data.len().max(1);
+    let wrapped = mapping.wrapped(ring);
+    if wrapped && present > offset {
+        let old = &data[offset..present];
+        let skip = match framing {
+            Framing::Text => old.len() - mapping::skip_partial(old, framing, checksum).len(),
+            Framing::LengthPrefixed => format::frame_start_cut(old, checksum),
+        };
+        cut(mapping, &old[skip..], base + offset + skip, cipher, each);
+    }
+    let mut new = &data[..offset.min(present)];
+    let mut start = 0;
+    if wrapped && framing == Framing::Text {
+        // 缺口后面第一行可能是跨过翻转处的那条记录的后半截，看时间戳认
+        let head = new
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(new.len(), |i| i + 1);
+        if head > 0 && format::timestamp(&new[..head]).is_none() {
+            each(Err(ReadError::Truncated { offset: base }));
+            (new, start) = (&new[head..], head);
+        }
+    }
+    if present < offset {
+        cut(mapping, new, base + start, cipher, each);
+    } else {
+        let committed = match framing {
+            Framing::Text => new.len(),
+            Framing::LengthPrefixed => format::committed(new, checksum),
+        };
+        let new = &new[..committed];
+        match framing {
+            Framing::Text => {
+                for record in format::records(new, framing, checksum) {
+                    each(Ok(decode(mapping, trim_newline(record))));
+                }
+            }
+            Framing::LengthPrefixed => {
+                frames(mapping, new, base + start, cipher, each);
+            }
+        }
+    }
+}
+
+// snapshot_truncated() 里结尾断在记录中间的一段，start 是它在整个缓冲区里的位置：
+// 完整的记录照常交出去，剩下的不全是 0（填充）的话报告成 ReadError::Truncated
+fn cut<'a>(
+    mapping: &Mapping,
+    segment: &'a [u8],
+    start: usize,
+    cipher: Option<&Cipher>,
+    each: &mut impl FnMut(std::result::Result<Cow<'a, str>, ReadError>),
+) {
+    let end = match mapping.framing() {
+        Framing::Text => {
+            let end = segment
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |i| i + 1);
+            for record in format::records(&segment[..end], Framing::Text, false) {
+                each(Ok(decode(mapping, trim_newline(record))));
+            }
+            end
+        }
+        Framing::LengthPrefixed => frames(mapping, segment, start, cipher, each),
+    };
+    if segment[end..].iter().any(|&b| b != 0) {
+        each(Err(ReadError::Truncated {
+            offset: start + end,
+        }));
+    }
+}
+
+fn trim_newline(record: &[u8]) -> &[u8] {
+    record.strip_suffix(b"\n").unwrap_or(record)
+}
+
+// 一条记录转换成字符串，Builder::escape_newlines 的文件还原转义的换行
+fn decode<'a>(mapping: &Mapping, record: &'a [u8]) -> Cow<'a, str> {
+    let record = String::from_utf8_lossy(record);
+    if mapping.escaped() {
+        format::unescape_lossy(record)
+    } else {
+        record
+    }
+}
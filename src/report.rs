@@ -0,0 +1,95 @@
+// 后台线程（Builder::flush_interval 的同步、Builder::compress_rotated 的压缩）和轮转出错时的报告。
+//
+// 这些地方不能通过 Log::log 写日志：flusher 出错时正拿着写锁，再拿就死锁了；轮转发生在写锁里、
+// 写一条记录的中途，再写一条又可能触发轮转。所以它们只把消息放在这里，由 Logger 下一次拿到写锁、
+// 写下一条记录之前（还有 flush()、close() 和 drop 时）作为 logger 自己的提示记录直接写进缓冲区，
+// 见 Logger::write_reports_locked()。那时已经不在轮转里了，写这些提示触发的轮转再出错，
+// 也只是放回这里等下一次，不会递归。
+
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, TryLockError};
+
+// 一直出错又一直没有写入的话最多攒这么多条，多出来的只计数
+const MAX_PENDING: usize = 16;
+
+#[derive(Debug, Default)]
+pub(crate) struct Reports {
+    // 写入方每条记录都要看一眼，不用每次都拿锁
+    pending: AtomicBool,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    messages: Vec<String>,
+    // 攒不下丢掉的条数
+    dropped: usize,
+}
+
+impl Reports {
+    pub(crate) fn push(&self, message: String) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.messages.len() < MAX_PENDING {
+            inner.messages.push(message);
+        } else {
+            inner.dropped += 1;
+        }
+        self.pending.store(true, Ordering::Release);
+    }
+
+    /// 取出攒着的消息，丢掉过的话最后一条说明丢了几条。
+    ///
+    /// 写入方在写锁里调用，不等锁：后台线程正拿着的话留到下一次。fork 出来的子进程里
+    /// 拿着锁的线程已经不在了，也只是再也取不到，不会卡住写入。
+    pub(crate) fn take(&self) -> Vec<String> {
+        if !self.pending.load(Ordering::Acquire) {
+            return Vec::new();
+        }
+        let mut inner = match self.inner.try_lock() {
+            Ok(inner) => inner,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return Vec::new(),
+        };
+        self.pending.store(false, Ordering::Release);
+        let mut messages = mem::take(&mut inner.messages);
+        if inner.dropped > 0 {
+            messages.push(format!(
+                "{} more background errors were not recorded",
+                mem::take(&mut inner.dropped)
+            ));
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_drains() {
+        let reports = Reports::default();
+        assert!(reports.take().is_empty());
+        reports.push("one".to_string());
+        reports.push("two".to_string());
+        assert_eq!(reports.take(), ["one", "two"]);
+        assert!(reports.take().is_empty());
+    }
+
+    #[test]
+    fn bounded() {
+        let reports = Reports::default();
+        for i in 0..MAX_PENDING + 3 {
+            reports.push(i.to_string());
+        }
+        let messages = reports.take();
+        assert_eq!(messages.len(), MAX_PENDING + 1);
+        assert_eq!(messages[0], "0");
+        assert_eq!(
+            messages.last().unwrap(),
+            "3 more background errors were not recorded"
+        );
+        assert!(reports.take().is_empty());
+    }
+}
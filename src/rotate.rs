@@ -0,0 +1,205 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 第 `n` 个轮转出来的文件：`name.n`
+pub(crate) fn segment(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// 压缩之后的 `name.n.zst`
+pub(crate) fn compressed(segment: &Path) -> PathBuf {
+    let mut name = OsString::from(segment.as_os_str());
+    name.push(".zst");
+    PathBuf::from(name)
+}
+
+/// `name.1` → `name.2` …，`name` → `name.1`，超过 `keep` 的删掉，压缩过的 `.zst` 一起改名。
+/// 调用前文件必须已经解除映射（Windows 上打开着的文件不能改名）。
+pub(crate) fn shift(path: &Path, keep: usize) -> io::Result<()> {
+    if keep == 0 {
+        return remove(path);
+    }
+    remove(&segment(path, keep))?;
+    remove(&compressed(&segment(path, keep)))?;
+    for n in (1..keep).rev() {
+        let (from, to) = (segment(path, n), segment(path, n + 1));
+        rename_existing(&from, &to)?;
+        rename_existing(&compressed(&from), &compressed(&to))?;
+    }
+    fs::rename(path, segment(path, 1))
+}
+
+fn rename_existing(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "compression")]
+pub(crate) use compress::Compressor;
+
+#[cfg(feature = "compression")]
+mod compress {
+    use super::{compressed, remove, segment, shift};
+    use crate::reader::LogReader;
+    use crate::report::Reports;
+    use std::fs::{self, File};
+    use std::io::{self, BufWriter};
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{self, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+
+    // Builder::compress_rotated 的后台线程：把刚轮转出来的 `name.1` 去掉 header、按时间顺序
+    // 还原成纯文本，压缩成 `name.1.zst` 再删掉原来的文件。
+    //
+    // 压缩期间写入方可能又轮转了几次，原来的 `name.1` 已经变成了 `name.3`。所以改名都在
+    // state 的锁里做，并且记着一共轮转了几次：第 r 次轮转出来的文件现在是 `name.(rotations - r + 1)`。
+    // 压缩好的数据先写到临时文件里，同步之后再改名、删掉原来的文件，中途崩溃的话原来的文件还在。
+    #[derive(Debug)]
+    pub(crate) struct Compressor {
+        state: Arc<Mutex<State>>,
+        jobs: Option<Sender<Job>>,
+        handle: Option<JoinHandle<()>>,
+    }
+
+    #[derive(Debug)]
+    struct State {
+        // Logger::swap_file 之后换成新的路径，重新计数，旧路径上排队的任务作废
+        path: PathBuf,
+        rotations: usize,
+        keep: usize,
+    }
+
+    #[derive(Debug)]
+    struct Job {
+        path: PathBuf,
+        rotation: usize,
+    }
+
+    impl State {
+        // 第 rotation 次轮转出来的文件现在的位置，已经被删掉了的话为 None
+        fn position(&self, job: &Job) -> Option<usize> {
+            let n = self.rotations - job.rotation + 1;
+            (job.path == self.path && n <= self.keep).then_some(n)
+        }
+    }
+
+    impl Compressor {
+        pub(crate) fn spawn(
+            path: &Path,
+            keep: usize,
+            reports: Arc<Reports>,
+        ) -> io::Result<Compressor> {
+            let state = Arc::new(Mutex::new(State {
+                path: path.to_path_buf(),
+                rotations: 0,
+                keep,
+            }));
+            let (jobs, rx) = mpsc::channel();
+            let handle = {
+                let state = state.clone();
+                thread::Builder::new()
+                    .name("mmlog-compressor".to_string())
+                    .spawn(move || {
+                        for job in rx {
+                            // 原来的文件留着，错误交给 Logger 写进去，见 report 模块
+                            if let Err(e) = compress(&state, &job) {
+                                reports.push(format!(
+                                    "compress_rotated: can't compress a rotated file of {}, \
+                                     left it uncompressed: {}",
+                                    job.path.display(),
+                                    e
+                                ));
+                            }
+                        }
+                    })?
+            };
+            Ok(Compressor {
+                state,
+                jobs: Some(jobs),
+                handle: Some(handle),
+            })
+        }
+
+        /// 代替 [`shift`]，成功的话把新的 `name.1` 交给后台线程。
+        pub(crate) fn shift(&self, path: &Path) -> io::Result<()> {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.path != path {
+                state.path = path.to_path_buf();
+                state.rotations = 0;
+            }
+            shift(path, state.keep)?;
+            if state.keep == 0 {
+                return Ok(());
+            }
+            state.rotations += 1;
+            if let Some(jobs) = &self.jobs {
+                let _ = jobs.send(Job {
+                    path: path.to_path_buf(),
+                    rotation: state.rotations,
+                });
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Compressor {
+        // 等排着队的都压缩完
+        fn drop(&mut self) {
+            self.jobs.take();
+            if let Some(handle) = self.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+
+    fn compress(state: &Mutex<State>, job: &Job) -> io::Result<()> {
+        // LogReader 打开时就复制了全部数据，之后文件再被改名也没关系
+        let (reader, tmp) = {
+            let state = state.lock().unwrap_or_else(|e| e.into_inner());
+            let n = match state.position(job) {
+                Some(n) => n,
+                None => return Ok(()),
+            };
+            let raw = segment(&job.path, n);
+            let reader = LogReader::open(&raw).map_err(io::Error::other)?;
+            let mut tmp = compressed(&raw).into_os_string();
+            tmp.push(format!(".tmp{}", job.rotation));
+            (reader, PathBuf::from(tmp))
+        };
+
+        let result = (|| {
+            let mut encoder = zstd::Encoder::new(BufWriter::new(File::create(&tmp)?), 0)?;
+            reader.dump_to(&mut encoder).map_err(io::Error::other)?;
+            let file = encoder.finish()?.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()
+        })();
+        if let Err(e) = result {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+
+        let state = state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.position(job) {
+            Some(n) => {
+                let raw = segment(&job.path, n);
+                fs::rename(&tmp, compressed(&raw))?;
+                remove(&raw)
+            }
+            None => remove(&tmp),
+        }
+    }
+}
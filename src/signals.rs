@@ -0,0 +1,129 @@
+use crate::{sys, Error, Logger, Result};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+// Logger::install_signal_handlers：信号处理函数里只把信号编号写进一个管道（write() 是
+// async-signal-safe 的），后台线程读管道，在普通的上下文里 reopen()、try_flush()。
+// 进程里只能有一个 logger 装信号处理函数。
+
+/// [`Logger::install_signal_handlers`] 用的信号。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Hup,
+    Usr1,
+    Usr2,
+}
+
+impl Signal {
+    fn number(self) -> libc::c_int {
+        match self {
+            Signal::Hup => libc::SIGHUP,
+            Signal::Usr1 => libc::SIGUSR1,
+            Signal::Usr2 => libc::SIGUSR2,
+        }
+    }
+}
+
+// 管道的写入端，装好之前是 -1
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+// 装好了才设置，装的过程中一直拿着
+static INSTALLED: Mutex<bool> = Mutex::new(false);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    sys::abort_on_unwind(|| {
+        let fd = PIPE.load(Ordering::Relaxed);
+        if fd < 0 {
+            return;
+        }
+        unsafe {
+            // 不能改掉被打断的代码看到的 errno
+            let errno = sys::errno();
+            // 管道满了就丢掉，后台线程还没处理完前面的
+            let _ = libc::write(fd, &(signal as u8) as *const u8 as *const libc::c_void, 1);
+            sys::set_errno(errno);
+        }
+    })
+}
+
+pub(crate) fn install(logger: &'static Logger, reopen: Signal, flush: Signal) -> Result<()> {
+    let mut installed = INSTALLED.lock().unwrap_or_else(PoisonError::into_inner);
+    if *installed {
+        return Err(Error::InvalidConfig(
+            "signal handlers are already installed",
+        ));
+    }
+    let mut fds = [0; 2];
+    unsafe {
+        errno_try!(libc::pipe(fds.as_mut_ptr()), -1, "pipe");
+        for fd in fds {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        // 信号处理函数里不能阻塞
+        libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK);
+    }
+    let (read, write) = (fds[0], fds[1]);
+    let spawned = thread::Builder::new()
+        .name("mmlog-signals".to_string())
+        .spawn(move || run(logger, read, reopen, flush));
+    if let Err(e) = spawned {
+        unsafe {
+            libc::close(read);
+            libc::close(write);
+        }
+        return Err(e.into());
+    }
+    PIPE.store(write, Ordering::Relaxed);
+    // 装到一半失败的话恢复已经装上的，关掉写入端，后台线程读到 EOF 关掉读取端退出
+    let mut previous: Vec<(libc::c_int, libc::sigaction)> = Vec::new();
+    for signal in [reopen, flush] {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut old: libc::sigaction = std::mem::zeroed();
+            errno_try!(
+                libc::sigaction(signal.number(), &action, &mut old),
+                -1,
+                "sigaction",
+                {
+                    let errno = sys::errno();
+                    for (number, old) in previous.iter().rev() {
+                        libc::sigaction(*number, old, std::ptr::null_mut());
+                    }
+                    PIPE.store(-1, Ordering::Relaxed);
+                    libc::close(write);
+                    sys::set_errno(errno);
+                }
+            );
+            previous.push((signal.number(), old));
+        }
+    }
+    *installed = true;
+    Ok(())
+}
+
+fn run(logger: &'static Logger, fd: libc::c_int, reopen: Signal, flush: Signal) {
+    let mut signal = 0u8;
+    loop {
+        let n = unsafe { libc::read(fd, &mut signal as *mut u8 as *mut libc::c_void, 1) };
+        if n != 1 {
+            if n == -1 && sys::errno() == libc::EINTR {
+                continue;
+            }
+            unsafe { libc::close(fd) };
+            return;
+        }
+        let signal = signal as libc::c_int;
+        // 同一个信号的话先 reopen 再同步
+        if signal == reopen.number() {
+            if let Err(e) = logger.reopen() {
+                logger.warn(format_args!("reopen: {}", e));
+            }
+        }
+        if signal == flush.number() {
+            let _ = logger.try_flush();
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::crypt::Cipher;
+use crate::format::{self, Anchor, Expiry, Framing};
+use crate::mapping::{Mapping, Ring};
+use crate::merge;
+use crate::parse::{self, Entry, Item};
+use crate::Result;
+use std::borrow::Cow;
+use std::io::Write;
+use std::time::Duration;
+
+/// [`Logger::snapshot`](crate::Logger::snapshot) 复制出来的缓冲区内容，之后的写入不会影响它。
+///
+/// 只有还读得到的记录：没翻转过的话是 offset 之前的部分，翻转过的话再加上 offset 之后、
+/// 跳过了被覆盖了一半的那条记录的部分，按时间顺序首尾相接放在一个 `Vec<u8>` 里，
+/// 占的内存就是这些字节，不是整个缓冲区。[`Builder::reserve_for_errors`](crate::Builder::reserve_for_errors)
+/// 的两个环和 [`Builder::channels`](crate::Builder::channels) 的各个通道各复制一份，
+/// 读的时候按时间合在一起。[`Builder::retention`](crate::Builder::retention) 过期的记录在读的时候跳过。
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    // 每个环一份：（数据，较旧的那一段的长度），后面是较新的一段
+    rings: Vec<(Vec<u8>, usize)>,
+    offset: usize,
+    framing: Framing,
+    checksum: bool,
+    escaped: bool,
+    anchor: Option<Anchor>,
+    // Builder::retention，读的时候跳过过期的记录
+    retention: Option<Duration>,
+    // Builder::encrypt 的话读的时候解密
+    cipher: Option<Cipher>,
+}
+
+impl Snapshot {
+    /// 调用者必须持有写锁。每个环最多复制两次，不做别的事
+    pub(crate) fn copy(mapping: &Mapping, cipher: Option<&Cipher>) -> Snapshot {
+        let rings = mapping
+            .rings()
+            .map(|ring| {
+                let (old, new) = mapping.halves(ring);
+                let mut data = Vec::with_capacity(old.len() + new.len());
+                data.extend_from_slice(old);
+                data.extend_from_slice(new);
+                (data, old.len())
+            })
+            .collect();
+        Snapshot {
+            rings,
+            offset: mapping.ring_offset(Ring::Main),
+            framing: mapping.framing(),
+            checksum: mapping.checksum(),
+            escaped: mapping.escaped(),
+            anchor: mapping.anchor(),
+            retention: mapping.retention(),
+            cipher: cipher.cloned(),
+        }
+    }
+
+    /// 只复制第一个环里从位置 `from`（见 `Mapping::position()`）到现在的 offset 之间的数据，
+    /// 见 [`Logger::read_since_bookmark`](crate::Logger::read_since_bookmark)。
+    /// 调用者必须持有写锁，保证 `from` 在记录的边界上、之后的数据还没有被覆盖
+    pub(crate) fn since(mapping: &Mapping, from: u64, cipher: Option<&Cipher>) -> Snapshot {
+        let size = mapping.ring_size(Ring::Main);
+        let (offset, pos) = mapping.position(Ring::Main);
+        let data = mapping.slice(Ring::Main);
+        let start = (from % size as u64) as usize;
+        let len = (pos - from) as usize;
+        // 绕过末尾的话分成两段，长度前缀格式的记录不跨过末尾，分别拆开
+        let first = len.min(size - start);
+        let mut copy = Vec::with_capacity(len);
+        copy.extend_from_slice(&data[start..start + first]);
+        copy.extend_from_slice(&data[..len - first]);
+        Snapshot {
+            rings: vec![(copy, first)],
+            offset,
+            framing: mapping.framing(),
+            checksum: mapping.checksum(),
+            escaped: mapping.escaped(),
+            anchor: mapping.anchor(),
+            retention: mapping.retention(),
+            cipher: cipher.cloned(),
+        }
+    }
+
+    /// `close()` 之后的快照
+    pub(crate) fn empty() -> Snapshot {
+        Snapshot {
+            rings: Vec::new(),
+            offset: 0,
+            framing: Framing::Text,
+            checksum: false,
+            escaped: false,
+            anchor: None,
+            retention: None,
+            cipher: None,
+        }
+    }
+
+    /// 复制时 header 里的 offset，两个环的话是第一个环的。
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// 复制出来的字节数，包括长度前缀和校验和，加密了的话是密文的长度。
+    pub fn len(&self) -> usize {
+        self.rings.iter().map(|(data, _)| data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lines(&self) -> impl Iterator<Item = Cow<'_, [u8]>> {
+        let halves = self.rings.iter().map(|(data, split)| data.split_at(*split));
+        let mut expiry = Expiry::new(self.retention, self.anchor);
+        merge::rings(halves, self.framing, self.checksum, self.cipher.as_ref())
+            .into_iter()
+            .filter(move |line| expiry.keep(line))
+    }
+
+    /// 按时间顺序逐条返回记录（不含结尾的换行），和 [`LogReader::records`](crate::reader::LogReader::records)
+    /// 一样：校验和不对的记录被跳过，[`Builder::escape_newlines`](crate::Builder::escape_newlines)
+    /// 转义的换行会被还原。
+    pub fn records(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        let escaped = self.escaped;
+        self.lines().map(move |line| {
+            let record = match line {
+                Cow::Borrowed(line) => {
+                    String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line))
+                }
+                Cow::Owned(line) => {
+                    let record = line.strip_suffix(b"\n").unwrap_or(&line);
+                    Cow::Owned(String::from_utf8_lossy(record).into_owned())
+                }
+            };
+            if escaped {
+                format::unescape_lossy(record)
+            } else {
+                record
+            }
+        })
+    }
+
+    /// 按时间顺序逐条返回解析好的记录，见 [`LogReader::entries`](crate::reader::LogReader::entries)。
+    pub fn entries(&self) -> impl Iterator<Item = Entry> + '_ {
+        parse::items(self.records(), self.framing == Framing::Text).filter_map(|item| match item {
+            Item::Entry { entry, .. } => Some(entry.anchored(self.anchor.as_ref())),
+            Item::Malformed(_) => None,
+        })
+    }
+
+    /// 按时间顺序把记录写成纯文本，返回写出的字节数，和 [`Logger::dump_to`](crate::Logger::dump_to)
+    /// 的结果一样：长度前缀被去掉，转义过的换行仍然是转义的。
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<u64> {
+        let mut n = 0;
+        for line in self.lines() {
+            w.write_all(&line)?;
+            n += line.len() as u64;
+        }
+        Ok(n)
+    }
+}
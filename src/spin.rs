@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 自旋这么多次还拿不到锁的话改成 yield_now()，把 CPU 让给持有锁的线程
+const SPIN_LIMIT: u64 = 64;
+
+/// 写锁的竞争统计。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// 需要自旋才拿到锁的次数
+    pub contended: u64,
+    /// 等锁时自旋（包括让出 CPU）的总次数
+    pub spins: u64,
+    /// 等待时间的粗略分布：<1µs, <10µs, <100µs, <1ms, <10ms, >=10ms
+    pub wait_histogram: [u64; 6],
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    contended: AtomicU64,
+    spins: AtomicU64,
+    histogram: [AtomicU64; 6],
+}
+
+impl Metrics {
+    fn record(&self, spins: u64, wait: Duration) {
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        self.spins.fetch_add(spins, Ordering::Relaxed);
+        let bucket = match wait.as_nanos() {
+            0..=999 => 0,
+            1_000..=9_999 => 1,
+            10_000..=99_999 => 2,
+            100_000..=999_999 => 3,
+            1_000_000..=9_999_999 => 4,
+            _ => 5,
+        };
+        self.histogram[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct SpinLock {
+    locked: AtomicBool,
+    metrics: Option<Box<Metrics>>,
+}
+
+impl SpinLock {
+    pub(crate) fn new(metrics: bool) -> SpinLock {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            metrics: if metrics {
+                Some(Default::default())
+            } else {
+                None
+            },
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub(crate) fn lock(&self) -> LockGuard<'_> {
+        if !self.try_acquire() {
+            self.lock_contended();
+        }
+        LockGuard(self)
+    }
+
+    /// 只试一次（一次 `compare_exchange`），拿不到锁时返回 `None`。
+    pub(crate) fn try_lock(&self) -> Option<LockGuard<'_>> {
+        // 不能用 then_some()：拿不到锁时也会构造出 LockGuard，drop 的时候把别人的锁放开了
+        if self.try_acquire() {
+            Some(LockGuard(self))
+        } else {
+            None
+        }
+    }
+
+    /// 最多自旋 `timeout` 这么久，拿不到锁时返回 `None`。
+    pub(crate) fn try_lock_for(&self, timeout: Duration) -> Option<LockGuard<'_>> {
+        let start = Instant::now();
+        while !self.try_acquire() {
+            if start.elapsed() >= timeout {
+                return None;
+            }
+            std::hint::spin_loop();
+        }
+        Some(LockGuard(self))
+    }
+
+    #[cold]
+    fn lock_contended(&self) {
+        // 只有真的需要自旋时才去读时钟，没有竞争的路径不受影响
+        let start = self.metrics.as_ref().map(|_| Instant::now());
+        let mut spins = 0;
+        loop {
+            // 只读不写，等锁看起来空了再去抢，不会让缓存行来回跳
+            while self.locked.load(Ordering::Relaxed) {
+                spins += 1;
+                if spins < SPIN_LIMIT {
+                    std::hint::spin_loop();
+                } else {
+                    thread::yield_now();
+                }
+            }
+            if self.try_acquire() {
+                break;
+            }
+        }
+        if let (Some(metrics), Some(start)) = (&self.metrics, start) {
+            metrics.record(spins, start.elapsed());
+        }
+    }
+
+    /// 不管谁拿着都把锁放开。只能在 fork 出来的子进程里用：拿着锁的线程没有跟过来，
+    /// 它的 `LockGuard` 永远不会被 drop。
+    pub(crate) fn reset(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+
+    fn unlock(&self) {
+        let was = self.locked.swap(false, Ordering::Release);
+        debug_assert!(was);
+    }
+
+    pub(crate) fn stats(&self) -> Option<LockStats> {
+        self.metrics.as_ref().map(|m| LockStats {
+            contended: m.contended.load(Ordering::Relaxed),
+            spins: m.spins.load(Ordering::Relaxed),
+            wait_histogram: [
+                m.histogram[0].load(Ordering::Relaxed),
+                m.histogram[1].load(Ordering::Relaxed),
+                m.histogram[2].load(Ordering::Relaxed),
+                m.histogram[3].load(Ordering::Relaxed),
+                m.histogram[4].load(Ordering::Relaxed),
+                m.histogram[5].load(Ordering::Relaxed),
+            ],
+        })
+    }
+}
+
+#[derive(Debug)]
+#[repr(transparent)]
+pub(crate) struct LockGuard<'a>(&'a SpinLock);
+
+impl<'a> Drop for LockGuard<'a> {
+    fn drop(&mut self) {
+        self.0.unlock();
+    }
+}
@@ -0,0 +1,222 @@
+// 各个 Unix 之间不一样的地方都放在这里。
+
+/// 当前线程的 errno。
+pub(crate) fn errno() -> libc::c_int {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        *libc::__errno_location()
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    unsafe {
+        *libc::__error()
+    }
+    #[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+    unsafe {
+        *libc::__errno()
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    {
+        std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+    }
+}
+
+/// 设置当前线程的 errno，信号处理函数返回之前要恢复原来的值。
+pub(crate) fn set_errno(errno: libc::c_int) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        *libc::__errno_location() = errno;
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    unsafe {
+        *libc::__error() = errno;
+    }
+    #[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+    unsafe {
+        *libc::__errno() = errno;
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    let _ = errno;
+}
+
+// 只有 Linux 有，别的系统上 Builder::populate 什么都不做
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) const MAP_POPULATE: libc::c_int = libc::MAP_POPULATE;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) const MAP_POPULATE: libc::c_int = 0;
+
+// libc 里没有 Android 的定义，bionic 里是 1
+#[cfg(target_os = "android")]
+pub(crate) const PTHREAD_PROCESS_SHARED: libc::c_int = 1;
+#[cfg(not(target_os = "android"))]
+pub(crate) const PTHREAD_PROCESS_SHARED: libc::c_int = libc::PTHREAD_PROCESS_SHARED;
+
+/// `CLOCK_MONOTONIC` 的读数，见 [`Clock::Monotonic`](crate::format::Clock::Monotonic)。
+pub(crate) fn monotonic() -> std::time::Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // 参数都是对的，不会失败
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    std::time::Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// 当前线程的 id，和 `ps`、`top` 里看到的一样（如果系统有这个概念的话）。
+pub(crate) fn current_tid() -> u64 {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe {
+        libc::gettid() as u64
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    unsafe {
+        let mut tid = 0;
+        libc::pthread_threadid_np(0, &mut tid);
+        tid
+    }
+    // 别的系统只能拿 pthread_t 凑合，进程内唯一
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    )))]
+    unsafe {
+        libc::pthread_self() as usize as u64
+    }
+}
+
+/// 设置 `PTHREAD_MUTEX_ROBUST`，不支持的系统什么都不做，返回 0。
+///
+/// 不支持的话持有进程间锁的进程死掉之后，别的进程会一直等下去。
+pub(crate) unsafe fn set_robust(attr: *mut libc::pthread_mutexattr_t) -> libc::c_int {
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    {
+        libc::pthread_mutexattr_setrobust(attr, libc::PTHREAD_MUTEX_ROBUST)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+    {
+        let _ = attr;
+        0
+    }
+}
+
+/// 给文件的 `[0, len)` 分配磁盘空间，返回错误码。没有 `posix_fallocate` 的系统返回 `EOPNOTSUPP`。
+pub(crate) unsafe fn fallocate(fd: libc::c_int, len: usize) -> libc::c_int {
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    {
+        libc::posix_fallocate(fd, 0, len as libc::off_t)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    {
+        let _ = (fd, len);
+        libc::EOPNOTSUPP
+    }
+}
+
+/// `pthread_mutex_lock` 返回 `EOWNERDEAD` 之后调用，只在支持 robust mutex 的系统上有事可做。
+pub(crate) unsafe fn make_consistent(mutex: *mut libc::pthread_mutex_t) {
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    libc::pthread_mutex_consistent(mutex);
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+    let _ = mutex;
+}
+
+/// 在 `extern "C"` 的回调（信号处理函数、`pthread_atfork` 的回调）里调用 `f`，`f` panic 的话
+/// 直接 `abort()`，不让 panic 展开穿过 C 的栈帧。不用 `catch_unwind`：信号处理函数里只能调用
+/// 异步信号安全的函数，而回调里出了这种意外，进程也没法接着跑了。
+pub(crate) fn abort_on_unwind<R>(f: impl FnOnce() -> R) -> R {
+    // 只有展开时才会被 drop
+    struct Abort;
+
+    impl Drop for Abort {
+        fn drop(&mut self) {
+            unsafe { libc::abort() }
+        }
+    }
+
+    let abort = Abort;
+    let r = f();
+    std::mem::forget(abort);
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use std::thread;
+
+    #[test]
+    fn errno_after_failed_call() {
+        let ret = unsafe { libc::close(-1) };
+        assert_eq!(ret, -1);
+        assert_eq!(errno(), libc::EBADF);
+        let err = unsafe { Error::from_errno("close") };
+        assert!(matches!(
+            err,
+            Error::Io {
+                errno: libc::EBADF,
+                context: "close"
+            }
+        ));
+
+        set_errno(libc::EINTR);
+        assert_eq!(errno(), libc::EINTR);
+        set_errno(0);
+        assert_eq!(errno(), 0);
+    }
+
+    #[test]
+    fn tid_per_thread() {
+        let tid = current_tid();
+        assert_ne!(tid, 0);
+        assert_eq!(current_tid(), tid);
+        let others: Vec<_> = (0..4)
+            .map(|_| thread::spawn(current_tid))
+            .map(|t| t.join().unwrap())
+            .collect();
+        assert!(others.iter().all(|&t| t != 0 && t != tid), "{:?}", others);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        assert_eq!(tid, unsafe { libc::syscall(libc::SYS_gettid) } as u64);
+    }
+
+    #[test]
+    fn abort_instead_of_unwinding() {
+        assert_eq!(abort_on_unwind(|| 42), 42);
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            // 子进程里不用打印 panic 的消息
+            std::panic::set_hook(Box::new(|_| {}));
+            abort_on_unwind(|| panic!("in a handler"));
+            unsafe { libc::_exit(0) };
+        }
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        assert!(libc::WIFSIGNALED(status), "{:#x}", status);
+        assert_eq!(libc::WTERMSIG(status), libc::SIGABRT);
+    }
+
+    #[test]
+    fn monotonic_goes_forward() {
+        let before = monotonic();
+        thread::sleep(std::time::Duration::from_millis(1));
+        assert!(monotonic() > before);
+    }
+}
@@ -0,0 +1,185 @@
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+// SpaceSaving 草图的槽位数，只保证 top-K（K <= SLOTS）近似准确
+const SLOTS: usize = 16;
+const NAME_LEN: usize = 48;
+
+/// 取 target 的顶层部分，例如 `hyper::proto::h1` -> `hyper`。
+pub(crate) fn top_level(target: &str) -> &str {
+    match target.find("::") {
+        Some(i) => &target[..i],
+        None => target,
+    }
+}
+
+/// `target` 是否属于模块 `prefix`，例如 `myapp` 包括 `myapp::db`，但不包括 `myapplication`。
+pub(crate) fn in_module(prefix: &str, target: &str) -> bool {
+    match target.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    used: bool,
+    name: [u8; NAME_LEN],
+    len: usize,
+    bytes: u64,
+}
+
+impl Slot {
+    const EMPTY: Slot = Slot {
+        used: false,
+        name: [0; NAME_LEN],
+        len: 0,
+        bytes: 0,
+    };
+
+    fn name(&self) -> &[u8] {
+        &self.name[..self.len]
+    }
+
+    fn assign(&mut self, name: &str, bytes: u64) {
+        self.used = true;
+        self.name[..name.len()].copy_from_slice(name.as_bytes());
+        self.len = name.len();
+        self.bytes = bytes;
+    }
+}
+
+#[derive(Debug)]
+struct Quota {
+    name: String,
+    limit: u64,
+    used: AtomicU64,
+    generation: AtomicUsize,
+    marked: AtomicBool,
+}
+
+pub(crate) enum Admit {
+    Write,
+    Drop,
+    // 第一次超额，需要写一条标记记录
+    Exceeded { target: String, limit: u64 },
+}
+
+#[derive(Debug)]
+pub(crate) struct Targets {
+    slots: Option<UnsafeCell<[Slot; SLOTS]>>,
+    quotas: Vec<Quota>,
+    dropped: AtomicU64,
+}
+
+impl Targets {
+    pub(crate) fn new(track: bool, quotas: &[(String, u64)]) -> Option<Targets> {
+        if !track && quotas.is_empty() {
+            return None;
+        }
+        Some(Targets {
+            slots: if track {
+                Some(UnsafeCell::new([Slot::EMPTY; SLOTS]))
+            } else {
+                None
+            },
+            quotas: quotas
+                .iter()
+                .map(|(name, limit)| Quota {
+                    name: name.clone(),
+                    limit: *limit,
+                    used: AtomicU64::new(0),
+                    generation: AtomicUsize::new(0),
+                    marked: AtomicBool::new(false),
+                })
+                .collect(),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// 统计一条即将写入的记录，返回是否允许写入。
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须持有 Logger 的写锁。
+    pub(crate) unsafe fn admit(&self, target: &str, bytes: usize, generation: usize) -> Admit {
+        let top = top_level(target);
+        let bytes = bytes as u64;
+
+        // quotas 按长度从长到短排好了，见 Builder::target_quota
+        if let Some(quota) = self.quotas.iter().find(|q| in_module(&q.name, target)) {
+            // 环形缓冲区翻转一次就是新的一代，配额重新计算
+            if quota.generation.load(Ordering::Relaxed) != generation {
+                quota.generation.store(generation, Ordering::Relaxed);
+                quota.used.store(0, Ordering::Relaxed);
+                quota.marked.store(false, Ordering::Relaxed);
+            }
+            let used = quota.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+            if used > quota.limit {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                if quota.marked.swap(true, Ordering::Relaxed) {
+                    return Admit::Drop;
+                }
+                return Admit::Exceeded {
+                    target: quota.name.clone(),
+                    limit: quota.limit,
+                };
+            }
+        }
+
+        if let Some(slots) = &self.slots {
+            Self::account(&mut *slots.get(), top, bytes);
+        }
+        Admit::Write
+    }
+
+    fn account(slots: &mut [Slot; SLOTS], name: &str, bytes: u64) {
+        // 超长的名字直接截断，只影响展示
+        let mut len = cmp::min(name.len(), NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let name = &name[..len];
+
+        let mut min = 0;
+        for i in 0..SLOTS {
+            if !slots[i].used {
+                slots[i].assign(name, bytes);
+                return;
+            }
+            if slots[i].name() == name.as_bytes() {
+                slots[i].bytes += bytes;
+                return;
+            }
+            if slots[i].bytes < slots[min].bytes {
+                min = i;
+            }
+        }
+        // SpaceSaving：替换最小的槽位，计数继承（偏大估计）
+        let floor = slots[min].bytes;
+        slots[min].assign(name, floor + bytes);
+    }
+
+    /// # Safety
+    ///
+    /// 调用者必须持有 Logger 的写锁。
+    pub(crate) unsafe fn top(&self, k: usize) -> Vec<(String, u64)> {
+        let slots = match &self.slots {
+            Some(slots) => *slots.get(),
+            None => return Vec::new(),
+        };
+        let mut top: Vec<(String, u64)> = slots
+            .iter()
+            .filter(|s| s.used)
+            .map(|s| (String::from_utf8_lossy(s.name()).into_owned(), s.bytes))
+            .collect();
+        top.sort_by_key(|t| std::cmp::Reverse(t.1));
+        top.truncate(k);
+        top
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
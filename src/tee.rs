@@ -0,0 +1,164 @@
+use log::{Log, Record};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+// Builder::tee_stderr、Builder::logcat 和 Builder::also_log：写入缓冲区之后再把记录转给别的地方。
+// 总是在释放写锁之后调用，慢的或者 panic 的 sink 不会影响缓冲区。
+#[derive(Debug)]
+pub(crate) struct Tee {
+    stderr: bool,
+    // 只在 Android 上为真
+    #[cfg_attr(not(target_os = "android"), allow(dead_code))]
+    logcat: bool,
+    sinks: Vec<Sink>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Sink(pub(crate) Arc<dyn Log>);
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sink(..)")
+    }
+}
+
+impl Tee {
+    pub(crate) fn new(stderr: bool, logcat: bool, sinks: &[Sink]) -> Option<Tee> {
+        let logcat = logcat && cfg!(target_os = "android");
+        if !stderr && !logcat && sinks.is_empty() {
+            return None;
+        }
+        Some(Tee {
+            stderr,
+            logcat,
+            sinks: sinks.to_vec(),
+        })
+    }
+
+    /// `formatted` 是写进缓冲区的那一行（含换行），`stderr` 为真时一定有。
+    pub(crate) fn forward(&self, record: &Record, formatted: Option<&str>) {
+        if let (true, Some(line)) = (self.stderr, formatted) {
+            // 终端关了之类的，没什么能做的
+            let _ = io::stderr().lock().write_all(line.as_bytes());
+        }
+        #[cfg(target_os = "android")]
+        if self.logcat {
+            logcat::write(record);
+        }
+        for Sink(sink) in &self.sinks {
+            if sink.enabled(record.metadata()) {
+                sink.log(record);
+            }
+        }
+    }
+
+    pub(crate) fn flush(&self) {
+        for Sink(sink) in &self.sinks {
+            sink.flush();
+        }
+    }
+}
+
+// 级别和 tag 的换算在别的平台上也编译，好在测试里检查
+#[cfg(any(target_os = "android", test))]
+mod logcat {
+    use log::Level;
+    #[cfg(target_os = "android")]
+    use log::Record;
+    #[cfg(target_os = "android")]
+    use std::ffi::c_char;
+    use std::ffi::{c_int, CString};
+
+    // android/log.h
+    pub(super) const ANDROID_LOG_VERBOSE: c_int = 2;
+    pub(super) const ANDROID_LOG_DEBUG: c_int = 3;
+    pub(super) const ANDROID_LOG_INFO: c_int = 4;
+    pub(super) const ANDROID_LOG_WARN: c_int = 5;
+    pub(super) const ANDROID_LOG_ERROR: c_int = 6;
+
+    // 老版本的 Android 不接受更长的 tag
+    pub(super) const MAX_TAG: usize = 23;
+
+    #[cfg(target_os = "android")]
+    #[link(name = "log")]
+    extern "C" {
+        fn __android_log_write(prio: c_int, tag: *const c_char, text: *const c_char) -> c_int;
+    }
+
+    /// logcat 自己有时间戳和级别，只写消息本身，target 作为 tag。
+    #[cfg(target_os = "android")]
+    pub(super) fn write(record: &Record) {
+        let tag = c_string(tag(record.target()).to_string());
+        let text = c_string(record.args().to_string());
+        unsafe { __android_log_write(priority(record.level()), tag.as_ptr(), text.as_ptr()) };
+    }
+
+    pub(super) fn priority(level: Level) -> c_int {
+        match level {
+            Level::Error => ANDROID_LOG_ERROR,
+            Level::Warn => ANDROID_LOG_WARN,
+            Level::Info => ANDROID_LOG_INFO,
+            Level::Debug => ANDROID_LOG_DEBUG,
+            Level::Trace => ANDROID_LOG_VERBOSE,
+        }
+    }
+
+    // 最多 MAX_TAG 个字节，不切开多字节的字符
+    pub(super) fn tag(target: &str) -> &str {
+        if target.len() <= MAX_TAG {
+            return target;
+        }
+        let mut end = MAX_TAG;
+        while !target.is_char_boundary(end) {
+            end -= 1;
+        }
+        &target[..end]
+    }
+
+    // 中间的 \0 会让 logcat 截断，直接去掉
+    pub(super) fn c_string(mut s: String) -> CString {
+        s.retain(|c| c != '\0');
+        CString::new(s).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::logcat::*;
+    use super::Tee;
+    use log::Level;
+
+    #[test]
+    fn logcat_elsewhere_is_a_noop() {
+        let tee = Tee::new(false, true, &[]);
+        assert_eq!(tee.is_some(), cfg!(target_os = "android"));
+    }
+
+    #[test]
+    fn priorities() {
+        assert_eq!(priority(Level::Error), ANDROID_LOG_ERROR);
+        assert_eq!(priority(Level::Warn), ANDROID_LOG_WARN);
+        assert_eq!(priority(Level::Info), ANDROID_LOG_INFO);
+        assert_eq!(priority(Level::Debug), ANDROID_LOG_DEBUG);
+        assert_eq!(priority(Level::Trace), ANDROID_LOG_VERBOSE);
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(tag("app::net"), "app::net");
+        let long = "a".repeat(MAX_TAG);
+        assert_eq!(tag(&long), long);
+        assert_eq!(tag(&format!("{}b", long)), long);
+        // 第 23 个字节落在“网”的中间，整个字去掉
+        let target = format!("{}网络", "a".repeat(MAX_TAG - 2));
+        assert_eq!(tag(&target), "a".repeat(MAX_TAG - 2));
+        assert_eq!(tag(""), "");
+    }
+
+    #[test]
+    fn nul_bytes_are_removed() {
+        assert_eq!(c_string("a\0b\0".to_string()).as_bytes(), b"ab");
+        assert_eq!(c_string(String::new()).as_bytes(), b"");
+    }
+}
@@ -0,0 +1,312 @@
+//! 在单元测试里检查代码写了哪些日志，见 [`Capture`]；固定时间戳和线程 id，见 `freeze`。
+//!
+//! `freeze`，以及 `fail_syncs` 这样让操作失败的钩子，要打开 `test-util` feature，只在测试里用：
+//!
+//! ```toml
+//! [dev-dependencies]
+//! mmlog = { version = "0.1", features = ["test-util"] }
+//! ```
+
+use crate::parse::Entry;
+use crate::{Builder, Framing, Logger};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+#[cfg(feature = "test-util")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock};
+#[cfg(feature = "test-util")]
+use std::time::Duration;
+
+// freeze() 设置的时间戳（纳秒）和线程 id，u64::MAX 表示没有设置
+#[cfg(feature = "test-util")]
+static FROZEN_NOW: AtomicU64 = AtomicU64::new(u64::MAX);
+#[cfg(feature = "test-util")]
+static FROZEN_TID: AtomicU64 = AtomicU64::new(u64::MAX);
+// fail_syncs() 设置的、接下来还要失败的同步次数
+#[cfg(feature = "test-util")]
+static FAILING_SYNCS: AtomicU64 = AtomicU64::new(0);
+// fail_spawns() 设置的、接下来还要失败的后台线程启动次数
+#[cfg(feature = "test-util")]
+static FAILING_SPAWNS: AtomicU64 = AtomicU64::new(0);
+
+// 全局 logger 只能装一次：第一次 Capture::start 时装上 DISPATCH，之后只换里面的 logger
+static DISPATCH: Dispatch = Dispatch {
+    active: RwLock::new(None),
+};
+static INSTALLED: OnceLock<bool> = OnceLock::new();
+// 同一时间只能有一个 CaptureGuard
+static SERIAL: Mutex<()> = Mutex::new(());
+
+struct Dispatch {
+    active: RwLock<Option<Arc<Logger>>>,
+}
+
+impl Dispatch {
+    fn active(&self) -> Option<Arc<Logger>> {
+        self.active
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn set(&self, logger: Option<Arc<Logger>>) {
+        *self.active.write().unwrap_or_else(PoisonError::into_inner) = logger;
+    }
+}
+
+impl Log for Dispatch {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.active().is_some_and(|logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        // 不拿着读锁写，记录的参数里可能又有日志
+        if let Some(logger) = self.active() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// 把全局 logger 换成一个匿名映射（见 [`Builder::build_anonymous`]）的 [`Logger`]，
+/// 拿到写进去的记录，用来在测试里检查代码写了哪些日志。
+///
+/// `log` 的全局 logger 只能装一次，所以第一次 [`Capture::start`] 时装上一个转发用的 logger，
+/// 之后每次 `start` 只是换掉它后面的 `Logger`。已经用 [`Builder::init`] 等装过别的 logger 的
+/// 进程里不能用，`start` 会 panic。
+///
+/// [`CaptureGuard`] 拿着一把进程内的锁，`cargo test` 并行运行的几个测试里的 `start`
+/// 会依次等待，不需要 `--test-threads=1`。但记录是按全局 logger 收的，
+/// 同时在跑的、没有用 `Capture` 的测试写的日志也会收进来。
+pub enum Capture {}
+
+impl Capture {
+    /// 开始收集 `level` 和更严重的记录，同时把 [`log::max_level`] 设成 `level`。
+    /// 返回的 [`CaptureGuard`] drop 时停止收集，恢复原来的 `max_level`。
+    ///
+    /// ```
+    /// use log::Level;
+    /// use mmlog::assert_logged;
+    /// use mmlog::test::Capture;
+    ///
+    /// fn connect(retries: u32) {
+    ///     for i in 0..retries {
+    ///         log::debug!("attempt {}", i);
+    ///     }
+    ///     log::warn!("connect: timeout after {} retries", retries);
+    /// }
+    ///
+    /// let guard = Capture::start(Level::Info);
+    /// connect(3);
+    /// assert_logged!(guard, Level::Warn, contains "timeout");
+    /// let records = guard.records();
+    /// assert_eq!(records.len(), 1);
+    /// assert_eq!(records[0].message, "connect: timeout after 3 retries");
+    /// drop(guard);
+    ///
+    /// // 每次 start 都是空的
+    /// let guard = Capture::start(Level::Debug);
+    /// connect(2);
+    /// assert_logged!(guard, Level::Debug, contains "attempt 1");
+    /// assert_eq!(guard.records().len(), 3);
+    /// drop(guard);
+    /// assert_eq!(log::max_level(), log::LevelFilter::Off);
+    ///
+    /// let result = std::panic::catch_unwind(|| {
+    ///     let guard = Capture::start(Level::Info);
+    ///     connect(1);
+    ///     assert_logged!(guard, Level::Error, contains "timeout");
+    /// });
+    /// assert!(result.is_err());
+    /// ```
+    pub fn start(level: Level) -> CaptureGuard {
+        // 别的测试在拿着锁的时候 panic 了也没关系，drop 已经恢复了状态
+        let serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+        let installed = *INSTALLED.get_or_init(|| log::set_logger(&DISPATCH).is_ok());
+        assert!(
+            installed,
+            "mmlog::test::Capture: another global logger is already installed"
+        );
+        let logger = Builder::new()
+            .level(level)
+            // 多行的消息也是一条记录
+            .framing(Framing::LengthPrefixed)
+            .build_anonymous()
+            .expect("anonymous mapping for mmlog::test::Capture");
+        let logger = Arc::new(logger);
+        DISPATCH.set(Some(logger.clone()));
+        let max_level = log::max_level();
+        log::set_max_level(level.to_level_filter());
+        crate::invalidate_callsites();
+        CaptureGuard {
+            logger,
+            max_level,
+            _serial: serial,
+        }
+    }
+}
+
+/// [`Capture::start`] 返回的 guard，drop 时停止收集。
+pub struct CaptureGuard {
+    logger: Arc<Logger>,
+    // start 之前的 log::max_level()
+    max_level: LevelFilter,
+    // 最后释放
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl CaptureGuard {
+    /// 到现在为止收到的记录，从旧到新。缓冲区满了的话最旧的记录会被覆盖。
+    pub fn records(&self) -> Vec<Entry> {
+        self.logger
+            .tail(usize::MAX)
+            .iter()
+            .filter_map(|record| Entry::parse(record))
+            .collect()
+    }
+
+    /// 有没有级别是 `level`、消息里包含 `text` 的记录，见 [`assert_logged!`](crate::assert_logged)。
+    pub fn logged(&self, level: Level, text: &str) -> bool {
+        self.records()
+            .iter()
+            .any(|entry| entry.level == level && entry.message.contains(text))
+    }
+}
+
+impl Drop for CaptureGuard {
+    fn drop(&mut self) {
+        DISPATCH.set(None);
+        log::set_max_level(self.max_level);
+        crate::invalidate_callsites();
+    }
+}
+
+/// 检查 [`CaptureGuard`] 收到了级别是 `level`、消息里包含 `text` 的记录，
+/// 没有的话 panic，列出收到的所有记录。见 [`Capture::start`]。
+///
+/// ```text
+/// assert_logged!(guard, Level::Warn, contains "timeout");
+/// ```
+#[macro_export]
+macro_rules! assert_logged {
+    ($guard:expr, $level:expr, contains $text:expr $(,)?) => {{
+        let guard: &$crate::test::CaptureGuard = &$guard;
+        let level: ::log::Level = $level;
+        let text: &str = ::std::convert::AsRef::<str>::as_ref(&$text);
+        if !guard.logged(level, text) {
+            let records: ::std::vec::Vec<_> = guard
+                .records()
+                .into_iter()
+                .map(|entry| ::std::format!("{} {}", entry.level, entry.message))
+                .collect();
+            ::std::panic!(
+                "no {} record containing {:?}, captured: {:#?}",
+                level,
+                text,
+                records
+            );
+        }
+    }};
+}
+
+/// 之后这个进程里所有记录的时间戳都是 `now`（按 [`Builder::clock`] 的时钟算），
+/// 线程 id 都是 `tid`，直到 [`thaw`]。
+///
+/// 用来生成逐字节相同的文件，比如检查文件格式有没有变化的测试（见 `tests/golden.rs`）。
+/// 影响整个进程里的所有 [`Logger`]，不要在和别的测试共用的进程里用。只有打开了
+/// `test-util` feature 才有。
+///
+/// ```
+/// use log::Log;
+/// use std::time::Duration;
+///
+/// mmlog::test::freeze(Duration::new(1700000000, 5), 42);
+/// let logger = mmlog::Builder::new().build_anonymous().unwrap();
+/// logger.log(&log::Record::builder().level(log::Level::Info).args(format_args!("hi")).build());
+/// mmlog::test::thaw();
+/// assert_eq!(logger.tail(1), ["[1700000000.000000005s 42 I  ] hi"]);
+/// ```
+#[cfg(feature = "test-util")]
+pub fn freeze(now: Duration, tid: u64) {
+    FROZEN_NOW.store(now.as_nanos() as u64, Ordering::Relaxed);
+    FROZEN_TID.store(tid, Ordering::Relaxed);
+}
+
+/// 取消 [`freeze`]。
+#[cfg(feature = "test-util")]
+pub fn thaw() {
+    FROZEN_NOW.store(u64::MAX, Ordering::Relaxed);
+    FROZEN_TID.store(u64::MAX, Ordering::Relaxed);
+}
+
+/// 之后这个进程里的 `n` 次同步（[`Logger::flush`](log::Log::flush)、
+/// [`Builder::flush_interval`](crate::Builder::flush_interval) 的后台线程等）都返回
+/// `ErrorKind::Other` 的 I/O 错误，数据不写回文件，用来测试同步出错时的处理。
+///
+/// 和 [`freeze`] 一样影响整个进程，不要在和别的测试共用的进程里用，也只有打开了
+/// `test-util` feature 才有。
+///
+/// ```
+/// let path = std::env::temp_dir().join("mmlog-fail-syncs.log");
+/// let logger = mmlog::Builder::new().build(&path).unwrap();
+/// mmlog::test::fail_syncs(1);
+/// assert!(logger.try_flush().is_err());
+/// assert!(logger.try_flush().is_ok());
+/// ```
+#[cfg(feature = "test-util")]
+pub fn fail_syncs(n: u64) {
+    FAILING_SYNCS.store(n, Ordering::Relaxed);
+}
+
+/// 之后这个进程里 `n` 次启动 [`Builder::flush_interval`](crate::Builder::flush_interval)
+/// 的后台线程都失败，`build` 返回 [`Error::StdIo`](crate::Error::StdIo)，用来测试启动失败时的清理。
+///
+/// 和 [`freeze`] 一样影响整个进程，不要在和别的测试共用的进程里用，也只有打开了
+/// `test-util` feature 才有。
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let builder = || mmlog::Builder::new().flush_interval(Duration::from_millis(10));
+/// mmlog::test::fail_spawns(1);
+/// assert!(builder().build_anonymous().is_err());
+/// assert!(builder().build_anonymous().is_ok());
+/// ```
+#[cfg(feature = "test-util")]
+pub fn fail_spawns(n: u64) {
+    FAILING_SPAWNS.store(n, Ordering::Relaxed);
+}
+
+// 还有要失败的同步的话返回一个错误
+#[cfg(feature = "test-util")]
+pub(crate) fn failed_sync() -> Option<std::io::Error> {
+    FAILING_SYNCS
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+        .ok()
+        .map(|_| std::io::Error::other("sync failed (mmlog::test::fail_syncs)"))
+}
+
+// 还有要失败的线程启动的话返回一个错误
+#[cfg(feature = "test-util")]
+pub(crate) fn failed_spawn() -> Option<std::io::Error> {
+    FAILING_SPAWNS
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+        .ok()
+        .map(|_| std::io::Error::other("spawn failed (mmlog::test::fail_spawns)"))
+}
+
+#[cfg(feature = "test-util")]
+pub(crate) fn frozen_now() -> Option<Duration> {
+    match FROZEN_NOW.load(Ordering::Relaxed) {
+        u64::MAX => None,
+        nanos => Some(Duration::from_nanos(nanos)),
+    }
+}
+
+#[cfg(feature = "test-util")]
+pub(crate) fn frozen_tid() -> Option<u64> {
+    match FROZEN_TID.load(Ordering::Relaxed) {
+        u64::MAX => None,
+        tid => Some(tid),
+    }
+}
@@ -0,0 +1,156 @@
+//! 把 `tracing` 的事件写进同一个环形缓冲区。
+//!
+//! [`MmapLayer`] 把每个事件转换成一条 `log` 记录交给 [`Logger`]，所以级别、
+//! 模块过滤、格式和分隔方式都沿用 [`Builder`](crate::Builder) 的设置。
+//! 当前所在的 span 按从外到内的顺序写在消息前面：
+//!
+//! ```text
+//! [<时间戳> <tid> <level> <file>:<line> <target>] outer{a=1}:inner{b=2}: <message> k=v
+//! ```
+
+use crate::Logger;
+use std::fmt::{self, Write as _};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// 写入 [`Logger`] 的 `tracing_subscriber::Layer`。
+///
+/// ```
+/// use mmlog::tracing::MmapLayer;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let path = std::env::temp_dir().join("mmlog-tracing-doc.log");
+/// let logger = mmlog::Builder::new().build(&path).unwrap();
+/// let subscriber = tracing_subscriber::registry().with(MmapLayer::new(logger));
+/// tracing::subscriber::with_default(subscriber, || {
+///     let span = tracing::info_span!("request", id = 7);
+///     let _enter = span.enter();
+///     tracing::info!(user = "alice", "hello");
+/// });
+///
+/// let reader = mmlog::reader::LogReader::open(&path).unwrap();
+/// let record = reader.records().next().unwrap();
+/// assert!(record.ends_with("] request{id=7}: hello user=\"alice\""));
+/// ```
+#[derive(Debug)]
+pub struct MmapLayer {
+    logger: Logger,
+}
+
+impl MmapLayer {
+    pub fn new(logger: Logger) -> MmapLayer {
+        MmapLayer { logger }
+    }
+
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+}
+
+// 格式化好的 span 字段，存在 span 的 extensions 里
+struct SpanFields(String);
+
+impl<S> Layer<S> for MmapLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(SpanFields(fields.rest));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::default();
+            values.record(&mut fields);
+            let mut extensions = span.extensions_mut();
+            match extensions.get_mut::<SpanFields>() {
+                Some(SpanFields(old)) => old.push_str(&fields.rest),
+                None => extensions.insert(SpanFields(fields.rest)),
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let level = log_level(*meta.level());
+        let log_meta = log::Metadata::builder()
+            .level(level)
+            .target(meta.target())
+            .build();
+        if !log::Log::enabled(&self.logger, &log_meta) {
+            return;
+        }
+
+        let mut msg = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                msg.push_str(span.name());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    if !fields.is_empty() {
+                        // 去掉开头的空格
+                        let _ = write!(msg, "{{{}}}", &fields[1..]);
+                    }
+                }
+                msg.push(':');
+            }
+            msg.push(' ');
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        msg.push_str(&fields.message);
+        msg.push_str(&fields.rest);
+
+        log::Log::log(
+            &self.logger,
+            &log::Record::builder()
+                .metadata(log_meta)
+                .file(meta.file())
+                .line(meta.line())
+                .module_path(meta.module_path())
+                .args(format_args!("{}", msg))
+                .build(),
+        );
+    }
+}
+
+fn log_level(level: Level) -> log::Level {
+    match level {
+        Level::ERROR => log::Level::Error,
+        Level::WARN => log::Level::Warn,
+        Level::INFO => log::Level::Info,
+        Level::DEBUG => log::Level::Debug,
+        Level::TRACE => log::Level::Trace,
+    }
+}
+
+// `message` 字段单独放，其他字段写成 ` k=v`
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.rest, " {}={:?}", field.name(), value);
+        }
+    }
+}
@@ -0,0 +1,526 @@
+//! `mmlog-cat --tui` 的交互式界面，基于 [`LogReader`]，需要 `tui` feature。
+//!
+//! 上面是记录列表，每条记录占一行（只显示第一行），按级别着色；中间是选中的记录解析出来的字段和
+//! 原始字节；最下面一行是过滤条件、输入框和提示。按键：
+//!
+//! ```text
+//! ↑ ↓ / k j         上一条、下一条
+//! PgUp PgDn         翻页
+//! g Home / G End    第一条、最后一条
+//! 1 2 3 4 5         打开、关掉 ERROR、WARN、INFO、DEBUG、TRACE
+//! t                 只显示 target 以输入的前缀开头的记录
+//! / n N             边输入边向后查找子串，下一个、上一个
+//! @                 跳到第一条不早于输入的时间的记录，时间的写法见 format::parse_timestamp
+//! f                 跟随文件末尾，有新记录时重新打开文件并选中最后一条
+//! q Esc Ctrl-C      退出
+//! ```
+//!
+//! 输入框里 Enter 确认，Esc 清空并关掉输入框。解析不了的记录（续行、自定义格式）
+//! 和前一条记录一起显示或者隐藏。记录直接借用 [`LogReader`] 映射的内存，
+//! 级别和 target 在第一次过滤时才解析，画的时候只格式化看得见的那些行，
+//! 所以几十万条记录的文件也能马上打开。
+
+use crate::fmt::hex_dump;
+use crate::format::parse_timestamp;
+use crate::parse::Entry;
+use crate::reader::LogReader;
+use crate::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use log::Level;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+// 等按键的间隔，跟随时每隔这么久看一次有没有新记录
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+const LEVELS: [Level; 5] = [
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+    Level::Trace,
+];
+
+// 详情里最多显示消息的几行、原始字节的几行
+const MESSAGE_LINES: usize = 3;
+const HEX_LINES: usize = 6;
+// 三行字段、消息、原始字节的标题和内容，显示不下的各有一行 `…`，再加上边框
+const DETAILS_HEIGHT: u16 = (3 + MESSAGE_LINES + 1 + 1 + HEX_LINES + 1 + 2) as u16;
+
+/// 在终端里打开 `path`，直到按 `q` 退出。
+///
+/// `open` 打开文件，跟随时有新记录的话会再调用；`parse` 把一条记录解析成 [`Entry`]，
+/// 解析不了的返回 `None`，例如 [`Entry::parse`]。
+/// 终端在返回之前恢复原样，panic 的时候也是。
+pub fn run(
+    path: &Path,
+    open: impl Fn(&Path) -> Result<LogReader>,
+    parse: fn(&str) -> Option<Entry>,
+) -> Result<()> {
+    let mut reader = open(path)?;
+    let mut terminal = ratatui::try_init()?;
+    let mut state = State::new(parse);
+    let result = loop {
+        let written = reader.stats().records_written;
+        let mut viewer = Viewer::new(&reader, state);
+        // 打不开的话（例如正在轮转）下次再试
+        let next = watch(&mut terminal, &mut viewer, || {
+            open(path)
+                .ok()
+                .filter(|next| next.stats().records_written != written)
+        });
+        state = viewer.into_state();
+        match next {
+            Ok(Some(next)) => reader = next,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    ratatui::restore();
+    result
+}
+
+// 处理按键直到退出（返回 None），或者跟随时 reload 打开了有新记录的文件
+fn watch(
+    terminal: &mut DefaultTerminal,
+    viewer: &mut Viewer,
+    reload: impl Fn() -> Option<LogReader>,
+) -> Result<Option<LogReader>> {
+    loop {
+        terminal.draw(|frame| viewer.draw(frame))?;
+        if event::poll(POLL_INTERVAL)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && viewer.key(key) == Action::Quit {
+                    return Ok(None);
+                }
+            }
+        } else if viewer.following() {
+            if let Some(next) = reload() {
+                return Ok(Some(next));
+            }
+        }
+    }
+}
+
+/// [`Viewer::key`] 之后要做什么。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    Quit,
+}
+
+// 正在输入什么
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Input {
+    Target,
+    Search,
+    Time,
+}
+
+/// 重新打开文件时要留下来的东西：过滤条件、查找的内容、是否跟随和选中的记录。
+pub struct State {
+    parse: fn(&str) -> Option<Entry>,
+    // 按 LEVELS 的顺序
+    levels: [bool; 5],
+    target: String,
+    search: String,
+    time: String,
+    input: Option<Input>,
+    follow: bool,
+    // 选中的是 LogReader::records 里的第几条
+    selected: Option<usize>,
+    status: String,
+}
+
+impl State {
+    /// 所有级别都显示，没有过滤条件，选中第一条。
+    pub fn new(parse: fn(&str) -> Option<Entry>) -> State {
+        State {
+            parse,
+            levels: [true; 5],
+            target: String::new(),
+            search: String::new(),
+            time: String::new(),
+            input: None,
+            follow: false,
+            selected: None,
+            status: String::new(),
+        }
+    }
+}
+
+/// 一个打开的文件的界面，[`run`] 用它处理按键和画界面。
+pub struct Viewer<'a> {
+    reader: &'a LogReader,
+    records: Vec<&'a str>,
+    // 每条记录的级别和 target，第一次过滤时才解析
+    prefixes: Option<Vec<Option<(Level, String)>>>,
+    // 显示出来的记录是 records 里的第几条
+    visible: Vec<usize>,
+    // visible 里的下标
+    selected: usize,
+    top: usize,
+    // 上次画的时候列表有几行
+    height: usize,
+    // 开始查找时选中的位置，边输入边从这里往后找
+    search_start: usize,
+    state: State,
+}
+
+impl<'a> Viewer<'a> {
+    /// 跟随的话选中最后一条，不然选中 `state` 里的那条。
+    pub fn new(reader: &'a LogReader, state: State) -> Viewer<'a> {
+        let records: Vec<&str> = reader.records().collect();
+        let mut viewer = Viewer {
+            reader,
+            records,
+            visible: Vec::new(),
+            prefixes: None,
+            selected: 0,
+            top: 0,
+            height: 1,
+            search_start: 0,
+            state,
+        };
+        viewer.filter();
+        if viewer.state.follow {
+            viewer.selected = viewer.visible.len().saturating_sub(1);
+        }
+        viewer
+    }
+
+    /// 文件里有几条记录，包括被过滤掉的。
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// 文件里没有记录。
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// 选中的记录，没有记录显示出来的话是 `None`。
+    pub fn selected_record(&self) -> Option<&'a str> {
+        self.visible.get(self.selected).map(|&i| self.records[i])
+    }
+
+    /// 是否在跟随文件末尾，见 `f`。
+    pub fn following(&self) -> bool {
+        self.state.follow
+    }
+
+    /// 重新打开文件时交给下一个 [`Viewer::new`]。
+    pub fn into_state(mut self) -> State {
+        self.state.selected = self.visible.get(self.selected).copied();
+        self.state
+    }
+
+    /// 处理一次按键。
+    pub fn key(&mut self, key: KeyEvent) -> Action {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        self.state.status.clear();
+        if let Some(input) = self.state.input {
+            self.input(input, key.code);
+            return Action::Continue;
+        }
+        let last = self.visible.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            KeyCode::Up | KeyCode::Char('k') => self.go(self.selected.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.go(self.selected + 1),
+            KeyCode::PageUp => self.go(self.selected.saturating_sub(self.height)),
+            KeyCode::PageDown => self.go(self.selected + self.height),
+            KeyCode::Home | KeyCode::Char('g') => self.go(0),
+            KeyCode::End | KeyCode::Char('G') => self.go(last),
+            KeyCode::Char(c @ '1'..='5') => {
+                let i = c as usize - '1' as usize;
+                self.state.levels[i] = !self.state.levels[i];
+                self.filter();
+            }
+            KeyCode::Char('t') => self.state.input = Some(Input::Target),
+            KeyCode::Char('/') => {
+                self.state.input = Some(Input::Search);
+                self.state.search.clear();
+                self.search_start = self.selected;
+            }
+            KeyCode::Char('n') => self.find(true),
+            KeyCode::Char('N') => self.find(false),
+            KeyCode::Char('@') => {
+                self.state.input = Some(Input::Time);
+                self.state.time.clear();
+            }
+            KeyCode::Char('f') => {
+                self.state.follow = !self.state.follow;
+                if self.state.follow {
+                    self.selected = last;
+                }
+            }
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    // 手动移动就不再跟随
+    fn go(&mut self, selected: usize) {
+        self.selected = selected.min(self.visible.len().saturating_sub(1));
+        self.state.follow = false;
+    }
+
+    fn input(&mut self, input: Input, code: KeyCode) {
+        let text = match input {
+            Input::Target => &mut self.state.target,
+            Input::Search => &mut self.state.search,
+            Input::Time => &mut self.state.time,
+        };
+        let confirmed = match code {
+            KeyCode::Enter => true,
+            KeyCode::Esc => {
+                text.clear();
+                true
+            }
+            KeyCode::Backspace => {
+                text.pop();
+                false
+            }
+            KeyCode::Char(c) => {
+                text.push(c);
+                false
+            }
+            _ => return,
+        };
+        if confirmed {
+            self.state.input = None;
+        }
+        match input {
+            Input::Target => self.filter(),
+            Input::Search if self.state.search.is_empty() => self.selected = self.search_start,
+            Input::Search => self.find_from(self.search_start, true),
+            Input::Time if code == KeyCode::Enter => self.seek(),
+            Input::Time => {}
+        }
+    }
+
+    // 从 start 开始（包括 start）找下一个或者上一个包含 search 的记录，找到头了从另一头接着找
+    fn find_from(&mut self, start: usize, forward: bool) {
+        let n = self.visible.len();
+        if self.state.search.is_empty() || n == 0 {
+            return;
+        }
+        let found = (0..n)
+            .map(|k| {
+                if forward {
+                    (start + k) % n
+                } else {
+                    (start + n - k % n) % n
+                }
+            })
+            .find(|&i| self.records[self.visible[i]].contains(&self.state.search));
+        match found {
+            Some(i) => self.go(i),
+            None => self.state.status = format!("no match for {:?}", self.state.search),
+        }
+    }
+
+    fn find(&mut self, forward: bool) {
+        let n = self.visible.len().max(1);
+        let start = if forward {
+            (self.selected + 1) % n
+        } else {
+            (self.selected + n - 1) % n
+        };
+        self.find_from(start, forward);
+    }
+
+    fn seek(&mut self) {
+        let Some(at) = parse_timestamp(&self.state.time) else {
+            self.state.status = format!("bad time: {}", self.state.time);
+            return;
+        };
+        match self.reader.seek_time(UNIX_EPOCH + at) {
+            Some(record) => {
+                let i = self.visible.partition_point(|&i| i < record);
+                self.go(i);
+            }
+            None => self.state.status = "the timestamps have no wall-clock time".to_string(),
+        }
+    }
+
+    // 按级别和 target 重新过滤，尽量还选中原来那条记录
+    fn filter(&mut self) {
+        let selected = self
+            .visible
+            .get(self.selected)
+            .copied()
+            .or(self.state.selected.take())
+            .unwrap_or(0);
+        if self.state.levels == [true; 5] && self.state.target.is_empty() {
+            self.visible = (0..self.records.len()).collect();
+        } else {
+            let parse = self.state.parse;
+            let records = &self.records;
+            let prefixes = self.prefixes.get_or_insert_with(|| {
+                records
+                    .iter()
+                    .map(|record| parse(record).map(|entry| (entry.level, entry.target)))
+                    .collect()
+            });
+            let mut shown = true;
+            self.visible.clear();
+            for (i, prefix) in prefixes.iter().enumerate() {
+                // 解析不了的记录跟着前一条
+                if let Some((level, target)) = prefix {
+                    shown = self.state.levels[*level as usize - 1]
+                        && target.starts_with(&self.state.target);
+                }
+                if shown {
+                    self.visible.push(i);
+                }
+            }
+        }
+        let i = self.visible.partition_point(|&i| i < selected);
+        self.selected = i.min(self.visible.len().saturating_sub(1));
+    }
+
+    /// 画整个界面。
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [list, details, status] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(DETAILS_HEIGHT),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        self.draw_list(frame, list);
+        self.draw_details(frame, details);
+        frame.render_widget(Paragraph::new(self.status_line()), status);
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: Rect) {
+        self.height = area.height.saturating_sub(2).max(1) as usize;
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + self.height {
+            self.top = self.selected + 1 - self.height;
+        }
+        let end = self.visible.len().min(self.top + self.height);
+        let lines: Vec<Line> = (self.top..end)
+            .map(|i| {
+                let record = self.records[self.visible[i]];
+                let mut style = Style::default();
+                if let Some(level) = self.level(self.visible[i]) {
+                    style = style.fg(color(level));
+                }
+                if i == self.selected {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                Line::styled(record.lines().next().unwrap_or(""), style)
+            })
+            .collect();
+        let title = format!(" {}/{} records ", self.visible.len(), self.records.len());
+        let block = Block::default().borders(Borders::ALL).title(title);
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    // 没有过滤过的话现解析
+    fn level(&self, record: usize) -> Option<Level> {
+        match &self.prefixes {
+            Some(prefixes) => prefixes[record].as_ref().map(|(level, _)| *level),
+            None => (self.state.parse)(self.records[record]).map(|entry| entry.level),
+        }
+    }
+
+    fn draw_details(&self, frame: &mut Frame, area: Rect) {
+        let mut lines = Vec::new();
+        if let Some(record) = self.selected_record() {
+            match (self.state.parse)(record) {
+                Some(entry) => {
+                    let normalized = match self.reader.normalize(record) {
+                        Some(ns) => format!("{} ns since the epoch", ns),
+                        None => "no wall-clock time".to_string(),
+                    };
+                    lines.push(Line::from(format!(
+                        "time:    {}.{:09}s ({})",
+                        entry.timestamp.as_secs(),
+                        entry.timestamp.subsec_nanos(),
+                        normalized
+                    )));
+                    lines.push(Line::from(format!(
+                        "level:   {}  tid: {}  target: {}",
+                        entry.level, entry.tid, entry.target
+                    )));
+                    let location = match (&entry.file, entry.line) {
+                        (Some(file), Some(line)) => format!("{}:{}", file, line),
+                        (Some(file), None) => file.clone(),
+                        _ => "-".to_string(),
+                    };
+                    lines.push(Line::from(format!("file:    {}", location)));
+                    let mut message = entry.message.lines();
+                    for (i, line) in message.by_ref().take(MESSAGE_LINES).enumerate() {
+                        let label = if i == 0 { "message: " } else { "         " };
+                        lines.push(Line::from(format!("{}{}", label, line)));
+                    }
+                    if message.next().is_some() {
+                        lines.push(Line::from("         …"));
+                    }
+                }
+                None => lines.push(Line::from("not a parsable record")),
+            }
+            let dump = hex_dump("raw", record.as_bytes()).to_string();
+            let mut dump = dump.lines();
+            lines.extend(
+                dump.by_ref()
+                    .take(1 + HEX_LINES)
+                    .map(|line| Line::from(line.to_string())),
+            );
+            if dump.next().is_some() {
+                lines.push(Line::from("…"));
+            }
+        }
+        let block = Block::default().borders(Borders::ALL).title(" details ");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    fn status_line(&self) -> Line<'_> {
+        match self.state.input {
+            Some(Input::Target) => return Line::from(format!("target: {}▏", self.state.target)),
+            Some(Input::Search) => return Line::from(format!("/{}▏", self.state.search)),
+            Some(Input::Time) => return Line::from(format!("@{}▏", self.state.time)),
+            None => {}
+        }
+        let levels: String = LEVELS
+            .iter()
+            .zip(self.state.levels)
+            .map(|(level, on)| if on { &level.as_str()[..1] } else { "-" })
+            .collect();
+        let mut status = format!("levels {}", levels);
+        if !self.state.target.is_empty() {
+            status += &format!("  target {}", self.state.target);
+        }
+        if !self.state.search.is_empty() {
+            status += &format!("  /{}", self.state.search);
+        }
+        if self.state.follow {
+            status += "  following";
+        }
+        if !self.state.status.is_empty() {
+            status += &format!("  {}", self.state.status);
+        }
+        Line::from(status)
+    }
+}
+
+fn color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::Red,
+        Level::Warn => Color::Yellow,
+        Level::Info => Color::Green,
+        Level::Debug => Color::Blue,
+        Level::Trace => Color::DarkGray,
+    }
+}
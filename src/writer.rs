@@ -0,0 +1,83 @@
+use crate::{Error, Framing, Logger};
+use log::Level;
+use std::io;
+
+/// 把原始的字节写进 [`Logger`] 的缓冲区，不经过 `log` 宏，见 [`Logger::writer`]。
+///
+/// 每次 `write()` 拿一次写锁，写入的字节在缓冲区里是连续的一段，
+/// 不会和别的线程同时写的记录交错；offset、翻转和 header 里的计数和 `log()` 一样更新。
+/// 没有格式，也不会补换行：[`Framing::Text`] 的话读回来按换行拆分，
+/// [`Framing::LengthPrefixed`] 的话每次 `write()` 是一条记录，超过上限的部分留给下一次。
+/// `flush()` 就是 [`Logger::try_flush`]。
+///
+/// ```
+/// use log::Log;
+/// use std::io::Write;
+/// use std::thread;
+///
+/// let path = std::env::temp_dir().join("mmlog-writer.log");
+/// let logger = mmlog::Builder::new().build(&path).unwrap();
+/// thread::scope(|s| {
+///     s.spawn(|| {
+///         for i in 0..1000 {
+///             logger.log(
+///                 &log::Record::builder()
+///                     .level(log::Level::Info)
+///                     .args(format_args!("record {}", i))
+///                     .build(),
+///             );
+///         }
+///     });
+///     let mut writer = logger.writer();
+///     for i in 0..1000 {
+///         writer
+///             .write_all(format!("raw line {} {}\n", i, "x".repeat(100)).as_bytes())
+///             .unwrap();
+///     }
+///     writer.flush().unwrap();
+/// });
+/// assert_eq!(logger.stats().records_written, 2000);
+/// drop(logger);
+///
+/// let reader = mmlog::reader::LogReader::open(&path).unwrap();
+/// let raw: Vec<_> = reader.records().filter(|r| r.starts_with("raw")).collect();
+/// assert_eq!(raw.len(), 1000);
+/// assert!(raw.iter().all(|r| r.ends_with(&"x".repeat(100))));
+/// ```
+#[derive(Debug)]
+pub struct RingWriter<'a> {
+    logger: &'a Logger,
+}
+
+impl RingWriter<'_> {
+    pub(crate) fn new(logger: &Logger) -> RingWriter<'_> {
+        RingWriter { logger }
+    }
+}
+
+impl io::Write for RingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let logger = self.logger;
+        let len = match logger.framing {
+            Framing::Text if logger.rotate.is_some() => buf.len().min(logger.max_record()),
+            Framing::Text => buf.len(),
+            Framing::LengthPrefixed => buf.len().min(logger.max_frame()),
+        };
+        let _guard = logger.shared.lock();
+        if unsafe { logger.mapping() }.is_none() {
+            return Err(io::Error::other(Error::Closed));
+        }
+        unsafe { logger.write_locked(crate::ring(Level::Info, 0), &buf[..len]) };
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.logger.try_flush().map_err(|e| match e {
+            Error::StdIo(e) => e,
+            e => io::Error::other(e),
+        })
+    }
+}
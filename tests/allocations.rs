@@ -0,0 +1,209 @@
+// 热路径上不分配内存：预热之后的各种记录都不经过分配器，分配器失败时也照样写、不 panic。
+// 装了 CountingAllocator，还用了 mmlog::test::freeze，这个文件里只能有一个测试
+mod common;
+
+use common::log_with;
+use log::{Level, LevelFilter};
+use mmlog::format::{FormatKind, TimestampFormat};
+use mmlog::{Builder, Logger, KB};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::time::Duration;
+
+// 数分配次数、可以让分配失败的全局分配器。计数和失败都只算当前线程的，
+// cargo test 自己的线程、logger 的后台线程不受影响。分配失败时 Rust 直接中止进程
+// （handle_alloc_error），所以 fail_allocations 打开期间只要分配了一次，测试进程就会崩溃
+struct CountingAllocator;
+
+#[global_allocator]
+static ALLOC: CountingAllocator = CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static FAILING: Cell<bool> = const { Cell::new(false) };
+}
+
+impl CountingAllocator {
+    // 记下一次分配，返回这次是不是要失败。线程正在退出、计数已经销毁了的话不算
+    fn count(&self) -> bool {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        FAILING.try_with(Cell::get).unwrap_or(false)
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.count() {
+            return ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if self.count() {
+            return ptr::null_mut();
+        }
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if self.count() {
+            return ptr::null_mut();
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+// 当前线程到现在为止分配（包括 realloc）了几次
+fn allocations() -> u64 {
+    ALLOCATIONS.try_with(Cell::get).unwrap_or(0)
+}
+
+// 打开之后当前线程的分配都失败，直到关掉为止
+fn fail_allocations(fail: bool) {
+    let _ = FAILING.try_with(|f| f.set(fail));
+}
+
+const SIZE: usize = 64 * KB;
+const MAIN: Option<(&str, u32)> = Some(("src/main.rs", 7));
+const T0: Duration = Duration::from_secs(1_700_000_000);
+
+// 有代表性的几种调用：小记录、比缓冲区的一半还长的、比整个缓冲区还长的、
+// 跨过缓冲区末尾的、级别关掉的、target 被过滤掉的
+fn representative(logger: &Logger, large: &str, huge: &str) {
+    log_with(
+        logger,
+        Level::Info,
+        "app",
+        MAIN,
+        format_args!("small {} {}", 42, 'x'),
+    );
+    log_with(
+        logger,
+        Level::Warn,
+        "app::net",
+        MAIN,
+        format_args!("large {}", large),
+    );
+    log_with(
+        logger,
+        Level::Error,
+        "app",
+        MAIN,
+        format_args!("huge {}", huge),
+    );
+    for i in 0..SIZE / 100 + 1 {
+        log_with(
+            logger,
+            Level::Info,
+            "app",
+            MAIN,
+            format_args!("wrap {:>80}", i),
+        );
+    }
+    log_with(
+        logger,
+        Level::Debug,
+        "app",
+        MAIN,
+        format_args!("disabled {}", 1),
+    );
+    log_with(
+        logger,
+        Level::Error,
+        "noisy::io",
+        MAIN,
+        format_args!("filtered {}", 2),
+    );
+}
+
+fn loggers() -> Vec<Logger> {
+    let builder = || {
+        Builder::new()
+            .size(SIZE)
+            .module_level("noisy", LevelFilter::Off)
+    };
+    vec![
+        builder().build_anonymous().unwrap(),
+        builder()
+            .timestamp(TimestampFormat::Rfc3339Utc)
+            .build_anonymous()
+            .unwrap(),
+        builder()
+            .format_kind(FormatKind::Json)
+            .timestamp(TimestampFormat::Rfc3339Utc)
+            .build_anonymous()
+            .unwrap(),
+        builder()
+            .format_kind(FormatKind::Logfmt)
+            .build_anonymous()
+            .unwrap(),
+    ]
+}
+
+#[test]
+fn steady_state_does_not_allocate() {
+    let large = "l".repeat(SIZE * 3 / 4);
+    let huge = "h".repeat(SIZE * 3);
+    let loggers = loggers();
+
+    // 预热：线程局部的缓冲区长到最长的那条记录
+    mmlog::test::freeze(T0, 1);
+    for logger in &loggers {
+        representative(logger, &large, &huge);
+    }
+    let wraps: Vec<_> = loggers.iter().map(|l| l.stats().wrap_count).collect();
+
+    // 换到下一秒，RFC 3339 的日期缓存要重写
+    for (round, secs) in [1, 2, 3600].into_iter().enumerate() {
+        mmlog::test::freeze(T0 + Duration::from_secs(secs), 1);
+        let fail = round > 0;
+        let before = allocations();
+        fail_allocations(fail);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            for logger in &loggers {
+                representative(logger, &large, &huge);
+            }
+        }));
+        fail_allocations(false);
+        let allocated = allocations() - before;
+        assert!(result.is_ok(), "panicked with failing={}", fail);
+        assert_eq!(allocated, 0, "allocated with failing={}", fail);
+    }
+    mmlog::test::thaw();
+
+    for (logger, wraps) in loggers.iter().zip(wraps) {
+        assert!(logger.stats().wrap_count > wraps);
+        let tail = logger.tail(2);
+        assert!(tail[0].contains("wrap "));
+        assert!(tail[1].contains("wrap "));
+        let text = logger.tail(usize::MAX).join("\n");
+        assert!(!text.contains("disabled") && !text.contains("filtered"));
+    }
+    let tail = loggers[1].tail(1);
+    assert!(tail[0].starts_with("[2023-11-14T23:13:20."), "{}", tail[0]);
+
+    // 新的线程：第一条短记录之后，长一些的记录也不用再给格式化的缓冲区分配内存
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            log_with(&loggers[0], Level::Info, "app", MAIN, format_args!("x"));
+            for len in 1..200 {
+                let before = allocations();
+                log_with(
+                    &loggers[0],
+                    Level::Info,
+                    "app",
+                    MAIN,
+                    format_args!("{:>len$}", "x", len = len),
+                );
+                assert_eq!(allocations(), before, "allocated for {} bytes", len);
+            }
+        });
+    });
+}
@@ -0,0 +1,63 @@
+// 两种后端：mmap（默认）和关掉 mmap feature 之后的 std-file。
+// 两个都要测：cargo test 和 cargo test --no-default-features，
+// 写出来的文件逐字节相同由 tests/golden.rs 检查。
+mod common;
+
+use common::{fresh, log};
+use log::Log;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, KB};
+use std::path::PathBuf;
+
+const MMAP: bool = cfg!(all(feature = "mmap", unix));
+
+fn on_disk(path: &PathBuf) -> Vec<String> {
+    let reader = LogReader::open(path).unwrap();
+    reader.records().map(str::to_string).collect()
+}
+
+#[test]
+fn visible_after_flush() {
+    let path = fresh(&format!("mmlog-test-backend-flush-{}.log", MMAP));
+    let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+    log(&logger, "first");
+    // mmap 写进去别的进程马上就能看到，std-file 要等 flush
+    assert_eq!(on_disk(&path).len(), usize::from(MMAP));
+    logger.flush();
+    assert_eq!(on_disk(&path), logger.tail(usize::MAX));
+
+    // 绕回开头之后只写回改过的部分，文件里的和内存里的还是一样
+    for i in 0..20000 {
+        log(&logger, format_args!("record {}", i));
+    }
+    assert!(logger.stats().wrap_count > 0);
+    logger.flush();
+    assert_eq!(on_disk(&path), logger.tail(usize::MAX));
+
+    // 没 flush 的记录 drop 的时候写回
+    log(&logger, "last");
+    drop(logger);
+    assert!(on_disk(&path).last().unwrap().ends_with("] last"));
+}
+
+#[test]
+fn reopen_continues() {
+    let path = fresh(&format!("mmlog-test-backend-reopen-{}.log", MMAP));
+    let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+    log(&logger, "before");
+    let stats = logger.stats();
+    drop(logger);
+
+    let logger = Builder::new().open(&path).unwrap();
+    assert_eq!(logger.stats().capacity, stats.capacity);
+    assert_eq!(
+        logger.stats().used_bytes_since_wrap,
+        stats.used_bytes_since_wrap
+    );
+    log(&logger, "after");
+    drop(logger);
+    let records = on_disk(&path);
+    assert_eq!(records.len(), 2, "{:?}", records);
+    assert!(records[0].ends_with("] before"));
+    assert!(records[1].ends_with("] after"));
+}
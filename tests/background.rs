@@ -0,0 +1,96 @@
+// 轮转和 Builder::compress_rotated 的后台线程出错：不死锁，错误作为 Error 级别的提示记录写进日志
+mod common;
+
+use common::{fresh, log, segment};
+use mmlog::Builder;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// 死锁的话测试失败而不是一直挂着
+fn within_timeout(f: impl FnOnce() + Send + 'static) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        f();
+        tx.send(()).unwrap();
+    });
+    rx.recv_timeout(Duration::from_secs(30))
+        .expect("reporting a background error deadlocked");
+}
+
+fn errors(records: &[String]) -> Vec<&str> {
+    records
+        .iter()
+        .filter(|r| r.contains(" E ") && r.contains("] "))
+        .map(|r| r.split_once("] ").unwrap().1)
+        .collect()
+}
+
+#[test]
+fn failed_rotation_is_logged() {
+    within_timeout(|| {
+        let path = fresh("mmlog-test-background-rotate.log");
+        // name.1 是个目录，改名失败
+        std::fs::create_dir(segment(&path, 1)).unwrap();
+        let logger = Builder::new().rotate(1).build(&path).unwrap();
+        for i in 0..5000 {
+            log(&logger, format_args!("record {} {}", i, "x".repeat(200)));
+        }
+        // 在原来的文件里翻转，错误只报告在写下一条记录之前
+        assert!(logger.stats().wrap_count > 0);
+        let tail = logger.tail(usize::MAX);
+        let errors = errors(&tail);
+        assert!(!errors.is_empty(), "{:?}", &tail[tail.len() - 3..]);
+        assert!(
+            errors
+                .iter()
+                .all(|e| e.starts_with("rotate: can't start a new file")),
+            "{:?}",
+            errors
+        );
+
+        // 恢复之后照常轮转
+        std::fs::remove_dir(segment(&path, 1)).unwrap();
+        for i in 0..5000 {
+            log(&logger, format_args!("record {} {}", i, "x".repeat(200)));
+        }
+        assert!(segment(&path, 1).is_file());
+    });
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn failed_compression_is_logged() {
+    use mmlog::reader::LogReader;
+    use std::path::PathBuf;
+
+    within_timeout(|| {
+        let path = fresh("mmlog-test-background-compress.log");
+        // 第一次轮转压缩时用的临时文件的位置被目录占了
+        let tmp = PathBuf::from(format!("{}.1.zst.tmp1", path.display()));
+        let _ = std::fs::remove_dir(&tmp);
+        std::fs::create_dir(&tmp).unwrap();
+        let logger = Builder::new()
+            .rotate(2)
+            .compress_rotated(true)
+            .build(&path)
+            .unwrap();
+        for i in 0..4000 {
+            log(&logger, format_args!("record {} {}", i, "x".repeat(200)));
+        }
+        // drop 时等压缩线程做完，把它的错误写进去
+        drop(logger);
+        std::fs::remove_dir(&tmp).unwrap();
+
+        assert!(segment(&path, 1).is_file());
+        let reader = LogReader::open(&path).unwrap();
+        let records: Vec<String> = reader.records().map(str::to_string).collect();
+        let errors = errors(&records);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(
+            errors[0].starts_with("compress_rotated: can't compress a rotated file"),
+            "{:?}",
+            errors
+        );
+    });
+}
@@ -0,0 +1,171 @@
+// Logger::bookmark：书签记在文件里，重启之后接着读；缓冲区绕过书签、clear() 之后报告 gap
+mod common;
+
+use common::{fresh, log};
+use mmlog::format::{BOOKMARK_SLOTS, CAPACITY_POS, DATA_POS};
+use mmlog::{Builder, Error, Framing, LogicalPos, KB};
+use std::path::Path;
+
+fn messages(records: &[String]) -> Vec<&str> {
+    records
+        .iter()
+        .map(|r| r.split_once("] ").unwrap().1)
+        .collect()
+}
+
+#[test]
+fn persists_across_restarts() {
+    let path = fresh("mmlog-test-bookmarks-restart.log");
+    let open = |path: &Path| Builder::new().size(512 * KB).open_or_create(path).unwrap();
+    let logger = open(&path);
+    assert_eq!(logger.bookmark("ui").unwrap(), LogicalPos(0));
+    log(&logger, "one");
+    log(&logger, "two");
+
+    // 不前进的话下次还是这些
+    let seen = logger.read_since_bookmark("ui", false).unwrap();
+    assert_eq!(messages(&seen.records), ["one", "two"]);
+    assert_eq!(logger.bookmark("ui").unwrap(), LogicalPos(0));
+    let seen = logger.read_since_bookmark("ui", true).unwrap();
+    assert_eq!(messages(&seen.records), ["one", "two"]);
+    assert!(!seen.gap);
+    assert_eq!(logger.bookmark("ui").unwrap(), seen.end);
+    log(&logger, "three");
+    drop(logger);
+
+    for round in 0..3 {
+        let logger = open(&path);
+        let seen = logger.read_since_bookmark("ui", true).unwrap();
+        let expected = if round == 0 { vec!["three"] } else { vec![] };
+        assert_eq!(messages(&seen.records), expected);
+        assert!(!seen.gap);
+        // 另一个书签从头开始，互不影响
+        let other = logger.read_since_bookmark("audit", false).unwrap();
+        assert_eq!(messages(&other.records), ["one", "two", "three"]);
+        assert_eq!(logger.bookmark("audit").unwrap(), LogicalPos(0));
+    }
+
+    // 重新创建的文件没有以前的书签
+    let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+    assert_eq!(logger.bookmark("ui").unwrap(), LogicalPos(0));
+}
+
+#[test]
+fn wrapping_past_a_bookmark_is_a_gap() {
+    for framing in [Framing::Text, Framing::LengthPrefixed] {
+        let path = fresh(&format!("mmlog-test-bookmarks-gap-{:?}.log", framing));
+        let logger = Builder::new()
+            .size(512 * KB)
+            .framing(framing)
+            .build(&path)
+            .unwrap();
+        let mut next = 0;
+        let mut write = |n: usize| {
+            for _ in 0..n {
+                log(&logger, format_args!("{} {}", next, "x".repeat(200)));
+                next += 1;
+            }
+            next
+        };
+
+        // 每次都在绕一整圈之前读，记录一条不少，跨过末尾的那几次也一样
+        let mut expected = 0;
+        let mut written = 0;
+        for _ in 0..40 {
+            written = write(700);
+            let seen = logger.read_since_bookmark("ui", true).unwrap();
+            assert!(!seen.gap, "{:?}", framing);
+            for record in &seen.records {
+                let n: usize = record
+                    .split_once("] ")
+                    .unwrap()
+                    .1
+                    .split(' ')
+                    .next()
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                assert_eq!(n, expected, "{:?}", framing);
+                expected += 1;
+            }
+        }
+        assert_eq!(expected, written);
+        assert!(logger.stats().wrap_count >= 10);
+
+        // 写了不止一圈：gap，读到的是现在缓冲区里的记录
+        let written = write(4000);
+        let seen = logger.read_since_bookmark("ui", true).unwrap();
+        assert!(seen.gap, "{:?}", framing);
+        assert_eq!(
+            seen.records,
+            logger.snapshot().records().collect::<Vec<_>>()
+        );
+        assert!(seen
+            .records
+            .last()
+            .unwrap()
+            .contains(&format!("] {} ", written - 1)));
+        assert!(!logger.read_since_bookmark("ui", true).unwrap().gap);
+
+        // clear() 之后位置变小了，也是 gap
+        write(3);
+        logger.clear(false).unwrap();
+        write(2);
+        let seen = logger.read_since_bookmark("ui", true).unwrap();
+        assert!(seen.gap);
+        assert_eq!(seen.records.len(), 2);
+    }
+}
+
+#[test]
+fn errors() {
+    let path = fresh("mmlog-test-bookmarks-errors.log");
+    let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+    log(&logger, "one");
+    let end = logger.read_since_bookmark("b0", false).unwrap().end;
+    assert!(matches!(
+        logger.set_bookmark("b0", LogicalPos(end.0 + 1)),
+        Err(Error::InvalidConfig(_))
+    ));
+    for i in 0..BOOKMARK_SLOTS {
+        logger.set_bookmark(&format!("b{}", i), end).unwrap();
+    }
+    // 已有的书签还能改，新的放不下了
+    logger.set_bookmark("b3", LogicalPos(0)).unwrap();
+    assert_eq!(logger.bookmark("b3").unwrap(), LogicalPos(0));
+    assert!(matches!(
+        logger.set_bookmark("one too many", end),
+        Err(Error::InvalidConfig(_))
+    ));
+    logger.close().unwrap();
+    assert!(matches!(logger.bookmark("b0"), Err(Error::Closed)));
+
+    // 分了环的文件
+    let path = fresh("mmlog-test-bookmarks-reserved.log");
+    let logger = Builder::new()
+        .size(512 * KB)
+        .reserve_for_errors(0.25)
+        .build(&path)
+        .unwrap();
+    assert!(matches!(
+        logger.bookmark("ui"),
+        Err(Error::InvalidConfig(_))
+    ));
+    assert!(matches!(
+        logger.read_since_bookmark("ui", true),
+        Err(Error::InvalidConfig(_))
+    ));
+
+    // 版本 12 之前的文件没有书签表
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/text-v11.bin");
+    let mut file = std::fs::read(golden).unwrap();
+    let capacity =
+        u64::from_le_bytes(file[CAPACITY_POS..CAPACITY_POS + 8].try_into().unwrap()) as usize;
+    let start = u32::from_le_bytes(file[DATA_POS..DATA_POS + 4].try_into().unwrap()) as usize;
+    file.resize(start + capacity, 0);
+    let path = fresh("mmlog-test-bookmarks-v11.log");
+    std::fs::write(&path, &file).unwrap();
+    let logger = Builder::new().size(capacity).open(&path).unwrap();
+    let err = logger.bookmark("ui").unwrap_err();
+    assert!(err.to_string().contains("version 11"), "{}", err);
+}
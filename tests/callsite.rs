@@ -0,0 +1,37 @@
+// log_if! 的调用点缓存在 Builder::init 和 set_level 之后失效。
+// 装的是全局 logger，这个文件里只能有一个测试
+mod common;
+
+use common::fresh;
+use log::Level;
+use mmlog::Builder;
+
+// 每个调用点的级别不变，缓存才会命中
+fn info(msg: &str) {
+    mmlog::log_if!(target: "callsite", Level::Info, "{}", msg);
+}
+
+fn warn(msg: &str) {
+    mmlog::log_if!(target: "callsite", Level::Warn, "{}", msg);
+}
+
+#[test]
+fn init_and_set_level_refresh_callsites() {
+    // 还没有 logger，缓存下来的是关闭
+    info("before init");
+    warn("before init");
+
+    let path = fresh("mmlog-test-callsite.log");
+    let logger = Builder::new().level(Level::Info).init(&path).unwrap();
+    info("after init");
+    logger.set_level(Level::Warn);
+    info("after set_level");
+    warn("after set_level");
+
+    let messages: Vec<_> = logger
+        .tail(usize::MAX)
+        .into_iter()
+        .map(|r| r.split_once("] ").unwrap().1.to_string())
+        .collect();
+    assert_eq!(messages, ["after init", "after set_level"]);
+}
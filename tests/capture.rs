@@ -0,0 +1,89 @@
+// capture::pipe_child：用 sh 的 printf 当作输出已知的子进程
+#![cfg(unix)]
+use log::Level;
+use mmlog::parse::Entry;
+use mmlog::{capture, Builder, Logger};
+use std::process::{Command, Stdio};
+
+fn run(script: &str, level: Option<Level>) -> (u64, Vec<Entry>) {
+    let logger: &'static Logger = Box::leak(Box::new(
+        Builder::new()
+            .level(Level::Trace)
+            .build_anonymous()
+            .unwrap(),
+    ));
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let handle = match level {
+        Some(level) => capture::pipe_child_at(logger, &mut child, "child", level),
+        None => capture::pipe_child(logger, &mut child, "child"),
+    };
+    assert!(child.wait().unwrap().success());
+    let n = handle.join().unwrap().unwrap();
+    let entries = logger
+        .tail(usize::MAX)
+        .iter()
+        .map(|record| Entry::parse(record).unwrap())
+        .collect();
+    (n, entries)
+}
+
+fn messages(entries: &[Entry]) -> Vec<&str> {
+    entries.iter().map(|e| e.message.as_str()).collect()
+}
+
+#[test]
+fn stdout_and_stderr() {
+    let (n, entries) = run(
+        "printf 'out 1\\nout 2\\r\\n'; printf 'err 1\\n' >&2; printf 'out 3\\n'",
+        None,
+    );
+    assert_eq!(n, 4);
+    assert_eq!(entries.len(), 4);
+    assert!(entries
+        .iter()
+        .all(|e| e.target == "child" && e.level == Level::Info));
+    // 两个管道之间的先后不确定，各自的顺序不变
+    let mut messages = messages(&entries);
+    let out: Vec<_> = messages
+        .iter()
+        .copied()
+        .filter(|m| m.starts_with("out"))
+        .collect();
+    assert_eq!(out, ["out 1", "out 2", "out 3"]);
+    messages.sort();
+    assert_eq!(messages, ["err 1", "out 1", "out 2", "out 3"]);
+}
+
+#[test]
+fn partial_line_and_invalid_utf8() {
+    let (n, entries) = run(
+        "printf '\\377bad\\n'; printf 'no newline'",
+        Some(Level::Warn),
+    );
+    assert_eq!(n, 2);
+    assert_eq!(messages(&entries), ["\u{fffd}bad", "no newline"]);
+    assert!(entries.iter().all(|e| e.level == Level::Warn));
+}
+
+#[test]
+fn long_line_is_split() {
+    let (n, entries) = run("head -c 40000 /dev/zero | tr '\\0' x; echo", None);
+    assert_eq!(n, 3);
+    let messages = messages(&entries);
+    assert_eq!(messages.len(), 3);
+    assert!(messages.iter().all(|m| m.bytes().all(|b| b == b'x')));
+    assert_eq!(messages.iter().map(|m| m.len()).sum::<usize>(), 40000);
+}
+
+#[test]
+fn no_output() {
+    let (n, entries) = run("true", None);
+    assert_eq!(n, 0);
+    assert!(entries.is_empty());
+}
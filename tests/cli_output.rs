@@ -0,0 +1,216 @@
+// mmlog-cat --output：ndjson 和 tsv 是给脚本用的，每种用法的输出都要和 tests/cli-output/ 里的快照逐字节相同。
+// 有意修改写法的话重新生成：
+//
+//     MMLOG_BLESS=1 cargo test --test cli_output --all-features
+//
+// 用 mmlog::test::freeze 固定时间戳和线程 id，这个文件里只能有一个测试
+#![cfg(feature = "cli")]
+mod common;
+
+use common::log_with;
+use log::Level;
+use mmlog::format::{ANCHOR_POS, EXE_LEN, EXE_POS, PID_POS, STARTED_POS};
+use mmlog::{Builder, Clock, FormatKind, Logger, TimestampFormat, KB};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::Duration;
+
+const T0: Duration = Duration::from_secs(1_700_000_000);
+// 单调时钟的文件里和 T0 对应的读数
+const M0: Duration = Duration::from_secs(5000);
+
+fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mmlog-test-cli-{}.log", name))
+}
+
+fn snapshot(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/cli-output")
+        .join(name)
+}
+
+// 第 i 条记录在 start + i 秒。文本格式里没转义的换行让第三条记录多出一行解析不了的续行
+fn write(logger: &Logger, start: Duration) {
+    let records = [
+        (Level::Info, "app", None, "hello"),
+        (
+            Level::Warn,
+            "app::net",
+            Some("src/net.rs"),
+            "retrying\tin 1s",
+        ),
+        (
+            Level::Error,
+            "app::db",
+            Some("src/db.rs"),
+            "line one\nline two",
+        ),
+        (Level::Debug, "app", None, "quote \" and \\ and 中文"),
+    ];
+    for (i, (level, target, file, msg)) in records.into_iter().enumerate() {
+        mmlog::test::freeze(start + Duration::from_secs(i as u64), 1);
+        log_with(
+            logger,
+            level,
+            target,
+            file.map(|file| (file, 42)),
+            format_args!("{}", msg),
+        );
+    }
+}
+
+// 创建文件的进程号、启动时间和程序名清零，锚点换成固定的
+fn scrub(path: &Path, anchor: Option<(Duration, Duration)>) {
+    let mut file = std::fs::read(path).unwrap();
+    file[PID_POS..STARTED_POS + 8].fill(0);
+    file[EXE_POS..EXE_POS + EXE_LEN].fill(0);
+    if let Some((realtime, monotonic)) = anchor {
+        let at = &mut file[ANCHOR_POS..ANCHOR_POS + 16];
+        at[..8].copy_from_slice(&(realtime.as_nanos() as u64).to_le_bytes());
+        at[8..].copy_from_slice(&(monotonic.as_nanos() as u64).to_le_bytes());
+    }
+    std::fs::write(path, file).unwrap();
+}
+
+fn cat(args: &[&str]) -> Output {
+    let args = args.iter().map(|arg| match arg.strip_prefix('@') {
+        Some(name) => path(name).into_os_string(),
+        None => arg.into(),
+    });
+    Command::new(env!("CARGO_BIN_EXE_mmlog-cat"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn output_snapshots() {
+    let builder = || Builder::new().size(512 * KB).level(Level::Trace);
+    let files = [
+        ("text", builder()),
+        (
+            "json",
+            builder()
+                .format_kind(FormatKind::Json)
+                .timestamp(TimestampFormat::Rfc3339Utc),
+        ),
+        ("logfmt", builder().format_kind(FormatKind::Logfmt)),
+        ("uptime", builder().clock(Clock::Monotonic)),
+    ];
+    for (i, (name, builder)) in files.into_iter().enumerate() {
+        let logger = builder.build(path(name)).unwrap();
+        // 每个文件晚 10 秒，单调时钟的文件按锚点换算回同一个时刻
+        let start = if name == "uptime" { M0 } else { T0 };
+        write(&logger, start + Duration::from_secs(i as u64 * 10));
+        drop(logger);
+        let anchor = (name == "uptime").then_some((T0, M0));
+        scrub(&path(name), anchor);
+    }
+    mmlog::test::thaw();
+    let _ = std::fs::remove_file(path("missing"));
+
+    let cases: [(&str, &[&str]); 7] = [
+        ("text", &["@text"]),
+        ("json", &["@json"]),
+        ("logfmt", &["@logfmt"]),
+        ("uptime", &["@uptime"]),
+        (
+            "merge",
+            &["--merge", "@text", "@json", "@logfmt", "@uptime"],
+        ),
+        ("grep", &["--grep", "line", "-C", "1", "@text"]),
+        ("info", &["--info", "@uptime"]),
+    ];
+    let bless = std::env::var_os("MMLOG_BLESS").is_some();
+    for (case, args) in cases {
+        for (output, ext) in [("text", "txt"), ("ndjson", "ndjson"), ("tsv", "tsv")] {
+            let mut args = args.to_vec();
+            args.extend(["--output", output]);
+            let out = cat(&args);
+            assert!(out.status.success(), "{} {}: {:?}", case, output, out);
+            let stdout = String::from_utf8(out.stdout).unwrap();
+            let name = format!("{}.{}", case, ext);
+            if bless {
+                std::fs::write(snapshot(&name), &stdout).unwrap();
+                continue;
+            }
+            let expected = std::fs::read_to_string(snapshot(&name))
+                .unwrap_or_else(|e| panic!("{}: {}, regenerate with MMLOG_BLESS=1", name, e));
+            assert_eq!(stdout, expected, "{}", name);
+
+            // 每行都是一个 JSON 对象，或者列数固定的 TSV
+            for line in stdout.lines() {
+                match output {
+                    "ndjson" => {
+                        let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                        assert!(value["type"].is_string(), "{}", line);
+                    }
+                    "tsv" => {
+                        let columns = match case {
+                            "merge" => 8,
+                            "grep" => 9,
+                            "info" => line.split('\t').count(),
+                            _ => 7,
+                        };
+                        assert_eq!(line.split('\t').count(), columns, "{}", line);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // 四种格式的文件解析出来的字段一样，时间戳都换算成了同一个时刻；解析不了的续行只有 raw
+    let out = cat(&[
+        "--merge", "@text", "@json", "@logfmt", "@uptime", "--output", "ndjson",
+    ]);
+    let (records, raw): (Vec<serde_json::Value>, _) = String::from_utf8(out.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .partition(|record| !record["tid"].is_null());
+    let sources: Vec<_> = raw.iter().map(|r| r["source"].as_str().unwrap()).collect();
+    assert_eq!(
+        sources,
+        ["mmlog-test-cli-text.log", "mmlog-test-cli-uptime.log"]
+    );
+    for record in &raw {
+        assert_eq!(record["raw"], "line two");
+        assert!(record["timestamp_ns"].is_null() && record["message"].is_null());
+    }
+    assert_eq!(records.len(), 16);
+    for (i, record) in records.iter().enumerate() {
+        let expected = (T0 + Duration::from_secs(i as u64 / 4 * 10 + i as u64 % 4)).as_nanos();
+        assert_eq!(record["timestamp_ns"], expected as u64, "{}", record);
+        assert_eq!(record["tid"], 1, "{}", record);
+    }
+    let messages: Vec<_> = records
+        .iter()
+        .map(|r| r["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(messages[1], "retrying\tin 1s");
+    assert_eq!(messages[6], "line one\nline two");
+    assert_eq!(messages[2], "line one");
+    assert!(messages[..4] == messages[12..] && messages[4..8] == messages[8..12]);
+
+    // 退出码和写法无关
+    for output in ["text", "ndjson", "tsv"] {
+        let code = |args: &[&str]| {
+            let mut args = args.to_vec();
+            args.extend(["--output", output]);
+            cat(&args).status.code()
+        };
+        assert_eq!(code(&["@text"]), Some(0));
+        assert_eq!(code(&["--channel", "nope", "@text"]), Some(1));
+        assert_eq!(code(&["--session", "7", "@text"]), Some(1));
+        assert_eq!(code(&["@missing"]), Some(1));
+        assert_eq!(code(&["--tail"]), Some(2));
+    }
+    assert_eq!(cat(&["--output", "xml", "@text"]).status.code(), Some(2));
+    assert_eq!(cat(&["@text", "--output"]).status.code(), Some(2));
+    // 没有 tui feature 的话 --tui 本身就不行
+    assert_eq!(
+        cat(&["--tui", "--grep", "x", "@text"]).status.code(),
+        Some(2)
+    );
+}
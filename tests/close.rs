@@ -0,0 +1,80 @@
+// close() 之后 flush()、写日志、再 close() 和 drop
+mod common;
+
+use common::log;
+use log::Log;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Error, Logger, KB};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+fn closed(logger: Logger) {
+    log(&logger, "before close");
+    logger.close().unwrap();
+
+    logger.flush();
+    assert!(matches!(logger.try_flush(), Err(Error::Closed)));
+    log(&logger, "after close");
+    let err = logger.writer().write(b"raw\n").unwrap_err();
+    assert!(matches!(
+        err.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+        Some(Error::Closed)
+    ));
+    assert!(logger.tail(10).is_empty());
+    assert_eq!(logger.stats().records_written, 0);
+
+    // 再关一次报错，不会再解除一次映射
+    assert!(matches!(logger.close(), Err(Error::Closed)));
+    drop(logger);
+}
+
+#[test]
+fn close_flush_drop() {
+    let path = std::env::temp_dir().join("mmlog-test-close.log");
+    closed(Builder::new().size(64 * KB).build(&path).unwrap());
+    let reader = LogReader::open(&path).unwrap();
+    let records: Vec<_> = reader.records().collect();
+    assert_eq!(records.len(), 1, "{:?}", records);
+    assert!(records[0].ends_with("] before close"));
+
+    closed(Builder::new().size(64 * KB).build_anonymous().unwrap());
+    closed(
+        Builder::new()
+            .size(64 * KB)
+            .batched(4 * KB)
+            .build_anonymous()
+            .unwrap(),
+    );
+    closed(
+        Builder::new()
+            .size(64 * KB)
+            .flush_interval(Duration::from_millis(1))
+            .build_anonymous()
+            .unwrap(),
+    );
+}
+
+// 别的线程还在写的时候关掉
+#[test]
+fn close_while_logging() {
+    let logger = Builder::new().size(64 * KB).build_anonymous().unwrap();
+    let stop = AtomicBool::new(false);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                while !stop.load(Ordering::Relaxed) {
+                    log(&logger, "busy");
+                    logger.flush();
+                }
+            });
+        }
+        thread::sleep(Duration::from_millis(20));
+        logger.close().unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert!(matches!(logger.close(), Err(Error::Closed)));
+        stop.store(true, Ordering::Relaxed);
+    });
+    assert!(logger.tail(10).is_empty());
+}
@@ -0,0 +1,51 @@
+// 集成测试共用的小工具：写一条记录、准备一个干净的日志文件。
+// 每个测试文件 `mod common;` 引入，不是每个文件都用得到全部的函数
+#![allow(dead_code)]
+use log::{Level, Log, Record};
+use mmlog::Logger;
+use std::fmt::{Arguments, Display};
+use std::path::{Path, PathBuf};
+
+// 一条 Info 级别、没有 target 的记录
+pub fn log(logger: &Logger, msg: impl Display) {
+    log_at(logger, Level::Info, "", msg);
+}
+
+// 指定级别和 target
+pub fn log_at(logger: &Logger, level: Level, target: &str, msg: impl Display) {
+    log_with(logger, level, target, None, format_args!("{}", msg));
+}
+
+// 再带上源文件和行号，args 原样交给 logger
+pub fn log_with(
+    logger: &Logger,
+    level: Level,
+    target: &str,
+    file: Option<(&str, u32)>,
+    args: Arguments,
+) {
+    logger.log(
+        &Record::builder()
+            .level(level)
+            .target(target)
+            .file(file.map(|(file, _)| file))
+            .line(file.map(|(_, line)| line))
+            .args(args)
+            .build(),
+    );
+}
+
+// 临时目录里的 name，先删掉上次测试留下的文件和轮转出来的 .1、.2，有的测试把它们换成了目录
+pub fn fresh(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(name);
+    for p in [path.clone(), segment(&path, 1), segment(&path, 2)] {
+        let _ = std::fs::remove_file(&p);
+        let _ = std::fs::remove_dir(&p);
+    }
+    path
+}
+
+// 轮转出来的第 n 个旧文件
+pub fn segment(path: &Path, n: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), n))
+}
@@ -0,0 +1,75 @@
+// 很多线程抢写锁：一条不少，也没有被别的线程插进来写坏的记录
+mod common;
+
+use common::log;
+use log::Log;
+use mmlog::{Builder, Framing, Logger, KB, MB};
+use std::thread;
+
+const THREADS: usize = 16;
+const RECORDS: usize = 20000;
+
+fn stress(logger: Logger) {
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let logger = &logger;
+            s.spawn(move || {
+                for i in 0..RECORDS {
+                    log(logger, format_args!("thread {} record {} end", t, i));
+                }
+            });
+        }
+    });
+    logger.flush();
+    assert_eq!(logger.stats().records_written, (THREADS * RECORDS) as u64);
+    assert_eq!(logger.stats().wrap_count, 0);
+
+    // 每个线程的记录按顺序出现，每一条都完整
+    let mut next = [0; THREADS];
+    let records = logger.tail(usize::MAX);
+    assert_eq!(records.len(), THREADS * RECORDS);
+    for record in &records {
+        let msg = record.split_once("] ").unwrap().1;
+        let fields: Vec<_> = msg.split(' ').collect();
+        assert!(
+            fields.len() == 5
+                && fields[0] == "thread"
+                && fields[2] == "record"
+                && fields[4] == "end",
+            "{:?}",
+            record
+        );
+        let t: usize = fields[1].parse().unwrap();
+        let i: usize = fields[3].parse().unwrap();
+        assert_eq!(i, next[t], "{:?}", record);
+        next[t] += 1;
+    }
+    assert!(next.iter().all(|&n| n == RECORDS));
+}
+
+#[test]
+fn contended_text() {
+    stress(Builder::new().size(32 * MB).build_anonymous().unwrap());
+}
+
+#[test]
+fn contended_length_prefixed() {
+    stress(
+        Builder::new()
+            .size(32 * MB)
+            .framing(Framing::LengthPrefixed)
+            .build_anonymous()
+            .unwrap(),
+    );
+}
+
+#[test]
+fn contended_batched() {
+    stress(
+        Builder::new()
+            .size(32 * MB)
+            .batched(4 * KB)
+            .build_anonymous()
+            .unwrap(),
+    );
+}
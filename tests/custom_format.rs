@@ -0,0 +1,69 @@
+// Builder::format：自己的格式化函数，结尾补换行，出错时丢掉这条记录并计数
+mod common;
+
+use common::log_at;
+use log::Level;
+use mmlog::Builder;
+use std::cell::Cell;
+use std::io;
+
+thread_local! {
+    static REQUEST: Cell<u32> = const { Cell::new(0) };
+}
+
+#[test]
+fn custom_layout() {
+    let logger = Builder::new()
+        .format(|w, record| {
+            write!(
+                w,
+                "req={} {} {}",
+                REQUEST.with(Cell::get),
+                record.level(),
+                record.args()
+            )?;
+            // 自己写了换行的不会再补一个
+            if record.level() == Level::Warn {
+                writeln!(w)?;
+            }
+            Ok(())
+        })
+        .build_anonymous()
+        .unwrap();
+    REQUEST.with(|r| r.set(7));
+    log_at(&logger, Level::Info, "app", "hello");
+    REQUEST.with(|r| r.set(8));
+    log_at(&logger, Level::Warn, "app", "careful");
+    log_at(&logger, Level::Info, "app", "bye");
+
+    assert_eq!(
+        logger.tail(usize::MAX),
+        ["req=7 INFO hello", "req=8 WARN careful", "req=8 INFO bye"]
+    );
+    assert_eq!(logger.stats().records_written, 3);
+    assert_eq!(logger.format_errors(), 0);
+}
+
+#[test]
+fn failing_formatter() {
+    let logger = Builder::new()
+        .format(|w, record| {
+            if record.level() == Level::Error {
+                // 写了一半再出错，写了的部分也不能留下
+                write!(w, "half")?;
+                return Err(io::Error::other("broken"));
+            }
+            write!(w, "{}", record.args())
+        })
+        .build_anonymous()
+        .unwrap();
+    log_at(&logger, Level::Info, "app", "one");
+    log_at(&logger, Level::Error, "app", "lost");
+    log_at(&logger, Level::Error, "app", "lost");
+    log_at(&logger, Level::Info, "app", "two");
+
+    assert_eq!(logger.tail(usize::MAX), ["one", "two"]);
+    assert_eq!(logger.format_errors(), 2);
+    assert_eq!(logger.stats().records_dropped, 2);
+    assert_eq!(logger.stats().records_written, 2);
+}
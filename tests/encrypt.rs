@@ -0,0 +1,145 @@
+// Builder::encrypt：nonce 不重复、被改过的记录和不对的密钥
+#![cfg(feature = "encryption")]
+mod common;
+
+use common::{fresh, log};
+use log::Log;
+use mmlog::format::{self, DATA_POS, SALT_LEN, SEAL_NONCE};
+use mmlog::reader::{LogReader, ReadError};
+use mmlog::{Builder, Error};
+use std::collections::HashSet;
+use std::path::Path;
+
+const KEY: [u8; 32] = [5; 32];
+
+// 缓冲区开头连续的几条记录在文件里的位置：(长度前缀的位置, 内容的长度)
+fn frames(data: &[u8]) -> Vec<(usize, usize)> {
+    let start = u32::from_le_bytes(data[DATA_POS..DATA_POS + 4].try_into().unwrap()) as usize;
+    let mut at = start;
+    let mut frames = Vec::new();
+    loop {
+        let len = u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+        if len == 0 {
+            return frames;
+        }
+        frames.push((at, len));
+        at += 4 + len + format::FRAME_CHECKSUM;
+    }
+}
+
+fn nonces(path: &Path) -> Vec<Vec<u8>> {
+    let data = std::fs::read(path).unwrap();
+    frames(&data)
+        .into_iter()
+        .map(|(at, _)| data[at + 4..at + 4 + SEAL_NONCE].to_vec())
+        .collect()
+}
+
+// 改掉一条记录里的一个字节，再把校验和改对，只有 tag 认得出来
+fn tamper(path: &Path, record: usize, byte: impl Fn(usize) -> usize) {
+    let mut data = std::fs::read(path).unwrap();
+    let (at, len) = frames(&data)[record];
+    data[at + 4 + byte(len)] ^= 1;
+    let crc = format::checksum(&data[at + 4..at + 4 + len]);
+    data[at + 4 + len..at + 8 + len].copy_from_slice(&crc.to_le_bytes());
+    std::fs::write(path, data).unwrap();
+}
+
+#[test]
+fn nonces_are_never_reused() {
+    let path = fresh("mmlog-test-encrypt-nonces.log");
+    let mut seen = HashSet::new();
+    let mut salts = HashSet::new();
+    let mut check = |nonces: &[Vec<u8>]| {
+        for nonce in nonces {
+            salts.insert(nonce[..SALT_LEN].to_vec());
+            seen.insert(nonce.clone());
+        }
+    };
+
+    let logger = Builder::new().encrypt(KEY).build(&path).unwrap();
+    log(&logger, "one");
+    log(&logger, "two");
+    drop(logger);
+    let first = nonces(&path);
+    assert_eq!(first.len(), 2);
+    check(&first);
+
+    // 重新打开之后序号接着数，salt 也换了
+    let logger = Builder::new().encrypt(KEY).open(&path).unwrap();
+    log(&logger, "three");
+    logger.flush();
+    let second = nonces(&path);
+    assert_eq!(second.len(), 3);
+    check(&second);
+
+    // clear() 之后序号从 0 开始，salt 换了所以 nonce 还是新的
+    logger.clear(true).unwrap();
+    log(&logger, "four");
+    log(&logger, "five");
+    drop(logger);
+    let third = nonces(&path);
+    assert_eq!(third.len(), 2);
+    check(&third);
+
+    assert_eq!(seen.len(), 5);
+    assert_eq!(salts.len(), 3);
+    let reader = LogReader::open_encrypted(&path, &KEY).unwrap();
+    let records: Vec<_> = reader.records().collect();
+    assert_eq!(records.len(), 2);
+    assert!(records[1].ends_with("] five"));
+}
+
+#[test]
+fn tampered_records_are_reported() {
+    let path = fresh("mmlog-test-encrypt-tamper.log");
+    let logger = Builder::new().encrypt(KEY).build(&path).unwrap();
+    for i in 0..4 {
+        log(&logger, format_args!("record {}", i));
+    }
+    drop(logger);
+    // nonce、密文和 tag 各改一处
+    tamper(&path, 0, |_| 0);
+    tamper(&path, 1, |_| SEAL_NONCE + 1);
+    tamper(&path, 2, |len| len - 1);
+
+    let reader = LogReader::open_encrypted(&path, &KEY).unwrap();
+    let records: Vec<_> = reader.checked_records().collect();
+    assert_eq!(records.len(), 4);
+    for record in &records[..3] {
+        assert!(
+            matches!(record, Err(ReadError::Tampered { .. })),
+            "{:?}",
+            record
+        );
+    }
+    assert!(records[3].as_ref().unwrap().ends_with("] record 3"));
+    let plain: Vec<_> = reader.records().collect();
+    assert_eq!(plain.len(), 1);
+}
+
+#[test]
+fn wrong_key_is_rejected() {
+    let path = fresh("mmlog-test-encrypt-key.log");
+    let logger = Builder::new().encrypt(KEY).build(&path).unwrap();
+    log(&logger, "secret");
+    drop(logger);
+
+    let err = LogReader::open_encrypted(&path, &[6; 32]).unwrap_err();
+    assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+    let err = LogReader::open(&path).unwrap_err();
+    assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+    let err = Builder::new().encrypt([6; 32]).open(&path).unwrap_err();
+    assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+    // 没有打开成功的话文件不变
+    let reader = LogReader::open_encrypted(&path, &KEY).unwrap();
+    assert!(reader.records().next().unwrap().ends_with("] secret"));
+
+    // 没加密的文件不能当成加密的打开
+    let plain = fresh("mmlog-test-encrypt-plain.log");
+    drop(Builder::new().build(&plain).unwrap());
+    let err = LogReader::open_encrypted(&plain, &KEY).unwrap_err();
+    assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+    let err = Builder::new().encrypt(KEY).open(&plain).unwrap_err();
+    assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+}
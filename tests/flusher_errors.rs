@@ -0,0 +1,50 @@
+// Builder::flush_interval 的后台线程同步失败：它拿着写锁，不能直接写日志，
+// 错误在下一条记录之前写进去，一直失败的话只写一次。
+// mmlog::test::fail_syncs 影响整个进程，这个文件里只有一个测试
+mod common;
+
+use common::{fresh, log};
+use log::Log;
+use mmlog::Builder;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn flusher_error_is_logged_once() {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let path = fresh("mmlog-test-flusher-errors.log");
+        let logger = Builder::new()
+            .flush_interval(Duration::from_millis(5))
+            .build(&path)
+            .unwrap();
+        mmlog::test::fail_syncs(u64::MAX);
+        // 每条都让后台线程醒来同步一次
+        for i in 0..5 {
+            log(&logger, format_args!("failing {}", i));
+            thread::sleep(Duration::from_millis(50));
+        }
+        mmlog::test::fail_syncs(0);
+        log(&logger, "recovered");
+        logger.flush();
+        tx.send(logger.tail(usize::MAX)).unwrap();
+    });
+    let records = rx
+        .recv_timeout(Duration::from_secs(30))
+        .expect("reporting a flusher error deadlocked");
+    let messages: Vec<_> = records
+        .iter()
+        .map(|r| r.split_once("] ").unwrap().1)
+        .collect();
+    let failed: Vec<_> = messages
+        .iter()
+        .filter(|m| m.starts_with("flush_interval: background sync failed"))
+        .collect();
+    assert_eq!(failed.len(), 1, "{:?}", messages);
+    // 第一条记录同步失败之后，写第二条之前报告
+    assert_eq!(messages[0], "failing 0");
+    assert!(messages[1].starts_with("flush_interval:"), "{:?}", messages);
+    assert_eq!(messages.last(), Some(&"recovered"));
+    assert!(records[1].contains(" E "), "{:?}", records[1]);
+}
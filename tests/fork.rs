@@ -0,0 +1,52 @@
+// Builder::fork_safe：建 logger 失败时不在 fork 的登记表里留下指向已经释放的 logger 的指针。
+// 用了 mmlog::test::fail_spawns，这个文件里只能有一个测试
+#![cfg(all(feature = "mmap", unix))]
+mod common;
+
+use common::{fresh, log};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Error};
+use std::time::Duration;
+
+// 登记表的大小，见 src/fork.rs
+const SLOTS: usize = 32;
+
+fn builder() -> Builder {
+    Builder::new()
+        .fork_safe(true)
+        .flush_interval(Duration::from_millis(10))
+}
+
+#[test]
+fn failed_build_does_not_stay_registered() {
+    // 后台线程启动失败：每次都报错，登记表不会被占满
+    mmlog::test::fail_spawns(SLOTS as u64 * 2);
+    for _ in 0..SLOTS * 2 {
+        let err = builder().build_anonymous().unwrap_err();
+        assert!(matches!(err, Error::StdIo(_)), "{:?}", err);
+    }
+
+    // 登记表还是空的，正好放得下 SLOTS 个
+    let loggers: Vec<_> = (0..SLOTS)
+        .map(|_| builder().build_anonymous().unwrap())
+        .collect();
+    let err = builder().build_anonymous().unwrap_err();
+    assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+    drop(loggers);
+
+    // 子进程里的回调只碰到还活着的 logger
+    let path = fresh("mmlog-test-fork-child.log");
+    let logger = builder().build(&path).unwrap();
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0);
+    if pid == 0 {
+        log(&logger, "from child");
+        unsafe { libc::_exit(0) };
+    }
+    let mut status = -1;
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    assert_eq!(status, 0);
+    drop(logger);
+    let reader = LogReader::open(&path).unwrap();
+    assert!(reader.records().any(|r| r.ends_with("] from child")));
+}
@@ -0,0 +1,149 @@
+// 文件格式不能悄悄变化：同样的输入（固定的时间戳和线程 id、同样的几条记录）
+// 写出来的文件要和 tests/golden/ 里的逐字节相同。加上 --no-default-features 再跑一遍，
+// std-file 后端写出来的也必须一样。
+//
+// 有意修改格式的话要同时升级 format::VERSION，然后重新生成：
+//
+//     MMLOG_BLESS=1 cargo test --test golden --all-features
+//
+// 旧版本的文件留着，检查还能读。header 里的 offset 和计数是本机字节序，
+// 所以只在 64 位小端的机器上比较。这个文件里只能有一个测试，见 mmlog::test::freeze。
+#![cfg(all(target_endian = "little", target_pointer_width = "64"))]
+mod common;
+
+use common::log_with;
+use log::Level;
+use mmlog::format::{
+    ANCHOR_POS, BOOT_ID_LEN, BOOT_ID_POS, CAPACITY_POS, DATA_POS, EXE_LEN, EXE_POS, OFFSET_POS,
+    PID_POS, STARTED_POS, VERSION,
+};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Clock, FormatKind, Framing, Logger, TimestampFormat, KB};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// 文件名里的格式名和对应的设置
+type Mode = (&'static str, fn(Builder) -> Builder);
+
+fn golden(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+}
+
+fn write(logger: &Logger) {
+    let log = |level, target, file: Option<&str>, msg: &str| {
+        let file = file.map(|file| (file, 42));
+        log_with(logger, level, target, file, format_args!("{}", msg));
+    };
+    log(Level::Info, "app", None, "hello");
+    log(
+        Level::Warn,
+        "app::net",
+        Some("src/net.rs"),
+        "retrying [1/3] ]",
+    );
+    log(
+        Level::Error,
+        "app::db",
+        Some("src/db.rs"),
+        "line one\nline two",
+    );
+    log(
+        Level::Debug,
+        "bad] target",
+        Some("src/x.rs"),
+        "quote \" and \\",
+    );
+    log(Level::Trace, "app", None, "bye");
+}
+
+// 文件到最后一条记录为止，创建文件的进程号、启动时间、程序名、时钟锚点和开机 id 清零
+fn snapshot(path: &Path) -> Vec<u8> {
+    let mut file = std::fs::read(path).unwrap();
+    file[PID_POS..STARTED_POS + 8].fill(0);
+    file[EXE_POS..EXE_POS + EXE_LEN].fill(0);
+    file[ANCHOR_POS..ANCHOR_POS + 16].fill(0);
+    file[BOOT_ID_POS..BOOT_ID_POS + BOOT_ID_LEN].fill(0);
+    let start = u32::from_le_bytes(file[DATA_POS..DATA_POS + 4].try_into().unwrap()) as usize;
+    let offset = usize::from_ne_bytes(file[OFFSET_POS..OFFSET_POS + 8].try_into().unwrap());
+    file.truncate(start + offset);
+    file
+}
+
+#[test]
+fn golden_files() {
+    let modes: [Mode; 9] = [
+        ("text", |b| b),
+        ("rfc3339", |b| b.timestamp(TimestampFormat::Rfc3339Utc)),
+        ("extras", |b| {
+            b.sequence_numbers(true)
+                .thread_names(true)
+                .escape_newlines(true)
+        }),
+        ("length-prefixed", |b| b.framing(Framing::LengthPrefixed)),
+        ("json", |b| b.format_kind(FormatKind::Json)),
+        ("logfmt", |b| b.format_kind(FormatKind::Logfmt)),
+        ("monotonic", |b| b.clock(Clock::Monotonic)),
+        ("sanitize-off", |b| b.sanitize(false)),
+        // 读回来的时候用的是真的时钟，期限要够长
+        ("retention", |b| {
+            b.retention(Duration::from_secs(100 * 365 * 24 * 3600))
+        }),
+    ];
+    let bless = std::env::var_os("MMLOG_BLESS").is_some();
+    mmlog::test::freeze(Duration::new(1_700_000_000, 123_456_789), 4242);
+
+    for (mode, configure) in modes {
+        let name = format!("{}-v{}.bin", mode, VERSION);
+        let path = std::env::temp_dir().join(format!("mmlog-test-golden-{}", name));
+        let logger = configure(Builder::new().size(512 * KB).level(Level::Trace))
+            .build(&path)
+            .unwrap();
+        // 线程名固定
+        std::thread::scope(|s| {
+            std::thread::Builder::new()
+                .name("golden".to_string())
+                .spawn_scoped(s, || write(&logger))
+                .unwrap();
+        });
+        drop(logger);
+
+        let actual = snapshot(&path);
+        if bless {
+            std::fs::write(golden(&name), &actual).unwrap();
+            continue;
+        }
+        let expected = std::fs::read(golden(&name))
+            .unwrap_or_else(|e| panic!("{}: {}, regenerate with MMLOG_BLESS=1", name, e));
+        assert!(
+            actual == expected,
+            "{} changed, bump format::VERSION and regenerate with MMLOG_BLESS=1:\n{:?}\n{:?}",
+            name,
+            String::from_utf8_lossy(&actual),
+            String::from_utf8_lossy(&expected)
+        );
+    }
+    mmlog::test::thaw();
+
+    // 所有版本的文件都还能读
+    for entry in std::fs::read_dir(golden("")).unwrap() {
+        let golden = entry.unwrap().path();
+        let mut file = std::fs::read(&golden).unwrap();
+        let capacity =
+            u64::from_le_bytes(file[CAPACITY_POS..CAPACITY_POS + 8].try_into().unwrap()) as usize;
+        let start = u32::from_le_bytes(file[DATA_POS..DATA_POS + 4].try_into().unwrap()) as usize;
+        file.resize(start + capacity, 0);
+        let path = std::env::temp_dir().join("mmlog-test-golden-read.bin");
+        std::fs::write(&path, &file).unwrap();
+        let reader = LogReader::open(&path).unwrap_or_else(|e| panic!("{:?}: {}", golden, e));
+        let records: Vec<_> = reader.records().collect();
+        assert!(records[0].contains("hello"), "{:?}: {:?}", golden, records);
+        assert!(
+            records.last().unwrap().contains("bye"),
+            "{:?}: {:?}",
+            golden,
+            records
+        );
+    }
+}
@@ -0,0 +1,131 @@
+// LogReader::grep 和 mmlog-cat --grep：子串匹配，上下文跨过翻转的地方，重叠的上下文合在一起
+mod common;
+
+use common::log;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, KB};
+use std::path::Path;
+
+fn write(path: &Path, hits: &[usize], total: usize) {
+    let logger = Builder::new().size(512 * KB).build(path).unwrap();
+    for i in 0..total {
+        let msg = if hits.contains(&i) { "needle" } else { "hay" };
+        log(&logger, format_args!("{} {}", msg, i));
+    }
+}
+
+fn number(record: &str) -> usize {
+    record.rsplit(' ').next().unwrap().parse().unwrap()
+}
+
+#[test]
+fn merged_windows() {
+    let path = std::env::temp_dir().join("mmlog-test-grep.log");
+    write(&path, &[0, 2, 3, 10, 19], 20);
+    let reader = LogReader::open(&path).unwrap();
+
+    let matches = reader.grep("needle", 2);
+    let windows: Vec<_> = matches
+        .iter()
+        .map(|m| (m.start(), m.position, m.end()))
+        .collect();
+    // 开头和结尾的上下文不够 2 条；0、2、3 连成一段
+    assert_eq!(
+        windows,
+        [(0, 0, 2), (2, 2, 3), (3, 3, 6), (8, 10, 13), (17, 19, 20)]
+    );
+    for m in &matches {
+        assert_eq!(number(m.record), m.position);
+        let numbers: Vec<_> = m.before.iter().chain(&m.after).map(|r| number(r)).collect();
+        let expected: Vec<_> = (m.start()..m.end()).filter(|&i| i != m.position).collect();
+        assert_eq!(numbers, expected);
+    }
+
+    // 没有上下文就只有命中的记录
+    let matches = reader.grep("needle", 0);
+    assert_eq!(matches.len(), 5);
+    assert!(matches
+        .iter()
+        .all(|m| m.before.is_empty() && m.after.is_empty()));
+    // 大得离谱的上下文也不会越界
+    let matches = reader.grep("needle 19", usize::MAX);
+    assert_eq!((matches[0].start(), matches[0].end()), (0, 20));
+    assert!(reader.grep("nothing", 3).is_empty());
+}
+
+#[test]
+fn context_across_the_wrap() {
+    let path = std::env::temp_dir().join("mmlog-test-grep-wrap.log");
+    write(&path, &[], 20000);
+    let reader = LogReader::open(&path).unwrap();
+    assert!(reader.stats().wrap_count > 0);
+    // 找缓冲区末尾的最后一条记录：它后面的上下文在缓冲区开头
+    let offset = reader.stats().used_bytes_since_wrap;
+    let records: Vec<_> = reader.records().collect();
+    let mut seen = 0;
+    let at = records
+        .iter()
+        .rposition(|r| {
+            seen += r.len() + 1;
+            seen > offset
+        })
+        .unwrap();
+    let pattern = format!("hay {}", number(records[at]));
+    let matches: Vec<_> = reader
+        .grep(&pattern, 3)
+        .into_iter()
+        .filter(|m| m.record.ends_with(&pattern))
+        .collect();
+    assert_eq!(matches.len(), 1);
+    let m = &matches[0];
+    assert_eq!(m.position, at);
+    let numbers: Vec<_> = m
+        .before
+        .iter()
+        .chain([&m.record])
+        .chain(&m.after)
+        .map(|r| number(r))
+        .collect();
+    let first = number(records[at]) - 3;
+    assert_eq!(numbers, (first..first + 7).collect::<Vec<_>>());
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli_grep() {
+    use std::process::Command;
+
+    let path = std::env::temp_dir().join("mmlog-test-grep-cli.log");
+    write(&path, &[1, 8], 10);
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_mmlog-cat"))
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout
+            .lines()
+            .map(|line| match line {
+                "--" => line.to_string(),
+                _ => number(line).to_string(),
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(run(&["--grep", "needle"]), ["1", "8"]);
+    assert_eq!(
+        run(&["--grep", "needle", "-C", "1"]),
+        ["0", "1", "2", "--", "7", "8", "9"]
+    );
+    assert_eq!(
+        run(&["--grep", "needle", "-C", "3"]),
+        ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"]
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_mmlog-cat"))
+        .args(["-C", "1"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
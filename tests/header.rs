@@ -0,0 +1,145 @@
+// 打开 header 不对的文件：offset 越界、翻转次数溢出、不是 mmlog 的文件、版本不支持
+mod common;
+
+use common::{fresh, log};
+use mmlog::format::{MAGIC_POS, OFFSET_POS, VERSION, VERSION_POS, WRAPS_POS};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Error, MB};
+use std::path::Path;
+
+const WORD: usize = std::mem::size_of::<usize>();
+
+// 建一个有几条记录的文件，返回缓冲区的大小
+fn create(path: &Path) -> usize {
+    let logger = Builder::new().build(path).unwrap();
+    for i in 0..3 {
+        log(&logger, format_args!("record {}", i));
+    }
+    logger.stats().capacity
+}
+
+fn patch(path: &Path, at: usize, bytes: &[u8]) -> Vec<u8> {
+    let mut file = std::fs::read(path).unwrap();
+    file[at..at + bytes.len()].copy_from_slice(bytes);
+    std::fs::write(path, &file).unwrap();
+    file
+}
+
+#[test]
+fn offset_out_of_range() {
+    let path = fresh("mmlog-test-header-offset.log");
+    let capacity = create(&path);
+    let bogus = capacity + 100;
+    let crafted = patch(&path, OFFSET_POS, &bogus.to_ne_bytes());
+
+    let err = Builder::new().open(&path).unwrap_err();
+    assert!(
+        matches!(&err, Error::CorruptHeader(msg) if msg.contains(&bogus.to_string())),
+        "{:?}",
+        err
+    );
+    let err = LogReader::open(&path).unwrap_err();
+    assert!(matches!(err, Error::CorruptHeader(_)), "{:?}", err);
+    // 打开失败的话文件不变
+    assert!(std::fs::read(&path).unwrap() == crafted);
+
+    // repair 从头开始写，并且留下提示
+    let logger = Builder::new().repair(true).open(&path).unwrap();
+    let stats = logger.stats();
+    assert_eq!(stats.capacity, capacity);
+    assert!(stats.used_bytes_since_wrap < capacity);
+    log(&logger, "after repair");
+    let records = logger.tail(2);
+    assert!(
+        records[0].ends_with(&format!(
+            "repaired a corrupt header: offset {} was beyond the buffer size {}, reset to 0",
+            bogus, capacity
+        )),
+        "{:?}",
+        records
+    );
+    assert!(records[1].ends_with("] after repair"), "{:?}", records);
+    drop(logger);
+
+    let stored = std::fs::read(&path).unwrap()[OFFSET_POS..OFFSET_POS + WORD].to_vec();
+    let stored = usize::from_ne_bytes(stored.try_into().unwrap());
+    assert!(stored < capacity);
+    let reader = LogReader::open(&path).unwrap();
+    assert!(reader.records().last().unwrap().ends_with("] after repair"));
+}
+
+// 各种打开方式都报错，文件原样留着，不会被当成新文件重新初始化
+fn rejected(path: &Path, check: impl Fn(&Error) -> bool) {
+    let before = std::fs::read(path).unwrap();
+    let errors = [
+        Builder::new().open(path).map(drop).unwrap_err(),
+        Builder::new().open_or_create(path).map(drop).unwrap_err(),
+        Builder::new().size(MB).open(path).map(drop).unwrap_err(),
+        LogReader::open(path).map(drop).unwrap_err(),
+    ];
+    for err in &errors {
+        assert!(check(err), "{:?}", err);
+    }
+    assert!(std::fs::read(path).unwrap() == before);
+}
+
+#[test]
+fn wraps_overflow() {
+    // 翻转次数 × 缓冲区大小算逻辑位置时溢出，报错而不是 panic
+    let path = fresh("mmlog-test-header-wraps.log");
+    let capacity = create(&path);
+    for wraps in [u64::MAX, u64::MAX / capacity as u64] {
+        patch(&path, WRAPS_POS, &wraps.to_ne_bytes());
+        rejected(
+            &path,
+            |e| matches!(e, Error::CorruptHeader(msg) if msg.contains(&wraps.to_string())),
+        );
+        let err = LogReader::open_lenient(&path).map(drop).unwrap_err();
+        assert!(matches!(err, Error::CorruptHeader(_)), "{:?}", err);
+    }
+
+    // 不溢出的大翻转次数照常打开
+    let wraps = u64::MAX / capacity as u64 - 1;
+    patch(&path, WRAPS_POS, &wraps.to_ne_bytes());
+    let reader = LogReader::open(&path).unwrap();
+    assert_eq!(reader.stats().wrap_count, wraps);
+}
+
+#[test]
+fn wrong_magic() {
+    let path = fresh("mmlog-test-header-magic.log");
+    create(&path);
+    patch(&path, MAGIC_POS, b"XXXX");
+    rejected(
+        &path,
+        |e| matches!(e, Error::CorruptHeader(msg) if msg == "not an mmlog file"),
+    );
+}
+
+#[test]
+fn unsupported_version() {
+    for version in [VERSION + 1, 3] {
+        let path = fresh(&format!("mmlog-test-header-version-{}.log", version));
+        create(&path);
+        patch(&path, VERSION_POS, &version.to_le_bytes());
+        rejected(
+            &path,
+            |e| matches!(e, Error::UnsupportedVersion(v) if *v == version),
+        );
+    }
+}
+
+#[test]
+fn legacy_file() {
+    // 最早的格式：开头只有一个 offset，后面就是记录
+    let path = fresh("mmlog-test-header-legacy.log");
+    let mut file = vec![0; 512 * 1024 + WORD];
+    let record = b"1700000000.000000000 42 I [main.rs:1] hello\n";
+    file[..WORD].copy_from_slice(&record.len().to_ne_bytes());
+    file[WORD..WORD + record.len()].copy_from_slice(record);
+    std::fs::write(&path, &file).unwrap();
+    rejected(
+        &path,
+        |e| matches!(e, Error::CorruptHeader(msg) if msg.starts_with("legacy mmlog file")),
+    );
+}
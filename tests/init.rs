@@ -0,0 +1,36 @@
+// Builder::init：安装成全局 logger。一个进程只能装一次，所以这里只有一个测试
+mod common;
+
+use common::fresh;
+use log::{Level, LevelFilter, Log};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Error};
+
+#[test]
+fn init_installs_once() {
+    let path = fresh("mmlog-test-init.log");
+    let logger = Builder::new()
+        .level(Level::Warn)
+        .module_level("app::noisy", LevelFilter::Debug)
+        .init(&path)
+        .unwrap();
+    // 最大级别取最详细的那个
+    assert_eq!(log::max_level(), LevelFilter::Debug);
+
+    log::warn!(target: "app", "warned");
+    log::info!(target: "app", "filtered");
+    log::debug!(target: "app::noisy::db", "noisy");
+    logger.flush();
+
+    // 再装一次报错，不会 panic，也不会换掉已经装上的
+    let err = Builder::new()
+        .init(std::env::temp_dir().join("mmlog-test-init-again.log"))
+        .unwrap_err();
+    assert!(matches!(err, Error::SetLogger(_)), "{:?}", err);
+    log::error!(target: "app", "still here");
+    logger.flush();
+
+    let reader = LogReader::open(&path).unwrap();
+    let messages: Vec<_> = reader.entries().map(|e| e.message).collect();
+    assert_eq!(messages, ["warned", "noisy", "still here"]);
+}
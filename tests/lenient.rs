@@ -0,0 +1,172 @@
+// LogReader::open_lenient：比 header 里说的短的文件，缺口两边的记录照常读出来，
+// 跨过缺口的记录报告成 ReadError::Truncated
+mod common;
+
+use common::log;
+use mmlog::format::{HEADER_SIZE, META_SIZE};
+use mmlog::reader::{LogReader, ReadError};
+use mmlog::{Builder, Error, Framing, KB};
+use std::path::{Path, PathBuf};
+
+fn write(name: &str, framing: Framing, n: usize) -> (PathBuf, Vec<u8>) {
+    let path = std::env::temp_dir().join(format!("mmlog-test-lenient-{}-{:?}.log", name, framing));
+    let logger = Builder::new()
+        .size(512 * KB)
+        .framing(framing)
+        .build(&path)
+        .unwrap();
+    for i in 0..n {
+        log(&logger, format_args!("record {} end", i));
+    }
+    drop(logger);
+    let data = std::fs::read(&path).unwrap();
+    (path, data)
+}
+
+fn truncate(path: &Path, data: &[u8], len: usize) {
+    std::fs::write(path, &data[..len]).unwrap();
+}
+
+fn number(record: &str) -> usize {
+    let msg = record.rsplit_once("] ").unwrap().1;
+    msg.strip_prefix("record ")
+        .unwrap()
+        .strip_suffix(" end")
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+// from 之后第一条记录的正中间：截断的位置和这条记录的序号
+fn cut_inside(data: &[u8], from: usize) -> (usize, usize) {
+    let at = from
+        + data[from..]
+            .windows(7)
+            .position(|w| w == b"record ")
+            .unwrap();
+    let digits = &data[at + 7..];
+    let end = digits.iter().position(|&b| b == b' ').unwrap();
+    let n = std::str::from_utf8(&digits[..end])
+        .unwrap()
+        .parse()
+        .unwrap();
+    (at + 3, n)
+}
+
+#[test]
+fn not_wrapped() {
+    for framing in [Framing::Text, Framing::LengthPrefixed] {
+        let (path, data) = write("short", framing, 1000);
+        let start = data.len() - 512 * KB;
+        // 截在某条记录的正中间
+        let (cut, broken) = cut_inside(&data, start + 20000);
+        truncate(&path, &data, cut);
+
+        assert!(LogReader::open(&path).is_err());
+        let reader = LogReader::open_lenient(&path).unwrap();
+        let numbers: Vec<_> = reader.records().map(number).collect();
+        assert_eq!(numbers, (0..broken).collect::<Vec<_>>(), "{:?}", framing);
+        let checked: Vec<_> = reader.checked_records().collect();
+        assert!(
+            matches!(checked.last(), Some(Err(ReadError::Truncated { .. }))),
+            "{:?}",
+            framing
+        );
+        let summary = reader.summary();
+        assert!(summary.truncated());
+        assert_eq!(summary.expected_bytes, data.len() as u64);
+        assert_eq!(summary.present_bytes, cut as u64);
+        assert_eq!((summary.records, summary.corrupt), (broken, 1));
+    }
+}
+
+#[test]
+fn wrapped() {
+    for framing in [Framing::Text, Framing::LengthPrefixed] {
+        let (path, data) = write("wrapped", framing, 30000);
+        let full = LogReader::open(&path).unwrap();
+        let all: Vec<_> = full.records().map(number).collect();
+        let offset = full.stats().used_bytes_since_wrap;
+        let start = data.len() - 512 * KB;
+        // 截在 offset 之后的旧数据里：缺口前面是较旧的一段，后面是 offset 之前最新的一段
+        let (cut, broken) = cut_inside(&data, start + offset + (512 * KB - offset) / 2);
+        truncate(&path, &data, cut);
+
+        let reader = LogReader::open_lenient(&path).unwrap();
+        let numbers: Vec<_> = reader.records().map(number).collect();
+        let gap = numbers.iter().position(|&n| n > broken).unwrap();
+        // 缺口两边各自连续，前面到截断的那条为止，后面一直到最后一条
+        assert_eq!(numbers[0], all[0], "{:?}", framing);
+        assert!(numbers[..gap].windows(2).all(|w| w[1] == w[0] + 1));
+        assert_eq!(numbers[gap - 1] + 1, broken, "{:?}", framing);
+        assert!(numbers[gap..].windows(2).all(|w| w[1] == w[0] + 1));
+        assert_eq!(numbers.last(), Some(&29999));
+        assert!(numbers.len() < all.len());
+        // 截断处断开的那条在缺口的位置报告
+        let checked: Vec<_> = reader.checked_records().collect();
+        let truncated: Vec<_> = checked
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| matches!(r, Err(ReadError::Truncated { .. })))
+            .map(|(i, _)| i)
+            .collect();
+        assert!(
+            !truncated.is_empty() && truncated.len() <= 2,
+            "{:?}",
+            truncated
+        );
+        assert_eq!(truncated[0], gap, "{:?}", framing);
+        assert!(reader.records().all(|r| !r.contains('\0')));
+    }
+}
+
+#[test]
+fn whole_file_and_header() {
+    let (path, data) = write("whole", Framing::Text, 100);
+    // 没有截断的话和 open 一样
+    let reader = LogReader::open_lenient(&path).unwrap();
+    assert!(!reader.summary().truncated());
+    assert!(reader
+        .records()
+        .eq(LogReader::open(&path).unwrap().records()));
+
+    // 数据之前的部分都不全的话没法读
+    truncate(&path, &data, HEADER_SIZE + META_SIZE / 2);
+    let err = LogReader::open_lenient(&path).unwrap_err();
+    assert!(matches!(err, Error::CorruptHeader(_)), "{:?}", err);
+    // 正好截在数据开始的地方：什么都没有
+    let start = data.len() - 512 * KB;
+    truncate(&path, &data, start);
+    let reader = LogReader::open_lenient(&path).unwrap();
+    assert_eq!(reader.records().count(), 0);
+    assert_eq!(reader.summary().present_bytes, start as u64);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli_lenient() {
+    use std::process::Command;
+
+    let (path, data) = write("cli", Framing::Text, 100);
+    let (cut, broken) = cut_inside(&data, data.len() - 512 * KB + 1000);
+    truncate(&path, &data, cut);
+    let cat = |lenient: bool| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_mmlog-cat"));
+        if lenient {
+            cmd.arg("--lenient");
+        }
+        cmd.arg(&path).output().unwrap()
+    };
+    assert_eq!(cat(false).status.code(), Some(1));
+    let output = cat(true);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), broken);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("the file is truncated"), "{}", stderr);
+    assert!(
+        stderr.contains("cut off by the end of the file"),
+        "{}",
+        stderr
+    );
+}
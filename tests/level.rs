@@ -0,0 +1,59 @@
+// Logger::set_level：运行时调整级别，通过 init() 装上的同时更新 log::max_level
+mod common;
+
+use common::{fresh, log_at};
+use log::{Level, LevelFilter, Log};
+use mmlog::reader::LogReader;
+use mmlog::Builder;
+
+#[test]
+fn set_level_filters() {
+    let logger = Builder::new()
+        .level(Level::Warn)
+        .module_level("app::db", LevelFilter::Error)
+        .build_anonymous()
+        .unwrap();
+    assert_eq!(logger.level(), Level::Warn);
+    assert_eq!(logger.level_filter(), LevelFilter::Warn);
+    log_at(&logger, Level::Info, "app", "dropped");
+
+    logger.set_level(Level::Debug);
+    assert_eq!(logger.level(), Level::Debug);
+    assert!(logger.target_enabled(Level::Debug, "app"));
+    assert!(!logger.target_enabled(Level::Trace, "app"));
+    // 单独设置过的模块不受影响
+    assert!(!logger.target_enabled(Level::Warn, "app::db"));
+    log_at(&logger, Level::Debug, "app", "verbose");
+
+    logger.set_level(Level::Error);
+    log_at(&logger, Level::Warn, "app", "dropped again");
+    log_at(&logger, Level::Error, "app", "error");
+
+    let messages: Vec<_> = logger
+        .tail(usize::MAX)
+        .iter()
+        .map(|r| r.split_once("] ").unwrap().1.to_string())
+        .collect();
+    assert_eq!(messages, ["verbose", "error"]);
+}
+
+#[test]
+fn set_level_updates_max_level() {
+    let path = fresh("mmlog-test-level.log");
+    let logger = Builder::new().level(Level::Info).init(&path).unwrap();
+    assert_eq!(log::max_level(), LevelFilter::Info);
+    log::debug!(target: "app", "hidden");
+
+    logger.set_level(Level::Trace);
+    assert_eq!(log::max_level(), LevelFilter::Trace);
+    log::trace!(target: "app", "shown");
+
+    logger.set_level(Level::Warn);
+    assert_eq!(log::max_level(), LevelFilter::Warn);
+    log::info!(target: "app", "hidden");
+    logger.flush();
+
+    let reader = LogReader::open(&path).unwrap();
+    let messages: Vec<_> = reader.entries().map(|e| e.message).collect();
+    assert_eq!(messages, ["shown"]);
+}
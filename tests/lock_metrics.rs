@@ -0,0 +1,47 @@
+// Builder::lock_metrics：没有竞争时什么都不记，抢锁时计数和等待时间的分布对得上
+mod common;
+
+use common::log;
+use log::Log;
+use mmlog::{Builder, LockStats, MB};
+use std::thread;
+
+#[test]
+fn disabled_by_default() {
+    let logger = Builder::new().build_anonymous().unwrap();
+    log(&logger, "record 0");
+    assert_eq!(logger.lock_stats(), None);
+}
+
+#[test]
+fn uncontended() {
+    let logger = Builder::new().lock_metrics(true).build_anonymous().unwrap();
+    for i in 0..1000 {
+        log(&logger, format_args!("record {}", i));
+    }
+    logger.flush();
+    assert_eq!(logger.lock_stats(), Some(LockStats::default()));
+}
+
+#[test]
+fn contended() {
+    let logger = Builder::new()
+        .size(16 * MB)
+        .lock_metrics(true)
+        .build_anonymous()
+        .unwrap();
+    thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                for i in 0..20000 {
+                    log(&logger, format_args!("record {}", i));
+                }
+            });
+        }
+    });
+    let stats = logger.lock_stats().unwrap();
+    assert!(stats.contended > 0, "{:?}", stats);
+    // 每次等锁都落在分布的某一格里
+    assert_eq!(stats.wait_histogram.iter().sum::<u64>(), stats.contended);
+    assert_eq!(logger.stats().records_written, 8 * 20000);
+}
@@ -0,0 +1,362 @@
+// LogReader::items：解析不了的行作为续行或者 Item::Malformed 留在原来的位置，
+// 任何输入都不会 panic
+mod common;
+
+use common::{fresh, log, log_at};
+use log::Level;
+use mmlog::format::{
+    self, CHANNEL_ENTRY_SIZE, CHANNEL_TABLE_POS, DATA_POS, OFFSET_POS, RESERVED_WRAPS_POS,
+    WRAPS_POS,
+};
+use mmlog::parse::{Entry, Item, MAX_CONTINUATION};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Error, Framing, KB};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+// 记录就是原样的消息，想写什么样的行都行
+fn write_raw(name: &str, framing: Framing, lines: &[&str]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mmlog-test-malformed-{}.log", name));
+    let logger = Builder::new()
+        .size(512 * KB)
+        .framing(framing)
+        .format(|w, record| write!(w, "{}", record.args()))
+        .build(&path)
+        .unwrap();
+    for line in lines {
+        log(&logger, line);
+    }
+    drop(logger);
+    path
+}
+
+fn message(item: &Item) -> Option<(&str, usize)> {
+    match item {
+        Item::Entry { entry, continued } => Some((&entry.message, *continued)),
+        Item::Malformed(_) => None,
+    }
+}
+
+#[test]
+fn lines_in_place() {
+    let path = write_raw(
+        "in-place",
+        Framing::Text,
+        &[
+            "orphan",
+            "[1700000000.0s 1 E  app] panicked",
+            "  0: main",
+            "  1: start",
+            "[1700000001.0s 1 I  app] second",
+            "bad\0line",
+            "after the bad line",
+            "ts=1700000002.0s tid=1 level=warn target=app msg=third",
+            "more",
+        ],
+    );
+    let reader = LogReader::open(&path).unwrap();
+    let items: Vec<_> = reader.items().collect();
+    assert_eq!(items.len(), 6);
+    assert_eq!(items[0], Item::Malformed("orphan".to_string()));
+    assert_eq!(
+        message(&items[1]),
+        Some(("panicked\n  0: main\n  1: start", 2))
+    );
+    assert_eq!(message(&items[2]), Some(("second", 0)));
+    assert_eq!(items[3], Item::Malformed("bad\0line".to_string()));
+    assert_eq!(items[4], Item::Malformed("after the bad line".to_string()));
+    assert_eq!(message(&items[5]), Some(("third\nmore", 1)));
+
+    let messages: Vec<_> = reader.entries().map(|e| e.message).collect();
+    assert_eq!(
+        messages,
+        ["panicked\n  0: main\n  1: start", "second", "third\nmore"]
+    );
+
+    let summary = reader.summary();
+    assert_eq!(summary.records, 9);
+    assert_eq!(
+        (summary.well_formed, summary.continuation, summary.malformed),
+        (3, 3, 3)
+    );
+}
+
+#[test]
+fn continuation_is_capped() {
+    let line = "x".repeat(KB - 1);
+    let mut lines = vec!["[1700000000.0s 1 E  app] big"];
+    lines.extend(std::iter::repeat_n(line.as_str(), 100));
+    let path = write_raw("capped", Framing::Text, &lines);
+
+    let reader = LogReader::open(&path).unwrap();
+    let items: Vec<_> = reader.items().collect();
+    let (message, continued) = message(&items[0]).unwrap();
+    assert!(continued > 0);
+    assert!(message.len() - "big".len() <= MAX_CONTINUATION);
+    assert_eq!(message.len(), "big".len() + continued * KB);
+    assert_eq!(items.len(), 1 + 100 - continued);
+    assert!(items[1..]
+        .iter()
+        .all(|item| *item == Item::Malformed(line.clone())));
+}
+
+#[test]
+fn length_prefixed_records_are_not_continuations() {
+    let path = write_raw(
+        "length-prefixed",
+        Framing::LengthPrefixed,
+        &["[1700000000.0s 1 E  app] first", "not a record"],
+    );
+    let reader = LogReader::open(&path).unwrap();
+    let items: Vec<_> = reader.items().collect();
+    assert_eq!(message(&items[0]), Some(("first", 0)));
+    assert_eq!(items[1], Item::Malformed("not a record".to_string()));
+}
+
+// 确定的伪随机数，失败了能重现
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// 容易碰到解析器边界情况的字符
+const PIECES: &[&str] = &[
+    "[",
+    "]",
+    " ",
+    "#",
+    "\\x",
+    "\\x5",
+    "\\u",
+    "\\u00e9",
+    "\"",
+    "=",
+    ":",
+    "/",
+    "-",
+    "+",
+    ".",
+    "s",
+    "T",
+    "Z",
+    "E",
+    "W",
+    "I",
+    "D",
+    "0",
+    "9",
+    "999999999999999",
+    "99999999999999999999",
+    "é",
+    "\0",
+    "\n",
+];
+
+fn mutate(rng: &mut Rng, s: &str) -> String {
+    let mut s = s.to_string();
+    for _ in 0..1 + rng.below(4) {
+        let mut at = rng.below(s.len() + 1);
+        while !s.is_char_boundary(at) {
+            at -= 1;
+        }
+        match rng.below(3) {
+            0 => s.insert_str(at, PIECES[rng.below(PIECES.len())]),
+            1 => s.truncate(at),
+            _ => {
+                let mut end = (at + 1 + rng.below(8)).min(s.len());
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                s.replace_range(at..end.max(at), "");
+            }
+        }
+    }
+    s
+}
+
+#[test]
+fn never_panics() {
+    let samples = [
+        "[1700000000.5s 42 W src/main.rs:7 app::net] bad [frame] ]",
+        "#3 [2023-11-14T22:13:20.1Z 42/pool W 1 I  app] hi",
+        r"[1700000000.5s 42 E C:\x5cx\x5d.rs:1 a\x5d\x20[fake] hi",
+        "[2023-11-15T06:13:20.1+08:00 1 I  app] hi",
+        r#"ts=1700000000.5s tid=42 thread="io pool" level=warn target=app::net file=src/main.rs line=7 msg="bad \"frame\"\n\u00e9" peer=10.0.0.1"#,
+    ];
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut lines = Vec::new();
+    for _ in 0..20_000 {
+        let sample = samples[rng.below(samples.len())];
+        let line = mutate(&mut rng, sample);
+        let _ = Entry::parse(&line);
+        let _ = Entry::parse_logfmt(&line);
+        let _ = format::parse_timestamp(&line);
+        let _ = format::unsanitize(&line);
+        if lines.len() < 500 {
+            lines.push(line);
+        }
+    }
+
+    // 整个文件：随机的行，再随机改掉缓冲区里的字节和 header 里的 offset、翻转次数
+    for framing in [Framing::Text, Framing::LengthPrefixed] {
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let path = write_raw(&format!("fuzz-{:?}", framing), framing, &lines);
+        let original = std::fs::read(&path).unwrap();
+        let data =
+            u32::from_le_bytes(original[DATA_POS..DATA_POS + 4].try_into().unwrap()) as usize;
+        let size = original.len() - data;
+        for round in 0..100 {
+            let mut bytes = original.clone();
+            for _ in 0..rng.below(64) {
+                let at = data + rng.below(size);
+                bytes[at] = rng.next() as u8;
+            }
+            if round % 2 == 1 {
+                let offset = rng.below(size + 2) as u64;
+                bytes[OFFSET_POS..OFFSET_POS + 8].copy_from_slice(&offset.to_le_bytes());
+                bytes[WRAPS_POS..WRAPS_POS + 8]
+                    .copy_from_slice(&wraps(&mut rng, size).to_ne_bytes());
+            }
+            if round % 5 == 4 {
+                bytes.truncate(data + rng.below(size));
+            }
+            std::fs::write(&path, &bytes).unwrap();
+
+            for reader in [LogReader::open(&path), LogReader::open_lenient(&path)] {
+                let reader = match reader {
+                    Ok(reader) => reader,
+                    Err(_) => continue,
+                };
+                let summary = reader.summary();
+                let items = reader.items().count();
+                assert_eq!(items, summary.well_formed + summary.malformed);
+                assert_eq!(reader.entries().count(), summary.well_formed);
+                assert_eq!(
+                    reader.checked_records().count(),
+                    summary.records + summary.corrupt
+                );
+                let _ = reader.grep("record", 2);
+                let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+                let _ = reader
+                    .range(start, start + Duration::from_secs(3600))
+                    .count();
+            }
+        }
+    }
+}
+
+// 翻转次数取遍整个 u64，多取溢出的边界附近的值
+fn wraps(rng: &mut Rng, len: usize) -> u64 {
+    let edge = u64::MAX / len as u64;
+    match rng.below(4) {
+        0 => rng.below(3) as u64,
+        1 => rng.next(),
+        2 => edge - 2 + rng.below(4) as u64,
+        _ => u64::MAX - rng.below(2) as u64,
+    }
+}
+
+// 三种布局：一个环、给 Warn/Error 分出一个环、两个通道。返回文件和每个环的翻转次数的位置、长度
+fn rings(name: &str) -> Vec<(PathBuf, Vec<(usize, usize)>)> {
+    let entry = |i: usize| CHANNEL_TABLE_POS + i * CHANNEL_ENTRY_SIZE;
+    let layouts = [
+        ("main", Builder::new(), vec![(WRAPS_POS, 512 * KB)]),
+        (
+            "reserved",
+            Builder::new().reserve_for_errors(0.25),
+            vec![(WRAPS_POS, 384 * KB), (RESERVED_WRAPS_POS, 128 * KB)],
+        ),
+        (
+            "channels",
+            Builder::new().channels(&[("a", 256 * KB), ("b", 256 * KB)]),
+            // 通道表的每一项里翻转次数在第 56 个字节
+            vec![(entry(0) + 56, 256 * KB), (entry(1) + 56, 256 * KB)],
+        ),
+    ];
+    layouts
+        .into_iter()
+        .map(|(layout, builder, rings)| {
+            let path = fresh(&format!("mmlog-test-malformed-{}-{}.log", name, layout));
+            let logger = builder.size(512 * KB).build(&path).unwrap();
+            for (i, level) in [Level::Info, Level::Error].into_iter().enumerate() {
+                log_at(&logger, level, "", format_args!("record {}", i));
+            }
+            drop(logger);
+            (path, rings)
+        })
+        .collect()
+}
+
+#[test]
+fn fuzz_wrap_counters() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for (path, rings) in rings("fuzz-wraps") {
+        let original = std::fs::read(&path).unwrap();
+        for _ in 0..100 {
+            let mut bytes = original.clone();
+            for &(at, len) in &rings {
+                if rng.below(3) > 0 {
+                    bytes[at..at + 8].copy_from_slice(&wraps(&mut rng, len).to_ne_bytes());
+                }
+            }
+            std::fs::write(&path, &bytes).unwrap();
+
+            for reader in [LogReader::open(&path), LogReader::open_lenient(&path)] {
+                let reader = match reader {
+                    Ok(reader) => reader,
+                    Err(e) => {
+                        assert!(matches!(e, Error::CorruptHeader(_)), "{:?}", e);
+                        continue;
+                    }
+                };
+                let _ = reader.stats();
+                let _ = reader.records().count();
+                let _ = reader.summary();
+            }
+            if let Ok(logger) = Builder::new().open(&path) {
+                // 写满一圈，翻转次数再加一
+                let line = "x".repeat(KB);
+                for _ in 0..600 {
+                    log_at(&logger, Level::Error, "", &line);
+                }
+                let _ = logger.stats();
+                let _ = logger.tail(3);
+            }
+        }
+    }
+}
+
+#[test]
+fn max_wraps() {
+    for (path, rings) in rings("max-wraps") {
+        let original = std::fs::read(&path).unwrap();
+        for &(at, _) in &rings {
+            let mut bytes = original.clone();
+            bytes[at..at + 8].copy_from_slice(&u64::MAX.to_ne_bytes());
+            std::fs::write(&path, &bytes).unwrap();
+            let errors = [
+                LogReader::open(&path).map(drop).unwrap_err(),
+                LogReader::open_lenient(&path).map(drop).unwrap_err(),
+                Builder::new().open(&path).map(drop).unwrap_err(),
+            ];
+            for err in errors {
+                assert!(
+                    matches!(&err, Error::CorruptHeader(msg) if msg.contains(&u64::MAX.to_string())),
+                    "{:?} {:?}",
+                    path,
+                    err
+                );
+            }
+        }
+    }
+}
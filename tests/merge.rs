@@ -0,0 +1,154 @@
+// mmlog::merge：时间戳写法、记录格式和时钟都不一样的文件按换算成纳秒之后的时间合并。
+// 用 mmlog::test::freeze 控制每个文件写进去的时间戳，这个文件里只能有一个测试
+mod common;
+
+use common::log_at;
+use log::Level;
+use mmlog::format::{Clock, FormatKind, TimestampFormat, ANCHOR_POS};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, KB};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+const T0: Duration = Duration::from_secs(1_700_000_000);
+// 单调时钟的文件里和 T0 对应的读数
+const M0: Duration = Duration::from_secs(5000);
+
+fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mmlog-test-merge-{}.log", name))
+}
+
+// 换掉 header 里的锚点
+fn set_anchor(path: &Path, realtime: Duration, monotonic: Duration) {
+    let mut file = std::fs::read(path).unwrap();
+    let anchor = &mut file[ANCHOR_POS..ANCHOR_POS + 16];
+    anchor[..8].copy_from_slice(&(realtime.as_nanos() as u64).to_le_bytes());
+    anchor[8..].copy_from_slice(&(monotonic.as_nanos() as u64).to_le_bytes());
+    std::fs::write(path, file).unwrap();
+}
+
+#[test]
+fn cross_format_merge() {
+    let builder = || Builder::new().size(512 * KB);
+    let writers = [
+        ("epoch", builder()),
+        (
+            "utc",
+            builder()
+                .timestamp(TimestampFormat::Rfc3339Utc)
+                .format_kind(FormatKind::Json),
+        ),
+        (
+            "local",
+            builder()
+                .timestamp(TimestampFormat::LocalTime)
+                .format_kind(FormatKind::Logfmt),
+        ),
+        ("uptime", builder().clock(Clock::Monotonic)),
+        ("lost", builder().clock(Clock::Monotonic)),
+    ];
+    let loggers: Vec<_> = writers
+        .into_iter()
+        .map(|(name, builder)| (name, builder.build(path(name)).unwrap()))
+        .collect();
+
+    // 第 i 条记录在 T0 + i 秒，轮流写进前四个文件；最后一个文件也写，但是锚点丢了
+    let n = 20;
+    for i in 0..n {
+        let (name, logger) = &loggers[i % 4];
+        let at = if *name == "uptime" { M0 } else { T0 };
+        mmlog::test::freeze(at + Duration::from_secs(i as u64), 1);
+        log_at(logger, Level::Info, "app", format_args!("event {}", i));
+    }
+    for i in 0..3 {
+        mmlog::test::freeze(M0 + Duration::from_secs(i), 1);
+        log_at(
+            &loggers[4].1,
+            Level::Info,
+            "app",
+            format_args!("lost {}", i),
+        );
+    }
+    mmlog::test::thaw();
+    drop(loggers);
+    set_anchor(&path("uptime"), T0, M0);
+    set_anchor(&path("lost"), Duration::ZERO, Duration::ZERO);
+
+    // 每个文件的时间戳都换算成同一个纳秒数
+    for (i, name) in ["epoch", "utc", "local", "uptime"].iter().enumerate() {
+        let reader = LogReader::open(path(name)).unwrap();
+        assert!(reader.normalizable());
+        let first = reader.records().next().unwrap();
+        let expected = (T0 + Duration::from_secs(i as u64)).as_nanos() as i128;
+        assert_eq!(reader.normalize(first), Some(expected), "{}", name);
+        // 第 i + 4k 条记录在这个文件的第 k 条
+        let at = UNIX_EPOCH + T0 + Duration::from_secs(9);
+        assert_eq!(reader.seek_time(at), Some((9 - i).div_ceil(4)), "{}", name);
+        assert_eq!(reader.seek_time(UNIX_EPOCH), Some(0));
+        assert_eq!(reader.seek_time(at + Duration::from_secs(3600)), Some(5));
+    }
+    let lost = LogReader::open(path("lost")).unwrap();
+    assert!(!lost.normalizable());
+    assert_eq!(lost.normalize(lost.records().next().unwrap()), None);
+    assert_eq!(lost.seek_time(UNIX_EPOCH), None);
+
+    let names = ["epoch", "utc", "local", "uptime", "lost"];
+    let paths: Vec<_> = names.iter().map(|name| path(name)).collect();
+    let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    let merged = |paths: &[&Path]| {
+        let mut out = Vec::new();
+        let stats = mmlog::merge(paths, &mut out).unwrap();
+        let lines: Vec<_> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (source, record) = line.split_once(": ").unwrap();
+                let name = source.trim_start_matches("mmlog-test-merge-");
+                let name = name.trim_end_matches(".log").to_string();
+                // 默认格式在行尾，JSON 和 logfmt 在引号里
+                let at = record
+                    .find("event ")
+                    .or_else(|| record.find("lost "))
+                    .unwrap();
+                let message = record[at..].split('"').next().unwrap().to_string();
+                (name, message)
+            })
+            .collect::<Vec<_>>();
+        (stats, lines)
+    };
+
+    let (stats, lines) = merged(&paths[..4]);
+    assert_eq!(stats.records, n);
+    assert!(stats.warnings.is_empty());
+    let expected: Vec<_> = (0..n)
+        .map(|i| (names[i % 4].to_string(), format!("event {}", i)))
+        .collect();
+    assert_eq!(lines, expected);
+
+    // 换算不了的文件跟在最后，按文件里的顺序
+    let (stats, lines) = merged(&[paths[4], paths[0], paths[1], paths[2], paths[3]]);
+    assert_eq!(stats.records, n + 3);
+    assert_eq!(stats.warnings.len(), 1);
+    assert!(stats.warnings[0].starts_with("mmlog-test-merge-lost.log: "));
+    assert_eq!(lines[..n], expected);
+    let tail: Vec<_> = (0..3)
+        .map(|i| ("lost".to_string(), format!("lost {}", i)))
+        .collect();
+    assert_eq!(lines[n..], tail);
+
+    #[cfg(feature = "cli")]
+    {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_mmlog-cat"))
+            .arg("--merge")
+            .args(&paths)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().lines().count(),
+            n + 3
+        );
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with("mmlog-cat: mmlog-test-merge-lost.log: "));
+    }
+}
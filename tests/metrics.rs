@@ -0,0 +1,117 @@
+// Builder::emit_metrics：用一个记下所有值的 recorder 检查报告的数和 stats() 对得上
+#![cfg(feature = "metrics")]
+mod common;
+
+use common::log;
+use metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+use mmlog::Builder;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Recording(Mutex<HashMap<String, Arc<AtomicU64>>>);
+
+impl Recording {
+    fn value(&self, name: &str) -> u64 {
+        self.0.lock().unwrap()[name].load(Ordering::Relaxed)
+    }
+
+    fn gauge(&self, name: &str) -> f64 {
+        f64::from_bits(self.value(name))
+    }
+
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.0.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn register(&self, key: &Key) -> Arc<AtomicU64> {
+        let mut metrics = self.0.lock().unwrap();
+        metrics.entry(key.name().to_string()).or_default().clone()
+    }
+}
+
+impl Recorder for Recording {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        Counter::from_arc(self.register(key))
+    }
+
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+        Gauge::from_arc(self.register(key))
+    }
+
+    fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+        Histogram::noop()
+    }
+}
+
+struct Broken;
+
+impl fmt::Display for Broken {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Err(fmt::Error)
+    }
+}
+
+#[test]
+fn metrics_track_stats() {
+    let recording = Recording::default();
+    let logger = metrics::with_local_recorder(&recording, || {
+        Builder::new().emit_metrics("app.log").build_anonymous()
+    })
+    .unwrap();
+    assert_eq!(
+        recording.names(),
+        [
+            "app.log.bytes_written",
+            "app.log.dropped",
+            "app.log.flush_errors",
+            "app.log.records_written",
+            "app.log.utilization",
+        ]
+    );
+
+    for i in 0..1000 {
+        log(&logger, format_args!("record {}", i));
+    }
+    log(&logger, Broken);
+    logger.try_flush().unwrap();
+
+    let stats = logger.stats();
+    assert_eq!(recording.value("app.log.records_written"), 1000);
+    assert_eq!(
+        recording.value("app.log.records_written"),
+        stats.records_written
+    );
+    assert_eq!(
+        recording.value("app.log.bytes_written"),
+        stats.total_bytes_written
+    );
+    assert_eq!(recording.value("app.log.dropped"), stats.records_dropped);
+    assert_eq!(recording.value("app.log.dropped"), 1);
+    assert_eq!(recording.value("app.log.flush_errors"), 0);
+    // 抽样更新，最多差 64 条记录
+    let utilization = recording.gauge("app.log.utilization");
+    let actual = stats.used_bytes_since_wrap as f64 / stats.capacity as f64;
+    assert!(
+        utilization > 0.0 && utilization <= actual,
+        "{}",
+        utilization
+    );
+}
+
+#[test]
+fn off_by_default() {
+    let recording = Recording::default();
+    let logger =
+        metrics::with_local_recorder(&recording, || Builder::new().build_anonymous()).unwrap();
+    log(&logger, "quiet");
+    assert!(recording.names().is_empty());
+}
@@ -0,0 +1,49 @@
+// header 里的 offset 是原子的：写入方先写记录再 Release 存 offset，读取方 Acquire 读到
+// offset 之后，它之前的记录一定都是完整的。一边写一边 follow，一条不少、一条不坏
+#![cfg(all(feature = "mmap", unix))]
+mod common;
+
+use common::log;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Framing, MB};
+use std::thread;
+use std::time::Duration;
+
+const RECORDS: usize = 50000;
+
+fn follow_while_writing(framing: Framing) {
+    let path = std::env::temp_dir().join(format!("mmlog-test-offset-{:?}.log", framing));
+    let logger = Builder::new()
+        .size(8 * MB)
+        .framing(framing)
+        .build(&path)
+        .unwrap();
+    let mut reader = LogReader::open(&path).unwrap();
+    thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..RECORDS {
+                log(&logger, format_args!("record {} end", i));
+            }
+        });
+        let mut follow = reader.follow();
+        let mut next = 0;
+        while next < RECORDS {
+            for record in follow.next_batch(Duration::from_secs(10)).unwrap() {
+                let msg = record.split_once("] ").unwrap_or(("", &record)).1;
+                assert_eq!(msg, format!("record {} end", next), "{:?}", record);
+                next += 1;
+            }
+        }
+    });
+    assert_eq!(logger.stats().wrap_count, 0);
+}
+
+#[test]
+fn follow_text() {
+    follow_while_writing(Framing::Text);
+}
+
+#[test]
+fn follow_length_prefixed() {
+    follow_while_writing(Framing::LengthPrefixed);
+}
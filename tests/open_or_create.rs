@@ -0,0 +1,79 @@
+// Builder::open_or_create：没有文件就新建，有的话接着写，不会截断
+mod common;
+
+use common::{fresh, log};
+use log::Log;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, MB};
+use std::path::Path;
+use std::thread;
+
+fn messages(path: &Path) -> Vec<String> {
+    let reader = LogReader::open(path).unwrap();
+    reader.entries().map(|e| e.message).collect()
+}
+
+#[test]
+fn create_then_resume() {
+    let path = fresh("mmlog-test-open-or-create.log");
+    let logger = Builder::new().size(MB).open_or_create(&path).unwrap();
+    assert_eq!(logger.stats().capacity, MB);
+    assert_eq!(logger.stats().records_written, 0);
+    log(&logger, "one");
+    let stats = logger.stats();
+    drop(logger);
+
+    // 不指定大小的话沿用文件里的，从上次的位置继续
+    let logger = Builder::new().open_or_create(&path).unwrap();
+    assert_eq!(logger.stats().capacity, MB);
+    assert_eq!(
+        logger.stats().used_bytes_since_wrap,
+        stats.used_bytes_since_wrap
+    );
+    assert_eq!(logger.stats().records_written, stats.records_written);
+    log(&logger, "two");
+    drop(logger);
+    assert_eq!(messages(&path), ["one", "two"]);
+
+    // build() 还是截断
+    drop(Builder::new().size(MB).build(&path).unwrap());
+    assert!(messages(&path).is_empty());
+}
+
+#[test]
+fn empty_file_is_initialized() {
+    // logrotate 的 create 留下的空文件
+    let path = fresh("mmlog-test-open-or-create-empty.log");
+    std::fs::write(&path, b"").unwrap();
+    let logger = Builder::new().size(MB).open_or_create(&path).unwrap();
+    assert_eq!(logger.stats().capacity, MB);
+    log(&logger, "first");
+    drop(logger);
+    assert_eq!(messages(&path), ["first"]);
+}
+
+#[test]
+fn concurrent_opens_dont_truncate() {
+    for round in 0..20 {
+        let path = fresh(&format!("mmlog-test-open-or-create-race-{}.log", round));
+        let mut loggers: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| Builder::new().size(MB).open_or_create(&path).unwrap()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        // std-file 后端每个 logger 各有一份缓冲区，drop 时整个写回，只留一个
+        let logger = loggers.remove(0);
+        drop(loggers);
+        log(&logger, "kept");
+        logger.flush();
+
+        // 再打开一次不会抹掉已有的记录
+        let again = Builder::new().open_or_create(&path).unwrap();
+        assert_eq!(again.stats().capacity, MB);
+        assert_eq!(again.tail(10).len(), 1);
+        drop(again);
+        drop(logger);
+        assert_eq!(messages(&path), ["kept"]);
+    }
+}
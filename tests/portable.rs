@@ -0,0 +1,89 @@
+// 不管用哪个后端（mmap，或者非 Unix 系统上、关掉 mmap feature 时的 std-file），
+// header 里的字段和缓冲区的布局都一样：这里不用 LogReader，直接按 mmlog::format 的常量解析文件
+mod common;
+
+use common::log;
+use log::Log;
+use mmlog::format::{
+    BYTES_POS, CAPACITY_POS, DATA_POS, MAGIC, MAGIC_POS, OFFSET_POS, RECORDS_POS, VERSION,
+    VERSION_POS, WRAPS_POS,
+};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, KB};
+
+const WORD: usize = std::mem::size_of::<usize>();
+
+struct Header {
+    capacity: usize,
+    offset: usize,
+    start: usize,
+    records: u64,
+    bytes: u64,
+    wraps: u64,
+}
+
+fn header(file: &[u8]) -> Header {
+    let u64_ne = |at: usize| u64::from_ne_bytes(file[at..at + 8].try_into().unwrap());
+    assert_eq!(file[MAGIC_POS..MAGIC_POS + 4], MAGIC);
+    assert_eq!(
+        u32::from_le_bytes(file[VERSION_POS..VERSION_POS + 4].try_into().unwrap()),
+        VERSION
+    );
+    Header {
+        capacity: u64::from_le_bytes(file[CAPACITY_POS..CAPACITY_POS + 8].try_into().unwrap())
+            as usize,
+        offset: usize::from_ne_bytes(file[OFFSET_POS..OFFSET_POS + WORD].try_into().unwrap()),
+        start: u32::from_le_bytes(file[DATA_POS..DATA_POS + 4].try_into().unwrap()) as usize,
+        records: u64_ne(RECORDS_POS),
+        bytes: u64_ne(BYTES_POS),
+        wraps: u64_ne(WRAPS_POS),
+    }
+}
+
+#[test]
+fn header_and_buffer_layout() {
+    let path = std::env::temp_dir().join(format!(
+        "mmlog-test-portable-{}.log",
+        cfg!(all(feature = "mmap", unix))
+    ));
+    let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+    for i in 0..100 {
+        log(&logger, format_args!("record {}", i));
+    }
+    logger.flush();
+    let stats = logger.stats();
+
+    let file = std::fs::read(&path).unwrap();
+    let h = header(&file);
+    assert_eq!(file.len(), h.start + h.capacity);
+    assert_eq!(h.capacity, stats.capacity);
+    assert_eq!(h.offset, stats.used_bytes_since_wrap);
+    assert_eq!(
+        (h.records, h.bytes, h.wraps),
+        (100, stats.total_bytes_written, 0)
+    );
+    // 没翻转过的话记录从缓冲区开头一直排到 offset
+    let data = std::str::from_utf8(&file[h.start..h.start + h.offset]).unwrap();
+    let records: Vec<_> = data.lines().collect();
+    assert_eq!(records, logger.tail(usize::MAX));
+    assert!(file[h.start + h.offset..].iter().all(|&b| b == 0));
+
+    // 翻转之后 offset 之前是最新的记录，最后一条正好在 offset 处结束
+    for i in 100..20000 {
+        log(&logger, format_args!("record {}", i));
+    }
+    logger.flush();
+    let stats = logger.stats();
+    let file = std::fs::read(&path).unwrap();
+    let h = header(&file);
+    assert_eq!(h.offset, stats.used_bytes_since_wrap);
+    assert_eq!((h.records, h.wraps), (20000, stats.wrap_count));
+    assert!(h.wraps > 0);
+    let newest = &file[h.start..h.start + h.offset];
+    assert!(newest.ends_with(b"] record 19999\n"));
+    drop(logger);
+
+    let reader = LogReader::open(&path).unwrap();
+    assert_eq!(reader.stats().used_bytes_since_wrap, h.offset);
+    assert!(reader.records().last().unwrap().ends_with("] record 19999"));
+}
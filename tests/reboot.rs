@@ -0,0 +1,110 @@
+// Clock::Monotonic 的文件拿到重启过的机器上接着写：开机 id 对不上的话偏移挪到之前的记录之后，
+// 之前开机时写的记录换算不了墙上时间。改文件里的开机 id 假装重启过。
+// 用 mmlog::test::freeze 控制时钟，这个文件里只能有一个测试
+mod common;
+
+use common::{fresh, log};
+use mmlog::format::{ANCHOR_POS, BOOT_ID_LEN, BOOT_ID_POS, BOOT_POS};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Clock, KB, MB};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+const T0: Duration = Duration::from_secs(1_700_000_000);
+const HOUR: Duration = Duration::from_secs(3600);
+const FAKE_ID: [u8; BOOT_ID_LEN] = [0x5a; BOOT_ID_LEN];
+
+fn messages(reader: &LogReader) -> Vec<String> {
+    reader
+        .records()
+        .map(|r| r.rsplit_once("] ").unwrap().1.to_string())
+        .collect()
+}
+
+fn read_u64(file: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(file[pos..pos + 8].try_into().unwrap())
+}
+
+fn patch(path: &Path, pos: usize, bytes: &[u8]) {
+    let mut file = std::fs::read(path).unwrap();
+    file[pos..pos + bytes.len()].copy_from_slice(bytes);
+    std::fs::write(path, file).unwrap();
+}
+
+#[test]
+fn records_before_reboot() {
+    let path = fresh("mmlog-test-reboot.log");
+    mmlog::test::freeze(Duration::from_secs(10_000), 1);
+    let logger = Builder::new()
+        .size(512 * KB)
+        .clock(Clock::Monotonic)
+        .retention(HOUR)
+        .build(&path)
+        .unwrap();
+    log(&logger, "old one");
+    mmlog::test::freeze(Duration::from_secs(10_001), 1);
+    log(&logger, "old two");
+    drop(logger);
+    // 第一次开机偏移是 0
+    let file = std::fs::read(&path).unwrap();
+    assert_eq!(read_u64(&file, BOOT_POS), 0);
+    assert_ne!(file[BOOT_ID_POS..BOOT_ID_POS + BOOT_ID_LEN], FAKE_ID);
+
+    // 同一次开机再打开，偏移不变
+    let logger = Builder::new().open(&path).unwrap();
+    drop(logger);
+    assert_eq!(read_u64(&std::fs::read(&path).unwrap(), BOOT_POS), 0);
+
+    // 重启之后单调时钟从头开始，改大小的时候也要认得出来
+    patch(&path, BOOT_ID_POS, &FAKE_ID);
+    mmlog::test::freeze(Duration::from_secs(5), 1);
+    let logger = Builder::new().size(MB).open(&path).unwrap();
+    log(&logger, "new one");
+    mmlog::test::freeze(Duration::from_secs(5) + 2 * HOUR, 1);
+    log(&logger, "new two");
+    drop(logger);
+    let file = std::fs::read(&path).unwrap();
+    assert_ne!(file[BOOT_ID_POS..BOOT_ID_POS + BOOT_ID_LEN], FAKE_ID);
+    let boot = Duration::from_nanos(read_u64(&file, BOOT_POS));
+    assert!(boot > Duration::from_secs(10_001), "{:?}", boot);
+    // 锚点换成固定的：这次开机 5 秒时是 T0
+    let mut anchor = (T0.as_nanos() as u64).to_le_bytes().to_vec();
+    anchor.extend_from_slice(&((boot + Duration::from_secs(5)).as_nanos() as u64).to_le_bytes());
+    patch(&path, ANCHOR_POS, &anchor);
+
+    // 保留期限的截止时间早于这次开机，之前的记录不知道过没过期，都留着
+    mmlog::test::freeze(T0 + Duration::from_secs(10), 1);
+    let reader = LogReader::open(&path).unwrap();
+    assert_eq!(reader.anchor().unwrap().boot_start, boot);
+    assert_eq!(
+        messages(&reader),
+        ["old one", "old two", "new one", "new two"]
+    );
+    let records: Vec<_> = reader.records().collect();
+    assert_eq!(reader.normalize(records[0]), None);
+    assert_eq!(reader.normalize(records[1]), None);
+    assert_eq!(reader.normalize(records[2]), Some(T0.as_nanos() as i128));
+    // 换算不了的记录时间戳原样留着
+    let stamps: Vec<_> = reader.entries().map(|e| e.timestamp).collect();
+    assert_eq!(
+        stamps,
+        [
+            Duration::from_secs(10_000),
+            Duration::from_secs(10_001),
+            T0,
+            T0 + 2 * HOUR
+        ]
+    );
+    let in_range: Vec<_> = reader
+        .range(UNIX_EPOCH, UNIX_EPOCH + T0 + 3 * HOUR)
+        .map(|e| e.message)
+        .collect();
+    assert_eq!(in_range, ["new one", "new two"]);
+    assert_eq!(reader.seek_time(UNIX_EPOCH + T0), Some(2));
+
+    // 截止时间晚于这次开机的话之前的记录肯定过期了
+    mmlog::test::freeze(T0 + HOUR + Duration::from_secs(10), 1);
+    let reader = LogReader::open(&path).unwrap();
+    assert_eq!(messages(&reader), ["new two"]);
+    mmlog::test::thaw();
+}
@@ -0,0 +1,61 @@
+// 日志参数的 Display 实现里再写日志
+mod common;
+
+use common::{log, log_at};
+use log::{Level, Log};
+use mmlog::{Builder, Logger, KB};
+use std::fmt;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+struct Nested<'a>(&'a Logger);
+
+impl fmt::Display for Nested<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        log_at(self.0, Level::Warn, "", "inner");
+        f.write_str("outer")
+    }
+}
+
+// 死锁的话测试失败而不是一直挂着
+fn within_timeout(f: impl FnOnce() -> Vec<String> + Send + 'static) -> Vec<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || tx.send(f()).unwrap());
+    rx.recv_timeout(Duration::from_secs(10))
+        .expect("logging from a Display impl deadlocked")
+}
+
+fn log_nested(builder: Builder) -> Vec<String> {
+    within_timeout(move || {
+        let logger = builder.build_anonymous().unwrap();
+        log(&logger, Nested(&logger));
+        logger.flush();
+        logger.tail(usize::MAX)
+    })
+}
+
+#[test]
+fn display_logs() {
+    let records = log_nested(Builder::new().size(64 * KB));
+    assert_eq!(records.len(), 2, "{:?}", records);
+    // 嵌套的那条先格式化完，先写进去
+    assert!(records[0].ends_with("] inner"), "{:?}", records);
+    assert!(records[1].ends_with("] outer"), "{:?}", records);
+}
+
+#[test]
+fn display_logs_batched() {
+    let records = log_nested(Builder::new().size(64 * KB).batched(4 * KB));
+    assert_eq!(records.len(), 2, "{:?}", records);
+    assert!(records[0].ends_with("] inner"), "{:?}", records);
+    assert!(records[1].ends_with("] outer"), "{:?}", records);
+}
+
+#[test]
+fn display_logs_sequence() {
+    let records = log_nested(Builder::new().size(64 * KB).sequence_numbers(true));
+    assert_eq!(records.len(), 2, "{:?}", records);
+    assert!(records[0].starts_with("#0 "), "{:?}", records);
+    assert!(records[1].starts_with("#1 "), "{:?}", records);
+}
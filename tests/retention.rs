@@ -0,0 +1,176 @@
+// Builder::retention：保留期限记在 header 里，读出和导出时跳过过期的记录。
+// 用 mmlog::test::freeze 控制时钟，这个文件里只能有一个测试
+mod common;
+
+use common::{fresh, log};
+use log::Log;
+use mmlog::format::{FLAGS_POS, FLAG_RETENTION};
+use mmlog::reader::LogReader;
+use mmlog::{Builder, KB, MB};
+use std::path::Path;
+use std::time::Duration;
+
+const T0: Duration = Duration::from_secs(1_700_000_000);
+const HOUR: Duration = Duration::from_secs(3600);
+
+fn messages<S: AsRef<str>>(records: impl Iterator<Item = S>) -> Vec<String> {
+    records
+        .map(|r| match r.as_ref().split_once("] ") {
+            Some((_, msg)) => msg.to_string(),
+            None => r.as_ref().to_string(),
+        })
+        .collect()
+}
+
+fn read(path: &Path) -> Vec<String> {
+    messages(LogReader::open(path).unwrap().records())
+}
+
+#[test]
+fn retention_boundary() {
+    let path = std::env::temp_dir().join("mmlog-test-retention.log");
+    let logger = Builder::new()
+        .size(512 * KB)
+        .retention(HOUR)
+        .build(&path)
+        .unwrap();
+    mmlog::test::freeze(T0, 1);
+    log(&logger, "first");
+    mmlog::test::freeze(T0 + Duration::from_secs(1), 1);
+    log(&logger, "second\ncontinued");
+    mmlog::test::freeze(T0 + HOUR / 2, 1);
+    log(&logger, "third");
+    logger.flush();
+    let all = ["first", "second", "continued", "third"];
+
+    // 正好 retention 之前的记录还在，再晚一纳秒就过期了
+    mmlog::test::freeze(T0 + HOUR, 1);
+    assert_eq!(read(&path), all);
+    mmlog::test::freeze(T0 + HOUR + Duration::from_nanos(1), 1);
+    assert_eq!(read(&path), all[1..]);
+    // 续行跟着它前面的记录一起过期
+    mmlog::test::freeze(
+        T0 + HOUR + Duration::from_secs(1) + Duration::from_nanos(1),
+        1,
+    );
+    assert_eq!(read(&path), ["third"]);
+    let reader = LogReader::open(&path).unwrap();
+    assert!(reader.entries().map(|e| e.message).eq(["third"]));
+    let reader = LogReader::open_ignoring_retention(&path, None).unwrap();
+    assert_eq!(messages(reader.records()), all);
+
+    // 导出也一样
+    let snapshot = logger.snapshot();
+    assert_eq!(messages(snapshot.records()), ["third"]);
+    let mut out = Vec::new();
+    logger.dump_to(&mut out).unwrap();
+    assert_eq!(messages(String::from_utf8(out).unwrap().lines()), ["third"]);
+
+    // 保留期限在文件里：不设置 retention 再打开，读的时候照样按文件里的算
+    drop(logger);
+    let file = std::fs::read(&path).unwrap();
+    let flags = u32::from_le_bytes(file[FLAGS_POS..FLAGS_POS + 4].try_into().unwrap());
+    assert_ne!(flags & FLAG_RETENTION, 0);
+    let logger = Builder::new().open(&path).unwrap();
+    log(&logger, "fourth");
+    logger.flush();
+    assert_eq!(read(&path), ["third", "fourth"]);
+
+    // 跟踪时新写进来的过期记录也跳过
+    #[cfg(all(feature = "mmap", unix))]
+    let mut reader = LogReader::open(&path).unwrap();
+    #[cfg(all(feature = "mmap", unix))]
+    let mut follow = reader.follow();
+    mmlog::test::freeze(T0, 1);
+    log(&logger, "late but expired");
+    mmlog::test::freeze(T0 + 2 * HOUR, 1);
+    log(&logger, "fifth");
+    #[cfg(all(feature = "mmap", unix))]
+    assert_eq!(messages(follow.poll().unwrap().into_iter()), ["fifth"]);
+
+    // swap_file 换下来的旧文件
+    let next = std::env::temp_dir().join("mmlog-test-retention-next.log");
+    let archived = logger.swap_file(&next).unwrap();
+    mmlog::test::freeze(T0 + 2 * HOUR + HOUR / 2, 1);
+    assert_eq!(messages(archived.records()), ["fifth"]);
+    drop(archived);
+    drop(logger);
+
+    // 改大小时保留期限、标签和权限沿用原来的文件，这次的 Builder 里的不起作用
+    let path = fresh("mmlog-test-retention-resize.log");
+    let logger = Builder::new()
+        .size(512 * KB)
+        .retention(HOUR)
+        .tag("before")
+        .mode(0o640)
+        .build(&path)
+        .unwrap();
+    mmlog::test::freeze(T0, 1);
+    log(&logger, "stale");
+    mmlog::test::freeze(T0 + 2 * HOUR, 1);
+    log(&logger, "fresh");
+    drop(logger);
+    let logger = Builder::new()
+        .size(MB)
+        .retention(3 * HOUR)
+        .tag("after")
+        .mode(0o600)
+        .open(&path)
+        .unwrap();
+    assert_eq!(logger.stats().capacity, MB);
+    drop(logger);
+    assert_eq!(read(&path), ["fresh"]);
+    let reader = LogReader::open_ignoring_retention(&path, None).unwrap();
+    assert_eq!(messages(reader.records()), ["stale", "fresh"]);
+    assert_eq!(reader.metadata().unwrap().tag.as_deref(), Some("before"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+    // 保留期限是原来的一个小时，不是三个小时
+    mmlog::test::freeze(T0 + 3 * HOUR + Duration::from_nanos(1), 1);
+    assert!(read(&path).is_empty());
+    mmlog::test::thaw();
+
+    // mmlog-cat 用的是真的时钟
+    #[cfg(feature = "cli")]
+    {
+        use std::process::Command;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let path = std::env::temp_dir().join("mmlog-test-retention-cli.log");
+        let logger = Builder::new()
+            .size(512 * KB)
+            .retention(HOUR)
+            .build(&path)
+            .unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        mmlog::test::freeze(now - 2 * HOUR, 1);
+        log(&logger, "stale");
+        mmlog::test::thaw();
+        log(&logger, "fresh");
+        drop(logger);
+
+        let run = |args: &[&str]| {
+            Command::new(env!("CARGO_BIN_EXE_mmlog-cat"))
+                .args(args)
+                .arg(&path)
+                .output()
+                .unwrap()
+        };
+        let lines = |args: &[&str]| {
+            let output = run(args);
+            assert!(output.status.success(), "{:?}", output);
+            messages(String::from_utf8(output.stdout).unwrap().lines())
+        };
+        assert_eq!(lines(&[]), ["fresh"]);
+        assert_eq!(lines(&["--ignore-retention"]), ["stale", "fresh"]);
+        assert_eq!(lines(&["--ignore-retention", "--tail", "1"]), ["fresh"]);
+        assert_eq!(
+            run(&["--ignore-retention", "--lenient"]).status.code(),
+            Some(2)
+        );
+    }
+}
@@ -0,0 +1,39 @@
+// Builder::rotate 和进程间共享的文件
+#![cfg(all(feature = "mmap", unix))]
+mod common;
+
+use common::{fresh, log, segment};
+use mmlog::{Builder, Error};
+
+#[test]
+fn shared_file_is_not_rotated() {
+    let path = fresh("mmlog-test-rotate-shared.log");
+    // 别的进程用 shared 建的文件
+    drop(Builder::new().shared(true).build(&path).unwrap());
+
+    let logger = Builder::new().rotate(1).open(&path).unwrap();
+    for i in 0..10000 {
+        log(&logger, format_args!("record {} {}", i, "x".repeat(200)));
+    }
+    let stats = logger.stats();
+    assert_eq!(stats.records_written, 10000);
+    // 没有轮转，在原地翻转
+    assert!(stats.wrap_count > 0);
+    drop(logger);
+    assert!(!segment(&path, 1).exists());
+}
+
+#[test]
+fn shared_file_rotate_strict() {
+    let path = fresh("mmlog-test-rotate-shared-strict.log");
+    drop(Builder::new().shared(true).build(&path).unwrap());
+
+    let err = Builder::new()
+        .rotate(1)
+        .strict(true)
+        .open(&path)
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+    // 不用 strict 的话照常打开
+    Builder::new().rotate(1).open(&path).unwrap();
+}
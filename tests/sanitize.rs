@@ -0,0 +1,96 @@
+// target 和文件名想伪造记录前缀：`] `、换行、ANSI 转义序列
+mod common;
+
+use common::log_with;
+use log::Level;
+use mmlog::parse::Entry;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, KB};
+
+const HOSTILE: [&str; 7] = [
+    "app] [1700000000.0s 1 E src/lib.rs:1 admin] login ok",
+    "app\n[1700000000.0s 1 E src/lib.rs:1 admin] login ok",
+    "app\r\x0b\x00\x7f\u{85}",
+    "app with spaces",
+    r"app\x5d\x",
+    "好] ",
+    "",
+];
+
+// 前缀到第一个 `]` 为止，里面没有控制字符
+fn check_prefix(record: &str) {
+    let (prefix, message) = record.split_once(']').unwrap();
+    assert_eq!(message, " payload", "{:?}", record);
+    assert!(!prefix.chars().any(char::is_control), "{:?}", record);
+}
+
+#[test]
+fn hostile_targets_round_trip() {
+    let logger = Builder::new().size(64 * KB).build_anonymous().unwrap();
+    for target in HOSTILE {
+        log_with(
+            &logger,
+            Level::Info,
+            target,
+            Some((target, 7)),
+            format_args!("payload"),
+        );
+    }
+    let records = logger.tail(usize::MAX);
+    // 换行没有把一条记录拆成两条
+    assert_eq!(records.len(), HOSTILE.len(), "{:?}", records);
+    for (record, target) in records.iter().zip(HOSTILE) {
+        check_prefix(record);
+        let entry = Entry::parse(record).unwrap_or_else(|| panic!("{:?}", record));
+        assert_eq!(entry.target, target, "{:?}", record);
+        assert_eq!(entry.file.as_deref(), Some(target), "{:?}", record);
+        assert_eq!(entry.line, Some(7));
+        assert_eq!(entry.level, Level::Info);
+        assert_eq!(entry.message, "payload");
+    }
+}
+
+#[test]
+fn ansi_escapes_are_stripped() {
+    let path = std::env::temp_dir().join("mmlog-test-sanitize-ansi.log");
+    let logger = Builder::new().size(64 * KB).build(&path).unwrap();
+    log_with(
+        &logger,
+        Level::Info,
+        "\x1b[31mred\x1b[0m::\x1b]0;title\x07x",
+        Some(("\x1b[2Jsrc/main.rs", 7)),
+        format_args!("payload"),
+    );
+    drop(logger);
+
+    let reader = LogReader::open(&path).unwrap();
+    let record = reader.records().next().unwrap();
+    assert!(!record.contains('\x1b'), "{:?}", record);
+    check_prefix(record);
+    let entry = reader.entries().next().unwrap();
+    assert_eq!(entry.file.as_deref(), Some("src/main.rs"));
+    assert!(entry.target.starts_with("red::"), "{:?}", entry.target);
+    assert!(!entry.target.contains('\x1b'));
+}
+
+#[test]
+fn sanitize_off() {
+    let logger = Builder::new()
+        .size(64 * KB)
+        .sanitize(false)
+        .build_anonymous()
+        .unwrap();
+    log_with(
+        &logger,
+        Level::Info,
+        HOSTILE[1],
+        Some(("src/lib.rs", 7)),
+        format_args!("payload"),
+    );
+    // 关掉的话原样写进去，换行之后是一条伪造的 Error 记录
+    let records = logger.tail(usize::MAX);
+    assert_eq!(records.len(), 2, "{:?}", records);
+    let entry = Entry::parse(&records[1]).unwrap();
+    assert_eq!(entry.level, Level::Error);
+    assert_eq!(entry.target, "admin");
+}
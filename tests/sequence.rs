@@ -0,0 +1,67 @@
+// 序号、翻转次数和被覆盖的记录数：保存在 header 里，重新打开之后接着数
+mod common;
+
+use common::log;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, KB};
+
+fn seq(record: &str) -> u64 {
+    let rest = record.strip_prefix('#').unwrap();
+    rest[..rest.find(' ').unwrap()].parse().unwrap()
+}
+
+#[test]
+fn sequence_and_wraps() {
+    let path = std::env::temp_dir().join("mmlog-test-sequence.log");
+    let builder = || Builder::new().size(512 * KB).sequence_numbers(true);
+    let logger = builder().build(&path).unwrap();
+    assert_eq!(logger.stats().wrap_count, 0);
+    for i in 0..20000 {
+        log(&logger, format_args!("record {}", i));
+    }
+    let stats = logger.stats();
+    assert_eq!(stats.records_written, 20000);
+    assert!(stats.wrap_count > 0);
+    assert!(stats.total_bytes_written > stats.wrap_count * stats.capacity as u64);
+
+    // 缓冲区里剩下的是最新的、序号连续的那些
+    let tail = logger.tail(usize::MAX);
+    let first = seq(&tail[0]);
+    for (i, record) in tail.iter().enumerate() {
+        assert_eq!(seq(record), first + i as u64, "{:?}", record);
+        assert!(record.ends_with(&format!("] record {}", first + i as u64)));
+    }
+    assert_eq!(seq(tail.last().unwrap()), 19999);
+    drop(logger);
+
+    // 读取方从 header 里拿到同样的计数，知道前面有多少条被覆盖了
+    let reader = LogReader::open(&path).unwrap();
+    assert_eq!(reader.stats(), stats);
+    assert_eq!(reader.records().count(), tail.len());
+    assert_eq!(reader.overwritten(), first);
+
+    // 重新打开之后接着数
+    let logger = builder().open(&path).unwrap();
+    assert_eq!(logger.stats().records_written, 20000);
+    assert_eq!(logger.stats().wrap_count, stats.wrap_count);
+    log(&logger, "record 20000");
+    let last = logger.tail(1).pop().unwrap();
+    assert_eq!(seq(&last), 20000);
+    assert_eq!(logger.stats().records_written, 20001);
+}
+
+#[test]
+fn no_wrap_no_overwritten() {
+    let path = std::env::temp_dir().join("mmlog-test-sequence-short.log");
+    let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+    for i in 0..100 {
+        log(&logger, format_args!("record {}", i));
+    }
+    drop(logger);
+    let reader = LogReader::open(&path).unwrap();
+    assert_eq!(reader.stats().records_written, 100);
+    assert_eq!(reader.stats().wrap_count, 0);
+    assert_eq!(reader.overwritten(), 0);
+    // 没打开序号的话记录照常以时间戳开头
+    assert!(reader.records().all(|r| r.starts_with('[')));
+}
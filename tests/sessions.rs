@@ -0,0 +1,155 @@
+// LogReader::sessions：Builder::open 反复打开的文件里的每次运行，启动标记被覆盖了的算部分的一次
+mod common;
+
+use common::{fresh, log};
+use log::Log;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Logger, KB};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+fn open(path: &Path) -> Logger {
+    Builder::new()
+        .size(512 * KB)
+        .banner(true)
+        .open_or_create(path)
+        .unwrap()
+}
+
+// 第一次运行写满一圈多，启动标记被覆盖；第二次没有结束标记；第三次正常退出
+fn write(name: &str) -> PathBuf {
+    let path = fresh(&format!("mmlog-test-sessions-{}.log", name));
+    let logger = open(&path);
+    for i in 0..10_000 {
+        log(
+            &logger,
+            format_args!("first run, record {} {}", i, "x".repeat(40)),
+        );
+    }
+    drop(logger);
+
+    let logger = open(&path);
+    log(&logger, "second run, record 0");
+    log(&logger, "second run, record 1");
+    logger.flush();
+    std::mem::forget(logger);
+
+    let logger = open(&path);
+    log(&logger, "third run");
+    drop(logger);
+    path
+}
+
+#[test]
+fn sessions_in_a_reused_file() {
+    let before = SystemTime::now() - Duration::from_secs(1);
+    let path = write("api");
+    let reader = LogReader::open(&path).unwrap();
+    assert!(reader.stats().wrap_count > 0);
+
+    let sessions = reader.sessions();
+    assert_eq!(sessions.len(), 3);
+    assert_eq!(sessions[0].start, 0);
+    for pair in sessions.windows(2) {
+        assert_eq!(pair[0].end, pair[1].start);
+    }
+    assert_eq!(sessions[2].end, reader.records().count());
+
+    assert!(sessions[0].partial && sessions[0].clean);
+    assert_eq!((sessions[0].pid, sessions[0].started), (None, None));
+    let first: Vec<_> = reader.records_in_session(0).collect();
+    assert!(first[0].contains("first run, record "));
+    assert!(first
+        .last()
+        .unwrap()
+        .ends_with("] ==== mmlog clean shutdown ===="));
+
+    let pid = Some(std::process::id());
+    assert!(!sessions[1].partial && !sessions[1].clean);
+    assert_eq!(sessions[1].pid, pid);
+    let started = sessions[1].started.unwrap();
+    assert!(started > before && started <= SystemTime::now());
+    let second: Vec<_> = reader.records_in_session(1).collect();
+    assert_eq!(second.len(), 3);
+    assert!(second[0].contains("] ==== mmlog start pid="));
+    assert!(second[1].ends_with("] second run, record 0"));
+    assert!(second[2].ends_with("] second run, record 1"));
+
+    assert!(!sessions[2].partial && sessions[2].clean);
+    assert!(sessions[2].started.unwrap() >= started);
+    assert_eq!(reader.records_in_session(2).count(), 3);
+    assert_eq!(reader.records_in_session(3).count(), 0);
+
+    // runs() 和 sessions() 分法一样
+    let runs = reader.runs();
+    assert_eq!(runs.len(), 3);
+    assert_eq!(runs[0].banner, None);
+    assert_eq!(runs[0].records.len(), first.len() - 1);
+    assert_eq!(runs[1].records, &second[1..]);
+    assert!(runs[1].crashed());
+}
+
+#[test]
+fn without_markers() {
+    let path = std::env::temp_dir().join("mmlog-test-sessions-plain.log");
+    let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+    log(&logger, "one");
+    log(&logger, "two");
+    drop(logger);
+    let reader = LogReader::open(&path).unwrap();
+    let sessions = reader.sessions();
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0].partial && !sessions[0].clean);
+    assert_eq!((sessions[0].start, sessions[0].end), (0, 2));
+
+    let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+    drop(logger);
+    assert!(LogReader::open(&path).unwrap().sessions().is_empty());
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli_session() {
+    use std::process::Command;
+
+    let path = write("cli");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_mmlog-cat"))
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+    let lines = |args: &[&str]| {
+        let output = run(args);
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.rsplit_once("] ").unwrap().1.to_string())
+            .collect::<Vec<_>>()
+    };
+    let last = lines(&["--session", "last"]);
+    assert_eq!(last.len(), 3);
+    assert!(last[0].starts_with("==== mmlog start pid="));
+    assert_eq!(last[1..], ["third run", "==== mmlog clean shutdown ===="]);
+    assert_eq!(lines(&["--session", "2"]), last);
+    assert_eq!(
+        lines(&["--session", "1", "--tail", "2"]),
+        ["second run, record 0", "second run, record 1"]
+    );
+
+    let output = run(&["--session", "3"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run(&["--session", "first"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = run(&["--info"]);
+    let info = String::from_utf8(output.stdout).unwrap();
+    let sessions: Vec<_> = info.lines().filter(|l| l.starts_with("session ")).collect();
+    assert_eq!(sessions.len(), 3);
+    assert!(sessions[0].ends_with(", pid ?, started ?, clean, partial"));
+    let pid = format!(", pid {}, started ", std::process::id());
+    assert!(sessions[1].contains(&pid) && sessions[1].ends_with(", no shutdown"));
+    assert!(sessions[2].ends_with(", clean"));
+}
@@ -0,0 +1,63 @@
+// Logger::install_signal_handlers 失败之后还能再装。
+// 改了进程的文件描述符上限，这个文件里只能有一个测试
+#![cfg(all(feature = "mmap", unix))]
+use mmlog::{Builder, Error, Logger, Signal};
+use std::fs::File;
+
+fn fd_count() -> usize {
+    std::fs::read_dir("/proc/self/fd").map_or(0, |dir| dir.count())
+}
+
+#[test]
+fn failed_install_can_be_retried() {
+    let logger: &'static Logger = Box::leak(Box::new(Builder::new().build_anonymous().unwrap()));
+
+    // 把文件描述符用完，pipe() 失败
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    unsafe { assert_eq!(libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit), 0) };
+    let low = libc::rlimit {
+        rlim_cur: 256,
+        rlim_max: limit.rlim_max,
+    };
+    unsafe { assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &low), 0) };
+    let mut files = Vec::new();
+    loop {
+        match File::open("/dev/null") {
+            Ok(file) => files.push(file),
+            Err(e) if e.raw_os_error() == Some(libc::EMFILE) => break,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    let err = logger
+        .install_signal_handlers(Signal::Hup, Signal::Usr1)
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Io {
+                errno: libc::EMFILE,
+                context: "pipe"
+            }
+        ),
+        "{:?}",
+        err
+    );
+    drop(files);
+    unsafe { assert_eq!(libc::setrlimit(libc::RLIMIT_NOFILE, &limit), 0) };
+
+    // 失败的那次没有留下标记，也没有漏掉文件描述符
+    let before = fd_count();
+    logger
+        .install_signal_handlers(Signal::Hup, Signal::Usr1)
+        .unwrap();
+    if before > 0 {
+        assert_eq!(fd_count(), before + 2);
+    }
+    let err = logger
+        .install_signal_handlers(Signal::Hup, Signal::Usr1)
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidConfig(_)), "{:?}", err);
+}
@@ -0,0 +1,111 @@
+// 一边写日志一边 clear、轮转、swap_file 的时候 dump_to 和 snapshot：复制在写锁里完成，
+// 拿到的要么全是切换前的记录，要么全是切换后的，不会混在一起，也不会有写了一半的记录
+mod common;
+
+use common::{fresh, log, segment};
+use mmlog::{Builder, Logger};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+const ROUNDS: usize = 200;
+// 最小的 512 KB 的文件大概轮转十次
+const WRITES: usize = 5000;
+
+// 每条记录都完整，序号一个接一个
+fn consistent(records: impl Iterator<Item = impl AsRef<str>>) {
+    let numbers: Vec<usize> = records
+        .map(|record| {
+            let record = record.as_ref();
+            let (_, i) = record
+                .rsplit_once(" record ")
+                .unwrap_or_else(|| panic!("torn record {:?}", record));
+            i.parse()
+                .unwrap_or_else(|_| panic!("torn record {:?}", record))
+        })
+        .collect();
+    for pair in numbers.windows(2) {
+        assert_eq!(pair[1], pair[0] + 1, "{:?}", numbers);
+    }
+}
+
+// 一个线程不停地写，一个线程不停地做 switch，当前线程轮流 dump_to 和 snapshot，
+// 至少 ROUNDS 次，并且写了 WRITES 条记录
+fn race(logger: &Logger, switch: impl Fn() + Sync) {
+    let stop = AtomicBool::new(false);
+    let written = AtomicUsize::new(0);
+    thread::scope(|s| {
+        // 检查失败的话也要让别的线程停下来，不然 scope 一直等着
+        let _stop = Stop(&stop);
+        s.spawn(|| {
+            let _stop = Stop(&stop);
+            while !stop.load(Ordering::Relaxed) {
+                let i = written.fetch_add(1, Ordering::Relaxed);
+                log(logger, format_args!("{} record {}", "x".repeat(1000), i));
+            }
+        });
+        s.spawn(|| {
+            let _stop = Stop(&stop);
+            while !stop.load(Ordering::Relaxed) {
+                switch();
+                thread::yield_now();
+            }
+        });
+        let mut rounds = 0;
+        while rounds < ROUNDS || written.load(Ordering::Relaxed) < WRITES {
+            let mut out = Vec::new();
+            logger.dump_to(&mut out).unwrap();
+            consistent(String::from_utf8(out).unwrap().lines());
+            consistent(logger.snapshot().records());
+            rounds += 1;
+        }
+    });
+}
+
+struct Stop<'a>(&'a AtomicBool);
+
+impl Drop for Stop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn clear() {
+    let path = fresh("mmlog-test-race-clear.log");
+    let logger = Builder::new().build(&path).unwrap();
+    let zero = AtomicBool::new(false);
+    // 清空的时候轮流清零缓冲区
+    let clear = || {
+        logger
+            .clear(zero.fetch_xor(true, Ordering::Relaxed))
+            .unwrap();
+    };
+    race(&logger, clear);
+}
+
+#[test]
+fn rotate() {
+    let path = fresh("mmlog-test-race-rotate.log");
+    let logger = Builder::new().rotate(2).build(&path).unwrap();
+    // 写满了就轮转，不用别的线程切换
+    race(&logger, || {});
+    drop(logger);
+    assert!(segment(&path, 1).exists() && segment(&path, 2).exists());
+}
+
+#[test]
+fn swap_file() {
+    let paths = [
+        fresh("mmlog-test-race-swap-a.log"),
+        fresh("mmlog-test-race-swap-b.log"),
+    ];
+    let logger = Builder::new().build(&paths[0]).unwrap();
+    let next = AtomicUsize::new(1);
+    // 在两个文件之间来回换，换上的文件是新建的
+    let swap = || {
+        let path = &paths[next.fetch_add(1, Ordering::Relaxed) % 2];
+        let _ = std::fs::remove_file(path);
+        logger.swap_file(path).unwrap();
+    };
+    race(&logger, swap);
+}
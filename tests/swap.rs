@@ -0,0 +1,140 @@
+// swap_file()：一边写一边换文件，不丢记录、没有横跨两个文件的记录，新文件有元数据和开始标记
+mod common;
+
+use common::{fresh, log};
+use log::Log;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Error, MB};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+const THREADS: usize = 4;
+const RECORDS: usize = 5000;
+
+#[test]
+fn swap_under_load() {
+    let logger = Builder::new()
+        .size(4 * MB)
+        .build(fresh("mmlog-test-swap-0.log"))
+        .unwrap();
+    let done = AtomicBool::new(false);
+    let mut archives = Vec::new();
+    thread::scope(|s| {
+        let writers: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let logger = &logger;
+                s.spawn(move || {
+                    for i in 0..RECORDS {
+                        log(logger, format_args!("thread {} record {} end", t, i));
+                    }
+                })
+            })
+            .collect();
+        let swapper = s.spawn(|| {
+            let mut archives = Vec::new();
+            let mut n = 1;
+            while !done.load(Ordering::Relaxed) {
+                let path = fresh(&format!("mmlog-test-swap-{}.log", n));
+                archives.push(logger.swap_file(&path).unwrap());
+                n += 1;
+                thread::yield_now();
+            }
+            archives
+        });
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        archives = swapper.join().unwrap();
+    });
+    assert!(!archives.is_empty());
+
+    // 按文件的先后把记录接起来，每个线程的记录一条不少、按顺序、都是完整的
+    let mut records: Vec<String> = Vec::new();
+    for archive in &archives {
+        records.extend(archive.records().map(|r| r.into_owned()));
+    }
+    records.extend(logger.tail(usize::MAX));
+    let mut next = [0; THREADS];
+    for record in &records {
+        let msg = record.split_once("] ").unwrap().1;
+        let fields: Vec<_> = msg.split(' ').collect();
+        assert!(
+            fields.len() == 5 && fields[0] == "thread" && fields[4] == "end",
+            "{:?}",
+            record
+        );
+        let t: usize = fields[1].parse().unwrap();
+        let i: usize = fields[3].parse().unwrap();
+        assert_eq!(i, next[t], "{:?}", record);
+        next[t] += 1;
+    }
+    assert!(next.iter().all(|&n| n == RECORDS), "{:?}", next);
+}
+
+#[test]
+fn archived_buffer() {
+    let old = fresh("mmlog-test-swap-archived-old.log");
+    let new = fresh("mmlog-test-swap-archived-new.log");
+    let logger = Builder::new()
+        .banner_with(|| "app=swap".to_string())
+        .build(&old)
+        .unwrap();
+    log(&logger, "one");
+    log(&logger, "two");
+    let archive = logger.swap_file(&new).unwrap();
+    log(&logger, "three");
+
+    // 换下来之后旧文件不再有新的记录
+    let records: Vec<_> = archive.records().collect();
+    assert_eq!(records.len(), 3, "{:?}", records);
+    assert!(records[0].contains("==== mmlog start "), "{:?}", records);
+    assert!(records[1].ends_with("] one"));
+    assert!(records[2].ends_with("] two"));
+    let mut dumped = Vec::new();
+    let n = archive.dump_to(&mut dumped).unwrap();
+    assert_eq!(n as usize, dumped.len());
+    assert_eq!(
+        String::from_utf8(dumped).unwrap(),
+        records.join("\n") + "\n"
+    );
+    assert_eq!(archive.into_path().unwrap(), old);
+
+    let reader = LogReader::open(&old).unwrap();
+    let records: Vec<_> = reader.records().collect();
+    assert!(records.last().unwrap().ends_with("] two"), "{:?}", records);
+
+    // 新文件有这个进程的元数据，先是开始标记，然后接着写
+    drop(logger);
+    let reader = LogReader::open(&new).unwrap();
+    let meta = reader.metadata().unwrap();
+    assert_eq!(meta.pid, std::process::id());
+    let runs = reader.runs();
+    assert_eq!(runs.len(), 1, "{:?}", runs);
+    let banner = runs[0].banner.unwrap();
+    assert!(banner.ends_with(" app=swap ===="), "{:?}", banner);
+    assert_eq!(runs[0].records.len(), 1);
+    assert!(runs[0].records[0].ends_with("] three"));
+    assert!(runs[0].clean);
+}
+
+#[test]
+fn swap_anonymous_and_closed() {
+    let logger = Builder::new().build_anonymous().unwrap();
+    log(&logger, "anonymous");
+    let path = fresh("mmlog-test-swap-anonymous.log");
+    let archive = logger.swap_file(&path).unwrap();
+    assert!(archive.records().next().unwrap().ends_with("] anonymous"));
+    assert_eq!(archive.into_path().unwrap(), PathBuf::new());
+    log(&logger, "on disk");
+    logger.flush();
+    let reader = LogReader::open(&path).unwrap();
+    assert!(reader.records().last().unwrap().ends_with("] on disk"));
+
+    logger.close().unwrap();
+    let err = logger
+        .swap_file(fresh("mmlog-test-swap-closed.log"))
+        .unwrap_err();
+    assert!(matches!(err, Error::Closed), "{:?}", err);
+}
@@ -0,0 +1,62 @@
+// Logger::sync_now 和 Builder::sync_on：马上同步，达到级别的记录写完就同步
+mod common;
+
+use common::log_at;
+use log::Level;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Error, KB};
+use std::path::Path;
+
+const MMAP: bool = cfg!(all(feature = "mmap", unix));
+
+fn on_disk(path: &Path) -> usize {
+    LogReader::open(path).unwrap().records().count()
+}
+
+#[test]
+fn sync_now() {
+    let path = std::env::temp_dir().join(format!("mmlog-test-sync-now-{}.log", MMAP));
+    let logger = Builder::new().build(&path).unwrap();
+    log_at(&logger, Level::Info, "", "one");
+    logger.sync_now().unwrap();
+    assert_eq!(on_disk(&path), 1);
+    // 没有新的写入也没关系
+    logger.sync_now().unwrap();
+
+    let anonymous = Builder::new().build_anonymous().unwrap();
+    log_at(&anonymous, Level::Info, "", "one");
+    anonymous.sync_now().unwrap();
+
+    logger.close().unwrap();
+    assert!(matches!(logger.sync_now(), Err(Error::Closed)));
+}
+
+#[test]
+fn sync_on_level() {
+    let path = std::env::temp_dir().join(format!("mmlog-test-sync-on-{}.log", MMAP));
+    let logger = Builder::new().sync_on(Level::Warn).build(&path).unwrap();
+    log_at(&logger, Level::Info, "", "info");
+    // std-file 后端要等 flush 才写回文件，mmap 的写进去就能看到
+    assert_eq!(on_disk(&path), usize::from(MMAP));
+    log_at(&logger, Level::Warn, "", "warn");
+    assert_eq!(on_disk(&path), 2);
+    log_at(&logger, Level::Debug, "", "debug");
+    log_at(&logger, Level::Error, "", "error");
+    assert_eq!(on_disk(&path), 3);
+}
+
+#[test]
+fn sync_on_writes_the_batch() {
+    let logger = Builder::new()
+        .batched(64 * KB)
+        .sync_on(Level::Error)
+        .build_anonymous()
+        .unwrap();
+    log_at(&logger, Level::Info, "", "queued");
+    log_at(&logger, Level::Warn, "", "queued");
+    assert!(logger.tail(10).is_empty());
+    log_at(&logger, Level::Error, "", "now");
+    let records = logger.tail(10);
+    assert_eq!(records.len(), 3, "{:?}", records);
+    assert!(records[2].ends_with("] now"));
+}
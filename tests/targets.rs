@@ -0,0 +1,145 @@
+// Builder::track_targets、Builder::target_quota 和 Logger::quota_dropped
+mod common;
+
+use common::log_at;
+use log::Level;
+use mmlog::{Builder, Logger, KB};
+
+fn messages(logger: &Logger) -> Vec<String> {
+    logger
+        .tail(usize::MAX)
+        .iter()
+        .map(|r| r.split_once("] ").unwrap().1.to_string())
+        .collect()
+}
+
+#[test]
+fn top_targets_by_bytes() {
+    let logger = Builder::new()
+        .size(64 * KB)
+        .track_targets(true)
+        .build_anonymous()
+        .unwrap();
+    for _ in 0..10 {
+        log_at(&logger, Level::Info, "hyper::proto::h1", "parsed a header");
+    }
+    for _ in 0..5 {
+        log_at(&logger, Level::Info, "app", "request");
+    }
+    log_at(&logger, Level::Info, "hyper::client", "connected");
+    log_at(&logger, Level::Info, "db", "query");
+
+    let top = logger.top_targets(2);
+    let names: Vec<_> = top.iter().map(|(name, _)| name.as_str()).collect();
+    // 统计按顶层 target 合并
+    assert_eq!(names, ["hyper", "app"], "{:?}", top);
+    assert!(top[0].1 > top[1].1, "{:?}", top);
+
+    // 不超过槽位数的时候是准确值，加起来就是写入的字节数（tail() 去掉了每条末尾的换行）
+    let all = logger.top_targets(usize::MAX);
+    assert_eq!(all.len(), 3, "{:?}", all);
+    let total: u64 = all.iter().map(|(_, bytes)| bytes).sum();
+    let records = logger.tail(usize::MAX);
+    assert_eq!(records.len(), 17);
+    assert_eq!(
+        total,
+        records.iter().map(|r| r.len() as u64 + 1).sum::<u64>()
+    );
+
+    // 没有打开的话什么都没有
+    let logger = Builder::new().size(64 * KB).build_anonymous().unwrap();
+    log_at(&logger, Level::Info, "app", "request");
+    assert!(logger.top_targets(10).is_empty());
+}
+
+#[test]
+fn quota_drops_and_marks() {
+    let logger = Builder::new()
+        .size(64 * KB)
+        .target_quota("noisy", 1000)
+        .build_anonymous()
+        .unwrap();
+    let msg = "x".repeat(100);
+    for _ in 0..50 {
+        log_at(&logger, Level::Info, "noisy::inner", &msg);
+        log_at(&logger, Level::Info, "quiet", "still here");
+    }
+
+    let messages = messages(&logger);
+    let noisy = messages.iter().filter(|m| **m == msg).count();
+    let quiet = messages.iter().filter(|m| *m == "still here").count();
+    assert_eq!(quiet, 50);
+    assert!(noisy > 0 && noisy < 10, "{}", noisy);
+    // 只写一条提示
+    let markers: Vec<_> = messages
+        .iter()
+        .filter(|m| m.starts_with("quota exceeded: target noisy wrote more than 1000 bytes"))
+        .collect();
+    assert_eq!(markers.len(), 1, "{:?}", messages);
+    assert_eq!(logger.quota_dropped(), 50 - noisy as u64);
+    assert_eq!(logger.stats().records_dropped, logger.quota_dropped());
+}
+
+#[test]
+fn quota_matches_module_prefix() {
+    let logger = Builder::new()
+        .size(64 * KB)
+        .target_quota("hyper::proto", 500)
+        .target_quota("hyper", 100_000)
+        .build_anonymous()
+        .unwrap();
+    let msg = "x".repeat(100);
+    for _ in 0..20 {
+        for target in ["hyper::proto::h1", "hyper::client", "hyper_util", "hyper"] {
+            log_at(
+                &logger,
+                Level::Info,
+                target,
+                format_args!("{} {}", target, msg),
+            );
+        }
+    }
+
+    let messages = messages(&logger);
+    let count = |target: &str| {
+        let prefix = format!("{} x", target);
+        messages.iter().filter(|m| m.starts_with(&prefix)).count()
+    };
+    // 最长的匹配优先，hyper 的配额管不到 hyper::proto
+    assert!(count("hyper::proto::h1") < 5, "{:?}", messages);
+    assert_eq!(count("hyper::client"), 20);
+    assert_eq!(count("hyper_util"), 20);
+    assert_eq!(count("hyper"), 20);
+    assert!(messages
+        .iter()
+        .any(|m| m.starts_with("quota exceeded: target hyper::proto ")));
+    assert_eq!(
+        logger.quota_dropped(),
+        20 - count("hyper::proto::h1") as u64
+    );
+}
+
+#[test]
+fn quota_resets_after_wrap() {
+    let logger = Builder::new()
+        .size(64 * KB)
+        .target_quota("noisy", 1000)
+        .build_anonymous()
+        .unwrap();
+    let msg = "x".repeat(100);
+    for _ in 0..20 {
+        log_at(&logger, Level::Info, "noisy", &msg);
+    }
+    let dropped = logger.quota_dropped();
+    assert!(dropped > 0);
+
+    // 写满一轮，配额重新计算
+    let wraps = logger.stats().wrap_count;
+    let filler = "y".repeat(1000);
+    while logger.stats().wrap_count == wraps {
+        log_at(&logger, Level::Info, "filler", &filler);
+    }
+    log_at(&logger, Level::Info, "noisy", "back again");
+    assert_eq!(logger.quota_dropped(), dropped);
+    assert_eq!(messages(&logger).last().unwrap(), "back again");
+}
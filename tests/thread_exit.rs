@@ -0,0 +1,137 @@
+// 线程退出时 thread_local 的析构函数里写日志，和不是 std::thread 创建的线程写日志
+mod common;
+
+use common::log;
+use log::Log;
+use mmlog::{Builder, Logger, TimestampFormat, KB};
+use std::sync::OnceLock;
+use std::thread;
+
+struct LogOnDrop(&'static Logger, &'static str);
+
+impl Drop for LogOnDrop {
+    fn drop(&mut self) {
+        log(self.0, self.1);
+    }
+}
+
+thread_local! {
+    static GUARD: std::cell::RefCell<Option<LogOnDrop>> = const { std::cell::RefCell::new(None) };
+}
+
+// 析构函数执行的时候 mmlog 自己的 thread_local 可能已经销毁了，也可能还没初始化过
+fn log_at_exit(logger: &'static Logger, before: bool) {
+    thread::Builder::new()
+        .name("exiting".to_string())
+        .spawn(move || {
+            // 先登记的析构函数后执行：GUARD 在前面的话它执行时 mmlog 的 thread_local 已经销毁了
+            GUARD.with(|guard| *guard.borrow_mut() = Some(LogOnDrop(logger, "from destructor")));
+            if before {
+                log(logger, "before exit");
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn destructor_logs() {
+    static LOGGER: OnceLock<Logger> = OnceLock::new();
+    let logger = LOGGER.get_or_init(|| {
+        Builder::new()
+            .size(64 * KB)
+            .thread_names(true)
+            .build_anonymous()
+            .unwrap()
+    });
+    log_at_exit(logger, false);
+    log_at_exit(logger, true);
+    let records = logger.tail(usize::MAX);
+    let messages: Vec<_> = records
+        .iter()
+        .map(|r| r.split_once("] ").unwrap().1)
+        .collect();
+    assert_eq!(
+        messages,
+        ["from destructor", "before exit", "from destructor"],
+        "{:?}",
+        records
+    );
+    // 线程名字已经销毁了的话不写名字
+    assert!(records[1].contains("/exiting I "), "{:?}", records);
+}
+
+#[test]
+fn destructor_logs_batched() {
+    static LOGGER: OnceLock<Logger> = OnceLock::new();
+    let logger = LOGGER.get_or_init(|| {
+        Builder::new()
+            .size(64 * KB)
+            .thread_names(true)
+            .batched(4 * KB)
+            .build_anonymous()
+            .unwrap()
+    });
+    log_at_exit(logger, true);
+    logger.flush();
+    let records = logger.tail(usize::MAX);
+    assert_eq!(records.len(), 2, "{:?}", records);
+    assert!(records[0].ends_with("] before exit"), "{:?}", records);
+    assert!(records[1].ends_with("] from destructor"), "{:?}", records);
+}
+
+#[test]
+fn destructor_logs_rfc3339() {
+    static LOGGER: OnceLock<Logger> = OnceLock::new();
+    let logger = LOGGER.get_or_init(|| {
+        Builder::new()
+            .size(64 * KB)
+            .timestamp(TimestampFormat::Rfc3339Utc)
+            .build_anonymous()
+            .unwrap()
+    });
+    log_at_exit(logger, true);
+    let records = logger.tail(usize::MAX);
+    assert_eq!(records.len(), 2, "{:?}", records);
+    // 两条记录的日期都是算出来的，不管缓存还在不在
+    assert_eq!(records[0][..11], records[1][..11], "{:?}", records);
+    assert!(records[1].ends_with("] from destructor"), "{:?}", records);
+}
+
+#[cfg(all(target_os = "linux", feature = "mmap"))]
+#[test]
+fn pthread_logs() {
+    use std::ptr;
+
+    static LOGGER: OnceLock<Logger> = OnceLock::new();
+    let logger = LOGGER.get_or_init(|| {
+        Builder::new()
+            .size(64 * KB)
+            .thread_names(true)
+            .build_anonymous()
+            .unwrap()
+    });
+
+    extern "C" fn run(_: *mut libc::c_void) -> *mut libc::c_void {
+        let logger = LOGGER.get().unwrap();
+        log(logger, "from pthread");
+        unsafe { libc::gettid() as usize as *mut libc::c_void }
+    }
+
+    let mut thread = 0;
+    let mut tid = ptr::null_mut();
+    unsafe {
+        assert_eq!(
+            libc::pthread_create(&mut thread, ptr::null(), run, ptr::null_mut()),
+            0
+        );
+        assert_eq!(libc::pthread_join(thread, &mut tid), 0);
+    }
+    let records = logger.tail(usize::MAX);
+    assert_eq!(records.len(), 1, "{:?}", records);
+    // 时间戳之后是线程 id
+    let fields: Vec<_> = records[0].split(' ').collect();
+    assert_eq!(fields[1], (tid as usize).to_string(), "{:?}", records);
+    assert!(records[0].ends_with("] from pthread"));
+}
@@ -0,0 +1,70 @@
+// Builder::timestamp：固定时间戳（mmlog::test::freeze）检查每种写法，
+// 包括同一秒内复用缓存、跨秒跨天之后重新格式化。freeze 是全局的，所以这里只有一个测试
+mod common;
+
+use common::log;
+use mmlog::format::parse_timestamp;
+use mmlog::{Builder, Logger, TimestampFormat};
+use std::time::Duration;
+
+fn stamp(logger: &Logger, secs: u64, nanos: u32) -> String {
+    mmlog::test::freeze(Duration::new(secs, nanos), 1);
+    log(logger, "x");
+    let record = logger.tail(1).pop().unwrap();
+    let ts = record.strip_prefix('[').unwrap().split(' ').next().unwrap();
+    ts.to_string()
+}
+
+fn logger(format: TimestampFormat) -> Logger {
+    Builder::new().timestamp(format).build_anonymous().unwrap()
+}
+
+#[test]
+fn timestamp_formats() {
+    let unix = logger(TimestampFormat::UnixSecondsNanos);
+    let utc = logger(TimestampFormat::Rfc3339Utc);
+    let local = logger(TimestampFormat::LocalTime);
+
+    assert_eq!(
+        stamp(&unix, 1_700_000_000, 123_456_789),
+        "1700000000.123456789s"
+    );
+    assert_eq!(stamp(&unix, 1_700_000_000, 0), "1700000000s");
+
+    let cases = [
+        // 同一秒内只换纳秒
+        (
+            (1_700_000_000, 123_456_789),
+            "2023-11-14T22:13:20.123456789Z",
+        ),
+        ((1_700_000_000, 1), "2023-11-14T22:13:20.000000001Z"),
+        ((1_700_000_001, 0), "2023-11-14T22:13:21.000000000Z"),
+        // 跨天、往回跳、闰日
+        (
+            (1_700_006_399, 999_999_999),
+            "2023-11-14T23:59:59.999999999Z",
+        ),
+        ((1_700_006_400, 0), "2023-11-15T00:00:00.000000000Z"),
+        ((946_684_800, 5), "2000-01-01T00:00:00.000000005Z"),
+        ((951_782_400, 0), "2000-02-29T00:00:00.000000000Z"),
+        ((0, 0), "1970-01-01T00:00:00.000000000Z"),
+    ];
+    for ((secs, nanos), expected) in cases {
+        assert_eq!(stamp(&utc, secs, nanos), expected);
+        // 缓存按格式区分，同一秒换一种格式不会用错
+        let ts = stamp(&local, secs, nanos);
+        assert_eq!(
+            parse_timestamp(&ts),
+            Some(Duration::new(secs, nanos)),
+            "{}",
+            ts
+        );
+        assert!(
+            ts.ends_with('Z') || ts[ts.len() - 6..].starts_with(['+', '-']),
+            "{}",
+            ts
+        );
+        assert_eq!(stamp(&utc, secs, nanos), expected);
+    }
+    mmlog::test::thaw();
+}
@@ -0,0 +1,201 @@
+// mmlog::tui：用 TestBackend 画出界面，按键之后检查画出来的文字和选中的记录。
+// 用 mmlog::test::freeze 固定时间戳和线程 id，这个文件里只能有一个测试
+#![cfg(feature = "tui")]
+mod common;
+
+use common::{fresh, log_at};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use log::Level;
+use mmlog::parse::Entry;
+use mmlog::reader::LogReader;
+use mmlog::tui::{Action, State, Viewer};
+use mmlog::Builder;
+use ratatui::backend::TestBackend;
+use ratatui::Terminal;
+use std::time::Duration;
+
+const T0: Duration = Duration::from_secs(1_700_000_000);
+
+// 整个界面的文字，每行去掉末尾的空格
+fn screen(viewer: &mut Viewer) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+    terminal.draw(|frame| viewer.draw(frame)).unwrap();
+    let buffer = terminal.backend().buffer();
+    buffer
+        .content
+        .chunks(buffer.area.width as usize)
+        .map(|row| {
+            let line: String = row.iter().map(|cell| cell.symbol()).collect();
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn press(viewer: &mut Viewer, code: KeyCode) -> Action {
+    viewer.key(KeyEvent::new(code, KeyModifiers::NONE))
+}
+
+fn typed(viewer: &mut Viewer, text: &str) {
+    for c in text.chars() {
+        assert_eq!(press(viewer, KeyCode::Char(c)), Action::Continue);
+    }
+}
+
+fn selected(viewer: &Viewer) -> String {
+    viewer.selected_record().unwrap().to_string()
+}
+
+#[test]
+fn viewer() {
+    let path = fresh("mmlog-test-tui.log");
+    let logger = Builder::new().level(Level::Trace).build(&path).unwrap();
+    // 第 i 条记录在 T0 + i 秒。没转义的换行让第三条记录多出一条解析不了的续行
+    let records = [
+        (Level::Info, "app", "hello"),
+        (Level::Warn, "app::net", "retrying"),
+        (Level::Error, "app::db", "line one\nline two"),
+        (Level::Debug, "app", "details"),
+        (Level::Trace, "other", "noise"),
+        (Level::Info, "app::net", "hello again"),
+    ];
+    for (i, (level, target, msg)) in records.into_iter().enumerate() {
+        mmlog::test::freeze(T0 + Duration::from_secs(i as u64), 1);
+        log_at(&logger, level, target, msg);
+    }
+
+    let reader = LogReader::open(&path).unwrap();
+    let mut viewer = Viewer::new(&reader, State::new(Entry::parse));
+    assert_eq!(viewer.len(), 7);
+    let text = screen(&mut viewer);
+    assert!(text.contains(" 7/7 records "), "{}", text);
+    assert!(text.contains("levels EWIDT"), "{}", text);
+    assert!(selected(&viewer).ends_with("] hello"));
+
+    // 字段和原始字节
+    assert!(
+        text.contains("(1700000000000000000 ns since the epoch)"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("level:   INFO  tid: 1  target: app"),
+        "{}",
+        text
+    );
+    assert!(text.contains("message: hello"), "{}", text);
+    assert!(text.contains("00000000: 5b31 3730"), "{}", text);
+
+    // 续行在列表里单独一行，解析不了就只有原始字节
+    press(&mut viewer, KeyCode::Down);
+    press(&mut viewer, KeyCode::Char('j'));
+    assert!(selected(&viewer).ends_with("] line one"));
+    press(&mut viewer, KeyCode::Down);
+    assert_eq!(selected(&viewer), "line two");
+    assert!(screen(&mut viewer).contains("not a parsable record"));
+
+    // 关掉 ERROR 的话续行也跟着隐藏，选中的记录换成后面一条
+    press(&mut viewer, KeyCode::Char('1'));
+    let text = screen(&mut viewer);
+    assert!(text.contains(" 5/7 records "), "{}", text);
+    assert!(text.contains("levels -WIDT"), "{}", text);
+    assert!(!text.contains("line two"), "{}", text);
+    assert!(selected(&viewer).ends_with("] details"));
+    press(&mut viewer, KeyCode::Char('5'));
+    assert!(screen(&mut viewer).contains(" 4/7 records "));
+    press(&mut viewer, KeyCode::Char('1'));
+    press(&mut viewer, KeyCode::Char('5'));
+    assert!(screen(&mut viewer).contains(" 7/7 records "));
+
+    // target 前缀，边输入边过滤，Esc 清空
+    press(&mut viewer, KeyCode::Char('t'));
+    typed(&mut viewer, "app::n");
+    assert!(screen(&mut viewer).contains("target: app::n"));
+    press(&mut viewer, KeyCode::Enter);
+    let text = screen(&mut viewer);
+    assert!(text.contains(" 2/7 records "), "{}", text);
+    assert!(text.contains("target app::n"), "{}", text);
+    press(&mut viewer, KeyCode::Char('t'));
+    press(&mut viewer, KeyCode::Esc);
+    assert!(screen(&mut viewer).contains(" 7/7 records "));
+
+    // 边输入边查找，n、N 找到头了从另一头接着找
+    press(&mut viewer, KeyCode::Char('g'));
+    press(&mut viewer, KeyCode::Char('/'));
+    typed(&mut viewer, "hello a");
+    assert!(selected(&viewer).ends_with("] hello again"));
+    press(&mut viewer, KeyCode::Backspace);
+    press(&mut viewer, KeyCode::Backspace);
+    assert!(selected(&viewer).ends_with("] hello"));
+    press(&mut viewer, KeyCode::Enter);
+    press(&mut viewer, KeyCode::Char('n'));
+    assert!(selected(&viewer).ends_with("] hello again"));
+    press(&mut viewer, KeyCode::Char('n'));
+    assert!(selected(&viewer).ends_with("] hello"));
+    press(&mut viewer, KeyCode::Char('N'));
+    assert!(selected(&viewer).ends_with("] hello again"));
+    press(&mut viewer, KeyCode::Char('/'));
+    typed(&mut viewer, "nothing");
+    assert!(screen(&mut viewer).contains("/nothing▏"));
+    press(&mut viewer, KeyCode::Enter);
+    assert!(screen(&mut viewer).contains("no match for \"nothing\""));
+
+    // 跳到某个时间
+    press(&mut viewer, KeyCode::Char('@'));
+    typed(&mut viewer, "1700000002.5s");
+    press(&mut viewer, KeyCode::Enter);
+    assert!(selected(&viewer).ends_with("] details"));
+    press(&mut viewer, KeyCode::Char('@'));
+    typed(&mut viewer, "2023-11-14T22:13:21Z");
+    press(&mut viewer, KeyCode::Enter);
+    assert!(selected(&viewer).ends_with("] retrying"));
+    press(&mut viewer, KeyCode::Char('@'));
+    typed(&mut viewer, "soon");
+    press(&mut viewer, KeyCode::Enter);
+    assert!(screen(&mut viewer).contains("bad time: soon"));
+    assert!(selected(&viewer).ends_with("] retrying"));
+
+    // 跟随：f 选中最后一条，手动移动就不再跟随
+    press(&mut viewer, KeyCode::Char('f'));
+    assert!(viewer.following());
+    assert!(screen(&mut viewer).contains("following"));
+    assert!(selected(&viewer).ends_with("] hello again"));
+    press(&mut viewer, KeyCode::Char('k'));
+    assert!(!viewer.following());
+    press(&mut viewer, KeyCode::Char('G'));
+    assert!(!viewer.following());
+    press(&mut viewer, KeyCode::Char('f'));
+    press(&mut viewer, KeyCode::Char('5'));
+
+    // 有新记录的话重新打开文件，过滤条件留下来，选中最后一条
+    mmlog::test::freeze(T0 + Duration::from_secs(6), 1);
+    log_at(&logger, Level::Warn, "app", "newer");
+    log_at(&logger, Level::Trace, "app", "hidden");
+    mmlog::test::thaw();
+    let state = viewer.into_state();
+    let reader = LogReader::open(&path).unwrap();
+    let mut viewer = Viewer::new(&reader, state);
+    assert_eq!(viewer.len(), 9);
+    let text = screen(&mut viewer);
+    assert!(text.contains(" 7/9 records "), "{}", text);
+    assert!(
+        text.contains("levels EWID-  /nothing  following"),
+        "{}",
+        text
+    );
+    assert!(selected(&viewer).ends_with("] newer"));
+
+    // 不跟随的话还是选中原来那条
+    press(&mut viewer, KeyCode::Char('g'));
+    let state = viewer.into_state();
+    let mut viewer = Viewer::new(&reader, state);
+    assert!(selected(&viewer).ends_with("] hello"));
+
+    assert_eq!(
+        viewer.key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+        Action::Quit
+    );
+    assert_eq!(press(&mut viewer, KeyCode::Esc), Action::Quit);
+    assert_eq!(press(&mut viewer, KeyCode::Char('q')), Action::Quit);
+    drop(logger);
+}
@@ -0,0 +1,205 @@
+// 翻转的边界：刚好写到末尾的记录、比整个缓冲区还长的记录，和几个线程一起反复翻转。
+//
+// 写入路径在 sanitizer 下也跑这个文件：
+//
+//     RUSTFLAGS=-Zsanitizer=address cargo +nightly test --test wrap --target x86_64-unknown-linux-gnu
+//     RUSTFLAGS=-Zsanitizer=address cargo +nightly test --test wrap --no-default-features --target x86_64-unknown-linux-gnu
+//     MIRIFLAGS=-Zmiri-disable-isolation cargo +nightly miri test --test wrap --no-default-features
+//
+// AddressSanitizer 两种实现都是干净的；Miri 不支持 mmap，只能检查 std_file 实现。
+// ThreadSanitizer 见 examples/wrap.rs。
+mod common;
+
+use common::{fresh, log};
+use log::Log;
+use mmlog::format::DATA_POS;
+use mmlog::reader::LogReader;
+use mmlog::{Builder, Framing, Logger, Oversized, KB};
+use std::io::Write;
+use std::path::Path;
+use std::thread;
+
+// 文件里缓冲区的内容
+fn ring(logger: &Logger, path: &Path) -> Vec<u8> {
+    logger.flush();
+    let file = std::fs::read(path).unwrap();
+    let start = u32::from_le_bytes(file[DATA_POS..DATA_POS + 4].try_into().unwrap()) as usize;
+    file[start..start + logger.stats().capacity].to_vec()
+}
+
+// 逐字节绕圈写入的结果，返回新的 offset
+fn simulate(ring: &mut [u8], offset: usize, bytes: &[u8]) -> usize {
+    let mut at = offset;
+    for &b in bytes {
+        ring[at] = b;
+        at = (at + 1) % ring.len();
+    }
+    at
+}
+
+// 每个位置都不一样的字节，错位的话比较得出来
+fn pattern(len: usize, seed: usize) -> Vec<u8> {
+    (0..len).map(|i| ((i + seed) % 251) as u8 + 1).collect()
+}
+
+#[test]
+fn oversized_wraps_like_bytewise() {
+    let path = fresh("mmlog-test-wrap-oversized.log");
+    let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+    let size = logger.stats().capacity;
+    let mut expected = ring(&logger, &path);
+    let mut offset = 0;
+    let mut wraps = 0;
+
+    // 从开头和从中间开始写都试一遍
+    for (seed, len) in [
+        (1, size),
+        (2, size + 1),
+        (3, 3 * size),
+        (4, 10),
+        (5, size),
+        (6, size + 1),
+        (7, 3 * size),
+    ] {
+        let bytes = pattern(len, seed);
+        logger.writer().write_all(&bytes).unwrap();
+        wraps += ((offset + len) / size) as u64;
+        offset = simulate(&mut expected, offset, &bytes);
+
+        let stats = logger.stats();
+        assert_eq!(stats.used_bytes_since_wrap, offset, "len {}", len);
+        assert!(stats.used_bytes_since_wrap < size);
+        assert_eq!(stats.wrap_count, wraps, "len {}", len);
+        assert!(ring(&logger, &path) == expected, "len {}", len);
+    }
+}
+
+#[test]
+fn oversized_truncates() {
+    let path = fresh("mmlog-test-wrap-truncate.log");
+    let logger = Builder::new()
+        .size(512 * KB)
+        .oversized(Oversized::Truncate)
+        .build(&path)
+        .unwrap();
+    let size = logger.stats().capacity;
+    for len in [size, size + 1, 3 * size] {
+        log(&logger, "x".repeat(len));
+        let stats = logger.stats();
+        assert!(stats.used_bytes_since_wrap < size);
+        let last = logger.tail(1).pop().unwrap();
+        assert!(last.len() < size, "len {}", len);
+        assert!(last.contains("x...[truncated "), "len {}", len);
+    }
+    assert_eq!(logger.stats().records_truncated, 3);
+}
+
+// len 字节、换行结尾的一行
+fn line(len: usize, seed: usize) -> Vec<u8> {
+    let mut line: Vec<u8> = (0..len).map(|i| b'a' + ((i + seed) % 26) as u8).collect();
+    if let Some(last) = line.last_mut() {
+        *last = b'\n';
+    }
+    line
+}
+
+// 从 start 开始写一条记录，结尾落在缓冲区末尾前后两个字节之内
+#[test]
+fn boundary_sweep() {
+    let path = fresh("mmlog-test-wrap-boundary.log");
+    let size = Builder::new()
+        .size(512 * KB)
+        .build_anonymous()
+        .unwrap()
+        .stats()
+        .capacity;
+    for start in [0, 100] {
+        for len in size - start - 2..=size - start + 2 {
+            let logger = Builder::new().size(512 * KB).build(&path).unwrap();
+            let mut expected = ring(&logger, &path);
+            let mut offset = 0;
+            let mut total = 0;
+            for bytes in [line(start, 0), line(len, 1), b"next\n".to_vec()] {
+                logger.writer().write_all(&bytes).unwrap();
+                offset = simulate(&mut expected, offset, &bytes);
+                total += bytes.len();
+
+                // 正好写到末尾的话 offset 是 0 而不是 size，翻转只算一次
+                let stats = logger.stats();
+                assert_eq!(
+                    stats.used_bytes_since_wrap, offset,
+                    "start {} len {}",
+                    start, len
+                );
+                assert_eq!(
+                    stats.wrap_count,
+                    (total / size) as u64,
+                    "start {} len {}",
+                    start,
+                    len
+                );
+            }
+            assert!(
+                ring(&logger, &path) == expected,
+                "start {} len {}",
+                start,
+                len
+            );
+            // 下一条从头干净地开始
+            assert_eq!(logger.tail(1), ["next"], "start {} len {}", start, len);
+        }
+    }
+}
+
+// 几个线程一起写，翻转好几次之后每个线程留下来的记录还是连续的
+#[test]
+fn threads_wrap() {
+    const THREADS: usize = 4;
+    const RECORDS: usize = 300;
+    let padding = ".".repeat(1000);
+    for framing in [Framing::Text, Framing::LengthPrefixed] {
+        let path = fresh(&format!("mmlog-test-wrap-threads-{:?}.log", framing));
+        let logger = Builder::new()
+            .size(512 * KB)
+            .framing(framing)
+            .build(&path)
+            .unwrap();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let (logger, padding) = (&logger, &padding);
+                s.spawn(move || {
+                    for i in 0..RECORDS {
+                        log(
+                            logger,
+                            format_args!("{} thread {} record {}", padding, t, i),
+                        );
+                    }
+                });
+            }
+        });
+        let stats = logger.stats();
+        assert_eq!(stats.records_written, (THREADS * RECORDS) as u64);
+        assert!(stats.wrap_count > 0);
+        drop(logger);
+
+        let reader = LogReader::open(&path).unwrap();
+        assert!(reader.checked_records().all(|r| r.is_ok()));
+        let records: Vec<_> = reader.records().collect();
+        assert!(records.len() > RECORDS / 2);
+        for t in 0..THREADS {
+            let prefix = format!("thread {} record ", t);
+            let numbers: Vec<usize> = records
+                .iter()
+                .filter_map(|r| r.split_once(&prefix))
+                .map(|(_, n)| n.parse().unwrap())
+                .collect();
+            assert!(
+                numbers.windows(2).all(|w| w[1] == w[0] + 1),
+                "{:?}",
+                numbers
+            );
+        }
+        let last = format!(" record {}", RECORDS - 1);
+        assert!(records.last().unwrap().ends_with(&last));
+    }
+}